@@ -0,0 +1,14 @@
+use std::env;
+use std::path::Path;
+
+// The header is written next to the sources so that it can be checked in and used without Rust
+fn main() {
+    let dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+
+    println!("cargo:rerun-if-changed=src/lib.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+
+    cbindgen::generate(&dir)
+        .expect("Failed to generate the C header")
+        .write_to_file(Path::new(&dir).join("include").join("chip8rs.h"));
+}
@@ -0,0 +1,67 @@
+// Runs a tiny program which draws the digit 5, prints the screen and checks that a save state
+// brings the machine back to where it was
+
+#include <stdio.h>
+#include <stdlib.h>
+
+#include "chip8rs.h"
+
+// V0 = 5, I = sprite of the digit in V0, draw it at (V0, V0), loop forever
+static const uint8_t ROM[] = {0x60, 0x05, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x06};
+
+int main(void) {
+    Chip8Machine *machine = chip8_create(10);
+
+    if (machine == NULL || !chip8_load_rom(machine, ROM, sizeof(ROM))) {
+        fprintf(stderr, "error: %s\n", chip8_last_error());
+        return 1;
+    }
+
+    if (!chip8_run_frames(machine, 1)) {
+        fprintf(stderr, "error: %s\n", chip8_last_error());
+        return 1;
+    }
+
+    const uint32_t *pixels = chip8_get_pixels(machine);
+
+    for (int y = 5; y < 10; y++) {
+        for (int x = 5; x < 9; x++) {
+            putchar(pixels[y * CHIP8_FRAMEBUFFER_WIDTH + x] == 0xFFFFFF ? '#' : '.');
+        }
+
+        putchar('\n');
+    }
+
+    size_t size = chip8_save_state(machine, NULL, 0);
+    uint8_t *state = malloc(size);
+
+    chip8_save_state(machine, state, size);
+    chip8_reset(machine);
+    printf("reset PC=%03X\n", chip8_get_program_counter(machine));
+
+    if (!chip8_load_state(machine, state, size)) {
+        fprintf(stderr, "error: %s\n", chip8_last_error());
+        return 1;
+    }
+
+    printf("restored PC=%03X V0=%d\n", chip8_get_program_counter(machine), chip8_get_register(machine, 0));
+
+    if (chip8_load_state(machine, state, size - 1)) {
+        fprintf(stderr, "error: a truncated state was accepted\n");
+        return 1;
+    }
+
+    printf("truncated state: %s\n", chip8_last_error());
+
+    if (chip8_load_state(machine, NULL, 0)) {
+        fprintf(stderr, "error: an empty state was accepted\n");
+        return 1;
+    }
+
+    printf("empty state: %s\n", chip8_last_error());
+
+    free(state);
+    chip8_destroy(machine);
+
+    return 0;
+}
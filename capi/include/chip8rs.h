@@ -0,0 +1,127 @@
+#ifndef CHIP8RS_H
+#define CHIP8RS_H
+
+/* Generated by cbindgen from src/lib.rs, don't edit by hand */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Width of the framebuffer in pixels
+#define CHIP8_FRAMEBUFFER_WIDTH 64
+
+// Height of the framebuffer in pixels
+#define CHIP8_FRAMEBUFFER_HEIGHT 32
+
+// Size of the memory in bytes
+#define CHIP8_MEMORY_SIZE 4096
+
+// A machine running a ROM at a number of instructions per 60 Hz frame
+typedef struct Chip8Machine Chip8Machine;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Creates a machine without a ROM, running `speed` instructions per frame. Returns NULL when the
+// speed is 0.
+struct Chip8Machine *chip8_create(uint32_t speed);
+
+// Frees a machine. Passing NULL does nothing.
+void chip8_destroy(struct Chip8Machine *machine);
+
+// The message of the last error on this thread, which stays valid until the next one
+const char *chip8_last_error(void);
+
+// Replaces the program with a ROM in any of the formats the emulator reads, i.e. a plain binary
+// or an Octo cartridge, starting it from the beginning. Cartridges bring their own speed, colours
+// and quirks, although the quirks set with chip8_set_quirks win.
+//
+// # Safety
+//
+// `data` must point to `len` readable bytes, or may be NULL when `len` is 0.
+bool chip8_load_rom(struct Chip8Machine *machine, const uint8_t *data, size_t len);
+
+// Replaces the program with the ROM in a file. Archives must hold a single ROM.
+//
+// # Safety
+//
+// `path` must be a NUL-terminated string.
+bool chip8_load_file(struct Chip8Machine *machine, const char *path);
+
+// Enables the quirks in a comma-separated list (shift, load-store, jump, vf-reset, wrap,
+// display-wait, increment-by-x) or "none", for the running program and the ROMs loaded after it.
+//
+// # Safety
+//
+// `quirks` must be a NUL-terminated string.
+bool chip8_set_quirks(struct Chip8Machine *machine, const char *quirks);
+
+// Seeds the random number generator of the running program and the ROMs loaded after it
+void chip8_set_seed(struct Chip8Machine *machine, uint64_t seed);
+
+// Starts the ROM again from the beginning
+void chip8_reset(struct Chip8Machine *machine);
+
+// Executes instructions, counting down the timers once every frame's worth. Returns false when
+// the program fails, i.e. on an unknown instruction.
+bool chip8_step(struct Chip8Machine *machine, uint32_t count);
+
+// Runs whole frames, like chip8_step
+bool chip8_run_frames(struct Chip8Machine *machine, uint32_t count);
+
+// Presses or releases a keypad key from 0 to 15. Other keys are ignored.
+void chip8_set_key(struct Chip8Machine *machine, uint8_t key, bool pressed);
+
+// Releases all of the keypad keys
+void chip8_release_keys(struct Chip8Machine *machine);
+
+// The screen as CHIP8_FRAMEBUFFER_WIDTH * CHIP8_FRAMEBUFFER_HEIGHT 0xRRGGBB colours, row by row,
+// which stay valid until the machine runs or is changed
+const uint32_t *chip8_get_pixels(const struct Chip8Machine *machine);
+
+// Sets the colours of unlit and lit pixels, as 0xRRGGBB
+void chip8_set_palette(struct Chip8Machine *machine, uint32_t background, uint32_t foreground);
+
+// Whether the beeper is sounding, which it does for as long as the sound timer is running
+bool chip8_is_beeping(const struct Chip8Machine *machine);
+
+uint8_t chip8_get_sound_timer(const struct Chip8Machine *machine);
+
+uint8_t chip8_get_delay_timer(const struct Chip8Machine *machine);
+
+uint16_t chip8_get_program_counter(const struct Chip8Machine *machine);
+
+// The value of register VX, or 0 for registers past VF
+uint8_t chip8_get_register(const struct Chip8Machine *machine, uint8_t x);
+
+// Copies `len` bytes of memory starting at `addr` into `out`. Returns false when they go past
+// the end of memory.
+//
+// # Safety
+//
+// `out` must point to `len` writable bytes, or may be NULL when `len` is 0.
+bool chip8_read_memory(const struct Chip8Machine *machine, uint16_t addr, uint8_t *out, size_t len);
+
+// Writes a save state into `out` when it has room for it, and returns the size of the state
+// either way. Passing NULL and 0 finds out how much room is needed.
+//
+// # Safety
+//
+// `out` must point to `len` writable bytes, or be NULL when `len` is 0.
+size_t chip8_save_state(const struct Chip8Machine *machine, uint8_t *out, size_t len);
+
+// Restores a save state written by chip8_save_state
+//
+// # Safety
+//
+// `data` must point to `len` readable bytes, or may be NULL when `len` is 0.
+bool chip8_load_state(struct Chip8Machine *machine, const uint8_t *data, size_t len);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* CHIP8RS_H */
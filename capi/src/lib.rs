@@ -0,0 +1,325 @@
+// The C API of the emulator core. Machines are opaque handles created with chip8_create and freed
+// with chip8_destroy, and functions which can fail return false and leave a message for
+// chip8_last_error. The doc comments end up in include/chip8rs.h, which build.rs generates.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::path::Path;
+use std::slice;
+
+use chip8_rs::chip8::{Quirks, FRAMEBUFFER_HEIGHT, FRAMEBUFFER_WIDTH};
+use chip8_rs::debug::{Debugger, Stop};
+use chip8_rs::rom::{self, Rom};
+use chip8_rs::Chip8;
+
+/// Width of the framebuffer in pixels
+pub const CHIP8_FRAMEBUFFER_WIDTH: usize = 64;
+
+/// Height of the framebuffer in pixels
+pub const CHIP8_FRAMEBUFFER_HEIGHT: usize = 32;
+
+/// Size of the memory in bytes
+pub const CHIP8_MEMORY_SIZE: usize = 4096;
+
+// The header needs the values as literals
+const _: () = assert!(CHIP8_FRAMEBUFFER_WIDTH == FRAMEBUFFER_WIDTH && CHIP8_FRAMEBUFFER_HEIGHT == FRAMEBUFFER_HEIGHT);
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+/// A machine running a ROM at a number of instructions per 60 Hz frame
+pub struct Chip8Machine {
+    debugger: Debugger,
+    // The machine as the ROM was loaded, which resets go back to
+    initial: Chip8,
+    // The speed the machine was created with and the quirks set with chip8_set_quirks, which
+    // cartridges override and are overridden by respectively
+    speed: u32,
+    quirks: Option<Quirks>,
+    seed: Option<u64>,
+}
+
+impl Chip8Machine {
+    fn load(&mut self, rom: Rom) -> Result<(), String> {
+        let mut cpu = Chip8::new(&rom.data)?;
+        let profile = rom.profile.as_ref();
+
+        cpu.set_quirks(self.quirks.or(profile.map(|profile| profile.quirks)).unwrap_or_default());
+
+        if let Some(palette) = profile.and_then(|profile| profile.palette) {
+            cpu.set_palette(palette);
+        }
+
+        if let Some(seed) = self.seed {
+            cpu.set_seed(seed);
+        }
+
+        let speed = profile.and_then(|profile| profile.tickrate).map_or(self.speed, |speed| speed.max(1));
+
+        self.debugger = Debugger::new(cpu.clone(), speed);
+        self.initial = cpu;
+
+        Ok(())
+    }
+
+    fn cpu(&self) -> &Chip8 {
+        self.debugger.get_cpu()
+    }
+
+    fn cpu_mut(&mut self) -> &mut Chip8 {
+        self.debugger.get_cpu_mut()
+    }
+}
+
+/// Creates a machine without a ROM, running `speed` instructions per frame. Returns NULL when the
+/// speed is 0.
+#[no_mangle]
+pub extern "C" fn chip8_create(speed: u32) -> Option<Box<Chip8Machine>> {
+    if speed == 0 {
+        fail("Speed must be at least 1".to_string());
+        return None;
+    }
+
+    let cpu = Chip8::new(&[]).ok()?;
+
+    Some(Box::new(Chip8Machine {
+        debugger: Debugger::new(cpu.clone(), speed),
+        initial: cpu,
+        speed,
+        quirks: None,
+        seed: None,
+    }))
+}
+
+/// Frees a machine. Passing NULL does nothing.
+#[no_mangle]
+pub extern "C" fn chip8_destroy(machine: Option<Box<Chip8Machine>>) {
+    drop(machine);
+}
+
+/// The message of the last error on this thread, which stays valid until the next one
+#[no_mangle]
+pub extern "C" fn chip8_last_error() -> *const c_char {
+    LAST_ERROR.with(|error| error.borrow().as_ptr())
+}
+
+/// Replaces the program with a ROM in any of the formats the emulator reads, i.e. a plain binary
+/// or an Octo cartridge, starting it from the beginning. Cartridges bring their own speed, colours
+/// and quirks, although the quirks set with chip8_set_quirks win.
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes, or may be NULL when `len` is 0.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_load_rom(machine: &mut Chip8Machine, data: *const u8, len: usize) -> bool {
+    let data = bytes(data, len);
+
+    report(rom::parse(data.to_vec(), "ROM").and_then(|rom| machine.load(rom)))
+}
+
+/// Replaces the program with the ROM in a file. Archives must hold a single ROM.
+///
+/// # Safety
+///
+/// `path` must be a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_load_file(machine: &mut Chip8Machine, path: *const c_char) -> bool {
+    let path = CStr::from_ptr(path).to_string_lossy();
+    let mut choose = |names: &[String]| Err(format!("The archive holds several ROMs ({})", names.join(", ")));
+
+    report(rom::load(Path::new(path.as_ref()), &mut choose).and_then(|rom| machine.load(rom)))
+}
+
+/// Enables the quirks in a comma-separated list (shift, load-store, jump, vf-reset, wrap,
+/// display-wait, increment-by-x) or "none", for the running program and the ROMs loaded after it.
+///
+/// # Safety
+///
+/// `quirks` must be a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_set_quirks(machine: &mut Chip8Machine, quirks: *const c_char) -> bool {
+    let quirks = CStr::from_ptr(quirks).to_string_lossy().parse::<Quirks>();
+
+    report(quirks.map(|quirks| {
+        machine.quirks = Some(quirks);
+        machine.initial.set_quirks(quirks);
+        machine.cpu_mut().set_quirks(quirks);
+    }))
+}
+
+/// Seeds the random number generator of the running program and the ROMs loaded after it
+#[no_mangle]
+pub extern "C" fn chip8_set_seed(machine: &mut Chip8Machine, seed: u64) {
+    machine.seed = Some(seed);
+    machine.initial.set_seed(seed);
+    machine.cpu_mut().set_seed(seed);
+}
+
+/// Starts the ROM again from the beginning
+#[no_mangle]
+pub extern "C" fn chip8_reset(machine: &mut Chip8Machine) {
+    machine.debugger = Debugger::new(machine.initial.clone(), machine.debugger.get_speed());
+}
+
+/// Executes instructions, counting down the timers once every frame's worth. Returns false when
+/// the program fails, i.e. on an unknown instruction.
+#[no_mangle]
+pub extern "C" fn chip8_step(machine: &mut Chip8Machine, count: u32) -> bool {
+    for _ in 0..count {
+        if !check(machine.debugger.step()) {
+            return false;
+        }
+    }
+
+    true
+}
+
+/// Runs whole frames, like chip8_step
+#[no_mangle]
+pub extern "C" fn chip8_run_frames(machine: &mut Chip8Machine, count: u32) -> bool {
+    for _ in 0..count {
+        if !check(machine.debugger.run_frame()) {
+            return false;
+        }
+    }
+
+    true
+}
+
+/// Presses or releases a keypad key from 0 to 15. Other keys are ignored.
+#[no_mangle]
+pub extern "C" fn chip8_set_key(machine: &mut Chip8Machine, key: u8, pressed: bool) {
+    if key <= 0xF {
+        machine.cpu_mut().set_key_state(key as usize, pressed);
+    }
+}
+
+/// Releases all of the keypad keys
+#[no_mangle]
+pub extern "C" fn chip8_release_keys(machine: &mut Chip8Machine) {
+    machine.cpu_mut().release_keys();
+}
+
+/// The screen as CHIP8_FRAMEBUFFER_WIDTH * CHIP8_FRAMEBUFFER_HEIGHT 0xRRGGBB colours, row by row,
+/// which stay valid until the machine runs or is changed
+#[no_mangle]
+pub extern "C" fn chip8_get_pixels(machine: &Chip8Machine) -> *const u32 {
+    machine.cpu().get_pixels().as_ptr()
+}
+
+/// Sets the colours of unlit and lit pixels, as 0xRRGGBB
+#[no_mangle]
+pub extern "C" fn chip8_set_palette(machine: &mut Chip8Machine, background: u32, foreground: u32) {
+    machine.initial.set_palette([background, foreground]);
+    machine.cpu_mut().set_palette([background, foreground]);
+}
+
+/// Whether the beeper is sounding, which it does for as long as the sound timer is running
+#[no_mangle]
+pub extern "C" fn chip8_is_beeping(machine: &Chip8Machine) -> bool {
+    machine.cpu().get_sound_timer() > 0
+}
+
+#[no_mangle]
+pub extern "C" fn chip8_get_sound_timer(machine: &Chip8Machine) -> u8 {
+    machine.cpu().get_sound_timer()
+}
+
+#[no_mangle]
+pub extern "C" fn chip8_get_delay_timer(machine: &Chip8Machine) -> u8 {
+    machine.cpu().get_delay_timer()
+}
+
+#[no_mangle]
+pub extern "C" fn chip8_get_program_counter(machine: &Chip8Machine) -> u16 {
+    machine.cpu().get_program_counter()
+}
+
+/// The value of register VX, or 0 for registers past VF
+#[no_mangle]
+pub extern "C" fn chip8_get_register(machine: &Chip8Machine, x: u8) -> u8 {
+    machine.cpu().get_registers().get(x as usize).copied().unwrap_or(0)
+}
+
+/// Copies `len` bytes of memory starting at `addr` into `out`. Returns false when they go past
+/// the end of memory.
+///
+/// # Safety
+///
+/// `out` must point to `len` writable bytes, or may be NULL when `len` is 0.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_read_memory(machine: &Chip8Machine, addr: u16, out: *mut u8, len: usize) -> bool {
+    let end = (addr as usize).checked_add(len);
+
+    let Some(memory) = end.and_then(|end| machine.cpu().get_memory().get(addr as usize..end)) else {
+        return fail(format!("{} bytes at {:03X} is past the end of memory", len, addr));
+    };
+
+    if len > 0 {
+        slice::from_raw_parts_mut(out, len).copy_from_slice(memory);
+    }
+
+    true
+}
+
+/// Writes a save state into `out` when it has room for it, and returns the size of the state
+/// either way. Passing NULL and 0 finds out how much room is needed.
+///
+/// # Safety
+///
+/// `out` must point to `len` writable bytes, or be NULL when `len` is 0.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_save_state(machine: &Chip8Machine, out: *mut u8, len: usize) -> usize {
+    let state = machine.cpu().save_state();
+
+    if len >= state.len() {
+        slice::from_raw_parts_mut(out, state.len()).copy_from_slice(&state);
+    }
+
+    state.len()
+}
+
+/// Restores a save state written by chip8_save_state
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes, or may be NULL when `len` is 0.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_load_state(machine: &mut Chip8Machine, data: *const u8, len: usize) -> bool {
+    let state = bytes(data, len);
+
+    report(machine.cpu_mut().load_state(state))
+}
+
+// C callers pass NULL for empty buffers, which slices can't point to
+unsafe fn bytes<'a>(data: *const u8, len: usize) -> &'a [u8] {
+    match len {
+        0 => &[],
+        _ => slice::from_raw_parts(data, len),
+    }
+}
+
+// Without breakpoints or watchpoints the debugger only stops for errors
+fn check(stop: Option<Stop>) -> bool {
+    match stop {
+        Some(Stop::Error(err)) => fail(err),
+        _ => true,
+    }
+}
+
+fn report(result: Result<(), String>) -> bool {
+    match result {
+        Ok(()) => true,
+        Err(err) => fail(err),
+    }
+}
+
+fn fail(err: String) -> bool {
+    // Messages never hold NULs in practice, but C strings can't
+    let err = CString::new(err.replace('\0', " ")).unwrap_or_default();
+
+    LAST_ERROR.with(|error| *error.borrow_mut() = err);
+
+    false
+}
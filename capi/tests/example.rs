@@ -0,0 +1,46 @@
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+// Compiles examples/example.c against the generated header and the static library, and runs it
+#[test]
+#[cfg(unix)]
+fn example() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let target = Path::new(env!("CARGO_TARGET_TMPDIR")).join("capi");
+
+    // Tests are only linked against the Rust library, so the static one is built here. It goes to
+    // a directory of its own as the one the tests were built in is locked until they finish, and
+    // everything it needs has already been fetched for the tests.
+    let status = Command::new(env!("CARGO"))
+        .args(["build", "--offline", "--quiet", "--lib", "--manifest-path"])
+        .arg(dir.join("Cargo.toml"))
+        .arg("--target-dir")
+        .arg(&target)
+        .status()
+        .expect("Failed to run cargo");
+
+    assert!(status.success(), "Failed to build the static library");
+
+    let exe = target.join("example");
+    let compiler = env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let status = Command::new(compiler)
+        .arg(dir.join("examples").join("example.c"))
+        .arg("-I")
+        .arg(dir.join("include"))
+        .arg("-o")
+        .arg(&exe)
+        .arg(target.join("debug").join("libchip8rs.a"))
+        .args(["-lpthread", "-ldl", "-lm"])
+        .status()
+        .expect("Failed to run the C compiler");
+
+    assert!(status.success(), "Failed to compile example.c");
+
+    let output = Command::new(&exe).output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(stdout, "####\n#...\n####\n...#\n####\nreset PC=200\nrestored PC=206 V0=5\n\
+                        truncated state: Not a save state\nempty state: Not a save state\n");
+}
@@ -0,0 +1,15 @@
+#!/bin/sh
+# Replaces the bundled database with the latest files from the CHIP-8 database, along with its
+# licence. The emulator has to be rebuilt afterwards, as the files are compiled in.
+set -eu
+
+cd "$(dirname "$0")"
+
+base=https://raw.githubusercontent.com/chip-8/chip-8-database/master
+
+for file in programs.json platforms.json; do
+    curl -fsSL -o "$file.tmp" "$base/database/$file"
+    mv "$file.tmp" "$file"
+done
+
+curl -fsSL -o LICENSE "$base/LICENSE"
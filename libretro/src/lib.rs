@@ -0,0 +1,717 @@
+// A libretro core running the emulator in RetroArch and other libretro frontends. The frontend
+// calls the retro_* functions from a single thread, and the core keeps the machine and the
+// callbacks it was given in a global.
+
+mod sys;
+
+use std::ffi::{c_char, c_uint, c_void, CStr, CString};
+use std::path::Path;
+use std::ptr;
+use std::slice;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use chip8_rs::chip8::{Quirks, FRAMEBUFFER_HEIGHT, FRAMEBUFFER_WIDTH};
+use chip8_rs::database::Database;
+use chip8_rs::keypad::KeyHints;
+use chip8_rs::{rom, Chip8};
+
+use sys::*;
+
+const FPS: f64 = 60.0;
+const SAMPLE_RATE: f64 = 44100.0;
+const SAMPLES_PER_FRAME: usize = (SAMPLE_RATE / FPS) as usize;
+
+// The beeper of the SDL frontend
+const BEEP_FREQUENCY: f64 = 440.0;
+const BEEP_VOLUME: f64 = 0.25;
+
+const DEFAULT_SPEED: u32 = 10;
+
+// The keypad key each RetroPad button presses, by button id. The D-pad and A sit on the 2/4/6/8
+// cross and 5 most games move and act with, and the rest of the keys go on the other buttons.
+const BUTTON_KEYS: [usize; 16] = [
+    0x0, // B
+    0x3, // Y
+    0xE, // Select
+    0xF, // Start
+    0x2, // Up
+    0x8, // Down
+    0x4, // Left
+    0x6, // Right
+    0x5, // A
+    0x1, // X
+    0x7, // L
+    0x9, // R
+    0xA, // L2
+    0xB, // R2
+    0xC, // L3
+    0xD, // R3
+];
+
+// What the buttons do, for the frontend's controls menu
+const KEY_NAMES: [&CStr; 16] = [
+    c"Key 0", c"Key 1", c"Key 2", c"Key 3", c"Key 4", c"Key 5", c"Key 6", c"Key 7",
+    c"Key 8", c"Key 9", c"Key A", c"Key B", c"Key C", c"Key D", c"Key E", c"Key F",
+];
+
+// The palettes of the pause menu
+const PALETTES: [(&str, [u32; 2]); 5] = [
+    ("White", [0x000000, 0xFFFFFF]),
+    ("Amber", [0x1A0F00, 0xFFB000]),
+    ("Green", [0x051A05, 0x33FF66]),
+    ("LCD", [0x9BBC0F, 0x0F380F]),
+    ("Paper", [0xF0EAD8, 0x202020]),
+];
+
+const SPEED_OPTION: &CStr = c"chip8rs_speed";
+const PALETTE_OPTION: &CStr = c"chip8rs_palette";
+
+type QuirkField = fn(&mut Quirks) -> &mut bool;
+
+// Each quirk has an option which either leaves it as the ROM database has it or overrides it
+const QUIRK_OPTIONS: [(&CStr, &CStr, QuirkField); 7] = [
+    (c"chip8rs_quirk_shift", c"Shift quirk; auto|enabled|disabled", |quirks| &mut quirks.shift),
+    (c"chip8rs_quirk_load_store", c"Load/store quirk; auto|enabled|disabled", |quirks| &mut quirks.load_store),
+    (c"chip8rs_quirk_jump", c"Jump quirk; auto|enabled|disabled", |quirks| &mut quirks.jump),
+    (c"chip8rs_quirk_vf_reset", c"VF reset quirk; auto|enabled|disabled", |quirks| &mut quirks.vf_reset),
+    (c"chip8rs_quirk_wrap", c"Wrap quirk; auto|enabled|disabled", |quirks| &mut quirks.wrap),
+    (c"chip8rs_quirk_display_wait", c"Display wait quirk; auto|enabled|disabled", |quirks| &mut quirks.display_wait),
+    (c"chip8rs_quirk_increment_by_x", c"Increment by X quirk; auto|enabled|disabled",
+     |quirks| &mut quirks.increment_by_x),
+];
+
+static CORE: Mutex<Core> = Mutex::new(Core {
+    environment: None,
+    log: None,
+    video_refresh: None,
+    audio_sample_batch: None,
+    input_poll: None,
+    input_state: None,
+    game: None,
+});
+
+struct Core {
+    environment: Option<EnvironmentFn>,
+    log: Option<LogPrintfFn>,
+    video_refresh: Option<VideoRefreshFn>,
+    audio_sample_batch: Option<AudioSampleBatchFn>,
+    input_poll: Option<InputPollFn>,
+    input_state: Option<InputStateFn>,
+    game: Option<Game>,
+}
+
+struct Game {
+    cpu: Chip8,
+    // The machine as the ROM was loaded, which resets go back to
+    initial: Chip8,
+    // The settings from the ROM database or the ROM itself, which options set to auto keep
+    speed: u32,
+    quirks: Quirks,
+    palette: [u32; 2],
+    hints: KeyHints,
+    instructions: u32,
+    // The keys as the buttons last pressed them, so that only changes reach the machine
+    pressed: [bool; 16],
+    // Where the beeper's square wave is within its period, from 0 to 1
+    phase: f64,
+    crashed: bool,
+}
+
+impl Game {
+    fn button_key(&self, id: c_uint) -> usize {
+        let hint = match id {
+            RETRO_DEVICE_ID_JOYPAD_UP => self.hints.up,
+            RETRO_DEVICE_ID_JOYPAD_DOWN => self.hints.down,
+            RETRO_DEVICE_ID_JOYPAD_LEFT => self.hints.left,
+            RETRO_DEVICE_ID_JOYPAD_RIGHT => self.hints.right,
+            RETRO_DEVICE_ID_JOYPAD_A => self.hints.a,
+            RETRO_DEVICE_ID_JOYPAD_B => self.hints.b,
+            _ => None,
+        };
+
+        hint.unwrap_or(BUTTON_KEYS[id as usize])
+    }
+
+    fn apply_options(&mut self, environment: EnvironmentFn) {
+        let option = |key| get_variable(environment, key).filter(|value| value != "auto");
+
+        self.instructions = option(SPEED_OPTION).and_then(|value| value.parse().ok()).unwrap_or(self.speed);
+
+        let palette = option(PALETTE_OPTION)
+            .and_then(|value| PALETTES.iter().find(|(name, _)| *name == value))
+            .map_or(self.palette, |(_, palette)| *palette);
+
+        let mut quirks = self.quirks;
+
+        for (key, _, quirk) in QUIRK_OPTIONS {
+            match option(key).as_deref() {
+                Some("enabled") => *quirk(&mut quirks) = true,
+                Some("disabled") => *quirk(&mut quirks) = false,
+                _ => (),
+            }
+        }
+
+        for cpu in [&mut self.cpu, &mut self.initial] {
+            cpu.set_quirks(quirks);
+            cpu.set_palette(palette);
+        }
+    }
+
+    fn read_buttons(&mut self, input_state: InputStateFn) {
+        let mut pressed = [false; 16];
+
+        for id in 0..16 {
+            if unsafe { input_state(0, RETRO_DEVICE_JOYPAD, 0, id) } != 0 {
+                pressed[self.button_key(id)] = true;
+            }
+        }
+
+        for (key, pressed) in pressed.into_iter().enumerate() {
+            if self.pressed[key] != pressed {
+                self.cpu.set_key_state(key, pressed);
+            }
+        }
+
+        self.pressed = pressed;
+    }
+
+    fn render_audio(&mut self) -> Vec<i16> {
+        let mut samples = vec![0; SAMPLES_PER_FRAME * 2];
+
+        if self.cpu.get_sound_timer() == 0 {
+            return samples;
+        }
+
+        for frame in samples.chunks_mut(2) {
+            let level = if self.phase < 0.5 { BEEP_VOLUME } else { -BEEP_VOLUME };
+            let sample = (level * i16::MAX as f64) as i16;
+
+            frame.fill(sample);
+            self.phase = (self.phase + BEEP_FREQUENCY / SAMPLE_RATE).fract();
+        }
+
+        samples
+    }
+}
+
+fn core() -> MutexGuard<'static, Core> {
+    CORE.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn get_variable(environment: EnvironmentFn, key: &CStr) -> Option<String> {
+    let mut variable = Variable { key: key.as_ptr(), value: ptr::null() };
+
+    if !unsafe { environment(RETRO_ENVIRONMENT_GET_VARIABLE, &mut variable as *mut Variable as *mut c_void) }
+        || variable.value.is_null() {
+        return None;
+    }
+
+    Some(unsafe { CStr::from_ptr(variable.value) }.to_string_lossy().into_owned())
+}
+
+fn show_message(environment: EnvironmentFn, text: &str) {
+    let Ok(text) = CString::new(text) else {
+        return;
+    };
+    let mut message = Message { msg: text.as_ptr(), frames: 300 };
+
+    unsafe { environment(RETRO_ENVIRONMENT_SET_MESSAGE, &mut message as *mut Message as *mut c_void) };
+}
+
+// Errors go to the frontend's log, or on screen when it doesn't have one
+fn report_error(core: &Core, text: &str) {
+    if let Some(log) = core.log {
+        if let Ok(text) = CString::new(text) {
+            unsafe { log(RETRO_LOG_ERROR, c"%s\n".as_ptr(), text.as_ptr()) };
+        }
+    } else if let Some(environment) = core.environment {
+        show_message(environment, text);
+    }
+}
+
+fn set_variables(environment: EnvironmentFn) {
+    let mut variables = vec![
+        Variable {
+            key: SPEED_OPTION.as_ptr(),
+            value: c"Instructions per frame; auto|5|7|10|15|20|30|50|100|200|500|1000".as_ptr(),
+        },
+        Variable {
+            key: PALETTE_OPTION.as_ptr(),
+            value: c"Palette; auto|White|Amber|Green|LCD|Paper".as_ptr(),
+        },
+    ];
+
+    for (key, value, _) in QUIRK_OPTIONS {
+        variables.push(Variable { key: key.as_ptr(), value: value.as_ptr() });
+    }
+
+    variables.push(Variable { key: ptr::null(), value: ptr::null() });
+
+    unsafe { environment(RETRO_ENVIRONMENT_SET_VARIABLES, variables.as_mut_ptr() as *mut c_void) };
+}
+
+fn set_input_descriptors(environment: EnvironmentFn, game: &Game) {
+    let mut descriptors: Vec<InputDescriptor> = (0..16)
+        .map(|id| InputDescriptor {
+            port: 0,
+            device: RETRO_DEVICE_JOYPAD,
+            index: 0,
+            id,
+            description: KEY_NAMES[game.button_key(id)].as_ptr(),
+        })
+        .collect();
+
+    descriptors.push(InputDescriptor { port: 0, device: 0, index: 0, id: 0, description: ptr::null() });
+
+    unsafe { environment(RETRO_ENVIRONMENT_SET_INPUT_DESCRIPTORS, descriptors.as_mut_ptr() as *mut c_void) };
+}
+
+fn load_game(environment: Option<EnvironmentFn>, info: &GameInfo) -> Result<Game, String> {
+    if info.data.is_null() {
+        return Err("The frontend didn't pass the ROM's data".to_string());
+    }
+
+    let data = unsafe { slice::from_raw_parts(info.data as *const u8, info.size) };
+    let name = match info.path.is_null() {
+        true => "ROM".to_string(),
+        false => {
+            let path = unsafe { CStr::from_ptr(info.path) }.to_string_lossy().into_owned();
+            Path::new(&path).file_name().map_or(path.clone(), |name| name.to_string_lossy().into_owned())
+        }
+    };
+
+    let rom = rom::parse(data.to_vec(), &name)?;
+    let cpu = Chip8::new(&rom.data)?;
+
+    let mut game = Game {
+        initial: cpu.clone(),
+        speed: DEFAULT_SPEED,
+        quirks: Quirks::default(),
+        palette: cpu.get_palette(),
+        hints: KeyHints::default(),
+        instructions: DEFAULT_SPEED,
+        pressed: [false; 16],
+        phase: 0.0,
+        crashed: false,
+        cpu,
+    };
+
+    // Options embedded in the ROM win over the database, like in the emulator
+    let profiles = [Database::bundled().get_profile(&rom::hash(&rom.data)), rom.profile];
+
+    for profile in profiles.iter().flatten() {
+        game.quirks = profile.quirks;
+        game.hints = KeyHints::from_map(&profile.keys);
+        game.speed = profile.tickrate.unwrap_or(game.speed);
+        game.palette = profile.palette.unwrap_or(game.palette);
+    }
+
+    game.instructions = game.speed;
+
+    for cpu in [&mut game.cpu, &mut game.initial] {
+        cpu.set_quirks(game.quirks);
+        cpu.set_palette(game.palette);
+    }
+
+    if let Some(environment) = environment {
+        let mut format = RETRO_PIXEL_FORMAT_XRGB8888;
+
+        if !unsafe { environment(RETRO_ENVIRONMENT_SET_PIXEL_FORMAT, &mut format as *mut c_uint as *mut c_void) } {
+            return Err("The frontend doesn't support XRGB8888 frames".to_string());
+        }
+
+        set_input_descriptors(environment, &game);
+        game.apply_options(environment);
+    }
+
+    Ok(game)
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    RETRO_API_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(environment: EnvironmentFn) {
+    let mut log = LogCallback { log: None };
+    let mut core = core();
+
+    if !unsafe { environment(RETRO_ENVIRONMENT_GET_LOG_INTERFACE, &mut log as *mut LogCallback as *mut c_void) } {
+        log.log = None;
+    }
+
+    core.environment = Some(environment);
+    core.log = log.log;
+    set_variables(environment);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(video_refresh: VideoRefreshFn) {
+    core().video_refresh = Some(video_refresh);
+}
+
+// The core hands over a frame's worth of audio at once instead
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_: AudioSampleFn) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(audio_sample_batch: AudioSampleBatchFn) {
+    core().audio_sample_batch = Some(audio_sample_batch);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(input_poll: InputPollFn) {
+    core().input_poll = Some(input_poll);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(input_state: InputStateFn) {
+    core().input_state = Some(input_state);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    core().game = None;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_system_info(info: &mut SystemInfo) {
+    *info = SystemInfo {
+        library_name: c"chip8-rs".as_ptr(),
+        library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char,
+        valid_extensions: c"ch8|c8|gif|hex|bin".as_ptr(),
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_system_av_info(info: &mut SystemAvInfo) {
+    *info = SystemAvInfo {
+        geometry: GameGeometry {
+            base_width: FRAMEBUFFER_WIDTH as c_uint,
+            base_height: FRAMEBUFFER_HEIGHT as c_uint,
+            max_width: FRAMEBUFFER_WIDTH as c_uint,
+            max_height: FRAMEBUFFER_HEIGHT as c_uint,
+            aspect_ratio: FRAMEBUFFER_WIDTH as f32 / FRAMEBUFFER_HEIGHT as f32,
+        },
+        timing: SystemTiming { fps: FPS, sample_rate: SAMPLE_RATE },
+    };
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_: c_uint, _: c_uint) {}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    if let Some(game) = &mut core().game {
+        game.cpu = game.initial.clone();
+        game.pressed = [false; 16];
+        game.crashed = false;
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    let mut core = core();
+    let Core { environment, video_refresh, audio_sample_batch, input_poll, input_state, game, .. } = &mut *core;
+
+    let Some(game) = game else {
+        return;
+    };
+
+    if let Some(environment) = *environment {
+        let mut updated = false;
+
+        if unsafe { environment(RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE, &mut updated as *mut bool as *mut c_void) }
+            && updated {
+            game.apply_options(environment);
+        }
+    }
+
+    if let Some(input_poll) = input_poll {
+        unsafe { input_poll() };
+    }
+
+    if let Some(input_state) = *input_state {
+        game.read_buttons(input_state);
+    }
+
+    // A crashed program stays on its last frame until the game is reset
+    if !game.crashed {
+        if let Err(err) = game.cpu.run_frame(game.instructions) {
+            game.crashed = true;
+
+            if let Some(environment) = *environment {
+                show_message(environment, &format!("The program stopped: {}", err));
+            }
+        }
+    }
+
+    if let Some(video_refresh) = video_refresh {
+        let pixels = game.cpu.get_pixels();
+
+        unsafe {
+            video_refresh(pixels.as_ptr() as *const c_void, FRAMEBUFFER_WIDTH as c_uint, FRAMEBUFFER_HEIGHT as c_uint,
+                          FRAMEBUFFER_WIDTH * 4)
+        };
+    }
+
+    let samples = game.render_audio();
+
+    if let Some(audio_sample_batch) = audio_sample_batch {
+        unsafe { audio_sample_batch(samples.as_ptr(), SAMPLES_PER_FRAME) };
+    }
+}
+
+// Save states are always the same size, which lets the frontend rewind with them
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    core().game.as_ref().map_or(0, |game| game.cpu.save_state().len())
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    let core = core();
+    let Some(game) = &core.game else {
+        return false;
+    };
+
+    let state = game.cpu.save_state();
+
+    if data.is_null() || size < state.len() {
+        return false;
+    }
+
+    unsafe { slice::from_raw_parts_mut(data as *mut u8, state.len()) }.copy_from_slice(&state);
+
+    true
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    let mut core = core();
+    let Some(game) = &mut core.game else {
+        return false;
+    };
+
+    if data.is_null() {
+        return false;
+    }
+
+    let state = unsafe { slice::from_raw_parts(data as *const u8, size) };
+
+    if game.cpu.load_state(state).is_err() {
+        return false;
+    }
+
+    // The state brings its own keys, which the next frame's buttons are compared with
+    game.pressed = *game.cpu.get_keys();
+    game.crashed = false;
+
+    true
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_set(_: c_uint, _: bool, _: *const c_char) {}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game(info: Option<&GameInfo>) -> bool {
+    let Some(info) = info else {
+        return false;
+    };
+
+    let mut core = core();
+    let environment = core.environment;
+
+    match load_game(environment, info) {
+        Ok(game) => {
+            core.game = Some(game);
+            true
+        }
+        Err(err) => {
+            report_error(&core, &err);
+            false
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(_: c_uint, _: *const GameInfo, _: usize) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    core().game = None;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    RETRO_REGION_NTSC
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(_: c_uint) -> *mut c_void {
+    ptr::null_mut()
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(_: c_uint) -> usize {
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The core is a global, so the tests take turns with it
+    static TURN: Mutex<()> = Mutex::new(());
+
+    static MESSAGES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+    static FRAMES: Mutex<Vec<Vec<u32>>> = Mutex::new(Vec::new());
+    static SAMPLES: Mutex<usize> = Mutex::new(0);
+
+    // Draws a 0 in the top left corner and starts the delay timer, then loops
+    const ROM: [u8; 12] = [0x60, 0x00, 0xF0, 0x29, 0xD0, 0x15, 0x61, 0x30, 0xF1, 0x15, 0x12, 0x0A];
+
+    // A frontend without a log, options or input which supports everything else
+    unsafe extern "C" fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+        match cmd {
+            RETRO_ENVIRONMENT_SET_MESSAGE => {
+                let message = &*(data as *const Message);
+                let text = CStr::from_ptr(message.msg).to_string_lossy().into_owned();
+
+                MESSAGES.lock().unwrap().push(text);
+                true
+            }
+            RETRO_ENVIRONMENT_GET_LOG_INTERFACE | RETRO_ENVIRONMENT_GET_VARIABLE => false,
+            RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE => {
+                *(data as *mut bool) = false;
+                true
+            }
+            _ => true,
+        }
+    }
+
+    unsafe extern "C" fn video_refresh(data: *const c_void, width: c_uint, height: c_uint, pitch: usize) {
+        assert_eq!(pitch, width as usize * 4);
+
+        let pixels = slice::from_raw_parts(data as *const u32, (width * height) as usize);
+        FRAMES.lock().unwrap().push(pixels.to_vec());
+    }
+
+    unsafe extern "C" fn audio_sample_batch(_: *const i16, frames: usize) -> usize {
+        *SAMPLES.lock().unwrap() += frames;
+        frames
+    }
+
+    unsafe extern "C" fn input_poll() {}
+
+    unsafe extern "C" fn input_state(_: c_uint, _: c_uint, _: c_uint, _: c_uint) -> i16 {
+        0
+    }
+
+    fn start() -> MutexGuard<'static, ()> {
+        let turn = TURN.lock().unwrap_or_else(PoisonError::into_inner);
+
+        MESSAGES.lock().unwrap().clear();
+        FRAMES.lock().unwrap().clear();
+        *SAMPLES.lock().unwrap() = 0;
+
+        retro_set_environment(environment);
+        retro_set_video_refresh(video_refresh);
+        retro_set_audio_sample_batch(audio_sample_batch);
+        retro_set_input_poll(input_poll);
+        retro_set_input_state(input_state);
+        retro_init();
+
+        turn
+    }
+
+    fn game_info(data: &[u8]) -> GameInfo {
+        GameInfo {
+            path: c"/roms/test.ch8".as_ptr(),
+            data: data.as_ptr() as *const c_void,
+            size: data.len(),
+            meta: ptr::null(),
+        }
+    }
+
+    fn serialize() -> Vec<u8> {
+        let mut state = vec![0; retro_serialize_size()];
+
+        assert!(retro_serialize(state.as_mut_ptr() as *mut c_void, state.len()));
+        state
+    }
+
+    #[test]
+    fn frames_reach_the_frontend() {
+        let _turn = start();
+
+        assert!(retro_load_game(Some(&game_info(&ROM))));
+
+        retro_run();
+        retro_run();
+
+        let frames = FRAMES.lock().unwrap().clone();
+        let frame = frames.last().unwrap();
+
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frame.len(), FRAMEBUFFER_WIDTH * FRAMEBUFFER_HEIGHT);
+        assert_ne!(frame[0], frame[FRAMEBUFFER_WIDTH - 1]);
+        assert_eq!(*SAMPLES.lock().unwrap(), SAMPLES_PER_FRAME * 2);
+
+        retro_unload_game();
+        retro_deinit();
+    }
+
+    #[test]
+    fn states_round_trip() {
+        let _turn = start();
+
+        assert!(retro_load_game(Some(&game_info(&ROM))));
+        retro_run();
+
+        let state = serialize();
+
+        assert!(!retro_serialize(state.clone().as_mut_ptr() as *mut c_void, state.len() - 1));
+
+        retro_run();
+        assert_ne!(serialize(), state);
+
+        assert!(retro_unserialize(state.as_ptr() as *const c_void, state.len()));
+        assert_eq!(serialize(), state);
+
+        assert!(!retro_unserialize(ROM.as_ptr() as *const c_void, ROM.len()));
+        assert_eq!(serialize(), state);
+
+        retro_unload_game();
+        assert_eq!(retro_serialize_size(), 0);
+        retro_deinit();
+    }
+
+    #[test]
+    fn load_errors_are_shown_without_a_log() {
+        let _turn = start();
+        let mut info = game_info(&ROM);
+
+        info.data = ptr::null();
+
+        assert!(!retro_load_game(Some(&info)));
+        assert_eq!(*MESSAGES.lock().unwrap(), ["The frontend didn't pass the ROM's data"]);
+
+        // Without a game there's nothing to run
+        retro_run();
+        assert!(FRAMES.lock().unwrap().is_empty());
+
+        retro_deinit();
+    }
+}
@@ -0,0 +1,274 @@
+use std::path::PathBuf;
+
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyMemoryView};
+
+use chip8_rs::chip8::{Quirks, FRAMEBUFFER_HEIGHT, FRAMEBUFFER_WIDTH};
+use chip8_rs::debug::{Debugger, Stop};
+use chip8_rs::rom::{self, Rom};
+use chip8_rs::Chip8;
+
+const MEMORY_SIZE: usize = 4096;
+const DEFAULT_SPEED: u32 = 10;
+
+/// A machine running a ROM at a number of instructions per 60 Hz frame, with the timers counting
+/// down once every frame's worth of instructions like in the emulator
+#[pyclass(name = "Chip8", module = "chip8")]
+struct Machine {
+    debugger: Debugger,
+    // The machine as it was created, which resets go back to
+    initial: Chip8,
+}
+
+#[pymethods]
+impl Machine {
+    /// Runs a ROM in any of the formats the emulator reads, given as bytes. Octo cartridges bring
+    /// their own speed and quirks, which the arguments override.
+    #[new]
+    #[pyo3(signature = (rom, *, speed = None, quirks = None, seed = None))]
+    fn new(rom: &[u8], speed: Option<u32>, quirks: Option<&str>, seed: Option<u64>) -> PyResult<Self> {
+        let rom = rom::parse(rom.to_vec(), "ROM").map_err(PyValueError::new_err)?;
+
+        Machine::create(&rom, speed, quirks, seed)
+    }
+
+    /// Runs the ROM in a file. Archives must hold a single ROM.
+    #[staticmethod]
+    #[pyo3(signature = (path, *, speed = None, quirks = None, seed = None))]
+    fn load(path: PathBuf, speed: Option<u32>, quirks: Option<&str>, seed: Option<u64>) -> PyResult<Self> {
+        let mut choose = |names: &[String]| Err(format!("The archive holds several ROMs ({})", names.join(", ")));
+        let rom = rom::load(&path, &mut choose).map_err(PyValueError::new_err)?;
+
+        Machine::create(&rom, speed, quirks, seed)
+    }
+
+    /// Goes back to the machine as it was created
+    fn reset(&mut self) {
+        self.debugger = Debugger::new(self.initial.clone(), self.speed());
+    }
+
+    /// Runs a number of instructions
+    #[pyo3(signature = (count = 1))]
+    fn step(&mut self, py: Python<'_>, count: u64) -> PyResult<()> {
+        for i in 0..count {
+            // Long runs can be interrupted once every frame's worth
+            if i % self.speed() as u64 == 0 {
+                py.check_signals()?;
+            }
+
+            check(self.debugger.step())?;
+        }
+
+        Ok(())
+    }
+
+    /// Runs a number of frames' worth of instructions, counting the timers down after each
+    #[pyo3(signature = (count = 1))]
+    fn run_frames(&mut self, py: Python<'_>, count: u64) -> PyResult<()> {
+        for _ in 0..count {
+            py.check_signals()?;
+            check(self.debugger.run_frame())?;
+        }
+
+        Ok(())
+    }
+
+    fn set_key(&mut self, key: usize, pressed: bool) -> PyResult<()> {
+        if key > 0xF {
+            return Err(PyValueError::new_err(format!("Key must be from 0 to 15 ({})", key)));
+        }
+
+        self.debugger.get_cpu_mut().set_key_state(key, pressed);
+
+        Ok(())
+    }
+
+    fn release_keys(&mut self) {
+        self.debugger.get_cpu_mut().release_keys();
+    }
+
+    /// The screen as a 32 by 64 buffer of bytes which are 1 for lit pixels and 0 otherwise, i.e. for
+    /// numpy.asarray
+    fn framebuffer<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let cpu = self.debugger.get_cpu();
+        let pixels: Vec<u8> = cpu.get_lit_pixels().iter().map(|lit| *lit as u8).collect();
+
+        PyMemoryView::from(&PyBytes::new(py, &pixels))?
+            .call_method1("cast", ("B", (FRAMEBUFFER_HEIGHT, FRAMEBUFFER_WIDTH)))
+    }
+
+    /// Reads memory from an address, up to the end of memory by default
+    #[pyo3(signature = (address = 0, length = None))]
+    fn read_memory<'py>(&self, py: Python<'py>, address: usize, length: Option<usize>)
+                        -> PyResult<Bound<'py, PyBytes>> {
+        let length = length.unwrap_or(MEMORY_SIZE.saturating_sub(address));
+        let memory = self.debugger.get_cpu().get_memory();
+
+        match address.checked_add(length).and_then(|end| memory.get(address..end)) {
+            Some(bytes) => Ok(PyBytes::new(py, bytes)),
+            None => Err(PyValueError::new_err(format!("{} bytes at {:03X} is past the end of memory", length, address))),
+        }
+    }
+
+    fn write_memory(&mut self, address: usize, data: &[u8]) -> PyResult<()> {
+        if address.checked_add(data.len()).is_none_or(|end| end > MEMORY_SIZE) {
+            return Err(PyValueError::new_err(format!("{} bytes at {:03X} is past the end of memory", data.len(),
+                                                     address)));
+        }
+
+        let cpu = self.debugger.get_cpu_mut();
+
+        for (i, byte) in data.iter().enumerate() {
+            cpu.set_byte((address + i) as u16, *byte);
+        }
+
+        Ok(())
+    }
+
+    fn save_state<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.debugger.get_cpu().save_state())
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> PyResult<()> {
+        self.debugger.get_cpu_mut().load_state(state).map_err(PyValueError::new_err)
+    }
+
+    #[getter]
+    fn get_v(&self) -> Vec<u8> {
+        self.debugger.get_cpu().get_registers().to_vec()
+    }
+
+    #[setter]
+    fn set_v(&mut self, values: Vec<u8>) -> PyResult<()> {
+        if values.len() > 16 {
+            return Err(PyValueError::new_err("There are only 16 V registers"));
+        }
+
+        for (x, value) in values.into_iter().enumerate() {
+            self.debugger.get_cpu_mut().set_register(x, value);
+        }
+
+        Ok(())
+    }
+
+    #[getter]
+    fn get_i(&self) -> u16 {
+        self.debugger.get_cpu().get_program_index()
+    }
+
+    #[setter]
+    fn set_i(&mut self, value: u16) -> PyResult<()> {
+        self.debugger.get_cpu_mut().set_program_index(address(value)?);
+        Ok(())
+    }
+
+    #[getter]
+    fn get_pc(&self) -> u16 {
+        self.debugger.get_cpu().get_program_counter()
+    }
+
+    #[setter]
+    fn set_pc(&mut self, value: u16) -> PyResult<()> {
+        self.debugger.get_cpu_mut().set_program_counter(address(value)?);
+        Ok(())
+    }
+
+    /// The addresses of the calls which haven't returned yet, innermost last
+    #[getter]
+    fn get_stack(&self) -> Vec<u16> {
+        let cpu = self.debugger.get_cpu();
+
+        cpu.get_stack()[..cpu.get_stack_pointer()].to_vec()
+    }
+
+    #[getter]
+    fn get_dt(&self) -> u8 {
+        self.debugger.get_cpu().get_delay_timer()
+    }
+
+    #[setter]
+    fn set_dt(&mut self, value: u8) {
+        self.debugger.get_cpu_mut().set_delay_timer(value);
+    }
+
+    #[getter]
+    fn get_st(&self) -> u8 {
+        self.debugger.get_cpu().get_sound_timer()
+    }
+
+    #[setter]
+    fn set_st(&mut self, value: u8) {
+        self.debugger.get_cpu_mut().set_sound_timer(value);
+    }
+
+    #[getter]
+    fn get_speed(&self) -> u32 {
+        self.speed()
+    }
+
+    fn __repr__(&self) -> String {
+        let cpu = self.debugger.get_cpu();
+
+        format!("<Chip8 PC={:03X} I={:03X} speed={}>", cpu.get_program_counter(), cpu.get_program_index(),
+                self.speed())
+    }
+}
+
+impl Machine {
+    fn create(rom: &Rom, speed: Option<u32>, quirks: Option<&str>, seed: Option<u64>) -> PyResult<Self> {
+        let profile = rom.profile.as_ref();
+        let speed = speed.or(profile.and_then(|profile| profile.tickrate)).unwrap_or(DEFAULT_SPEED);
+
+        if speed == 0 {
+            return Err(PyValueError::new_err("Speed must be at least 1"));
+        }
+
+        let mut cpu = Chip8::new(&rom.data).map_err(PyValueError::new_err)?;
+
+        if let Some(profile) = profile {
+            cpu.set_quirks(profile.quirks);
+        }
+
+        if let Some(quirks) = quirks {
+            cpu.set_quirks(quirks.parse::<Quirks>().map_err(PyValueError::new_err)?);
+        }
+
+        if let Some(seed) = seed {
+            cpu.set_seed(seed);
+        }
+
+        Ok(Machine {
+            debugger: Debugger::new(cpu.clone(), speed),
+            initial: cpu,
+        })
+    }
+
+    fn speed(&self) -> u32 {
+        self.debugger.get_speed()
+    }
+}
+
+// Without breakpoints or watchpoints the debugger only stops for errors
+fn check(stop: Option<Stop>) -> PyResult<()> {
+    match stop {
+        Some(Stop::Error(err)) => Err(PyRuntimeError::new_err(err)),
+        _ => Ok(()),
+    }
+}
+
+fn address(value: u16) -> PyResult<u16> {
+    match value {
+        0..=0xFFF => Ok(value),
+        _ => Err(PyValueError::new_err(format!("Address must be from 0 to 4095 ({})", value))),
+    }
+}
+
+#[pymodule]
+fn chip8(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<Machine>()?;
+    module.add("FRAMEBUFFER_WIDTH", FRAMEBUFFER_WIDTH)?;
+    module.add("FRAMEBUFFER_HEIGHT", FRAMEBUFFER_HEIGHT)?;
+
+    Ok(())
+}
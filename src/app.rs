@@ -0,0 +1,564 @@
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use chip8_rs::record::Recorder;
+use chip8_rs::scale::Upscaler;
+use chip8_rs::trace::Tracer;
+use chip8_rs::Chip8;
+
+use crate::memory::{MemoryAction, MemoryEditor};
+use crate::menu::{self, MenuAction, MenuStatus, PauseMenu, PALETTES, SLOT_COUNT, SPEEDS};
+use crate::osd::Osd;
+use crate::rpc::{Host, RpcServer};
+use crate::script::Script;
+use crate::settings::Settings;
+
+// What the user asked for while a ROM is running
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Control {
+    Continue,
+    Quit,
+    Reset,
+    // Go back to the ROM browser
+    #[cfg_attr(not(feature = "sdl"), allow(dead_code))]
+    Library,
+    // Open the pause menu
+    Menu,
+    // Show or hide the memory viewer
+    Memory,
+    // Show or hide the FPS and instructions per second counter
+    ToggleStats,
+    // Pause or resume the emulation
+    Pause,
+    // Run a single frame and pause
+    Step,
+    // Fast-forward while held
+    FastForward(bool),
+    // Switch to the next slow motion speed, or back to normal
+    SlowMotion,
+    // Load the script again from its file
+    ReloadScript,
+    // Start recording a GIF with the upscaler, or stop recording
+    #[cfg_attr(not(feature = "sdl"), allow(dead_code))]
+    Record(Upscaler),
+}
+
+// Input for navigating menus, independent of the keys or buttons it came from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MenuInput {
+    Up,
+    Down,
+    Left,
+    Right,
+    Select,
+    Back,
+    Favourite,
+    // A letter or digit typed, sent as well as anything else the key stands for
+    Char(char),
+}
+
+pub trait Frontend {
+    // Handles any pending input and returns anything other than Continue to stop the emulation
+    fn poll_events(&mut self, cpu: &mut Chip8) -> Control;
+
+    // Handles input while a menu is shown instead of passing it to the ROM, returns None once the
+    // frontend has been closed
+    fn poll_menu(&mut self) -> Option<Vec<MenuInput>>;
+
+    // Shows the screen with the on-screen display over it. The beeper sounds while beep is set,
+    // which the application decides as frames don't always run in real time.
+    fn present(&mut self, cpu: &Chip8, osd: &Osd, beep: bool);
+}
+
+const FRAME_DURATION: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+// Slow motion speeds in percent, the first being normal speed
+const SLOW_MOTION: [u32; 4] = [100, 50, 25, 10];
+
+// How emulated frames map onto real time. Timers count down once per emulated frame and the
+// beeper follows the sound timer, so they stay in step with the program at any speed.
+struct Timing {
+    paused: bool,
+    step: bool,
+    fast_forward: bool,
+    // Frames run per real frame while fast-forwarding, 0 runs as many as fit
+    fast_forward_rate: u32,
+    slow_motion: usize,
+}
+
+impl Timing {
+    fn get_frame_interval(&self) -> Duration {
+        if self.fast_forward {
+            FRAME_DURATION
+        } else {
+            FRAME_DURATION * 100 / SLOW_MOTION[self.slow_motion]
+        }
+    }
+
+    // Shown on screen whenever the emulation isn't running normally
+    fn get_status(&self) -> Option<String> {
+        if self.paused {
+            Some("Paused".to_string())
+        } else if self.fast_forward && self.fast_forward_rate == 0 {
+            Some("Fast forward".to_string())
+        } else if self.fast_forward {
+            Some(format!("Fast forward {}x", self.fast_forward_rate))
+        } else if self.slow_motion > 0 {
+            Some(format!("Slow motion {}%", SLOW_MOTION[self.slow_motion]))
+        } else {
+            None
+        }
+    }
+}
+
+// Counts frames and instructions to show how fast the emulation actually runs
+struct Stats {
+    enabled: bool,
+    frames: u32,
+    instructions: u32,
+    since: Instant,
+}
+
+pub struct Application<F: Frontend> {
+    cpu: Chip8,
+    frontend: F,
+    speed: u32,
+    base_speed: u32,
+    speed_index: usize,
+    base_palette: [u32; 2],
+    palette_index: usize,
+    timing: Timing,
+    osd: Osd,
+    menu: Option<PauseMenu>,
+    last_menu: PauseMenu,
+    memory: Option<MemoryEditor>,
+    last_memory: MemoryEditor,
+    slots: Vec<Option<Chip8>>,
+    stats: Stats,
+    tracer: Option<Tracer>,
+    rpc: Option<RpcServer>,
+    script: Option<Script>,
+    recording: Option<(Recorder, PathBuf)>,
+}
+
+impl<F: Frontend> Application<F> {
+    pub fn new(cpu: Chip8, frontend: F, settings: &Settings) -> Self {
+        Application {
+            base_palette: cpu.get_palette(),
+            cpu,
+            frontend,
+            speed: settings.speed,
+            base_speed: settings.speed,
+            speed_index: menu::DEFAULT_SPEED,
+            palette_index: 0,
+            timing: Timing {
+                paused: false,
+                step: false,
+                fast_forward: false,
+                fast_forward_rate: settings.fast_forward,
+                slow_motion: 0,
+            },
+            osd: Osd::default(),
+            menu: None,
+            last_menu: PauseMenu::default(),
+            memory: None,
+            last_memory: MemoryEditor::default(),
+            slots: vec![None; SLOT_COUNT],
+            stats: Stats {
+                enabled: false,
+                frames: 0,
+                instructions: 0,
+                since: Instant::now(),
+            },
+            tracer: None,
+            rpc: None,
+            script: None,
+            recording: None,
+        }
+    }
+
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    pub fn set_rpc(&mut self, rpc: RpcServer) {
+        self.rpc = Some(rpc);
+    }
+
+    pub fn set_script(&mut self, script: Script) {
+        self.script = Some(script);
+    }
+
+    // Replaces the running machine with a new one for the same ROM, i.e. to reset it. The palette
+    // and quirks chosen in the menu carry over, as do the save states.
+    pub fn set_cpu(&mut self, mut cpu: Chip8) {
+        cpu.set_quirks(self.cpu.get_quirks());
+        self.replace_cpu(cpu);
+    }
+
+    // Replaces the running machine with one which keeps its own quirks, i.e. a save state or
+    // another ROM
+    fn replace_cpu(&mut self, mut cpu: Chip8) {
+        cpu.set_palette(self.get_palette());
+
+        if let Some(profiler) = self.cpu.take_profiler() {
+            cpu.set_profiler(profiler);
+        }
+
+        if let Some(coverage) = self.cpu.take_coverage() {
+            cpu.set_coverage(coverage);
+        }
+
+        self.cpu = cpu;
+    }
+
+    pub fn get_cpu(&self) -> &Chip8 {
+        &self.cpu
+    }
+
+    #[cfg_attr(not(feature = "sdl"), allow(dead_code))]
+    pub fn into_frontend(self) -> F {
+        self.frontend
+    }
+
+    fn get_palette(&self) -> [u32; 2] {
+        PALETTES[self.palette_index].1.unwrap_or(self.base_palette)
+    }
+
+    // Runs until the frontend asks for something other than carrying on, which is returned
+    pub fn run(&mut self) -> Result<Control, String> {
+        let mut next_frame = Instant::now();
+
+        loop {
+            let control = if self.menu.is_some() {
+                self.poll_menu()
+            } else if self.memory.is_some() {
+                self.poll_memory()
+            } else {
+                self.frontend.poll_events(&mut self.cpu)
+            };
+
+            match control {
+                Control::Continue => (),
+                Control::Menu => {
+                    // Keys held when the menu opens would otherwise stay pressed, as their
+                    // releases go to the menu, and the same goes for fast-forwarding
+                    self.cpu.release_keys();
+                    self.timing.fast_forward = false;
+                    self.last_menu.open();
+                    self.menu = Some(std::mem::take(&mut self.last_menu));
+                }
+                Control::Memory => {
+                    // The viewer takes the keys, like the menu
+                    self.cpu.release_keys();
+                    self.timing.fast_forward = false;
+                    self.last_memory.open(&self.cpu);
+                    self.memory = Some(std::mem::take(&mut self.last_memory));
+                }
+                Control::ToggleStats => {
+                    self.stats.enabled = !self.stats.enabled;
+                    self.osd.stats = None;
+                }
+                Control::Pause => self.timing.paused = !self.timing.paused,
+                Control::Step => {
+                    self.timing.paused = true;
+                    self.timing.step = true;
+                }
+                Control::FastForward(enabled) => self.timing.fast_forward = enabled,
+                Control::SlowMotion => self.timing.slow_motion = (self.timing.slow_motion + 1) % SLOW_MOTION.len(),
+                Control::ReloadScript => self.reload_script(),
+                Control::Record(upscaler) => self.toggle_recording(upscaler),
+                control => return Ok(control),
+            }
+
+            // Requests are answered between frames, so they see the machine as the window shows it
+            if let Some(mut rpc) = self.rpc.take() {
+                rpc.poll(self);
+                self.rpc = Some(rpc);
+            }
+
+            let interval = self.timing.get_frame_interval();
+            let beep = match self.menu {
+                Some(_) => false,
+                None => self.run_frames(next_frame + interval)?,
+            };
+
+            self.update_osd();
+            self.frontend.present(&self.cpu, &self.osd, beep);
+
+            self.stats.frames += 1;
+
+            // Sleep until the next frame is due, but don't try to catch up after falling behind
+            next_frame += interval;
+
+            let now = Instant::now();
+
+            if next_frame > now {
+                thread::sleep(next_frame - now);
+            } else {
+                next_frame = now;
+            }
+        }
+    }
+
+    // Runs the frames which are due before the deadline and returns whether the beeper was on
+    // during any of them, so that short beeps aren't lost when several frames run at once
+    fn run_frames(&mut self, deadline: Instant) -> Result<bool, String> {
+        let timing = &self.timing;
+
+        let frames = if timing.step {
+            1
+        } else if timing.paused {
+            0
+        } else if timing.fast_forward {
+            timing.fast_forward_rate
+        } else {
+            1
+        };
+
+        let uncapped = frames == 0 && timing.fast_forward && !timing.paused;
+        self.timing.step = false;
+        let mut beep = false;
+        let mut count = 0;
+
+        while count < frames || (uncapped && (count == 0 || Instant::now() < deadline)) {
+            match (&mut self.script, &mut self.tracer) {
+                (Some(script), tracer) => script.run_frame(&mut self.cpu, self.speed, tracer.as_mut())?,
+                (None, Some(tracer)) => tracer.run_frame(&mut self.cpu, self.speed)?,
+                (None, None) => self.cpu.run_frame(self.speed)?,
+            }
+
+            self.stats.instructions += self.speed;
+
+            if let Some((recorder, _)) = &mut self.recording {
+                if let Err(err) = recorder.add_frame(self.cpu.get_pixels()) {
+                    self.osd.show_message(format!("Recording failed: {}", err));
+                    self.recording = None;
+                }
+            }
+
+            beep |= self.cpu.get_sound_timer() > 0;
+            count += 1;
+        }
+
+        Ok(beep)
+    }
+
+    // Loads the script again from its file, keeping the old one when the new one fails
+    fn reload_script(&mut self) {
+        let Some(script) = &self.script else {
+            self.osd.show_message("No script to reload");
+            return;
+        };
+
+        match script.reload(&mut self.cpu) {
+            Ok(script) => {
+                let name = script.get_path().file_name().unwrap_or_default().to_string_lossy();
+
+                self.osd.show_message(format!("Reloaded {}", name));
+                self.script = Some(script);
+            }
+            Err(err) => self.osd.show_message(err),
+        }
+    }
+
+    fn toggle_recording(&mut self, upscaler: Upscaler) {
+        if self.recording.is_some() {
+            self.stop_recording();
+            return;
+        }
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        let path = PathBuf::from(format!("chip8-rs-{}.gif", timestamp));
+
+        match Recorder::create(&path, upscaler) {
+            Ok(recorder) => {
+                self.osd.show_message(format!("Recording to {}", path.display()));
+                self.recording = Some((recorder, path));
+            }
+            Err(err) => self.osd.show_message(format!("Failed to start recording: {}", err)),
+        }
+    }
+
+    // Finishes the recording, if there is one. This has to happen before the application goes
+    // away, as the GIF is incomplete until then.
+    pub fn stop_recording(&mut self) {
+        let Some((recorder, path)) = self.recording.take() else {
+            return;
+        };
+
+        match recorder.finish() {
+            Ok(()) => {
+                println!("Saved recording to {}", path.display());
+                self.osd.show_message("Recording saved");
+            }
+            Err(err) => {
+                eprintln!("Failed to save recording: {}", err);
+                self.osd.show_message("Failed to save recording");
+            }
+        }
+    }
+
+    fn poll_menu(&mut self) -> Control {
+        let Some(inputs) = self.frontend.poll_menu() else {
+            return Control::Quit;
+        };
+
+        for input in inputs {
+            let Some(action) = self.menu.as_mut().and_then(|menu| menu.handle_input(input)) else {
+                continue;
+            };
+
+            match action {
+                MenuAction::Resume => self.close_menu(),
+                MenuAction::Reset => {
+                    self.close_menu();
+                    return Control::Reset;
+                }
+                MenuAction::Quit => return Control::Quit,
+                MenuAction::Speed(step) => {
+                    let last = SPEEDS.len() as isize - 1;
+                    self.speed_index = (self.speed_index as isize + step).clamp(0, last) as usize;
+                    self.speed = (self.base_speed.saturating_mul(SPEEDS[self.speed_index]) / 100).max(1);
+                    self.osd.show_message(format!("Speed {}%", SPEEDS[self.speed_index]));
+                }
+                MenuAction::Palette(step) => {
+                    let count = PALETTES.len() as isize;
+                    self.palette_index = (self.palette_index as isize + step).rem_euclid(count) as usize;
+                    self.cpu.set_palette(self.get_palette());
+                    self.osd.show_message(format!("Palette {}", PALETTES[self.palette_index].0));
+                }
+                MenuAction::ToggleQuirk(name) => {
+                    let mut quirks = self.cpu.get_quirks();
+                    let enabled = !quirks.get(name);
+
+                    quirks.set(name, enabled).expect("Menu quirks come from Quirks::NAMES");
+                    self.cpu.set_quirks(quirks);
+                    self.osd.show_message(format!("Quirk {} {}", name, if enabled { "on" } else { "off" }));
+                }
+                MenuAction::Save(slot) => {
+                    // The profile and coverage keep counting what actually ran, rather than
+                    // going back with the state
+                    let mut state = self.cpu.clone();
+                    state.take_profiler();
+                    state.take_coverage();
+
+                    self.slots[slot] = Some(state);
+                    self.osd.show_message(format!("State saved to slot {}", slot + 1));
+                }
+                MenuAction::Load(slot) => match &self.slots[slot] {
+                    Some(state) => {
+                        self.replace_cpu(state.clone());
+                        self.osd.show_message(format!("State loaded from slot {}", slot + 1));
+                        self.close_menu();
+                    }
+                    None => self.osd.show_message(format!("Slot {} is empty", slot + 1)),
+                },
+            }
+        }
+
+        Control::Continue
+    }
+
+    fn poll_memory(&mut self) -> Control {
+        let Some(inputs) = self.frontend.poll_menu() else {
+            return Control::Quit;
+        };
+
+        for input in inputs {
+            let Some(memory) = &mut self.memory else {
+                break;
+            };
+
+            match memory.handle_input(input, &mut self.cpu, self.timing.paused) {
+                Some(MemoryAction::Close) => {
+                    if let Some(memory) = self.memory.take() {
+                        self.last_memory = memory;
+                    }
+                }
+                Some(MemoryAction::Pause) => self.timing.paused = !self.timing.paused,
+                Some(MemoryAction::Step) => {
+                    self.timing.paused = true;
+                    self.timing.step = true;
+                }
+                Some(MemoryAction::Message(text)) => self.osd.show_message(text),
+                None => (),
+            }
+        }
+
+        Control::Continue
+    }
+
+    fn close_menu(&mut self) {
+        if let Some(menu) = self.menu.take() {
+            self.last_menu = menu;
+        }
+    }
+
+    fn update_osd(&mut self) {
+        let now = Instant::now();
+
+        self.osd.expire_messages(now);
+
+        if let Some(script) = &mut self.script {
+            for message in script.take_messages() {
+                self.osd.show_message(message);
+            }
+
+            self.osd.overlay = script.get_overlay();
+        }
+
+        let elapsed = now - self.stats.since;
+
+        if elapsed >= Duration::from_secs(1) {
+            if self.stats.enabled {
+                let seconds = elapsed.as_secs_f64();
+
+                self.osd.stats = Some(format!("{:.0} FPS  {:.0} IPS", self.stats.frames as f64 / seconds,
+                                              self.stats.instructions as f64 / seconds));
+            }
+
+            self.stats.frames = 0;
+            self.stats.instructions = 0;
+            self.stats.since = now;
+        }
+
+        let slots: Vec<bool> = self.slots.iter().map(Option::is_some).collect();
+        let status = MenuStatus {
+            speed: self.speed_index,
+            palette: self.palette_index,
+            quirks: self.cpu.get_quirks(),
+            slots: &slots,
+        };
+
+        self.osd.menu = self.menu.as_ref().map(|menu| menu.view(&status));
+
+        if let Some(memory) = &mut self.memory {
+            memory.update(&self.cpu);
+        }
+
+        self.osd.panel = self.memory.as_ref().map(|memory| memory.view(&self.cpu, self.timing.paused));
+        self.osd.status = self.timing.get_status();
+    }
+}
+
+impl<F: Frontend> Host for Application<F> {
+    fn get_machine(&mut self) -> Option<&mut Chip8> {
+        Some(&mut self.cpu)
+    }
+
+    fn set_machine(&mut self, cpu: Chip8, speed: u32) {
+        self.replace_cpu(cpu);
+        self.base_speed = speed;
+        self.speed = (speed.saturating_mul(SPEEDS[self.speed_index]) / 100).max(1);
+    }
+
+    fn set_paused(&mut self, paused: bool) {
+        self.timing.paused = paused;
+    }
+}
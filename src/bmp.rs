@@ -0,0 +1,45 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+// Writes an uncompressed 24-bit BMP image from pixels in the framebuffer's 0x00RRGGBB format
+pub fn write_bmp(path: &Path, pixels: &[u32], width: usize, height: usize) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+
+    // Rows are padded to a multiple of four bytes
+    let row_size = (width * 3).div_ceil(4) * 4;
+    let data_size = row_size * height;
+
+    let header_size = 14 + 40;
+
+    file.write_all(b"BM")?;
+    file.write_all(&((header_size + data_size) as u32).to_le_bytes())?;
+    file.write_all(&[0; 4])?;
+    file.write_all(&(header_size as u32).to_le_bytes())?;
+
+    file.write_all(&40u32.to_le_bytes())?;
+    file.write_all(&(width as i32).to_le_bytes())?;
+    file.write_all(&(height as i32).to_le_bytes())?;
+    file.write_all(&1u16.to_le_bytes())?;
+    file.write_all(&24u16.to_le_bytes())?;
+    file.write_all(&0u32.to_le_bytes())?;
+    file.write_all(&(data_size as u32).to_le_bytes())?;
+    file.write_all(&2835i32.to_le_bytes())?;
+    file.write_all(&2835i32.to_le_bytes())?;
+    file.write_all(&[0; 8])?;
+
+    let mut row = vec![0u8; row_size];
+
+    // BMP images are stored bottom-up
+    for line in pixels.chunks_exact(width).rev() {
+        for (x, pixel) in line.iter().enumerate() {
+            row[x * 3] = *pixel as u8;
+            row[(x * 3) + 1] = (*pixel >> 8) as u8;
+            row[(x * 3) + 2] = (*pixel >> 16) as u8;
+        }
+
+        file.write_all(&row)?;
+    }
+
+    file.flush()
+}
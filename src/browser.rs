@@ -0,0 +1,180 @@
+use chip8_rs::chip8::{FRAMEBUFFER_HEIGHT, FRAMEBUFFER_WIDTH};
+use chip8_rs::font::{self, ADVANCE, GLYPH_HEIGHT};
+
+use crate::app::MenuInput;
+use crate::library::Library;
+
+// The view is drawn at half the size of the default window, so it is scaled up by whole pixels
+pub const VIEW_WIDTH: usize = 512;
+pub const VIEW_HEIGHT: usize = 256;
+
+const BACKGROUND: u32 = 0x101418;
+const SELECTION: u32 = 0x284060;
+const TEXT: u32 = 0xE8E8E8;
+const DIM_TEXT: u32 = 0x8890A0;
+const FAVOURITE: u32 = 0xF0C040;
+
+const MARGIN: usize = 8;
+const HEADER_HEIGHT: usize = 16;
+const FOOTER_HEIGHT: usize = 14;
+const ROW_HEIGHT: usize = FRAMEBUFFER_HEIGHT + 8;
+const VISIBLE_ROWS: usize = (VIEW_HEIGHT - HEADER_HEIGHT - FOOTER_HEIGHT) / ROW_HEIGHT;
+
+const HELP: &str = "Enter play  F favourite  Esc quit    In game: F1 library  F5 reset";
+
+pub enum BrowserAction {
+    Launch(usize),
+    Quit,
+}
+
+pub struct Browser {
+    pub library: Library,
+    selected: usize,
+    top: usize,
+    view: Vec<u32>,
+}
+
+impl Browser {
+    pub fn new(library: Library) -> Self {
+        Browser {
+            library,
+            selected: 0,
+            top: 0,
+            view: vec![BACKGROUND; VIEW_WIDTH * VIEW_HEIGHT],
+        }
+    }
+
+    pub fn handle_input(&mut self, input: MenuInput) -> Option<BrowserAction> {
+        let last = self.library.entries.len().saturating_sub(1);
+
+        match input {
+            MenuInput::Up => self.selected = self.selected.saturating_sub(1),
+            MenuInput::Down => self.selected = (self.selected + 1).min(last),
+            MenuInput::Left => self.selected = self.selected.saturating_sub(VISIBLE_ROWS),
+            MenuInput::Right => self.selected = (self.selected + VISIBLE_ROWS).min(last),
+            MenuInput::Select if !self.library.entries.is_empty() => {
+                return Some(BrowserAction::Launch(self.selected));
+            }
+            MenuInput::Favourite if !self.library.entries.is_empty() => {
+                self.library.toggle_favourite(self.selected);
+                self.resort();
+            }
+            MenuInput::Back => return Some(BrowserAction::Quit),
+            _ => (),
+        }
+
+        self.scroll();
+
+        None
+    }
+
+    // Records that the entry was launched, keeping it selected for when the player comes back
+    pub fn launched(&mut self, index: usize) {
+        self.selected = index;
+        self.library.mark_played(index);
+        self.resort();
+    }
+
+    // Sorting moves the entries around, so the selection has to follow its entry
+    fn resort(&mut self) {
+        let path = self.library.entries.get(self.selected).map(|entry| entry.path.clone());
+
+        self.library.sort();
+
+        if let Some(path) = path {
+            self.selected = self.library.entries.iter().position(|entry| entry.path == path).unwrap_or(0);
+        }
+
+        self.scroll();
+    }
+
+    fn scroll(&mut self) {
+        if self.selected < self.top {
+            self.top = self.selected;
+        } else if self.selected >= self.top + VISIBLE_ROWS {
+            self.top = self.selected + 1 - VISIBLE_ROWS;
+        }
+    }
+
+    // Draws the view at VIEW_WIDTH x VIEW_HEIGHT, with play times relative to now in seconds since
+    // the Unix epoch
+    pub fn render(&mut self, now: u64) -> &[u32] {
+        self.view.fill(BACKGROUND);
+
+        let entries = &self.library.entries;
+        let header = format!("ROM library ({} found)", entries.len());
+
+        font::draw_text(&mut self.view, VIEW_WIDTH, MARGIN, (HEADER_HEIGHT - GLYPH_HEIGHT) / 2, &header, TEXT, 1);
+        font::draw_text(&mut self.view, VIEW_WIDTH, MARGIN, VIEW_HEIGHT - FOOTER_HEIGHT + (FOOTER_HEIGHT - GLYPH_HEIGHT) / 2,
+                        HELP, DIM_TEXT, 1);
+
+        if entries.is_empty() {
+            font::draw_text(&mut self.view, VIEW_WIDTH, MARGIN, HEADER_HEIGHT + MARGIN,
+                            "No ROMs found, add directories to [library] in the config file", DIM_TEXT, 1);
+
+            return &self.view;
+        }
+
+        let text_x = MARGIN * 2 + FRAMEBUFFER_WIDTH;
+        let max_title_chars = (VIEW_WIDTH - text_x - MARGIN * 2) / (ADVANCE * 2);
+
+        for (row, (index, entry)) in entries.iter().enumerate().skip(self.top).take(VISIBLE_ROWS).enumerate() {
+            let y = HEADER_HEIGHT + row * ROW_HEIGHT;
+
+            if index == self.selected {
+                fill_rect(&mut self.view, 0, y, VIEW_WIDTH, ROW_HEIGHT, SELECTION);
+            }
+
+            let top = y + (ROW_HEIGHT - FRAMEBUFFER_HEIGHT) / 2;
+
+            for (line, pixels) in entry.thumbnail.chunks_exact(FRAMEBUFFER_WIDTH).enumerate() {
+                let start = (top + line) * VIEW_WIDTH + MARGIN;
+                self.view[start..start + FRAMEBUFFER_WIDTH].copy_from_slice(pixels);
+            }
+
+            let title = truncate(&entry.title, max_title_chars);
+            font::draw_text(&mut self.view, VIEW_WIDTH, text_x, top + 2, &title, TEXT, 2);
+
+            let played = match entry.last_played {
+                Some(time) => format!("Played {}", format_age(now.saturating_sub(time))),
+                None => "Never played".to_string(),
+            };
+
+            font::draw_text(&mut self.view, VIEW_WIDTH, text_x, top + 2 + GLYPH_HEIGHT * 2 + 6, &played, DIM_TEXT, 1);
+
+            if entry.favourite {
+                font::draw_text(&mut self.view, VIEW_WIDTH, VIEW_WIDTH - MARGIN - ADVANCE * 2, top + 2, "*", FAVOURITE, 2);
+            }
+        }
+
+        &self.view
+    }
+}
+
+fn fill_rect(view: &mut [u32], x: usize, y: usize, width: usize, height: usize, color: u32) {
+    for row in view.chunks_exact_mut(VIEW_WIDTH).skip(y).take(height) {
+        row[x..(x + width).min(VIEW_WIDTH)].fill(color);
+    }
+}
+
+fn truncate(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+
+    let mut truncated: String = text.chars().take(max_chars.saturating_sub(3)).collect();
+    truncated.push_str("...");
+
+    truncated
+}
+
+fn format_age(seconds: u64) -> String {
+    let (value, unit) = match seconds {
+        0..=59 => return "just now".to_string(),
+        60..=3599 => (seconds / 60, "minute"),
+        3600..=86399 => (seconds / 3600, "hour"),
+        _ => (seconds / 86400, "day"),
+    };
+
+    format!("{} {}{} ago", value, unit, if value == 1 { "" } else { "s" })
+}
@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::chip8::{self, Quirks};
+use crate::database::RomProfile;
+use crate::{gif, octo};
+
+// Octo cartridges are GIF images which hide a JSON payload with the program's source and its
+// options in the lowest two bits of each pixel's colour index, four pixels to a byte starting
+// with the highest bits. The payload is prefixed by its length as a 32-bit big-endian integer
+// and continues through the frames of the animation in order.
+pub struct Cartridge {
+    pub program: String,
+    pub options: Options,
+}
+
+#[derive(Deserialize, Default)]
+struct Payload {
+    #[serde(default)]
+    program: String,
+    #[serde(default)]
+    options: Options,
+}
+
+// The options stored by Octo, the ones without an equivalent here (i.e. the XO-CHIP colours or
+// the touch input mode) are ignored
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Options {
+    pub tickrate: Option<u32>,
+    pub background_color: Option<String>,
+    pub fill_color: Option<String>,
+    pub shift_quirks: Option<bool>,
+    pub load_store_quirks: Option<bool>,
+    pub jump_quirks: Option<bool>,
+    pub logic_quirks: Option<bool>,
+    pub clip_quirks: Option<bool>,
+    pub v_blank_quirks: Option<bool>,
+}
+
+pub fn is_cartridge(data: &[u8]) -> bool {
+    gif::is_gif(data)
+}
+
+pub fn decode(data: &[u8]) -> Result<Cartridge, String> {
+    let frames = gif::decode_frames(data)?;
+
+    let mut bytes = Vec::new();
+    let mut byte = 0u8;
+    let mut bit_count = 0;
+
+    for index in frames.iter().flat_map(|frame| &frame.indices) {
+        byte = byte << 2 | (index & 0x03);
+        bit_count += 2;
+
+        if bit_count == 8 {
+            bytes.push(byte);
+            byte = 0;
+            bit_count = 0;
+        }
+    }
+
+    let len = match bytes.get(..4) {
+        Some(len) => u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize,
+        None => return Err("Image is too small to be an Octo cartridge".to_string()),
+    };
+
+    let json = bytes
+        .get(4..4 + len)
+        .ok_or("Image doesn't contain an Octo cartridge (payload is truncated)")?;
+
+    let payload: Payload = serde_json::from_slice(json)
+        .map_err(|err| format!("Image doesn't contain an Octo cartridge ({})", err))?;
+
+    Ok(Cartridge {
+        program: payload.program,
+        options: payload.options,
+    })
+}
+
+impl Cartridge {
+    pub fn assemble(&self) -> Result<Vec<u8>, String> {
+        octo::assemble(&self.program).map_err(|err| format!("Failed to compile cartridge: {}", err))
+    }
+
+    // The options as they would be recommended by the ROM database
+    pub fn get_profile(&self, title: &str) -> RomProfile {
+        let options = &self.options;
+        let mut quirks = Quirks::default();
+
+        let flags = [
+            (&mut quirks.shift, options.shift_quirks),
+            (&mut quirks.load_store, options.load_store_quirks),
+            (&mut quirks.jump, options.jump_quirks),
+            (&mut quirks.vf_reset, options.logic_quirks),
+            (&mut quirks.wrap, options.clip_quirks.map(|clip| !clip)),
+            (&mut quirks.display_wait, options.v_blank_quirks),
+        ];
+
+        for (flag, value) in flags {
+            if let Some(value) = value {
+                *flag = value;
+            }
+        }
+
+        let palette = match (&options.background_color, &options.fill_color) {
+            (Some(bg), Some(fg)) => chip8::parse_palette(&format!("{},{}", bg, fg)).ok(),
+            _ => None,
+        };
+
+        RomProfile {
+            title: title.to_string(),
+            platform: None,
+            quirks,
+            tickrate: options.tickrate,
+            palette,
+            keys: HashMap::new(),
+        }
+    }
+}
@@ -0,0 +1,1004 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::coverage::Coverage;
+use crate::profile::Profiler;
+
+pub const FRAMEBUFFER_WIDTH: usize = 64;
+pub const FRAMEBUFFER_HEIGHT: usize = 32;
+pub const FRAMEBUFFER_SIZE: usize = FRAMEBUFFER_WIDTH * FRAMEBUFFER_HEIGHT;
+
+// Colours of unlit and lit pixels in the framebuffer's 0x00RRGGBB format
+pub const DEFAULT_PALETTE: [u32; 2] = [0x000000, 0xFFFFFF];
+
+// Identifies save states, followed by a version byte which changes with their layout
+const STATE_MAGIC: &[u8; 4] = b"C8ST";
+const STATE_VERSION: u8 = 2;
+
+// Behaviours which differ between CHIP-8 implementations. Everything is disabled by default, which
+// matches the original COSMAC VIP interpreter except for the VF reset.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Quirks {
+    // 8XY6/8XYE shift VX in place instead of loading the shifted value from VY
+    pub shift: bool,
+    // FX55/FX65 leave I unchanged instead of incrementing it past the last register
+    pub load_store: bool,
+    // BNNN jumps to XNN plus VX instead of NNN plus V0
+    pub jump: bool,
+    // 8XY1/8XY2/8XY3 reset VF to zero
+    pub vf_reset: bool,
+    // Sprites wrap around the edges of the screen instead of being clipped
+    pub wrap: bool,
+    // DXYN waits for the start of the next frame before drawing, like the VIP waiting for the
+    // vertical blank interrupt
+    pub display_wait: bool,
+    // FX55/FX65 increment I by X instead of X + 1, unless load-store leaves it unchanged
+    pub increment_by_x: bool,
+}
+
+impl Quirks {
+    pub const NAMES: [&'static str; 7] =
+        ["shift", "load-store", "jump", "vf-reset", "wrap", "display-wait", "increment-by-x"];
+
+    fn flag_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "shift" => Some(&mut self.shift),
+            "load-store" => Some(&mut self.load_store),
+            "jump" => Some(&mut self.jump),
+            "vf-reset" => Some(&mut self.vf_reset),
+            "wrap" => Some(&mut self.wrap),
+            "display-wait" => Some(&mut self.display_wait),
+            "increment-by-x" => Some(&mut self.increment_by_x),
+            _ => None,
+        }
+    }
+
+    pub fn set(&mut self, name: &str, enabled: bool) -> Result<(), String> {
+        let flag = self
+            .flag_mut(name)
+            .ok_or_else(|| format!("Unknown quirk ({})", name))?;
+
+        *flag = enabled;
+
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> bool {
+        let mut quirks = *self;
+
+        quirks.flag_mut(name).map(|flag| *flag).unwrap_or(false)
+    }
+}
+
+impl FromStr for Quirks {
+    type Err = String;
+
+    // Parses a comma-separated list of the quirks to enable, i.e. "shift,load-store"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut quirks = Quirks::default();
+
+        for name in s.split(',').map(str::trim).filter(|s| !s.is_empty() && *s != "none") {
+            quirks.set(name, true)?;
+        }
+
+        Ok(quirks)
+    }
+}
+
+impl fmt::Display for Quirks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let enabled: Vec<&str> = Quirks::NAMES
+            .iter()
+            .copied()
+            .filter(|name| self.get(name))
+            .collect();
+
+        if enabled.is_empty() {
+            f.write_str("none")
+        } else {
+            f.write_str(&enabled.join(","))
+        }
+    }
+}
+
+// Parses a palette given as the unlit and lit colours in hex, i.e. "000000,FFFFFF"
+pub fn parse_palette(s: &str) -> Result<[u32; 2], String> {
+    let colors = s
+        .split(',')
+        .map(|c| u32::from_str_radix(c.trim().trim_start_matches('#'), 16)
+            .ok()
+            .filter(|c| *c <= 0xFFFFFF))
+        .collect::<Option<Vec<u32>>>();
+
+    match colors.as_deref() {
+        Some(&[bg, fg]) => Ok([bg, fg]),
+        _ => Err(format!("Palette must be two hex colours separated by a comma ({})", s)),
+    }
+}
+
+// Cloning a machine is how save states are taken
+#[derive(Clone)]
+pub struct Chip8 {
+    memory: [u8; 4096],
+    registers: [u8; 16],
+    // Which pixels are lit, and the same in the palette's colours for presenting
+    screen: [bool; FRAMEBUFFER_SIZE],
+    framebuffer: [u32; FRAMEBUFFER_SIZE],
+    stack: [u16; 16],
+    keys: [bool; 16],
+    opcode: u16,
+
+    index: u16,
+    program_counter: u16,
+    stack_pointer: usize,
+
+    random: Xoroshiro128,
+    quirks: Quirks,
+    palette: [u32; 2],
+
+    delay_timer: u8,
+    sound_timer: u8,
+
+    beep_flag: bool,
+    // Whether a frame has started since the last sprite was drawn, for the display-wait quirk
+    vblank: bool,
+
+    last_key: Option<usize>,
+
+    profiler: Option<Box<Profiler>>,
+    coverage: Option<Box<Coverage>>,
+}
+
+impl Chip8 {
+    pub fn new(rom: &[u8]) -> Result<Self, String> {
+        let mut chip8 = Chip8 {
+            memory: [0; 4096],
+            registers: [0; 16],
+            screen: [false; FRAMEBUFFER_SIZE],
+            framebuffer: [DEFAULT_PALETTE[0]; FRAMEBUFFER_SIZE],
+            stack: [0; 16],
+            keys: [false; 16],
+            opcode: 0,
+
+            index: 0,
+            program_counter: 0,
+            stack_pointer: 0,
+
+            random: Xoroshiro128([0x7020de7ee5e88ab7, 0xe587fbb5ba4fccee]),
+            quirks: Quirks::default(),
+            palette: DEFAULT_PALETTE,
+
+            delay_timer: 0,
+            sound_timer: 0,
+
+            beep_flag: false,
+            vblank: false,
+
+            last_key: None,
+
+            profiler: None,
+            coverage: None,
+        };
+
+        chip8.load_fontset(include_bytes!("fontset.bin"))?;
+        chip8.load_rom(rom)?;
+
+        Ok(chip8)
+    }
+
+    fn load_fontset(&mut self, bytes: &[u8]) -> Result<(), String> {
+        let start = 0x050;
+        let end = 0x0A0;
+        let len = end - start;
+
+        if bytes.len() > len {
+            Err(format!("Fontset ROM exceeds maximum size (cap: {}, len: {})", len, bytes.len()))
+        } else {
+            self.memory[start..end]
+                .copy_from_slice(bytes);
+
+            Ok(())
+        }
+    }
+
+    fn load_rom(&mut self, bytes: &[u8]) -> Result<usize, String> {
+        let start = 0x200;
+        let end = self.memory.len();
+
+        if bytes.len() > end - start {
+            Err(format!("Game ROM exceeds maximum size (cap: {}, len: {})", end - start, bytes.len()))
+        } else {
+            self.program_counter = start as u16;
+            self.index = 0x0;
+
+            self.stack = [0u16; 16];
+            self.stack_pointer = 0;
+
+            self.memory[start..start + bytes.len()]
+                .copy_from_slice(bytes);
+
+            Ok(self.memory.len())
+        }
+    }
+
+    fn check_range(&self, addr: usize, len: usize) -> Result<(), String> {
+        if addr + len > self.memory.len() {
+            Err(format!("Memory access out of bounds ({:04X}, len: {})", addr, len))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn step(&mut self) -> Result<(), String> {
+        self.check_range(self.program_counter as usize, 2)?;
+
+        let pc = self.program_counter;
+
+        self.opcode = (self.memory[self.program_counter as usize] as u16) << 8
+            | self.memory[self.program_counter as usize + 1] as u16;
+
+        match self.opcode & 0xF000 {
+            // 0NNN - Calls RCA 1802 program at address NNN
+            0x0000 => {
+                match self.opcode & 0x0FFF {
+                    0x0000 => {
+                        self.program_counter += 2;
+                    }
+                    // 00E0 - Clear framebuffer
+                    0x00E0 => {
+                        self.screen.fill(false);
+                        self.framebuffer.fill(self.palette[0]);
+                        self.program_counter += 2;
+                    }
+                    // 00EE - Returns from subroutine
+                    0x00EE => {
+                        if self.stack_pointer == 0 {
+                            return Err("Couldn't pop from stack (stack is empty)".to_string());
+                        }
+
+                        self.stack_pointer -= 1;
+
+                        self.program_counter = self.stack[self.stack_pointer];
+                        self.program_counter += 2;
+                    }
+                    _ => return Err(format!("Unknown instruction ({:04X})", self.opcode)),
+                }
+            }
+            // 1NNN - Jumps to address NNN
+            0x1000 => {
+                self.program_counter = self.opcode & 0x0FFF;
+            }
+            // 2NNN - Calls subroutine at NNN
+            0x2000 => {
+                if self.stack_pointer >= 15 {
+                    return Err("Couldn't push into stack (stack has exceeded maximum size)".to_string());
+                }
+
+                self.stack[self.stack_pointer] = self.program_counter;
+                self.stack_pointer += 1;
+
+                self.program_counter = self.opcode & 0x0FFF;
+            }
+            // 3XNN - Skips the next instruction if VX equals NN
+            0x3000 => {
+                if self.registers[(self.opcode as usize & 0x0F00) >> 8]
+                    == (self.opcode & 0x00FF) as u8
+                {
+                    self.program_counter += 4;
+                } else {
+                    self.program_counter += 2;
+                }
+            }
+            // 4XNN - Skips the next instruction if VX does not equal NN
+            0x4000 => {
+                if self.registers[(self.opcode as usize & 0x0F00) >> 8]
+                    != (self.opcode & 0x00FF) as u8
+                {
+                    self.program_counter += 4;
+                } else {
+                    self.program_counter += 2;
+                }
+            }
+            // 5XY0 - Skips the next instruction if VX equals VY
+            0x5000 => {
+                if self.registers[(self.opcode as usize & 0x0F00) >> 8]
+                    == self.registers[(self.opcode as usize & 0x00F0) >> 4]
+                {
+                    self.program_counter += 4;
+                } else {
+                    self.program_counter += 2;
+                }
+            }
+            // 6XNN - Sets VX to NN
+            0x6000 => {
+                self.registers[(self.opcode as usize & 0x0F00) >> 8] = (self.opcode & 0x00FF) as u8;
+                self.program_counter += 2;
+            }
+            // 7XNN - Adds NN to VX (carry flag is not changed)
+            0x7000 => {
+                let (result, _) = self.registers[(self.opcode as usize & 0x0F00) >> 8]
+                    .overflowing_add((self.opcode & 0x00FF) as u8);
+
+                self.registers[(self.opcode as usize & 0x0F00) >> 8] = result;
+                self.program_counter += 2;
+            }
+            // 8XNO - Sets VX to a value calculated from VX and VY
+            0x8000 => {
+                let x = (self.opcode as usize & 0x0F00) >> 8;
+                let y = (self.opcode as usize & 0x00F0) >> 4;
+
+                // The flag is written after the result, so it wins when VX is VF
+                match self.opcode & 0x000F {
+                    // 8XY0 - Sets VX to VY
+                    0x0000 => self.registers[x] = self.registers[y],
+                    // 8XY1 - Sets VX to VX OR VY
+                    0x0001 => {
+                        self.registers[x] |= self.registers[y];
+                        self.reset_flag();
+                    }
+                    // 8XY2 - Sets VX to VX AND VY
+                    0x0002 => {
+                        self.registers[x] &= self.registers[y];
+                        self.reset_flag();
+                    }
+                    // 8XY3 - Sets VX to VX XOR VY
+                    0x0003 => {
+                        self.registers[x] ^= self.registers[y];
+                        self.reset_flag();
+                    }
+                    // 8XY4 - Sets VX to VX + VY (sets VF to 1 if a carry occurs, otherwise 0)
+                    0x0004 => {
+                        let (result, carry) = self.registers[x].overflowing_add(self.registers[y]);
+
+                        self.registers[x] = result;
+                        self.registers[0xF] = if carry { 1 } else { 0 };
+                    }
+                    // 8XY5 - Sets VX to VX - VY (sets VF to 0 if a borrow occurs, otherwise 1)
+                    0x0005 => {
+                        let (result, borrow) = self.registers[x].overflowing_sub(self.registers[y]);
+
+                        self.registers[x] = result;
+                        self.registers[0xF] = if borrow { 0 } else { 1 };
+                    }
+                    // 8XY6 - Sets VX to VY >> 1 (sets VF to the least significant bit of VY before the shift)
+                    0x0006 => {
+                        let value = self.registers[if self.quirks.shift { x } else { y }];
+
+                        self.registers[x] = value >> 1;
+                        self.registers[0xF] = value & 0b00000001;
+                    }
+                    // 8XY7 - Sets VX to VY - VX. (sets VF to 0 if a borrow occurs, otherwise 1)
+                    0x0007 => {
+                        let (result, borrow) = self.registers[y].overflowing_sub(self.registers[x]);
+
+                        self.registers[x] = result;
+                        self.registers[0xF] = if borrow { 0 } else { 1 };
+                    }
+                    // 8XYE - Sets VX to VY << 1 (sets VF to the most significant bit of VY before the shift)
+                    0x000E => {
+                        let value = self.registers[if self.quirks.shift { x } else { y }];
+
+                        self.registers[x] = value << 1;
+                        self.registers[0xF] = value >> 7;
+                    }
+                    _ => return Err(format!("Unknown instruction ({:04X})", self.opcode)),
+                }
+
+                self.program_counter += 2;
+            }
+            // 9XY0 - Skips the next instruction if VX doesn't equal VY
+            0x9000 => {
+                if self.registers[(self.opcode as usize & 0x0F00) >> 8]
+                    != self.registers[(self.opcode as usize & 0x00F0) >> 4]
+                {
+                    self.program_counter += 4;
+                } else {
+                    self.program_counter += 2;
+                }
+            }
+            // ANNN - Sets I to the address NNN
+            0xA000 => {
+                self.index = self.opcode & 0x0FFF;
+                self.program_counter += 2;
+            }
+            // BNNN - Jumps to the address NNN plus V0 (or XNN plus VX)
+            0xB000 => {
+                let offset = if self.quirks.jump {
+                    self.registers[(self.opcode as usize & 0x0F00) >> 8]
+                } else {
+                    self.registers[0x0]
+                };
+
+                self.program_counter = (self.opcode & 0x0FFF) + offset as u16;
+            }
+            // CXNN - Sets VX to the result of a bitwise and operation on a random number (between 0 and 255) and NN
+            0xC000 => {
+                self.registers[(self.opcode as usize & 0x0F00) >> 8] =
+                    self.rand() & (self.opcode & 0x00FF) as u8;
+
+                self.program_counter += 2;
+            }
+            // DXYN runs again until the next frame starts, so that it waits like on the VIP
+            0xD000 if self.quirks.display_wait && !self.vblank => (),
+            // DXYN - Draws a sprite at coordinates (VX, VY) that has the dimensions of 8xN
+            0xD000 => {
+                // The starting position always wraps, only the rest of the sprite is clipped
+                let dst_x = self.registers[(self.opcode as usize & 0x0F00) >> 8] as usize % FRAMEBUFFER_WIDTH;
+                let dst_y = self.registers[(self.opcode as usize & 0x00F0) >> 4] as usize % FRAMEBUFFER_HEIGHT;
+
+                let width = 8;
+                let height = (self.opcode & 0x000F) as usize;
+
+                self.check_range(self.index as usize, height)?;
+
+                self.registers[0xF] = 0;
+
+                for y in 0..height {
+                    let src_pixel = self.memory[self.index as usize + y];
+
+                    for x in 0..width {
+                        let (px, py) = if self.quirks.wrap {
+                            ((dst_x + x) % FRAMEBUFFER_WIDTH, (dst_y + y) % FRAMEBUFFER_HEIGHT)
+                        } else if dst_x + x >= FRAMEBUFFER_WIDTH || dst_y + y >= FRAMEBUFFER_HEIGHT {
+                            continue;
+                        } else {
+                            (dst_x + x, dst_y + y)
+                        };
+
+                        if (src_pixel & (0x80 >> x)) != 0 {
+                            let dst = px + (py * FRAMEBUFFER_WIDTH);
+
+                            if self.screen[dst] {
+                                self.registers[0xF] = 1;
+                            }
+
+                            self.screen[dst] = !self.screen[dst];
+                            self.framebuffer[dst] = self.palette[self.screen[dst] as usize];
+                        }
+                    }
+                }
+
+                self.vblank = false;
+                self.program_counter += 2;
+            }
+            0xE000 => {
+                let x = (self.opcode as usize & 0x0F00) >> 8;
+
+                match self.opcode & 0x00FF {
+                    // EX9E - Skips the next instruction if the key stored in VX is pressed
+                    0x009E => {
+                        if self.keys[self.registers[x] as usize & 0xF] {
+                            self.program_counter += 4;
+                        } else {
+                            self.program_counter += 2;
+                        }
+                    }
+                    // EXA1 - Skips the next instruction if the key stored in VX is not pressed
+                    0x00A1 => {
+                        if !self.keys[self.registers[x] as usize & 0xF] {
+                            self.program_counter += 4;
+                        } else {
+                            self.program_counter += 2;
+                        }
+                    }
+                    _ => return Err(format!("Unknown instruction ({:04X})", self.opcode)),
+                }
+            }
+            0xF000 => {
+                let x = (self.opcode as usize & 0x0F00) >> 8;
+
+                match self.opcode & 0x00FF {
+                    // FX07 - Sets VX to the value of the delay timer
+                    0x0007 => {
+                        self.registers[x] = self.delay_timer;
+                        self.program_counter += 2;
+                    }
+                    // FX0A - Sets VX to the next key press, blocking all other instructions until it is received
+                    0x000A => {
+                        if let Some(key) = self.last_key {
+                            self.registers[x] = key as u8;
+                            self.program_counter += 2;
+                        }
+                    }
+                    // FX15 - Sets the delay timer to VX
+                    0x0015 => {
+                        self.delay_timer = self.registers[x];
+                        self.program_counter += 2;
+                    }
+                    // FX18 - Sets the sound timer to VX
+                    0x0018 => {
+                        self.sound_timer = self.registers[x];
+                        self.program_counter += 2;
+                    }
+                    // FX1E - Sets I to VX + I
+                    0x001E => {
+                        self.index = self.index.wrapping_add(self.registers[x] as u16);
+                        self.program_counter += 2;
+                    }
+                    // FX29 - Sets I to the location of the sprite for the character in VX
+                    0x0029 => {
+                        let c = self.registers[x] as u16;
+
+                        self.index = 0x050 + (c * 5);
+                        self.program_counter += 2;
+                    }
+                    // FX33 - Sets VX to the binary-coded deciaml representation of I
+                    0x0033 => {
+                        let x = self.registers[x];
+
+                        self.check_range(self.index as usize, 3)?;
+
+                        self.memory[self.index as usize] = x / 100;
+                        self.memory[self.index as usize + 1] = (x / 10) % 10;
+                        self.memory[self.index as usize + 2] = (x % 100) % 10;
+
+                        self.program_counter += 2;
+                    }
+                    // FX55 - Stores V0 to VX (including VX) in memory starting at address I
+                    0x0055 => {
+                        self.check_range(self.index as usize, x + 1)?;
+
+                        for x in 0..=x {
+                            self.memory[self.index as usize + x] = self.registers[x];
+                        }
+
+                        if !self.quirks.load_store {
+                            self.index += x as u16 + !self.quirks.increment_by_x as u16;
+                        }
+
+                        self.program_counter += 2;
+                    }
+                    // FX65 - Fills V0 to VX (including VX) with values from memory starting at address I
+                    0x0065 => {
+                        self.check_range(self.index as usize, x + 1)?;
+
+                        for x in 0..=x {
+                            self.registers[x] = self.memory[self.index as usize + x];
+                        }
+
+                        if !self.quirks.load_store {
+                            self.index += x as u16 + !self.quirks.increment_by_x as u16;
+                        }
+
+                        self.program_counter += 2;
+                    }
+                    _ => return Err(format!("Unknown instruction ({:04X})", self.opcode)),
+                }
+            }
+            _ => return Err(format!("Unknown instruction ({:04X})", self.opcode)),
+        }
+
+        if let Some(profiler) = &mut self.profiler {
+            profiler.record(pc, self.opcode);
+        }
+
+        if let Some(coverage) = &mut self.coverage {
+            coverage.record(pc, self.opcode, self.program_counter);
+        }
+
+        self.last_key = None;
+
+        Ok(())
+    }
+
+    // Runs a single 60 Hz frame worth of instructions and then counts down the timers
+    pub fn run_frame(&mut self, instructions: u32) -> Result<(), String> {
+        for _ in 0..instructions {
+            self.step()?;
+        }
+
+        self.tick_timers();
+
+        Ok(())
+    }
+
+    fn reset_flag(&mut self) {
+        if self.quirks.vf_reset {
+            self.registers[0xF] = 0;
+        }
+    }
+
+    // Counts down the delay and sound timers, which should happen at 60 Hz regardless of how many
+    // instructions are executed in between
+    pub fn tick_timers(&mut self) {
+        self.vblank = true;
+
+        if self.delay_timer > 0 {
+            self.delay_timer -= 1;
+        }
+
+        if self.sound_timer > 0 {
+            if self.sound_timer == 1 {
+                self.beep_flag = true;
+            }
+
+            self.sound_timer -= 1;
+        }
+    }
+
+    pub fn get_registers(&self) -> &[u8; 16] {
+        &self.registers
+    }
+
+    pub fn set_register(&mut self, x: usize, value: u8) {
+        self.registers[x] = value;
+    }
+
+    // The screen in the palette's colours
+    pub fn get_pixels(&self) -> &[u32] {
+        &self.framebuffer
+    }
+
+    // The screen as lit and unlit pixels, which unlike the colours can be told apart whatever
+    // the palette is
+    pub fn get_lit_pixels(&self) -> &[bool] {
+        &self.screen
+    }
+
+    pub fn get_stack(&self) -> &[u16; 16] {
+        &self.stack
+    }
+
+    pub fn get_opcode(&self) -> u16 {
+        self.opcode
+    }
+
+    pub fn get_program_counter(&self) -> u16 {
+        self.program_counter
+    }
+
+    pub fn set_program_counter(&mut self, addr: u16) {
+        self.program_counter = addr & 0x0FFF;
+    }
+
+    pub fn get_program_index(&self) -> u16 {
+        self.index
+    }
+
+    pub fn set_program_index(&mut self, addr: u16) {
+        self.index = addr & 0x0FFF;
+    }
+
+    pub fn get_stack_pointer(&self) -> usize {
+        self.stack_pointer
+    }
+
+    pub fn set_stack_pointer(&mut self, sp: usize) {
+        self.stack_pointer = sp.min(self.stack.len());
+    }
+
+    pub fn get_delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn get_sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    pub fn set_delay_timer(&mut self, value: u8) {
+        self.delay_timer = value;
+    }
+
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.sound_timer = value;
+    }
+
+    pub fn get_memory(&self) -> &[u8; 4096] {
+        &self.memory
+    }
+
+    // Changes a byte of memory, i.e. from a debugger
+    pub fn set_byte(&mut self, addr: u16, value: u8) {
+        self.memory[addr as usize & 0x0FFF] = value;
+    }
+
+    pub fn get_quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    pub fn get_palette(&self) -> [u32; 2] {
+        self.palette
+    }
+
+    // Changes the colours of unlit and lit pixels, recolouring anything already on screen
+    pub fn set_palette(&mut self, palette: [u32; 2]) {
+        for (pixel, lit) in self.framebuffer.iter_mut().zip(self.screen) {
+            *pixel = palette[lit as usize];
+        }
+
+        self.palette = palette;
+    }
+
+    pub fn set_seed(&mut self, seed: u64) {
+        // Expand the seed with SplitMix64, as Xoroshiro128 must not be seeded with all zeroes
+        let mut state = seed;
+        let mut next = || {
+            state = state.wrapping_add(0x9E3779B97F4A7C15);
+
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+            z ^ (z >> 31)
+        };
+
+        self.random = Xoroshiro128([next(), next()]);
+    }
+
+    pub fn get_keys(&self) -> &[bool; 16] {
+        &self.keys
+    }
+
+    pub fn set_key_state(&mut self, key: usize, pressed: bool) {
+        self.keys[key] = pressed;
+        self.last_key = Some(key);
+    }
+
+    // Lets go of every key without it counting as a key press, i.e. when the keys stop being
+    // passed through while a menu is open
+    pub fn release_keys(&mut self) {
+        self.keys = [false; 16];
+    }
+
+    // Starts counting the instructions executed from here on, following the calls already on the
+    // stack
+    pub fn set_profiler(&mut self, mut profiler: Profiler) {
+        profiler.set_call_stack(&self.stack[..self.stack_pointer], &self.memory);
+        self.profiler = Some(Box::new(profiler));
+    }
+
+    pub fn get_profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_deref()
+    }
+
+    pub fn take_profiler(&mut self) -> Option<Profiler> {
+        self.profiler.take().map(|profiler| *profiler)
+    }
+
+    // Starts recording which instructions are executed, on top of the coverage passed in
+    pub fn set_coverage(&mut self, coverage: Coverage) {
+        self.coverage = Some(Box::new(coverage));
+    }
+
+    pub fn get_coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_deref()
+    }
+
+    pub fn take_coverage(&mut self) -> Option<Coverage> {
+        self.coverage.take().map(|coverage| *coverage)
+    }
+
+    fn rand(&mut self) -> u8 {
+        (self.random.next_u64() & 0x000000FF) as u8
+    }
+
+    // Saves everything the program can observe as bytes, so that it can be restored with
+    // load_state later or in another process. The palette, profiler and coverage aren't part of
+    // it, and pixels are only stored as lit or unlit.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = Vec::with_capacity(STATE_SIZE);
+
+        state.extend(STATE_MAGIC);
+        state.push(STATE_VERSION);
+        state.extend(self.memory);
+        state.extend(self.registers);
+        state.extend(self.screen.map(|lit| lit as u8));
+        state.extend(self.stack.iter().flat_map(|addr| addr.to_le_bytes()));
+        state.extend(self.keys.map(|pressed| pressed as u8));
+        state.extend(self.opcode.to_le_bytes());
+        state.extend(self.index.to_le_bytes());
+        state.extend(self.program_counter.to_le_bytes());
+        state.push(self.stack_pointer as u8);
+        state.extend(self.random.0.iter().flat_map(|word| word.to_le_bytes()));
+        state.push(Quirks::NAMES.iter().rev().fold(0, |bits, name| bits << 1 | self.quirks.get(name) as u8));
+        state.push(self.delay_timer);
+        state.push(self.sound_timer);
+        state.push(self.beep_flag as u8);
+        state.push(self.vblank as u8);
+        state.push(self.last_key.map_or(0xFF, |key| key as u8));
+        state
+    }
+
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+        if state.len() != STATE_SIZE || !state.starts_with(STATE_MAGIC) {
+            return Err("Not a save state".to_string());
+        }
+
+        if state[STATE_MAGIC.len()] != STATE_VERSION {
+            return Err(format!("Unsupported save state version ({})", state[STATE_MAGIC.len()]));
+        }
+
+        let mut rest = &state[STATE_MAGIC.len() + 1..];
+        let mut take = |len: usize| {
+            let (bytes, remaining) = rest.split_at(len);
+            rest = remaining;
+            bytes
+        };
+
+        let memory = take(4096);
+        let registers = take(16);
+        let pixels = take(FRAMEBUFFER_SIZE);
+        let stack = take(32);
+        let keys = take(16);
+        let words = take(6);
+        let stack_pointer = take(1)[0] as usize;
+        let random = take(16);
+        let [quirks, delay_timer, sound_timer, beep_flag, vblank, last_key] = take(6).try_into().expect("Sizes add up");
+
+        if stack_pointer > self.stack.len() || (last_key != 0xFF && last_key > 0xF) {
+            return Err("Save state is corrupt".to_string());
+        }
+
+        let word = |bytes: &[u8], i: usize| u16::from_le_bytes([bytes[i * 2], bytes[i * 2 + 1]]);
+        let long = |i: usize| u64::from_le_bytes(random[i * 8..i * 8 + 8].try_into().expect("Slices are 8 bytes"));
+
+        self.memory.copy_from_slice(memory);
+        self.registers.copy_from_slice(registers);
+
+        for (lit, byte) in self.screen.iter_mut().zip(pixels) {
+            *lit = *byte != 0;
+        }
+
+        self.set_palette(self.palette);
+
+        for (i, addr) in self.stack.iter_mut().enumerate() {
+            *addr = word(stack, i) & 0x0FFF;
+        }
+
+        for (pressed, byte) in self.keys.iter_mut().zip(keys) {
+            *pressed = *byte != 0;
+        }
+
+        self.opcode = word(words, 0);
+        self.index = word(words, 1) & 0x0FFF;
+        self.program_counter = word(words, 2) & 0x0FFF;
+        self.stack_pointer = stack_pointer;
+        self.random = Xoroshiro128([long(0), long(1)]);
+
+        for (i, name) in Quirks::NAMES.iter().enumerate() {
+            self.quirks.set(name, quirks & 1 << i != 0)?;
+        }
+
+        self.delay_timer = delay_timer;
+        self.sound_timer = sound_timer;
+        self.beep_flag = beep_flag != 0;
+        self.vblank = vblank != 0;
+        self.last_key = (last_key != 0xFF).then_some(last_key as usize);
+
+        Ok(())
+    }
+}
+
+// Size of a save state: the header, memory, registers, pixels, stack, keys, three 16-bit
+// registers, SP, the random state, the quirks, the timers, the beep and vblank flags and the last key
+const STATE_SIZE: usize = 5 + 4096 + 16 + FRAMEBUFFER_SIZE + 32 + 16 + 6 + 1 + 16 + 1 + 2 + 2 + 1;
+
+// The xoroshiro128+ generator (https://prng.di.unimi.it/xoroshiro128plus.c), implemented here so
+// that its state can be saved
+#[derive(Clone)]
+struct Xoroshiro128([u64; 2]);
+
+impl Xoroshiro128 {
+    fn next_u64(&mut self) -> u64 {
+        let [s0, mut s1] = self.0;
+        let result = s0.wrapping_add(s1);
+
+        s1 ^= s0;
+        self.0 = [s0.rotate_left(55) ^ s1 ^ (s1 << 14), s1.rotate_left(36)];
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Runs a program to its end and returns VF
+    fn flag_after(program: &[u8]) -> u8 {
+        let mut cpu = Chip8::new(program).unwrap();
+
+        for _ in 0..program.len() / 2 {
+            cpu.step().unwrap();
+        }
+
+        cpu.get_registers()[0xF]
+    }
+
+    #[test]
+    fn flag_wins_when_x_is_f() {
+        // VF = FF, V1 = 01, VF += V1 carries
+        assert_eq!(flag_after(&[0x6F, 0xFF, 0x61, 0x01, 0x8F, 0x14]), 1);
+        // VF = 05, V1 = 01, VF -= V1 doesn't borrow
+        assert_eq!(flag_after(&[0x6F, 0x05, 0x61, 0x01, 0x8F, 0x15]), 1);
+        // V1 = 02, VF = V1 >> 1 shifts out a 0
+        assert_eq!(flag_after(&[0x6F, 0xFF, 0x61, 0x02, 0x8F, 0x16]), 0);
+        // VF = 01, V1 = 05, VF = V1 - VF doesn't borrow
+        assert_eq!(flag_after(&[0x6F, 0x01, 0x61, 0x05, 0x8F, 0x17]), 1);
+        // V1 = 81, VF = V1 << 1 shifts out a 1
+        assert_eq!(flag_after(&[0x6F, 0x00, 0x61, 0x81, 0x8F, 0x1E]), 1);
+    }
+
+    #[test]
+    fn collisions_dont_depend_on_the_palette() {
+        // Draws the 0 from the font twice at (0, 0), which erases it again
+        let program = [0x60, 0x00, 0xF0, 0x29, 0xD0, 0x05, 0xD0, 0x05];
+        let mut cpu = Chip8::new(&program).unwrap();
+
+        cpu.set_palette([0x123456, 0x123456]);
+
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+
+        assert_eq!(cpu.get_registers()[0xF], 0);
+        assert!(cpu.get_lit_pixels().iter().any(|lit| *lit));
+
+        cpu.step().unwrap();
+
+        assert_eq!(cpu.get_registers()[0xF], 1);
+        assert!(cpu.get_lit_pixels().iter().all(|lit| !lit));
+
+        cpu.set_palette(DEFAULT_PALETTE);
+        assert!(cpu.get_pixels().iter().all(|pixel| *pixel == DEFAULT_PALETTE[0]));
+    }
+
+    #[test]
+    fn display_wait_draws_once_per_frame() {
+        // Draws the 0 from the font twice and then loops forever
+        let program = [0x60, 0x00, 0xF0, 0x29, 0xD0, 0x05, 0xD0, 0x05, 0x12, 0x08];
+        let mut cpu = Chip8::new(&program).unwrap();
+        let lit = |cpu: &Chip8| cpu.get_lit_pixels().iter().filter(|lit| **lit).count();
+
+        cpu.set_quirks("display-wait".parse().unwrap());
+
+        // Nothing is drawn until a frame has started, and then one sprite per frame
+        cpu.run_frame(10).unwrap();
+        assert_eq!(lit(&cpu), 0);
+
+        cpu.run_frame(10).unwrap();
+        assert_ne!(lit(&cpu), 0);
+
+        cpu.run_frame(10).unwrap();
+        assert_eq!(lit(&cpu), 0);
+        assert_eq!(cpu.get_program_counter(), 0x208);
+    }
+
+    #[test]
+    fn store_increments_follow_the_quirks() {
+        // I = 300, store V0 to V2
+        let index_after = |quirks: &str| {
+            let mut cpu = Chip8::new(&[0xA3, 0x00, 0xF2, 0x55]).unwrap();
+
+            cpu.set_quirks(quirks.parse().unwrap());
+            cpu.step().unwrap();
+            cpu.step().unwrap();
+            cpu.get_program_index()
+        };
+
+        assert_eq!(index_after("none"), 0x303);
+        assert_eq!(index_after("increment-by-x"), 0x302);
+        assert_eq!(index_after("load-store,increment-by-x"), 0x300);
+    }
+
+    #[test]
+    fn loaded_return_addresses_stay_in_memory() {
+        // Returns from a call whose address is past the end of memory
+        let mut cpu = Chip8::new(&[0x00, 0xEE]).unwrap();
+        let mut state = cpu.save_state();
+        let stack = STATE_MAGIC.len() + 1 + 4096 + 16 + FRAMEBUFFER_SIZE;
+        let stack_pointer = stack + 32 + 16 + 6;
+
+        state[stack..stack + 2].copy_from_slice(&[0xFF, 0xFF]);
+        state[stack_pointer] = 1;
+
+        cpu.load_state(&state).unwrap();
+        assert_eq!(cpu.get_stack()[0], 0x0FFF);
+
+        cpu.step().unwrap();
+        assert!(cpu.step().is_err());
+    }
+}
@@ -0,0 +1,343 @@
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand};
+
+use chip8_rs::chip8::{self, Quirks};
+use chip8_rs::keypad::Keymap;
+use chip8_rs::scale::Upscaler;
+use chip8_rs::trace::TraceFormat;
+
+use crate::settings::{RenderMode, MAX_SPEED};
+
+/// A simple emulator for the CHIP-8 programming language
+#[derive(Parser, Debug)]
+#[command(name = "chip8-rs", version, args_conflicts_with_subcommands = true, arg_required_else_help = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub run: RunArgs,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Run a ROM in a window or the terminal (the default when no command is given)
+    Run(RunArgs),
+    /// Run a ROM without any display and print the screen once it has finished
+    Headless(HeadlessArgs),
+    /// Print a disassembly of a ROM
+    Disasm(RomArgs),
+    /// Print information about a ROM
+    Info(RomArgs),
+    /// Browse the ROMs in a directory and run them in a window
+    Browse(BrowseArgs),
+    /// Run a ROM in lockstep with a trace from another emulator and report where they diverge
+    Compare(CompareArgs),
+    /// Print a disassembly of a ROM marking the instructions which recorded runs never executed
+    Coverage(CoverageArgs),
+    /// Print the control flow of a ROM, found by following its jumps, calls and skips
+    Cfg(CfgArgs),
+    /// Debug a ROM with gdb, serving the remote serial protocol on a local port
+    Gdb(GdbArgs),
+    /// Serve the Debug Adapter Protocol on stdin and stdout, for debugging ROMs in an editor
+    Dap(DapArgs),
+    /// Run without any display, controlled by JSON-RPC requests on a local socket
+    Rpc(RpcArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct RomArgs {
+    /// Path to the ROM file, or - to read it from stdin
+    pub rom: PathBuf,
+
+    #[command(flatten)]
+    pub source: SourceArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct RunArgs {
+    /// Path to the ROM file, or - to read it from stdin
+    pub rom: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub source: SourceArgs,
+
+    #[command(flatten)]
+    pub emulator: EmulatorArgs,
+
+    #[command(flatten)]
+    pub display: DisplayArgs,
+
+    #[command(flatten)]
+    pub trace: TraceArgs,
+
+    #[command(flatten)]
+    pub profile: ProfileArgs,
+
+    /// Rhai script to run alongside the ROM, reloaded with F9
+    #[arg(long, value_name = "PATH")]
+    pub script: Option<PathBuf>,
+
+    /// Accept JSON-RPC requests on a port on 127.0.0.1, a HOST:PORT or a unix:PATH socket
+    #[arg(long, value_name = "ADDR")]
+    pub rpc: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct BrowseArgs {
+    /// Directories to search for ROMs, in addition to the ones in the config file
+    pub dirs: Vec<PathBuf>,
+
+    #[command(flatten)]
+    pub emulator: EmulatorArgs,
+
+    #[command(flatten)]
+    pub display: DisplayArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct HeadlessArgs {
+    /// Path to the ROM file, or - to read it from stdin
+    pub rom: PathBuf,
+
+    #[command(flatten)]
+    pub source: SourceArgs,
+
+    /// Number of frames to run for
+    #[arg(long, default_value_t = 600)]
+    pub frames: u32,
+
+    /// Save the final screen as a BMP image
+    #[arg(long, value_name = "PATH")]
+    pub screenshot: Option<PathBuf>,
+
+    /// Record the run as an animated GIF
+    #[arg(long, value_name = "PATH")]
+    pub record: Option<PathBuf>,
+
+    /// Pixel-art upscaler for the screenshot and the recording (none, scale2x, scale3x, epx, xbr)
+    #[arg(long, value_name = "NAME")]
+    pub upscaler: Option<Upscaler>,
+
+    /// Rhai script to run alongside the ROM, printing to stderr
+    #[arg(long, value_name = "PATH")]
+    pub script: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub emulator: EmulatorArgs,
+
+    #[command(flatten)]
+    pub trace: TraceArgs,
+
+    #[command(flatten)]
+    pub profile: ProfileArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct CompareArgs {
+    /// Path to the ROM file, or - to read it from stdin
+    pub rom: PathBuf,
+
+    /// Trace of the reference emulator, one line of PC=0200 OP=00E0 V0=00 ... I=0000 fields per
+    /// instruction
+    pub reference: PathBuf,
+
+    /// Number of instructions before the divergence to show
+    #[arg(long, value_name = "N", default_value_t = 10)]
+    pub context: usize,
+
+    #[command(flatten)]
+    pub source: SourceArgs,
+
+    #[command(flatten)]
+    pub emulator: EmulatorArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct CoverageArgs {
+    /// Path to the ROM file, or - to read it from stdin
+    pub rom: PathBuf,
+
+    /// Coverage files recorded with --coverage, which are added up
+    #[arg(required = true)]
+    pub coverage: Vec<PathBuf>,
+
+    /// Write the report as an HTML page instead of printing it
+    #[arg(long, value_name = "PATH")]
+    pub html: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub source: SourceArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct CfgArgs {
+    /// Path to the ROM file, or - to read it from stdin
+    pub rom: PathBuf,
+
+    /// Print the basic blocks as a Graphviz graph instead of a summary
+    #[arg(long)]
+    pub dot: bool,
+
+    /// Print which subroutines call which as a Graphviz graph instead of a summary
+    #[arg(long, conflicts_with = "dot")]
+    pub calls: bool,
+
+    #[command(flatten)]
+    pub source: SourceArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct GdbArgs {
+    /// Path to the ROM file, or - to read it from stdin
+    pub rom: PathBuf,
+
+    /// Port to listen on, on 127.0.0.1
+    #[arg(long, default_value_t = 1234)]
+    pub port: u16,
+
+    #[command(flatten)]
+    pub source: SourceArgs,
+
+    #[command(flatten)]
+    pub emulator: EmulatorArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct DapArgs {
+    /// Listen on this port on 127.0.0.1 instead of using stdin and stdout
+    #[arg(long)]
+    pub port: Option<u16>,
+
+    #[command(flatten)]
+    pub emulator: EmulatorArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct RpcArgs {
+    /// Path to the ROM file to start with, or - to read it from stdin
+    pub rom: Option<PathBuf>,
+
+    /// Port on 127.0.0.1, HOST:PORT or unix:PATH socket to listen on
+    #[arg(long, value_name = "ADDR", default_value = "8888")]
+    pub listen: String,
+
+    #[command(flatten)]
+    pub source: SourceArgs,
+
+    #[command(flatten)]
+    pub emulator: EmulatorArgs,
+}
+
+#[derive(Args, Debug, Default)]
+pub struct SourceArgs {
+    /// ROM to load from a zip archive holding several, instead of asking
+    #[arg(long, value_name = "NAME")]
+    pub entry: Option<String>,
+}
+
+#[derive(Args, Clone, Debug, Default)]
+pub struct EmulatorArgs {
+    /// Configuration file to use instead of the one in the user's config directory
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Instructions executed per 60 Hz frame
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..=MAX_SPEED as i64))]
+    pub speed: Option<u32>,
+
+    /// Seed for the random number generator used by CXNN
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Frames run per frame while fast-forwarding, or 0 to run as fast as possible
+    #[arg(long, value_name = "N")]
+    pub fast_forward: Option<u32>,
+
+    /// Comma-separated list of quirks to enable (shift, load-store, jump, vf-reset, wrap, display-wait,
+    /// increment-by-x) or "none"
+    #[arg(long, value_name = "LIST")]
+    pub quirks: Option<Quirks>,
+
+    /// Unlit and lit pixel colours, i.e. "000000,FFFFFF"
+    #[arg(long, value_name = "BG,FG", value_parser = chip8::parse_palette)]
+    pub palette: Option<[u32; 2]>,
+
+    /// Keyboard characters for the keypad keys 0 to F, i.e. "x123qweasdzc4rfv"
+    #[arg(long, value_name = "KEYS")]
+    pub keymap: Option<Keymap>,
+
+    /// Disable the beeper
+    #[arg(long)]
+    pub mute: bool,
+
+    /// Don't configure the emulator from the ROM database
+    #[arg(long)]
+    pub no_database: bool,
+}
+
+#[derive(Args, Debug, Default)]
+pub struct DisplayArgs {
+    /// Size of an emulated pixel in the window
+    #[arg(long, value_name = "N")]
+    pub scale: Option<usize>,
+
+    /// Comma-separated list of post-processing filters, i.e. "scanlines=0.4,bloom=0.6:4" (bloom takes a
+    /// strength and a radius)
+    #[arg(long, value_name = "LIST")]
+    pub filters: Option<String>,
+
+    /// Pixel-art upscaler (none, scale2x, scale3x, epx, xbr)
+    #[arg(long, value_name = "NAME")]
+    pub upscaler: Option<Upscaler>,
+
+    /// Draw in the terminal instead of a window (halfblock or braille)
+    #[arg(long, value_name = "MODE", num_args = 0..=1, default_missing_value = "halfblock")]
+    pub terminal: Option<RenderMode>,
+}
+
+#[derive(Args, Debug, Default)]
+pub struct TraceArgs {
+    /// Write every executed instruction to a file
+    #[arg(long, value_name = "PATH")]
+    pub trace: Option<PathBuf>,
+
+    /// Format of the trace file (text or binary)
+    #[arg(long, value_name = "FORMAT", default_value = "text")]
+    pub trace_format: TraceFormat,
+
+    /// Only trace instructions in these hex address ranges, i.e. "200-2FF,3A0"
+    #[arg(long, value_name = "RANGES")]
+    pub trace_addr: Option<String>,
+
+    /// Only trace these opcode classes, given by their first hex digit, i.e. "8,D"
+    #[arg(long, value_name = "LIST")]
+    pub trace_ops: Option<String>,
+
+    /// Only trace the frames in this range, i.e. "100-200"
+    #[arg(long, value_name = "RANGE")]
+    pub trace_frames: Option<String>,
+}
+
+#[derive(Args, Debug, Default)]
+pub struct ProfileArgs {
+    /// Count the instructions executed at every address and in every subroutine, and write a
+    /// report to a file once the ROM stops
+    #[arg(long, value_name = "PATH")]
+    pub profile: Option<PathBuf>,
+
+    /// Write the instructions executed in every call stack to a file, in the collapsed format
+    /// taken by flamegraph tools
+    #[arg(long, value_name = "PATH")]
+    pub profile_stacks: Option<PathBuf>,
+
+    /// Number of addresses listed in the profile report
+    #[arg(long, value_name = "N", default_value_t = 20)]
+    pub profile_top: usize,
+
+    /// Record which instructions are executed and which ways skips go to a file, adding to the
+    /// coverage already in it
+    #[arg(long, value_name = "PATH")]
+    pub coverage: Option<PathBuf>,
+}
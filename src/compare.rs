@@ -0,0 +1,223 @@
+use std::collections::VecDeque;
+use std::fmt;
+
+use crate::chip8::Chip8;
+use crate::disasm;
+use crate::trace;
+
+// Reference logs have one line per executed instruction with the state before executing it, as
+// whitespace-separated KEY=VALUE fields with hex values (optionally prefixed with 0x):
+//
+//   PC=0200 OP=00E0 V0=00 V1=00 ... VF=00 I=0000 SP=0 DT=00 ST=00
+//
+// The keys are case-insensitive and may come in any order. Only PC is required, fields which a
+// line doesn't have aren't compared and anything which isn't a KEY=VALUE field (i.e. a cycle
+// count) is ignored. Empty lines and lines starting with # are skipped.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RefState {
+    pub line: usize,
+    pub pc: u16,
+    pub opcode: Option<u16>,
+    pub registers: [Option<u8>; 16],
+    pub index: Option<u16>,
+    pub stack_pointer: Option<u8>,
+    pub delay_timer: Option<u8>,
+    pub sound_timer: Option<u8>,
+}
+
+pub fn parse_reference(text: &str) -> Result<Vec<RefState>, String> {
+    let mut states = Vec::new();
+
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut state = RefState { line: i + 1, ..RefState::default() };
+        let mut pc = None;
+
+        for (key, value) in line.split_whitespace().filter_map(|field| field.split_once('=')) {
+            // Only the fields which are compared are parsed, so that others may hold anything
+            let word = || {
+                let digits = value.trim_start_matches("0x").trim_start_matches("0X");
+
+                u16::from_str_radix(digits, 16)
+                    .map_err(|_| format!("Line {}: Invalid value for {} ({})", i + 1, key, value))
+            };
+
+            let byte = || {
+                word().and_then(|value| {
+                    u8::try_from(value)
+                        .map_err(|_| format!("Line {}: Value for {} doesn't fit a byte ({:X})", i + 1, key, value))
+                })
+            };
+
+            match key.to_ascii_uppercase().as_str() {
+                "PC" => pc = Some(word()?),
+                "OP" | "OPCODE" => state.opcode = Some(word()?),
+                "I" => state.index = Some(word()?),
+                "SP" => state.stack_pointer = Some(byte()?),
+                "DT" => state.delay_timer = Some(byte()?),
+                "ST" => state.sound_timer = Some(byte()?),
+                key => {
+                    let register = key.strip_prefix('V').filter(|x| x.len() == 1).and_then(|x| x.chars().next());
+
+                    if let Some(x) = register.and_then(|x| x.to_digit(16)) {
+                        state.registers[x as usize] = Some(byte()?);
+                    }
+                }
+            }
+        }
+
+        state.pc = pc.ok_or_else(|| format!("Line {}: Missing PC", i + 1))?;
+        states.push(state);
+    }
+
+    Ok(states)
+}
+
+// An executed instruction, kept to show what led up to a divergence
+#[derive(Clone, Debug)]
+pub struct Executed {
+    pub step: usize,
+    pub pc: u16,
+    pub opcode: u16,
+}
+
+#[derive(Debug)]
+pub struct Divergence {
+    pub step: usize,
+    pub frame: usize,
+    pub reference: RefState,
+    // The fields side by side as (name, reference, ours), with None where the reference doesn't
+    // have the field
+    pub fields: Vec<(String, Option<String>, String)>,
+    pub history: Vec<Executed>,
+    // Set when the emulator failed to execute the instruction, rather than ending up in a
+    // different state
+    pub error: Option<String>,
+}
+
+pub enum Outcome {
+    // Every step of the reference matched
+    Match(usize),
+    Diverged(Box<Divergence>),
+}
+
+// Runs the CPU in lockstep with the reference, ticking the timers after every frame's worth of
+// instructions, and stops at the first step where the states differ
+pub fn compare(cpu: &mut Chip8, reference: &[RefState], instructions_per_frame: u32, context: usize) -> Outcome {
+    let per_frame = instructions_per_frame.max(1) as usize;
+    let mut history = VecDeque::with_capacity(context + 1);
+
+    for (step, expected) in reference.iter().enumerate() {
+        let fields = diff_fields(cpu, expected);
+        let diverged = fields.iter().any(|(_, theirs, ours)| theirs.as_ref().is_some_and(|theirs| theirs != ours));
+
+        let pc = cpu.get_program_counter();
+        let opcode = trace::fetch(cpu, pc);
+
+        let error = if diverged { None } else { cpu.step().err() };
+
+        if diverged || error.is_some() {
+            return Outcome::Diverged(Box::new(Divergence {
+                step,
+                frame: step / per_frame,
+                reference: expected.clone(),
+                fields,
+                history: history.into(),
+                error,
+            }));
+        }
+
+        if context > 0 {
+            if history.len() == context {
+                history.pop_front();
+            }
+
+            history.push_back(Executed { step, pc, opcode });
+        }
+
+        if (step + 1) % per_frame == 0 {
+            cpu.tick_timers();
+        }
+    }
+
+    Outcome::Match(reference.len())
+}
+
+fn diff_fields(cpu: &Chip8, expected: &RefState) -> Vec<(String, Option<String>, String)> {
+    let hex2 = |v: u8| format!("{:02X}", v);
+    let hex4 = |v: u16| format!("{:04X}", v);
+
+    let mut fields = vec![
+        ("PC".to_string(), Some(hex4(expected.pc)), hex4(cpu.get_program_counter())),
+        ("OP".to_string(), expected.opcode.map(hex4), hex4(trace::fetch(cpu, cpu.get_program_counter()))),
+        ("I".to_string(), expected.index.map(hex4), hex4(cpu.get_program_index())),
+    ];
+
+    for (x, value) in cpu.get_registers().iter().enumerate() {
+        fields.push((format!("V{:X}", x), expected.registers[x].map(hex2), hex2(*value)));
+    }
+
+    fields.push(("SP".to_string(), expected.stack_pointer.map(hex2), hex2(cpu.get_stack_pointer() as u8)));
+    fields.push(("DT".to_string(), expected.delay_timer.map(hex2), hex2(cpu.get_delay_timer())));
+    fields.push(("ST".to_string(), expected.sound_timer.map(hex2), hex2(cpu.get_sound_timer())));
+
+    fields
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Diverged at step {} (frame {}, reference line {})", self.step, self.frame, self.reference.line)?;
+
+        if let Some(err) = &self.error {
+            writeln!(f, "The emulator failed to execute the instruction: {}", err)?;
+        }
+
+        if !self.history.is_empty() {
+            writeln!(f, "\nPreceding instructions:")?;
+
+            for executed in &self.history {
+                let text = disasm::disassemble(executed.opcode).unwrap_or_else(|| "???".to_string());
+
+                writeln!(f, "  {:>8}  {:04X}  {:04X}  {}", executed.step, executed.pc, executed.opcode, text)?;
+            }
+        }
+
+        writeln!(f, "\n         reference  chip8-rs")?;
+
+        for (name, theirs, ours) in &self.fields {
+            let differs = theirs.as_ref().is_some_and(|theirs| theirs != ours);
+
+            writeln!(f, "  {} {:<4} {:<10} {}", if differs { '*' } else { ' ' }, name,
+                     theirs.as_deref().unwrap_or("-"), ours)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_which_arent_compared_are_ignored() {
+        let states = parse_reference("cycle=70000 frame=1a2b3c PC=0x0202 v3=0A I=300 12345\n").unwrap();
+
+        assert_eq!(states.len(), 1);
+        assert_eq!(states[0].pc, 0x202);
+        assert_eq!(states[0].registers[3], Some(0x0A));
+        assert_eq!(states[0].index, Some(0x300));
+    }
+
+    #[test]
+    fn compared_fields_must_be_valid() {
+        assert_eq!(parse_reference("PC=0200 V0=zz").unwrap_err(), "Line 1: Invalid value for V0 (zz)");
+        assert_eq!(parse_reference("\nPC=0200 DT=100").unwrap_err(), "Line 2: Value for DT doesn't fit a byte (100)");
+        assert_eq!(parse_reference("V0=00").unwrap_err(), "Line 1: Missing PC");
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+// The configuration file consists of the global settings, which may be overridden for specific
+// ROMs in sections keyed by the SHA-1 of the ROM, i.e. [roms.<sha1>.timing]
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub display: DisplayConfig,
+    pub audio: AudioConfig,
+    pub timing: TimingConfig,
+    pub quirks: QuirksConfig,
+    pub input: InputConfig,
+    pub library: LibraryConfig,
+    pub roms: HashMap<String, Config>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct DisplayConfig {
+    pub scale: Option<usize>,
+    pub filters: Option<String>,
+    pub upscaler: Option<String>,
+    pub palette: Option<String>,
+    // Render mode for the terminal frontend, or "none" to use a window
+    pub terminal: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct AudioConfig {
+    pub enabled: Option<bool>,
+    pub volume: Option<f32>,
+    pub frequency: Option<f32>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct TimingConfig {
+    pub speed: Option<u32>,
+    pub seed: Option<u64>,
+    pub fast_forward: Option<u32>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct QuirksConfig {
+    pub shift: Option<bool>,
+    pub load_store: Option<bool>,
+    pub jump: Option<bool>,
+    pub vf_reset: Option<bool>,
+    pub wrap: Option<bool>,
+    pub display_wait: Option<bool>,
+    pub increment_by_x: Option<bool>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct InputConfig {
+    pub keymap: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct LibraryConfig {
+    // Directories searched for ROMs by the browser, a leading ~ is the home directory
+    pub dirs: Vec<String>,
+}
+
+impl LibraryConfig {
+    #[cfg_attr(not(feature = "sdl"), allow(dead_code))]
+    pub fn get_dirs(&self) -> Vec<PathBuf> {
+        self.dirs
+            .iter()
+            .map(|dir| match (dir.strip_prefix("~/"), env::var_os("HOME")) {
+                (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+                _ => PathBuf::from(dir),
+            })
+            .collect()
+    }
+}
+
+impl Config {
+    // Loads the configuration from the given path, or from the default location when there is
+    // none. Only a missing file at the default location is not an error.
+    pub fn load(path: Option<&Path>) -> Result<Config, String> {
+        let (path, required) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => match default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Config::default()),
+            },
+        };
+
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound && !required => return Ok(Config::default()),
+            Err(err) => return Err(format!("Failed to read config file {}: {}", path.display(), err)),
+        };
+
+        let mut config: Config = toml::from_str(&text)
+            .map_err(|err| format!("Failed to parse config file {}: {}", path.display(), err))?;
+
+        if config.roms.values().any(|rom| !rom.roms.is_empty() || !rom.library.dirs.is_empty()) {
+            return Err(format!("Failed to parse config file {}: ROM sections can't contain [roms] or [library]",
+                               path.display()));
+        }
+
+        config.roms = config.roms
+            .drain()
+            .map(|(hash, rom)| (hash.to_ascii_lowercase(), rom))
+            .collect();
+
+        Ok(config)
+    }
+
+    pub fn get_rom(&self, hash: &str) -> Option<&Config> {
+        self.roms.get(hash)
+    }
+}
+
+// $XDG_CONFIG_HOME/chip8-rs/config.toml, falling back to ~/.config when the variable is not set
+pub fn default_path() -> Option<PathBuf> {
+    user_dir().map(|dir| dir.join("config.toml"))
+}
+
+// Directory holding the user's own ROM database entries, in the same format as the bundled ones
+pub fn database_dir() -> Option<PathBuf> {
+    user_dir().map(|dir| dir.join("database"))
+}
+
+// Favourites and play times of the ROM browser
+#[cfg_attr(not(feature = "sdl"), allow(dead_code))]
+pub fn library_path() -> Option<PathBuf> {
+    user_dir().map(|dir| dir.join("library.json"))
+}
+
+fn user_dir() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("chip8-rs"))
+}
+
+fn config_dir() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        return Some(PathBuf::from(dir));
+    }
+
+    if cfg!(windows) {
+        if let Some(dir) = env::var_os("APPDATA") {
+            return Some(PathBuf::from(dir));
+        }
+    }
+
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".config"))
+}
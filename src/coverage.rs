@@ -0,0 +1,320 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Write};
+
+use crate::disasm;
+
+// Where programs are loaded, the listings start here
+const ROM_START: usize = 0x200;
+
+const HEADER: &str = "# chip8-rs coverage";
+
+// Skip instructions, whose outcomes are recorded as well as whether they were executed
+const SKIPS: [&str; 6] = ["3XNN", "4XNN", "5XY0", "9XY0", "EX9E", "EXA1"];
+
+// How often a skip instruction skipped the next one or didn't
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Branch {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+// Which addresses a ROM executed instructions at, and which ways its skips went. Coverage files
+// have one line per executed address after a header naming the ROM by its SHA-1:
+//
+//   # chip8-rs coverage
+//   rom <SHA-1>
+//   <address> <count> [<taken> <not taken>]
+//
+// with the address in hex and the counts in decimal, the last two only for skips.
+#[derive(Clone, Debug)]
+pub struct Coverage {
+    rom_hash: String,
+    counts: Vec<u64>,
+    branches: BTreeMap<u16, Branch>,
+}
+
+impl Coverage {
+    pub fn new(rom_hash: &str) -> Self {
+        Coverage {
+            rom_hash: rom_hash.to_ascii_lowercase(),
+            counts: vec![0; 4096],
+            branches: BTreeMap::new(),
+        }
+    }
+
+    // Counts an instruction after it has been executed, next being the address it continued at
+    pub fn record(&mut self, pc: u16, opcode: u16, next: u16) {
+        self.counts[pc as usize] += 1;
+
+        if is_skip(opcode) {
+            let branch = self.branches.entry(pc).or_default();
+
+            if next == pc.wrapping_add(4) {
+                branch.taken += 1;
+            } else {
+                branch.not_taken += 1;
+            }
+        }
+    }
+
+    pub fn get_rom_hash(&self) -> &str {
+        &self.rom_hash
+    }
+
+    pub fn get_count(&self, addr: u16) -> u64 {
+        self.counts.get(addr as usize).copied().unwrap_or(0)
+    }
+
+    pub fn get_branch(&self, addr: u16) -> Option<Branch> {
+        self.branches.get(&addr).copied()
+    }
+
+    // Adds up the coverage of another run of the same ROM
+    pub fn merge(&mut self, other: &Coverage) -> Result<(), String> {
+        if other.rom_hash != self.rom_hash {
+            return Err(format!("Coverage is of a different ROM ({} rather than {})", other.rom_hash,
+                               self.rom_hash));
+        }
+
+        for (count, other) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other;
+        }
+
+        for (addr, other) in &other.branches {
+            let branch = self.branches.entry(*addr).or_default();
+
+            branch.taken += other.taken;
+            branch.not_taken += other.not_taken;
+        }
+
+        Ok(())
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut lines = text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty());
+
+        if lines.next().map(|(_, line)| line.trim()) != Some(HEADER) {
+            return Err("Not a coverage file (missing header)".to_string());
+        }
+
+        let hash = lines
+            .next()
+            .and_then(|(_, line)| line.trim().strip_prefix("rom "))
+            .ok_or("Coverage file doesn't name its ROM")?;
+
+        let mut coverage = Coverage::new(hash.trim());
+
+        for (i, line) in lines {
+            let invalid = || format!("Line {}: Invalid coverage ({})", i + 1, line.trim());
+            let fields: Vec<&str> = line.split_whitespace().collect();
+
+            let addr = fields.first().and_then(|s| u16::from_str_radix(s, 16).ok()).ok_or_else(invalid)?;
+            let counts = fields[1..].iter().map(|s| s.parse::<u64>()).collect::<Result<Vec<_>, _>>();
+
+            if addr as usize >= coverage.counts.len() {
+                return Err(invalid());
+            }
+
+            match counts.as_deref() {
+                Ok([count]) => coverage.counts[addr as usize] = *count,
+                Ok([count, taken, not_taken]) => {
+                    coverage.counts[addr as usize] = *count;
+                    coverage.branches.insert(addr, Branch { taken: *taken, not_taken: *not_taken });
+                }
+                _ => return Err(invalid()),
+            }
+        }
+
+        Ok(coverage)
+    }
+
+    pub fn write(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "{}", HEADER)?;
+        writeln!(out, "rom {}", self.rom_hash)?;
+
+        for (addr, count) in self.counts.iter().enumerate().filter(|(_, count)| **count > 0) {
+            match self.branches.get(&(addr as u16)) {
+                Some(branch) => writeln!(out, "{:03X} {} {} {}", addr, count, branch.taken, branch.not_taken)?,
+                None => writeln!(out, "{:03X} {}", addr, count)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    // Splits a ROM into the lines of a listing. Instructions are taken to be aligned to where the
+    // ROM starts, unless one was executed at an odd address, in which case the byte before it is
+    // listed on its own. Whatever wouldn't fit in memory is left out.
+    fn annotate(&self, rom: &[u8]) -> Vec<Line> {
+        let rom = &rom[..rom.len().min(self.counts.len() - ROM_START)];
+        let mut lines = Vec::new();
+        let mut offset = 0;
+
+        while offset < rom.len() {
+            let addr = ROM_START + offset;
+            let count = self.counts[addr];
+
+            if offset + 1 < rom.len() && (count > 0 || self.counts[addr + 1] == 0) {
+                let opcode = (rom[offset] as u16) << 8 | rom[offset + 1] as u16;
+                let branch = is_skip(opcode).then(|| self.get_branch(addr as u16).unwrap_or_default());
+
+                lines.push(Line { addr: addr as u16, opcode: Some(opcode), byte: 0, count, branch });
+                offset += 2;
+            } else {
+                lines.push(Line { addr: addr as u16, opcode: None, byte: rom[offset], count, branch: None });
+                offset += 1;
+            }
+        }
+
+        lines
+    }
+
+    // Writes a disassembly of the ROM with the executions of every instruction, marking the ones
+    // which never ran with ! and the skips which only ever went one way with ?
+    pub fn write_listing(&self, out: &mut dyn Write, rom: &[u8]) -> io::Result<()> {
+        let lines = self.annotate(rom);
+
+        writeln!(out, "{}\n", Summary::of(&lines))?;
+        writeln!(out, "       count  addr  opcode  instruction")?;
+
+        for line in &lines {
+            let text = format!("{} {:>10}  {:04X}  {:<6}  {:<16}  {}", line.get_marker(), line.count, line.addr,
+                               line.get_code(), line.get_text(), line.get_skips());
+
+            writeln!(out, "{}", text.trim_end())?;
+        }
+
+        Ok(())
+    }
+
+    // The listing as a standalone HTML page, with uncovered code highlighted
+    pub fn write_html(&self, out: &mut dyn Write, rom: &[u8], title: &str) -> io::Result<()> {
+        let lines = self.annotate(rom);
+
+        writeln!(out, "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">")?;
+        writeln!(out, "<title>Coverage of {}</title>", escape_html(title))?;
+        writeln!(out, "<style>")?;
+        writeln!(out, "body {{ font-family: sans-serif; }}")?;
+        writeln!(out, "table {{ border-collapse: collapse; font-family: monospace; }}")?;
+        writeln!(out, "td {{ padding: 0 1em; }}")?;
+        writeln!(out, "td.count {{ text-align: right; }}")?;
+        writeln!(out, "tr.uncovered {{ background: #f8d0d0; }}")?;
+        writeln!(out, "tr.partial {{ background: #f8ecc0; }}")?;
+        writeln!(out, "</style>\n</head>\n<body>")?;
+        writeln!(out, "<h1>Coverage of {}</h1>", escape_html(title))?;
+        writeln!(out, "<p>{}</p>", Summary::of(&lines))?;
+        writeln!(out, "<table>")?;
+        writeln!(out, "<tr><th>Count</th><th>Address</th><th>Opcode</th><th>Instruction</th><th>Skips</th></tr>")?;
+
+        for line in &lines {
+            let class = match line.get_marker() {
+                '!' => " class=\"uncovered\"",
+                '?' => " class=\"partial\"",
+                _ => "",
+            };
+
+            let text = escape_html(&line.get_text());
+
+            writeln!(out, "<tr{}><td class=\"count\">{}</td><td>{:04X}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                     class, line.count, line.addr, line.get_code(), text, line.get_skips())?;
+        }
+
+        writeln!(out, "</table>\n</body>\n</html>")
+    }
+}
+
+// An instruction or a lone byte of a listing
+struct Line {
+    addr: u16,
+    opcode: Option<u16>,
+    byte: u8,
+    count: u64,
+    // Set for skip instructions
+    branch: Option<Branch>,
+}
+
+impl Line {
+    fn get_marker(&self) -> char {
+        match self.branch {
+            _ if self.count == 0 => '!',
+            Some(branch) if branch.taken == 0 || branch.not_taken == 0 => '?',
+            _ => ' ',
+        }
+    }
+
+    fn get_code(&self) -> String {
+        match self.opcode {
+            Some(opcode) => format!("{:04X}", opcode),
+            None => format!("{:02X}", self.byte),
+        }
+    }
+
+    fn get_skips(&self) -> String {
+        self.branch
+            .map(|branch| format!("taken {}, not taken {}", branch.taken, branch.not_taken))
+            .unwrap_or_default()
+    }
+
+    fn get_text(&self) -> String {
+        match self.opcode {
+            Some(opcode) => disasm::disassemble(opcode).unwrap_or_else(|| "???".to_string()),
+            None => String::new(),
+        }
+    }
+}
+
+struct Summary {
+    instructions: usize,
+    covered: usize,
+    skips: usize,
+    both_ways: usize,
+}
+
+impl Summary {
+    fn of(lines: &[Line]) -> Self {
+        let instructions = lines.iter().filter(|line| line.opcode.is_some());
+        let branches = lines.iter().filter_map(|line| line.branch);
+
+        Summary {
+            instructions: instructions.clone().count(),
+            covered: instructions.filter(|line| line.count > 0).count(),
+            skips: branches.clone().count(),
+            both_ways: branches.filter(|branch| branch.taken > 0 && branch.not_taken > 0).count(),
+        }
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let percent = match self.instructions {
+            0 => 0.0,
+            instructions => self.covered as f64 * 100.0 / instructions as f64,
+        };
+
+        write!(f, "Executed {} of {} instructions ({:.1}%), {} of {} skips went both ways", self.covered,
+               self.instructions, percent, self.both_ways, self.skips)
+    }
+}
+
+fn is_skip(opcode: u16) -> bool {
+    disasm::pattern(opcode).is_some_and(|pattern| SKIPS.contains(&pattern))
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn listings_stop_at_the_end_of_memory() {
+        let coverage = Coverage::new("");
+        let lines = coverage.annotate(&[0x12; 4000]);
+
+        assert_eq!(lines.len(), (4096 - ROM_START) / 2);
+        assert_eq!(lines.last().unwrap().addr, 0xFFE);
+    }
+}
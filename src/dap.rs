@@ -0,0 +1,696 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+
+use chip8_rs::debug::{Debugger, Stop};
+use chip8_rs::rom::{Format, Rom};
+use chip8_rs::symbols::SymbolMap;
+use chip8_rs::{disasm, octo, trace, Chip8};
+
+use crate::cli::{EmulatorArgs, SourceArgs};
+
+const THREAD_ID: u64 = 1;
+const FRAME: Duration = Duration::from_nanos(1_000_000_000 / 60);
+const MEMORY_SIZE: usize = 4096;
+
+// Variable references of the scopes, which are the same in every frame
+const REGISTERS: u64 = 1;
+const TIMERS: u64 = 2;
+
+// Serves a single session of the Debug Adapter Protocol, as used by VS Code, over a pair of streams
+// (stdin and stdout, or a socket). Messages are read on a separate thread so that a running
+// program can be paused.
+pub fn serve(input: Box<dyn Read + Send>, output: Box<dyn Write>, emulator: &EmulatorArgs) -> Result<(), String> {
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        let mut reader = BufReader::new(input);
+
+        while let Ok(Some(message)) = read_message(&mut reader) {
+            if sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+
+    Session::new(output, emulator)
+        .run(receiver)
+        .map_err(|err| format!("Connection to the debugger failed: {}", err))
+}
+
+// Reads a message framed by a Content-Length header, or returns None at the end of the stream
+fn read_message(reader: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+
+    loop {
+        let mut line = String::new();
+
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        let line = line.trim_end();
+
+        if line.is_empty() {
+            break;
+        }
+
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let length = length.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Missing Content-Length"))?;
+    let mut body = vec![0; length];
+
+    reader.read_exact(&mut body)?;
+
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+#[derive(Clone, Copy)]
+enum Running {
+    No,
+    Continue,
+    // Until the stack is back to this depth, to step over a call or out of a subroutine
+    ToDepth(usize),
+}
+
+struct Session<'a> {
+    output: Box<dyn Write>,
+    seq: u64,
+    emulator: &'a EmulatorArgs,
+    debugger: Option<Debugger>,
+    symbols: SymbolMap,
+    // Directory which the file names in the symbols are relative to
+    base: PathBuf,
+    // Breakpoints set on the lines of each source file, and on instructions in the disassembly
+    source_breakpoints: HashMap<String, Vec<u16>>,
+    instruction_breakpoints: Vec<u16>,
+    stop_on_entry: bool,
+    running: Running,
+    // Why a step stopped, which is reported after the response to it
+    stepped: Option<Stop>,
+    terminated: bool,
+}
+
+impl<'a> Session<'a> {
+    fn new(output: Box<dyn Write>, emulator: &'a EmulatorArgs) -> Self {
+        Session {
+            output,
+            seq: 0,
+            emulator,
+            debugger: None,
+            symbols: SymbolMap::default(),
+            base: PathBuf::new(),
+            source_breakpoints: HashMap::new(),
+            instruction_breakpoints: Vec::new(),
+            stop_on_entry: false,
+            running: Running::No,
+            stepped: None,
+            terminated: false,
+        }
+    }
+
+    fn run(&mut self, receiver: Receiver<Value>) -> io::Result<()> {
+        let mut next_frame = Instant::now();
+
+        while !self.terminated {
+            let message = match self.running {
+                Running::No => match receiver.recv() {
+                    Ok(message) => Some(message),
+                    Err(_) => return Ok(()),
+                },
+                _ => match receiver.recv_timeout(next_frame.saturating_duration_since(Instant::now())) {
+                    Ok(message) => Some(message),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => return Ok(()),
+                },
+            };
+
+            match message {
+                Some(message) => {
+                    let was_stopped = matches!(self.running, Running::No);
+
+                    self.handle(&message)?;
+
+                    if was_stopped && !matches!(self.running, Running::No) {
+                        next_frame = Instant::now();
+                    }
+                }
+                None => {
+                    self.run_frame()?;
+
+                    next_frame += FRAME;
+
+                    if next_frame < Instant::now() {
+                        next_frame = Instant::now();
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn run_frame(&mut self) -> io::Result<()> {
+        let Some(debugger) = &mut self.debugger else {
+            self.running = Running::No;
+            return Ok(());
+        };
+
+        let stop = match self.running {
+            Running::ToDepth(depth) => debugger.run_frame_until(|cpu| cpu.get_stack_pointer() <= depth),
+            _ => debugger.run_frame(),
+        };
+
+        if let Some(stop) = stop {
+            self.running = Running::No;
+            self.send_stopped(&stop)?;
+        }
+
+        Ok(())
+    }
+
+    fn handle(&mut self, message: &Value) -> io::Result<()> {
+        if message["type"] != "request" {
+            return Ok(());
+        }
+
+        let command = message["command"].as_str().unwrap_or_default();
+        let args = &message["arguments"];
+
+        let result = match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsInstructionBreakpoints": true,
+                "supportsReadMemoryRequest": true,
+                "supportsDisassembleRequest": true,
+                "supportsEvaluateForHovers": true,
+                "supportsTerminateRequest": true,
+            })),
+            "launch" => self.launch(args),
+            "setBreakpoints" => self.set_breakpoints(args),
+            "setInstructionBreakpoints" => self.set_instruction_breakpoints(args),
+            "setExceptionBreakpoints" => Ok(json!({})),
+            "configurationDone" => Ok(json!({})),
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "CHIP-8" }] })),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => Ok(json!({ "scopes": [
+                { "name": "Registers", "variablesReference": REGISTERS, "expensive": false },
+                { "name": "Timers", "variablesReference": TIMERS, "expensive": false },
+            ]})),
+            "variables" => self.variables(args),
+            "evaluate" => self.evaluate(args),
+            "readMemory" => self.read_memory(args),
+            "disassemble" => self.disassemble(args),
+            "continue" => self.resume(Running::Continue).map(|_| json!({ "allThreadsContinued": true })),
+            "next" | "stepIn" | "stepOut" => self.step(command),
+            "pause" => Ok(json!({})),
+            "terminate" | "disconnect" => Ok(json!({})),
+            _ => Err(format!("Unsupported request ({})", command)),
+        };
+
+        let succeeded = result.is_ok();
+
+        let response = match result {
+            Ok(body) => json!({
+                "type": "response",
+                "request_seq": message["seq"],
+                "command": command,
+                "success": true,
+                "body": body,
+            }),
+            Err(err) => json!({
+                "type": "response",
+                "request_seq": message["seq"],
+                "command": command,
+                "success": false,
+                "message": err,
+            }),
+        };
+
+        self.send(response)?;
+
+        // Events which have to follow the response
+        match command {
+            "launch" if succeeded => self.send_event("initialized", json!({})),
+            "configurationDone" if self.stop_on_entry => self.send_event("stopped", json!({
+                "reason": "entry",
+                "threadId": THREAD_ID,
+                "allThreadsStopped": true,
+            })),
+            "configurationDone" => {
+                self.running = Running::Continue;
+                Ok(())
+            }
+            "next" | "stepIn" | "stepOut" => match self.stepped.take() {
+                Some(stop) => self.send_stopped(&stop),
+                None => Ok(()),
+            },
+            "pause" if !matches!(self.running, Running::No) => {
+                self.running = Running::No;
+                self.send_event("stopped", json!({
+                    "reason": "pause",
+                    "threadId": THREAD_ID,
+                    "allThreadsStopped": true,
+                }))
+            }
+            "terminate" => {
+                self.running = Running::No;
+                self.send_event("terminated", json!({}))
+            }
+            "disconnect" => {
+                self.terminated = true;
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Loads the ROM given as "program". Octo sources (.8o) are assembled, which also maps their
+    // lines to addresses; otherwise "symbols" can name a map in the format of SymbolMap.
+    fn launch(&mut self, args: &Value) -> Result<Value, String> {
+        let program = args["program"].as_str().ok_or("Missing \"program\" to debug")?;
+        let path = Path::new(program);
+        let directory = path.parent().unwrap_or(Path::new("")).to_path_buf();
+
+        let rom = if path.extension().is_some_and(|ext| ext == "8o") {
+            let source = fs::read_to_string(path)
+                .map_err(|err| format!("Failed to read {}: {}", path.display(), err))?;
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let (data, symbols) = octo::assemble_with_symbols(&source, &name)
+                .map_err(|err| format!("Failed to assemble {}: {}", path.display(), err))?;
+
+            self.symbols = symbols;
+            self.base = directory;
+
+            Rom { data, format: Format::OctoSource, profile: None }
+        } else {
+            crate::load_rom(path, &SourceArgs::default())?
+        };
+
+        if let Some(symbols) = args["symbols"].as_str() {
+            let path = Path::new(symbols);
+            let text = fs::read_to_string(path)
+                .map_err(|err| format!("Failed to read symbols {}: {}", path.display(), err))?;
+
+            self.symbols = SymbolMap::parse(&text).map_err(|err| format!("{}: {}", path.display(), err))?;
+            self.base = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        }
+
+        let settings = crate::load_settings(self.emulator, &rom)?;
+
+        self.debugger = Some(Debugger::new(settings.create_cpu(&rom.data)?, settings.speed));
+        self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+
+        Ok(json!({}))
+    }
+
+    fn get_debugger(&self) -> Result<&Debugger, String> {
+        self.debugger.as_ref().ok_or_else(|| "No program has been launched".to_string())
+    }
+
+    fn set_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let path = PathBuf::from(args["source"]["path"].as_str().ok_or("Missing source path")?);
+        let matches_file = |file: &str| path.ends_with(file) || self.base.join(file) == path;
+
+        let mut addresses = Vec::new();
+        let mut breakpoints = Vec::new();
+
+        for line in args["breakpoints"].as_array().into_iter().flatten().filter_map(|b| b["line"].as_u64()) {
+            match self.symbols.get_address(matches_file, line as usize) {
+                Some(addr) => {
+                    addresses.push(addr);
+                    breakpoints.push(json!({
+                        "verified": true,
+                        "line": line,
+                        "instructionReference": format!("0x{:03X}", addr),
+                    }));
+                }
+                None => breakpoints.push(json!({
+                    "verified": false,
+                    "line": line,
+                    "message": "No instructions on this line",
+                })),
+            }
+        }
+
+        self.source_breakpoints.insert(path.to_string_lossy().into_owned(), addresses);
+        self.update_breakpoints();
+
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    fn set_instruction_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let mut breakpoints = Vec::new();
+
+        self.instruction_breakpoints.clear();
+
+        for breakpoint in args["breakpoints"].as_array().into_iter().flatten() {
+            let addr = breakpoint["instructionReference"]
+                .as_str()
+                .and_then(parse_address)
+                .map(|addr| addr as i64 + breakpoint["offset"].as_i64().unwrap_or(0))
+                .filter(|addr| (0..MEMORY_SIZE as i64).contains(addr));
+
+            match addr {
+                Some(addr) => {
+                    self.instruction_breakpoints.push(addr as u16);
+                    breakpoints.push(json!({
+                        "verified": true,
+                        "instructionReference": format!("0x{:03X}", addr),
+                    }));
+                }
+                None => breakpoints.push(json!({ "verified": false, "message": "Invalid address" })),
+            }
+        }
+
+        self.update_breakpoints();
+
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    fn update_breakpoints(&mut self) {
+        let Some(debugger) = &mut self.debugger else {
+            return;
+        };
+
+        debugger.clear_breakpoints();
+
+        for addr in self.source_breakpoints.values().flatten().chain(&self.instruction_breakpoints) {
+            debugger.add_breakpoint(*addr);
+        }
+    }
+
+    // The frames are the current instruction and then the calls on the stack, innermost first.
+    // Each is named after the subroutine it is in, which is where the call below it went.
+    fn stack_trace(&self) -> Result<Value, String> {
+        let cpu = self.get_debugger()?.get_cpu();
+        let stack = &cpu.get_stack()[..cpu.get_stack_pointer()];
+
+        let mut frames = Vec::new();
+        let mut pc = cpu.get_program_counter();
+
+        for depth in (0..=stack.len()).rev() {
+            let function = match depth {
+                0 => 0x200,
+                _ => call_target(cpu, stack[depth - 1]),
+            };
+
+            let name = match self.symbols.get_label(function) {
+                Some(label) => label.to_string(),
+                None => disasm::subroutine_name(function),
+            };
+
+            let mut frame = json!({
+                "id": frames.len(),
+                "name": name,
+                "line": 0,
+                "column": 0,
+                "instructionPointerReference": format!("0x{:03X}", pc),
+            });
+
+            if let Some((file, line)) = self.symbols.get_location(pc) {
+                let path = self.base.join(file);
+
+                frame["line"] = json!(line);
+                frame["column"] = json!(1);
+                frame["source"] = json!({
+                    "name": path.file_name().unwrap_or_default().to_string_lossy(),
+                    "path": path.to_string_lossy(),
+                });
+            }
+
+            frames.push(frame);
+
+            if depth > 0 {
+                pc = stack[depth - 1];
+            }
+        }
+
+        Ok(json!({ "stackFrames": frames, "totalFrames": frames.len() }))
+    }
+
+    fn variables(&self, args: &Value) -> Result<Value, String> {
+        let reference = args["variablesReference"].as_u64().unwrap_or_default();
+        let cpu = self.get_debugger()?.get_cpu();
+
+        let variables: Vec<Value> = get_variables(cpu)
+            .into_iter()
+            .filter(|variable| variable.scope == reference)
+            .map(|variable| {
+                let mut value = json!({ "name": variable.name, "value": variable.value, "variablesReference": 0 });
+
+                if let Some(addr) = variable.address {
+                    value["memoryReference"] = json!(format!("0x{:03X}", addr));
+                }
+
+                value
+            })
+            .collect();
+
+        Ok(json!({ "variables": variables }))
+    }
+
+    // Only the names of the registers and timers can be evaluated, i.e. for watches and hovers
+    fn evaluate(&self, args: &Value) -> Result<Value, String> {
+        let expression = args["expression"].as_str().unwrap_or_default().trim();
+        let cpu = self.get_debugger()?.get_cpu();
+
+        let variable = get_variables(cpu)
+            .into_iter()
+            .find(|variable| variable.name.eq_ignore_ascii_case(expression))
+            .ok_or_else(|| format!("Unknown register ({})", expression))?;
+
+        let mut result = json!({ "result": variable.value, "variablesReference": 0 });
+
+        if let Some(addr) = variable.address {
+            result["memoryReference"] = json!(format!("0x{:03X}", addr));
+        }
+
+        Ok(result)
+    }
+
+    fn read_memory(&self, args: &Value) -> Result<Value, String> {
+        let start = args["memoryReference"].as_str().and_then(parse_address).ok_or("Invalid memory reference")?;
+        let start = (start as i64).saturating_add(args["offset"].as_i64().unwrap_or(0));
+        let count = args["count"].as_u64().unwrap_or(0).min(i64::MAX as u64) as i64;
+        let memory = self.get_debugger()?.get_cpu().get_memory();
+
+        let first = start.clamp(0, MEMORY_SIZE as i64);
+        let end = start.saturating_add(count).clamp(first, MEMORY_SIZE as i64);
+        let data = &memory[first as usize..end as usize];
+
+        Ok(json!({
+            "address": format!("0x{:03X}", first),
+            "data": base64(data),
+            "unreadableBytes": count - data.len() as i64,
+        }))
+    }
+
+    // Decodes instructions at 2 byte steps around an address, marking the ones outside of memory.
+    // No more instructions are decoded than fit in memory.
+    fn disassemble(&self, args: &Value) -> Result<Value, String> {
+        let start = args["memoryReference"].as_str().and_then(parse_address).ok_or("Invalid memory reference")?;
+        let start = (start as i64)
+            .saturating_add(args["offset"].as_i64().unwrap_or(0))
+            .saturating_add(args["instructionOffset"].as_i64().unwrap_or(0).saturating_mul(2));
+        let count = args["instructionCount"].as_u64().unwrap_or(0).min(MEMORY_SIZE as u64 / 2) as i64;
+        let memory = self.get_debugger()?.get_cpu().get_memory();
+
+        let mut instructions = Vec::new();
+
+        for addr in (start..start.saturating_add(count * 2)).step_by(2) {
+            if !(0..MEMORY_SIZE as i64 - 1).contains(&addr) {
+                instructions.push(json!({
+                    "address": format!("0x{:03X}", addr.max(0)),
+                    "instruction": "",
+                    "presentationHint": "invalid",
+                }));
+                continue;
+            }
+
+            let opcode = (memory[addr as usize] as u16) << 8 | memory[addr as usize + 1] as u16;
+            let mut instruction = json!({
+                "address": format!("0x{:03X}", addr),
+                "instructionBytes": format!("{:04X}", opcode),
+                "instruction": disasm::disassemble(opcode).unwrap_or_else(|| "???".to_string()),
+            });
+
+            if let Some(label) = self.symbols.get_label(addr as u16) {
+                instruction["symbol"] = json!(label);
+            }
+
+            if let Some((file, line)) = self.symbols.get_location(addr as u16) {
+                instruction["line"] = json!(line);
+                instruction["location"] = json!({ "path": self.base.join(file).to_string_lossy() });
+            }
+
+            instructions.push(instruction);
+        }
+
+        Ok(json!({ "instructions": instructions }))
+    }
+
+    fn resume(&mut self, running: Running) -> Result<(), String> {
+        self.get_debugger()?;
+        self.running = running;
+
+        Ok(())
+    }
+
+    // Steps a single instruction. Stepping over a call (2NNN) or out of a subroutine runs until the
+    // stack is back to the depth it should be at.
+    fn step(&mut self, command: &str) -> Result<Value, String> {
+        let cpu = self.get_debugger()?.get_cpu();
+        let depth = cpu.get_stack_pointer();
+        let is_call = trace::fetch(cpu, cpu.get_program_counter()) & 0xF000 == 0x2000;
+
+        match (command, &mut self.debugger) {
+            ("stepOut", _) if depth > 0 => self.running = Running::ToDepth(depth - 1),
+            ("next", _) if is_call => self.running = Running::ToDepth(depth),
+            (_, Some(debugger)) => self.stepped = Some(debugger.step().unwrap_or(Stop::Finished)),
+            (_, None) => (),
+        }
+
+        Ok(json!({}))
+    }
+
+    fn send_stopped(&mut self, stop: &Stop) -> io::Result<()> {
+        let reason = match stop {
+            Stop::Breakpoint(_) => "breakpoint",
+            Stop::Finished => "step",
+            Stop::Watchpoint(..) => "data breakpoint",
+            Stop::Error(_) => "exception",
+        };
+
+        self.send_event("stopped", json!({
+            "reason": reason,
+            "description": stop.to_string(),
+            "text": stop.to_string(),
+            "threadId": THREAD_ID,
+            "allThreadsStopped": true,
+        }))
+    }
+
+    fn send_event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+
+        let body = message.to_string();
+
+        write!(self.output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+        self.output.flush()
+    }
+}
+
+struct Variable {
+    scope: u64,
+    name: String,
+    value: String,
+    // Registers holding addresses can be opened in the memory view
+    address: Option<u16>,
+}
+
+fn get_variables(cpu: &Chip8) -> Vec<Variable> {
+    let variable = |scope, name: String, value: String, address| Variable { scope, name, value, address };
+
+    let mut variables: Vec<Variable> = cpu
+        .get_registers()
+        .iter()
+        .enumerate()
+        .map(|(x, v)| variable(REGISTERS, format!("V{:X}", x), format!("0x{:02X}", v), None))
+        .collect();
+
+    let index = cpu.get_program_index();
+    let pc = cpu.get_program_counter();
+
+    variables.extend([
+        variable(REGISTERS, "I".to_string(), format!("0x{:03X}", index), Some(index)),
+        variable(REGISTERS, "PC".to_string(), format!("0x{:03X}", pc), Some(pc)),
+        variable(REGISTERS, "SP".to_string(), cpu.get_stack_pointer().to_string(), None),
+        variable(TIMERS, "DT".to_string(), cpu.get_delay_timer().to_string(), None),
+        variable(TIMERS, "ST".to_string(), cpu.get_sound_timer().to_string(), None),
+    ]);
+
+    variables
+}
+
+// Where the call at an address on the stack went
+fn call_target(cpu: &Chip8, call: u16) -> u16 {
+    trace::fetch(cpu, call) & 0x0FFF
+}
+
+fn parse_address(s: &str) -> Option<u16> {
+    let s = s.trim();
+    let digits = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X"));
+
+    match digits {
+        Some(digits) => u16::from_str_radix(digits, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::new();
+
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let bits = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(bits >> (18 - i * 6)) as usize & 0x3F] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(emulator: &EmulatorArgs) -> Session<'_> {
+        let mut session = Session::new(Box::new(io::sink()), emulator);
+
+        session.debugger = Some(Debugger::new(Chip8::new(&[0x00, 0xE0]).unwrap(), 10));
+        session
+    }
+
+    #[test]
+    fn huge_memory_requests_are_cut_short() {
+        let emulator = EmulatorArgs::default();
+        let session = session(&emulator);
+
+        let memory = session
+            .read_memory(&json!({ "memoryReference": "0x200", "offset": i64::MAX, "count": u64::MAX }))
+            .unwrap();
+        assert_eq!(memory["address"], "0x1000");
+        assert_eq!(memory["unreadableBytes"], i64::MAX);
+
+        let args = json!({ "memoryReference": "0x200", "instructionOffset": i64::MIN, "instructionCount": u64::MAX });
+        let instructions = session.disassemble(&args).unwrap();
+        assert_eq!(instructions["instructions"].as_array().unwrap().len(), MEMORY_SIZE / 2);
+    }
+}
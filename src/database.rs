@@ -0,0 +1,291 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::chip8::{self, Quirks};
+
+// An offline ROM database using the schema of the community chip-8-database
+// (https://github.com/chip-8/chip-8-database). ROMs are identified by the SHA-1 of their contents.
+pub struct Database {
+    programs: Vec<Program>,
+    hashes: HashMap<String, usize>,
+    platforms: Vec<Platform>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Program {
+    pub title: String,
+    pub description: Option<String>,
+    pub release: Option<String>,
+    pub authors: Vec<String>,
+    pub roms: HashMap<String, RomInfo>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, rename_all = "camelCase")]
+pub struct RomInfo {
+    pub file: Option<String>,
+    pub embedded_title: Option<String>,
+    pub description: Option<String>,
+    // Platforms the ROM runs on, in order of preference
+    pub platforms: Vec<String>,
+    // Quirks which differ from the platform's defaults when running on it
+    pub quirky_platforms: HashMap<String, PlatformQuirks>,
+    pub tickrate: Option<u32>,
+    pub start_address: Option<u16>,
+    pub keys: HashMap<String, u8>,
+    pub colors: Option<Colors>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Colors {
+    pub pixels: Vec<String>,
+    pub buzzer: Option<String>,
+    pub silence: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Platform {
+    pub id: String,
+    pub name: String,
+    pub default_tickrate: Option<u32>,
+    pub quirks: PlatformQuirks,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+#[serde(default, rename_all = "camelCase")]
+pub struct PlatformQuirks {
+    pub shift: Option<bool>,
+    pub memory_increment_by_x: Option<bool>,
+    pub memory_leave_i_unchanged: Option<bool>,
+    pub wrap: Option<bool>,
+    pub jump: Option<bool>,
+    pub vblank: Option<bool>,
+    pub logic: Option<bool>,
+}
+
+impl PlatformQuirks {
+    fn apply(&self, quirks: &mut Quirks) {
+        let flags = [
+            (&mut quirks.shift, self.shift),
+            (&mut quirks.load_store, self.memory_leave_i_unchanged),
+            (&mut quirks.increment_by_x, self.memory_increment_by_x),
+            (&mut quirks.wrap, self.wrap),
+            (&mut quirks.jump, self.jump),
+            (&mut quirks.display_wait, self.vblank),
+            (&mut quirks.vf_reset, self.logic),
+        ];
+
+        for (flag, value) in flags {
+            if let Some(value) = value {
+                *flag = value;
+            }
+        }
+    }
+}
+
+// The settings recommended by the database for a specific ROM
+#[derive(Debug, Clone)]
+pub struct RomProfile {
+    pub title: String,
+    pub platform: Option<String>,
+    pub quirks: Quirks,
+    pub tickrate: Option<u32>,
+    pub palette: Option<[u32; 2]>,
+    pub keys: HashMap<String, u8>,
+}
+
+// Platforms whose programs only use instructions which the interpreter understands
+pub const SUPPORTED_PLATFORMS: [&str; 3] = ["originalChip8", "modernChip8", "chip48"];
+
+impl Database {
+    pub fn new() -> Self {
+        Database {
+            programs: Vec::new(),
+            hashes: HashMap::new(),
+            platforms: Vec::new(),
+        }
+    }
+
+    // The database which is compiled into the binary
+    pub fn bundled() -> Self {
+        let mut db = Database::new();
+
+        db.add_programs_json(include_str!("../db/programs.json"))
+            .expect("Bundled programs.json is invalid");
+        db.add_platforms_json(include_str!("../db/platforms.json"))
+            .expect("Bundled platforms.json is invalid");
+
+        db
+    }
+
+    // Merges the programs.json and platforms.json files in a directory into the database, if
+    // they exist. Entries loaded later take priority over earlier ones.
+    pub fn load_dir(&mut self, dir: &Path) -> Result<(), String> {
+        if let Some(json) = read_optional(&dir.join("programs.json"))? {
+            self.add_programs_json(&json)?;
+        }
+
+        if let Some(json) = read_optional(&dir.join("platforms.json"))? {
+            self.add_platforms_json(&json)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn add_programs_json(&mut self, json: &str) -> Result<(), String> {
+        let programs: Vec<Program> = serde_json::from_str(json)
+            .map_err(|err| format!("Failed to parse program list: {}", err))?;
+
+        for program in programs {
+            let index = self.programs.len();
+
+            for hash in program.roms.keys() {
+                self.hashes.insert(hash.to_ascii_lowercase(), index);
+            }
+
+            self.programs.push(program);
+        }
+
+        Ok(())
+    }
+
+    pub fn add_platforms_json(&mut self, json: &str) -> Result<(), String> {
+        let platforms: Vec<Platform> = serde_json::from_str(json)
+            .map_err(|err| format!("Failed to parse platform list: {}", err))?;
+
+        for platform in platforms {
+            self.platforms.retain(|p| p.id != platform.id);
+            self.platforms.push(platform);
+        }
+
+        Ok(())
+    }
+
+    pub fn lookup(&self, hash: &str) -> Option<(&Program, &RomInfo)> {
+        let program = &self.programs[*self.hashes.get(hash)?];
+        let rom = program.roms
+            .iter()
+            .find(|(h, _)| h.eq_ignore_ascii_case(hash))
+            .map(|(_, rom)| rom)?;
+
+        Some((program, rom))
+    }
+
+    pub fn get_platform(&self, id: &str) -> Option<&Platform> {
+        self.platforms.iter().find(|p| p.id == id)
+    }
+
+    // Resolves the settings for a ROM by combining its entry with the defaults of its preferred
+    // platform
+    pub fn get_profile(&self, hash: &str) -> Option<RomProfile> {
+        let (program, rom) = self.lookup(hash)?;
+
+        let platform_id = rom.platforms.first();
+        let platform = platform_id.and_then(|id| self.get_platform(id));
+
+        let mut quirks = Quirks::default();
+
+        if let Some(platform) = platform {
+            platform.quirks.apply(&mut quirks);
+        }
+
+        if let Some(overrides) = platform_id.and_then(|id| rom.quirky_platforms.get(id)) {
+            overrides.apply(&mut quirks);
+        }
+
+        let palette = rom.colors
+            .as_ref()
+            .filter(|colors| colors.pixels.len() >= 2)
+            .and_then(|colors| chip8::parse_palette(&colors.pixels[..2].join(",")).ok());
+
+        let title = rom.embedded_title
+            .clone()
+            .filter(|_| program.title.is_empty())
+            .unwrap_or_else(|| program.title.clone());
+
+        Some(RomProfile {
+            title,
+            platform: platform_id.cloned(),
+            quirks,
+            tickrate: rom.tickrate.or_else(|| platform.and_then(|p| p.default_tickrate)),
+            palette,
+            keys: rom.keys.clone(),
+        })
+    }
+}
+
+impl Default for Database {
+    fn default() -> Self {
+        Database::new()
+    }
+}
+
+fn read_optional(path: &Path) -> Result<Option<String>, String> {
+    match fs::read_to_string(path) {
+        Ok(text) => Ok(Some(text)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(format!("Failed to read {}: {}", path.display(), err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom;
+
+    #[test]
+    fn bundled_roms_run_on_known_platforms() {
+        let db = Database::bundled();
+
+        for program in &db.programs {
+            for (hash, info) in &program.roms {
+                assert!(db.get_profile(hash).is_some(), "{} ({})", program.title, hash);
+
+                for platform in &info.platforms {
+                    assert!(db.get_platform(platform).is_some(), "{} runs on {}", program.title, platform);
+                }
+            }
+        }
+
+        for platform in SUPPORTED_PLATFORMS {
+            assert!(db.get_platform(platform).is_some(), "{}", platform);
+        }
+    }
+
+    #[test]
+    fn roms_are_found_by_their_hash() {
+        let rom = [0x00, 0xE0, 0x12, 0x02];
+        let mut db = Database::bundled();
+
+        let programs = format!(r##"[{{
+            "title": "Clear",
+            "roms": {{
+                "{}": {{
+                    "platforms": ["originalChip8"],
+                    "quirkyPlatforms": {{ "originalChip8": {{ "shift": true }} }},
+                    "tickrate": 20,
+                    "colors": {{ "pixels": ["#000000", "#FF8000"] }}
+                }}
+            }}
+        }}]"##, rom::hash(&rom).to_ascii_uppercase());
+
+        db.add_programs_json(&programs).unwrap();
+
+        let profile = db.get_profile(&rom::hash(&rom)).unwrap();
+
+        assert_eq!(profile.title, "Clear");
+        assert_eq!(profile.platform.as_deref(), Some("originalChip8"));
+        assert!(profile.quirks.shift && profile.quirks.display_wait && profile.quirks.vf_reset);
+        assert!(!profile.quirks.jump);
+        assert_eq!(profile.tickrate, Some(20));
+        assert_eq!(profile.palette, Some([0x000000, 0xFF8000]));
+        assert!(db.get_profile(&rom::hash(&rom[..2])).is_none());
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::BTreeSet;
+use std::fmt;
+
+use crate::chip8::Chip8;
+use crate::trace;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchKind {
+    Write,
+    Read,
+    // Either reads or writes
+    Access,
+}
+
+// A range of memory where accessing it stops the program
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub kind: WatchKind,
+    pub addr: u16,
+    pub len: u16,
+}
+
+// Why the program stopped
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Stop {
+    Breakpoint(u16),
+    // The condition it was run until became true, i.e. when stepping over a call
+    Finished,
+    // The watchpoint and the address which was accessed, as either a read or a write
+    Watchpoint(Watchpoint, u16, WatchKind),
+    // The interpreter couldn't execute the instruction, which is left unexecuted
+    Error(String),
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stop::Breakpoint(addr) => write!(f, "Breakpoint at {:03X}", addr),
+            Stop::Finished => write!(f, "Stepped"),
+            Stop::Watchpoint(_, addr, WatchKind::Write) => write!(f, "Write to {:03X}", addr),
+            Stop::Watchpoint(_, addr, _) => write!(f, "Read from {:03X}", addr),
+            Stop::Error(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+// The memory an instruction is going to read or write, besides fetching itself, as the kind of
+// access, the first address and the number of bytes
+pub fn memory_access(cpu: &Chip8, opcode: u16) -> Option<(WatchKind, u16, u16)> {
+    let index = cpu.get_program_index();
+    let x = (opcode & 0x0F00) >> 8;
+
+    match (opcode & 0xF000, opcode & 0x00FF) {
+        (0xD000, _) => Some((WatchKind::Read, index, opcode & 0x000F)),
+        (0xF000, 0x33) => Some((WatchKind::Write, index, 3)),
+        (0xF000, 0x55) => Some((WatchKind::Write, index, x + 1)),
+        (0xF000, 0x65) => Some((WatchKind::Read, index, x + 1)),
+        _ => None,
+    }
+}
+
+// Runs a machine under the control of a debugger, with breakpoints on instructions and watchpoints
+// on memory. Timers tick after every frame's worth of instructions, so that they keep in step
+// with the program however it is stepped through.
+pub struct Debugger {
+    cpu: Chip8,
+    speed: u32,
+    breakpoints: BTreeSet<u16>,
+    watchpoints: Vec<Watchpoint>,
+    executed: u64,
+}
+
+impl Debugger {
+    pub fn new(cpu: Chip8, speed: u32) -> Self {
+        Debugger {
+            cpu,
+            speed: speed.max(1),
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+            executed: 0,
+        }
+    }
+
+    pub fn get_cpu(&self) -> &Chip8 {
+        &self.cpu
+    }
+
+    pub fn get_cpu_mut(&mut self) -> &mut Chip8 {
+        &mut self.cpu
+    }
+
+    pub fn get_speed(&self) -> u32 {
+        self.speed
+    }
+
+    // Number of instructions executed so far
+    pub fn get_executed(&self) -> u64 {
+        self.executed
+    }
+
+    pub fn get_breakpoints(&self) -> &BTreeSet<u16> {
+        &self.breakpoints
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.remove(&addr);
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn get_watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        if !self.watchpoints.contains(&watchpoint) {
+            self.watchpoints.push(watchpoint);
+        }
+    }
+
+    pub fn remove_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.retain(|w| *w != watchpoint);
+    }
+
+    // Executes a single instruction, returning why to stop if it hit a watchpoint or failed
+    pub fn step(&mut self) -> Option<Stop> {
+        let opcode = trace::fetch(&self.cpu, self.cpu.get_program_counter());
+        let access = memory_access(&self.cpu, opcode);
+
+        if let Err(err) = self.cpu.step() {
+            return Some(Stop::Error(err));
+        }
+
+        self.executed += 1;
+
+        if self.executed.is_multiple_of(self.speed as u64) {
+            self.cpu.tick_timers();
+        }
+
+        let (kind, start, len) = access?;
+
+        // The ends are worked out in usize, as a range can end past the last 16-bit address
+        let end = |addr: u16, len: u16| addr as usize + len as usize;
+
+        self.watchpoints.iter().find_map(|watchpoint| {
+            let matches = watchpoint.kind == kind || watchpoint.kind == WatchKind::Access;
+            let first = start.max(watchpoint.addr);
+
+            (matches && (first as usize) < end(start, len).min(end(watchpoint.addr, watchpoint.len)))
+                .then_some(Stop::Watchpoint(*watchpoint, first, kind))
+        })
+    }
+
+    // Runs until the end of the current frame, stopping early at breakpoints and watchpoints. The
+    // instruction at the PC is executed even if it has a breakpoint, so that running carries on
+    // from one.
+    pub fn run_frame(&mut self) -> Option<Stop> {
+        self.run_frame_until(|_| false)
+    }
+
+    // Like run_frame, but also stops once the condition holds after an instruction
+    pub fn run_frame_until(&mut self, done: impl Fn(&Chip8) -> bool) -> Option<Stop> {
+        loop {
+            if let Some(stop) = self.step() {
+                return Some(stop);
+            }
+
+            if done(&self.cpu) {
+                return Some(Stop::Finished);
+            }
+
+            let pc = self.cpu.get_program_counter();
+
+            if self.breakpoints.contains(&pc) {
+                return Some(Stop::Breakpoint(pc));
+            }
+
+            if self.executed.is_multiple_of(self.speed as u64) {
+                return None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watchpoints_reaching_past_memory() {
+        // I = FFE, store V0 and V1 at the last two bytes of memory
+        let cpu = Chip8::new(&[0xAF, 0xFE, 0xF1, 0x55]).unwrap();
+        let mut debugger = Debugger::new(cpu, 10);
+        let watchpoint = Watchpoint { kind: WatchKind::Write, addr: 0xFFF, len: u16::MAX };
+
+        debugger.add_watchpoint(watchpoint);
+
+        assert_eq!(debugger.step(), None);
+        assert_eq!(debugger.step(), Some(Stop::Watchpoint(watchpoint, 0xFFF, WatchKind::Write)));
+    }
+}
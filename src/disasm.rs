@@ -0,0 +1,129 @@
+// Disassembles a single instruction into the mnemonics popularised by Cowgod's technical reference.
+// Returns None for words which don't decode to any instruction known to the interpreter.
+pub fn disassemble(opcode: u16) -> Option<String> {
+    let x = (opcode & 0x0F00) >> 8;
+    let y = (opcode & 0x00F0) >> 4;
+    let n = opcode & 0x000F;
+    let nn = opcode & 0x00FF;
+    let nnn = opcode & 0x0FFF;
+
+    let text = match opcode & 0xF000 {
+        0x0000 => match nnn {
+            0x00E0 => "CLS".to_string(),
+            0x00EE => "RET".to_string(),
+            _ => format!("SYS {:03X}", nnn),
+        },
+        0x1000 => format!("JP {:03X}", nnn),
+        0x2000 => format!("CALL {:03X}", nnn),
+        0x3000 => format!("SE V{:X}, {:02X}", x, nn),
+        0x4000 => format!("SNE V{:X}, {:02X}", x, nn),
+        0x5000 => format!("SE V{:X}, V{:X}", x, y),
+        0x6000 => format!("LD V{:X}, {:02X}", x, nn),
+        0x7000 => format!("ADD V{:X}, {:02X}", x, nn),
+        0x8000 => {
+            let mnemonic = match n {
+                0x0 => "LD",
+                0x1 => "OR",
+                0x2 => "AND",
+                0x3 => "XOR",
+                0x4 => "ADD",
+                0x5 => "SUB",
+                0x6 => "SHR",
+                0x7 => "SUBN",
+                0xE => "SHL",
+                _ => return None,
+            };
+
+            format!("{} V{:X}, V{:X}", mnemonic, x, y)
+        }
+        0x9000 => format!("SNE V{:X}, V{:X}", x, y),
+        0xA000 => format!("LD I, {:03X}", nnn),
+        0xB000 => format!("JP V0, {:03X}", nnn),
+        0xC000 => format!("RND V{:X}, {:02X}", x, nn),
+        0xD000 => format!("DRW V{:X}, V{:X}, {:X}", x, y, n),
+        0xE000 => match nn {
+            0x9E => format!("SKP V{:X}", x),
+            0xA1 => format!("SKNP V{:X}", x),
+            _ => return None,
+        },
+        0xF000 => match nn {
+            0x07 => format!("LD V{:X}, DT", x),
+            0x0A => format!("LD V{:X}, K", x),
+            0x15 => format!("LD DT, V{:X}", x),
+            0x18 => format!("LD ST, V{:X}", x),
+            0x1E => format!("ADD I, V{:X}", x),
+            0x29 => format!("LD F, V{:X}", x),
+            0x33 => format!("LD B, V{:X}", x),
+            0x55 => format!("LD [I], V{:X}", x),
+            0x65 => format!("LD V{:X}, [I]", x),
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    Some(text)
+}
+
+// What reports call the subroutine at an address, the program itself being main
+pub fn subroutine_name(addr: u16) -> String {
+    if addr == 0x200 {
+        "main".to_string()
+    } else {
+        format!("sub_{:03X}", addr)
+    }
+}
+
+// The pattern of an instruction as written in the usual opcode tables, i.e. "8XY4" or "DXYN", which
+// is what instructions are grouped by. Returns None for the same words as disassemble.
+pub fn pattern(opcode: u16) -> Option<&'static str> {
+    let pattern = match opcode & 0xF000 {
+        0x0000 => match opcode & 0x0FFF {
+            0x00E0 => "00E0",
+            0x00EE => "00EE",
+            _ => "0NNN",
+        },
+        0x1000 => "1NNN",
+        0x2000 => "2NNN",
+        0x3000 => "3XNN",
+        0x4000 => "4XNN",
+        0x5000 => "5XY0",
+        0x6000 => "6XNN",
+        0x7000 => "7XNN",
+        0x8000 => match opcode & 0x000F {
+            0x0 => "8XY0",
+            0x1 => "8XY1",
+            0x2 => "8XY2",
+            0x3 => "8XY3",
+            0x4 => "8XY4",
+            0x5 => "8XY5",
+            0x6 => "8XY6",
+            0x7 => "8XY7",
+            0xE => "8XYE",
+            _ => return None,
+        },
+        0x9000 => "9XY0",
+        0xA000 => "ANNN",
+        0xB000 => "BNNN",
+        0xC000 => "CXNN",
+        0xD000 => "DXYN",
+        0xE000 => match opcode & 0x00FF {
+            0x9E => "EX9E",
+            0xA1 => "EXA1",
+            _ => return None,
+        },
+        _ => match opcode & 0x00FF {
+            0x07 => "FX07",
+            0x0A => "FX0A",
+            0x15 => "FX15",
+            0x18 => "FX18",
+            0x1E => "FX1E",
+            0x29 => "FX29",
+            0x33 => "FX33",
+            0x55 => "FX55",
+            0x65 => "FX65",
+            _ => return None,
+        },
+    };
+
+    Some(pattern)
+}
@@ -0,0 +1,344 @@
+use std::str::FromStr;
+
+// Blurring further than this only costs time, as the glow is spread too thin to see
+const MAX_BLOOM_RADIUS: usize = 32;
+
+// Software post-processing applied to the framebuffer after it has been scaled up to the output
+// resolution. Everything here runs on the CPU so that it works without any GPU support.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Filter {
+    // Darkens the lower half of every emulated pixel row
+    Scanlines { intensity: f32 },
+    // Leaves a dark gap of the given width (in output pixels) between emulated pixels
+    Grid { gap: usize },
+    // Cuts the corners off each emulated pixel
+    Rounded { radius: f32 },
+    // Adds a blurred copy of the image on top of itself
+    Bloom { radius: usize, strength: f32 },
+    // Bends the image as if it were displayed on a curved tube
+    Barrel { amount: f32 },
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = match s.split_once('=') {
+            Some((name, value)) => (name.trim(), Some(value.trim())),
+            None => (s.trim(), None),
+        };
+
+        match name {
+            "scanlines" => Ok(Filter::Scanlines {
+                intensity: parse_value(name, value, 0.5)?,
+            }),
+            "grid" => Ok(Filter::Grid {
+                gap: parse_value(name, value, 1)?,
+            }),
+            "rounded" => Ok(Filter::Rounded {
+                radius: parse_value(name, value, 0.5)?,
+            }),
+            // bloom=STRENGTH:RADIUS, where either may be left out
+            "bloom" => {
+                let (strength, radius) = match value.and_then(|value| value.split_once(':')) {
+                    Some((strength, radius)) => {
+                        (Some(strength).filter(|s| !s.is_empty()), Some(radius).filter(|s| !s.is_empty()))
+                    }
+                    None => (value, None),
+                };
+                let radius = parse_value(name, radius, 4)?;
+
+                if radius > MAX_BLOOM_RADIUS {
+                    return Err(format!("Bloom radius must be at most {} ({})", MAX_BLOOM_RADIUS, radius));
+                }
+
+                Ok(Filter::Bloom {
+                    radius,
+                    strength: parse_value(name, strength, 0.6)?,
+                })
+            }
+            "barrel" => Ok(Filter::Barrel {
+                amount: parse_value(name, value, 0.1)?,
+            }),
+            _ => Err(format!("Unknown filter ({})", name)),
+        }
+    }
+}
+
+fn parse_value<T: FromStr>(name: &str, value: Option<&str>, default: T) -> Result<T, String> {
+    match value {
+        Some(value) => value
+            .parse()
+            .map_err(|_| format!("Invalid value for filter {} ({})", name, value)),
+        None => Ok(default),
+    }
+}
+
+// Parses a comma-separated list of filters, i.e. "scanlines=0.4,bloom"
+pub fn parse_chain(spec: &str) -> Result<Vec<Filter>, String> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(Filter::from_str)
+        .collect()
+}
+
+pub struct FilterChain {
+    filters: Vec<Filter>,
+    scale: usize,
+    width: usize,
+    height: usize,
+    output: Vec<u32>,
+    scratch: Vec<u32>,
+    glow: Vec<u32>,
+}
+
+impl FilterChain {
+    pub fn new(filters: Vec<Filter>, src_width: usize, src_height: usize, scale: usize) -> Self {
+        let width = src_width * scale;
+        let height = src_height * scale;
+
+        FilterChain {
+            filters,
+            scale,
+            width,
+            height,
+            output: vec![0; width * height],
+            scratch: vec![0; width * height],
+            glow: vec![0; width * height],
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    pub fn get_output_size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    // The source image may be larger than the framebuffer (i.e. when it has been upscaled), but
+    // the filters always work in terms of the emulated pixels
+    pub fn process(&mut self, src: &[u32], src_width: usize, src_height: usize) -> &[u32] {
+        for y in 0..self.height {
+            let row = &src[((y * src_height) / self.height) * src_width..];
+
+            for x in 0..self.width {
+                self.output[x + (y * self.width)] = row[(x * src_width) / self.width];
+            }
+        }
+
+        for filter in &self.filters {
+            match *filter {
+                Filter::Scanlines { intensity } => {
+                    scanlines(&mut self.output, self.width, self.scale, intensity)
+                }
+                Filter::Grid { gap } => {
+                    grid(&mut self.output, self.width, self.scale, gap)
+                }
+                Filter::Rounded { radius } => {
+                    rounded(&mut self.output, self.width, self.scale, radius)
+                }
+                Filter::Bloom { radius, strength } => {
+                    bloom(&mut self.output, &mut self.scratch, &mut self.glow, self.width, self.height, radius, strength)
+                }
+                Filter::Barrel { amount } => {
+                    barrel(&mut self.output, &mut self.scratch, self.width, self.height, amount)
+                }
+            }
+        }
+
+        &self.output
+    }
+}
+
+fn scanlines(buf: &mut [u32], width: usize, scale: usize, intensity: f32) {
+    let factor = 1.0 - intensity.clamp(0.0, 1.0);
+
+    for (y, row) in buf.chunks_exact_mut(width).enumerate() {
+        if (y % scale) < scale.div_ceil(2) {
+            continue;
+        }
+
+        for pixel in row {
+            *pixel = multiply(*pixel, factor);
+        }
+    }
+}
+
+fn grid(buf: &mut [u32], width: usize, scale: usize, gap: usize) {
+    let gap = gap.min(scale - 1);
+
+    for (y, row) in buf.chunks_exact_mut(width).enumerate() {
+        let row_gap = (y % scale) >= scale - gap;
+
+        for (x, pixel) in row.iter_mut().enumerate() {
+            if row_gap || (x % scale) >= scale - gap {
+                *pixel = 0;
+            }
+        }
+    }
+}
+
+fn rounded(buf: &mut [u32], width: usize, scale: usize, radius: f32) {
+    // The radius is given as a fraction of the emulated pixel's half-width
+    let radius = radius.clamp(0.0, 1.0) * (scale as f32 / 2.0);
+    let center = (scale as f32 - 1.0) / 2.0;
+    let inner = center - radius;
+
+    for (y, row) in buf.chunks_exact_mut(width).enumerate() {
+        let dy = ((y % scale) as f32 - center).abs() - inner;
+
+        for (x, pixel) in row.iter_mut().enumerate() {
+            let dx = ((x % scale) as f32 - center).abs() - inner;
+
+            if dx > 0.0 && dy > 0.0 && (dx * dx) + (dy * dy) > radius * radius {
+                *pixel = 0;
+            }
+        }
+    }
+}
+
+fn bloom(buf: &mut [u32], scratch: &mut [u32], glow: &mut [u32], width: usize, height: usize,
+         radius: usize, strength: f32) {
+    box_blur_horizontal(buf, scratch, width, radius);
+    box_blur_vertical(scratch, glow, width, height, radius);
+
+    for (pixel, glow) in buf.iter_mut().zip(glow.iter()) {
+        *pixel = add(*pixel, multiply(*glow, strength));
+    }
+}
+
+fn box_blur_horizontal(src: &[u32], dst: &mut [u32], width: usize, radius: usize) {
+    let window = (radius * 2 + 1) as u32;
+
+    for (src_row, dst_row) in src.chunks_exact(width).zip(dst.chunks_exact_mut(width)) {
+        let mut sum = [0u32; 3];
+
+        for &pixel in &src_row[..=radius.min(width - 1)] {
+            accumulate(&mut sum, pixel, true);
+        }
+
+        for x in 0..width {
+            dst_row[x] = average(&sum, window);
+
+            if x + radius + 1 < width {
+                accumulate(&mut sum, src_row[x + radius + 1], true);
+            }
+
+            if x >= radius {
+                accumulate(&mut sum, src_row[x - radius], false);
+            }
+        }
+    }
+}
+
+fn box_blur_vertical(src: &[u32], dst: &mut [u32], width: usize, height: usize, radius: usize) {
+    let window = (radius * 2 + 1) as u32;
+
+    for x in 0..width {
+        let mut sum = [0u32; 3];
+
+        for y in 0..=radius.min(height - 1) {
+            accumulate(&mut sum, src[x + (y * width)], true);
+        }
+
+        for y in 0..height {
+            dst[x + (y * width)] = average(&sum, window);
+
+            if y + radius + 1 < height {
+                accumulate(&mut sum, src[x + ((y + radius + 1) * width)], true);
+            }
+
+            if y >= radius {
+                accumulate(&mut sum, src[x + ((y - radius) * width)], false);
+            }
+        }
+    }
+}
+
+fn barrel(buf: &mut [u32], scratch: &mut [u32], width: usize, height: usize, amount: f32) {
+    scratch.copy_from_slice(buf);
+
+    let half_w = width as f32 / 2.0;
+    let half_h = height as f32 / 2.0;
+
+    // Scale the result so that the corners stay in place and only the edges are pulled inwards
+    let norm = 1.0 + (amount * 2.0);
+
+    for y in 0..height {
+        let ny = (y as f32 + 0.5 - half_h) / half_h;
+
+        for x in 0..width {
+            let nx = (x as f32 + 0.5 - half_w) / half_w;
+            let distortion = (1.0 + amount * ((nx * nx) + (ny * ny))) / norm;
+
+            let sx = (nx * distortion * half_w) + half_w;
+            let sy = (ny * distortion * half_h) + half_h;
+
+            buf[x + (y * width)] = if sx < 0.0 || sy < 0.0 || sx >= width as f32 || sy >= height as f32 {
+                0
+            } else {
+                scratch[sx as usize + (sy as usize * width)]
+            };
+        }
+    }
+}
+
+fn accumulate(sum: &mut [u32; 3], pixel: u32, add: bool) {
+    let channels = [(pixel >> 16) & 0xFF, (pixel >> 8) & 0xFF, pixel & 0xFF];
+
+    for (s, c) in sum.iter_mut().zip(channels) {
+        if add {
+            *s += c;
+        } else {
+            *s -= c;
+        }
+    }
+}
+
+fn average(sum: &[u32; 3], count: u32) -> u32 {
+    ((sum[0] / count) << 16) | ((sum[1] / count) << 8) | (sum[2] / count)
+}
+
+fn multiply(pixel: u32, factor: f32) -> u32 {
+    let r = (((pixel >> 16) & 0xFF) as f32 * factor) as u32;
+    let g = (((pixel >> 8) & 0xFF) as f32 * factor) as u32;
+    let b = ((pixel & 0xFF) as f32 * factor) as u32;
+
+    (r.min(0xFF) << 16) | (g.min(0xFF) << 8) | b.min(0xFF)
+}
+
+fn add(a: u32, b: u32) -> u32 {
+    let r = (((a >> 16) & 0xFF) + ((b >> 16) & 0xFF)).min(0xFF);
+    let g = (((a >> 8) & 0xFF) + ((b >> 8) & 0xFF)).min(0xFF);
+    let bl = ((a & 0xFF) + (b & 0xFF)).min(0xFF);
+
+    (r << 16) | (g << 8) | bl
+}
+
+pub fn as_bytes(buf: &[u32]) -> &[u8] {
+    let len = buf.len();
+    let ptr = buf.as_ptr() as *const u8;
+
+    unsafe {
+        std::slice::from_raw_parts(ptr, len * 4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bloom_parts_may_be_left_out() {
+        let bloom = |s: &str| s.parse::<Filter>().unwrap();
+
+        assert_eq!(bloom("bloom"), Filter::Bloom { radius: 4, strength: 0.6 });
+        assert_eq!(bloom("bloom=0.3"), Filter::Bloom { radius: 4, strength: 0.3 });
+        assert_eq!(bloom("bloom=0.3:"), Filter::Bloom { radius: 4, strength: 0.3 });
+        assert_eq!(bloom("bloom=:8"), Filter::Bloom { radius: 8, strength: 0.6 });
+        assert_eq!(bloom("bloom=:"), Filter::Bloom { radius: 4, strength: 0.6 });
+        assert!("bloom=0.3:64".parse::<Filter>().is_err());
+    }
+}
@@ -0,0 +1,423 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt;
+use std::io::{self, Write};
+
+use crate::disasm;
+
+// Where programs are loaded and start
+const START: u16 = 0x200;
+
+// How an instruction passes control on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Flow {
+    Next,
+    Jump(u16),
+    Call(u16),
+    Skip,
+    Return,
+    Computed,
+    Invalid,
+}
+
+fn flow(opcode: u16) -> Flow {
+    let nnn = opcode & 0x0FFF;
+
+    match disasm::pattern(opcode) {
+        // 0000 does nothing on this interpreter, other machine code routines aren't supported
+        _ if opcode == 0x0000 => Flow::Next,
+        Some("0NNN") | None => Flow::Invalid,
+        Some("00EE") => Flow::Return,
+        Some("1NNN") => Flow::Jump(nnn),
+        Some("2NNN") => Flow::Call(nnn),
+        Some("3XNN" | "4XNN" | "5XY0" | "9XY0" | "EX9E" | "EXA1") => Flow::Skip,
+        Some("BNNN") => Flow::Computed,
+        _ => Flow::Next,
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EdgeKind {
+    // Carrying on with the next instruction, including when a skip doesn't skip
+    Next,
+    Jump,
+    // A skip skipping the next instruction
+    Skip,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Edge {
+    pub target: u16,
+    pub kind: EdgeKind,
+}
+
+// Control flow which can't be followed without running the program
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Problem {
+    // BNNN, which jumps to NNN plus V0
+    ComputedJump,
+    // FX33 or FX55 writing over instructions, with I set by an ANNN earlier in the same block
+    CodeWrite(u16),
+    // A jump, call or skip to somewhere outside of the ROM
+    OutsideRom(u16),
+    // Running past the last instruction of the ROM
+    EndOfRom,
+    InvalidInstruction,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::ComputedJump => write!(f, "computed jump"),
+            Problem::CodeWrite(addr) => write!(f, "writes over code at {:03X}", addr),
+            Problem::OutsideRom(addr) => write!(f, "continues outside the ROM at {:03X}", addr),
+            Problem::EndOfRom => write!(f, "runs off the end of the ROM"),
+            Problem::InvalidInstruction => write!(f, "invalid instruction"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Unresolved {
+    // The instruction where control flow is lost
+    pub addr: u16,
+    pub opcode: u16,
+    pub problem: Problem,
+}
+
+// A run of instructions which is only ever entered at the start and left at the end, apart from
+// calls which return to it
+#[derive(Clone, Debug)]
+pub struct Block {
+    pub start: u16,
+    pub instructions: Vec<(u16, u16)>,
+    pub edges: Vec<Edge>,
+    pub calls: Vec<u16>,
+    pub unresolved: Vec<Unresolved>,
+    pub returns: bool,
+}
+
+impl Block {
+    // The address of the last instruction
+    pub fn get_last(&self) -> u16 {
+        self.instructions.last().map(|(addr, _)| *addr).unwrap_or(self.start)
+    }
+}
+
+// The program at 0x200 or a subroutine, with the blocks reachable from its entry point without
+// going through calls. Blocks can belong to several, i.e. when subroutines share code.
+#[derive(Clone, Debug)]
+pub struct Function {
+    pub entry: u16,
+    pub blocks: Vec<u16>,
+    pub calls: BTreeSet<u16>,
+}
+
+pub struct FlowGraph {
+    pub blocks: BTreeMap<u16, Block>,
+    pub functions: BTreeMap<u16, Function>,
+}
+
+// Recovers the control flow of a ROM without running it, by following every jump, call, skip and
+// return from where it starts. Anything only known at runtime is reported as unresolved rather than
+// guessed, so the code reached through it is missing from the graph.
+pub fn analyze(rom: &[u8]) -> FlowGraph {
+    let fetch = |addr: u16| {
+        let offset = (addr as usize).checked_sub(START as usize)?;
+        let bytes = rom.get(offset..offset + 2)?;
+
+        Some((bytes[0] as u16) << 8 | bytes[1] as u16)
+    };
+
+    // Find every reachable instruction and where blocks start
+    let mut leaders = BTreeSet::from([START]);
+    let mut entries = BTreeSet::from([START]);
+    let mut code = BTreeSet::new();
+    let mut pending = vec![START];
+
+    while let Some(addr) = pending.pop() {
+        let Some(opcode) = fetch(addr) else {
+            continue;
+        };
+
+        if !code.insert(addr) {
+            continue;
+        }
+
+        let next = addr.wrapping_add(2);
+
+        match flow(opcode) {
+            Flow::Next => pending.push(next),
+            Flow::Jump(target) => {
+                leaders.insert(target);
+                pending.push(target);
+            }
+            Flow::Call(target) => {
+                entries.insert(target);
+                leaders.insert(target);
+                pending.extend([target, next]);
+            }
+            Flow::Skip => {
+                leaders.extend([next, next.wrapping_add(2)]);
+                pending.extend([next, next.wrapping_add(2)]);
+            }
+            Flow::Return | Flow::Computed | Flow::Invalid => (),
+        }
+    }
+
+    let is_code = |addr: u16| code.contains(&addr) || code.contains(&addr.wrapping_sub(1));
+
+    // Split the instructions into blocks
+    let mut blocks = BTreeMap::new();
+
+    for leader in leaders.iter().copied().filter(|addr| code.contains(addr)) {
+        let mut block = Block {
+            start: leader,
+            instructions: Vec::new(),
+            edges: Vec::new(),
+            calls: Vec::new(),
+            unresolved: Vec::new(),
+            returns: false,
+        };
+
+        let mut addr = leader;
+        let mut index = None;
+
+        loop {
+            let opcode = fetch(addr).expect("Blocks only hold reachable instructions");
+            let next = addr.wrapping_add(2);
+
+            let mut problems = Vec::new();
+            let mut edges = Vec::new();
+
+            block.instructions.push((addr, opcode));
+
+            // Follow I through the block to catch writes over the program
+            let x = (opcode & 0x0F00) >> 8;
+
+            match disasm::pattern(opcode) {
+                Some("ANNN") => index = Some(opcode & 0x0FFF),
+                Some("FX33" | "FX55") => {
+                    let len = if opcode & 0x00FF == 0x33 { 3 } else { x + 1 };
+
+                    if let Some(start) = index {
+                        if let Some(target) = (start..start + len).find(|addr| is_code(*addr)) {
+                            problems.push(Problem::CodeWrite(target));
+                        }
+                    }
+
+                    index = None;
+                }
+                Some("FX1E" | "FX29" | "FX65") => index = None,
+                _ => (),
+            }
+
+            let mut end = match flow(opcode) {
+                Flow::Next => false,
+                Flow::Call(target) => {
+                    // The subroutine may well change I
+                    block.calls.push(target);
+                    index = None;
+
+                    if fetch(target).is_none() {
+                        problems.push(Problem::OutsideRom(target));
+                    }
+
+                    false
+                }
+                Flow::Jump(target) => {
+                    edges.push(Edge { target, kind: EdgeKind::Jump });
+                    true
+                }
+                Flow::Skip => {
+                    edges.push(Edge { target: next, kind: EdgeKind::Next });
+                    edges.push(Edge { target: next.wrapping_add(2), kind: EdgeKind::Skip });
+                    true
+                }
+                Flow::Return => {
+                    block.returns = true;
+                    true
+                }
+                Flow::Computed => {
+                    problems.push(Problem::ComputedJump);
+                    true
+                }
+                Flow::Invalid => {
+                    problems.push(Problem::InvalidInstruction);
+                    true
+                }
+            };
+
+            // Blocks also end where another one starts
+            if !end && fetch(next).is_none() {
+                problems.push(Problem::EndOfRom);
+                end = true;
+            } else if !end && leaders.contains(&next) {
+                edges.push(Edge { target: next, kind: EdgeKind::Next });
+                end = true;
+            }
+
+            for edge in edges {
+                match fetch(edge.target) {
+                    Some(_) => block.edges.push(edge),
+                    None => problems.push(Problem::OutsideRom(edge.target)),
+                }
+            }
+
+            block.unresolved.extend(problems.into_iter().map(|problem| Unresolved { addr, opcode, problem }));
+
+            if end {
+                break;
+            }
+
+            addr = next;
+        }
+
+        blocks.insert(leader, block);
+    }
+
+    // Group the blocks into the program and its subroutines
+    let mut functions = BTreeMap::new();
+
+    for entry in entries.into_iter().filter(|addr| blocks.contains_key(addr)) {
+        let mut seen = BTreeSet::from([entry]);
+        let mut queue = VecDeque::from([entry]);
+        let mut calls = BTreeSet::new();
+
+        while let Some(start) = queue.pop_front() {
+            let block = &blocks[&start];
+
+            calls.extend(block.calls.iter().copied());
+
+            for edge in &block.edges {
+                if seen.insert(edge.target) {
+                    queue.push_back(edge.target);
+                }
+            }
+        }
+
+        functions.insert(entry, Function { entry, blocks: seen.into_iter().collect(), calls });
+    }
+
+    FlowGraph { blocks, functions }
+}
+
+impl FlowGraph {
+    pub fn get_unresolved(&self) -> impl Iterator<Item = &Unresolved> {
+        self.blocks.values().flat_map(|block| &block.unresolved)
+    }
+
+    // Lists the subroutines with their blocks and where they lead, then everything unresolved
+    pub fn write_summary(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "{} blocks in {} subroutines, {} unresolved", self.blocks.len(), self.functions.len(),
+                 self.get_unresolved().count())?;
+
+        for function in self.functions.values() {
+            writeln!(out, "\n{} ({:03X})", disasm::subroutine_name(function.entry), function.entry)?;
+
+            if !function.calls.is_empty() {
+                let calls: Vec<String> = function.calls.iter().map(|addr| disasm::subroutine_name(*addr)).collect();
+
+                writeln!(out, "  calls {}", calls.join(", "))?;
+            }
+
+            for block in function.blocks.iter().map(|start| &self.blocks[start]) {
+                let mut exits: Vec<String> = block
+                    .edges
+                    .iter()
+                    .map(|edge| match edge.kind {
+                        EdgeKind::Skip => format!("{:03X} (skip)", edge.target),
+                        _ => format!("{:03X}", edge.target),
+                    })
+                    .collect();
+
+                if block.returns {
+                    exits.push("return".to_string());
+                }
+
+                if !block.unresolved.is_empty() {
+                    exits.push("unresolved".to_string());
+                }
+
+                writeln!(out, "  {:03X}-{:03X}  -> {}", block.start, block.get_last(), exits.join(", "))?;
+            }
+        }
+
+        if self.get_unresolved().next().is_some() {
+            writeln!(out, "\nUnresolved")?;
+
+            for unresolved in self.get_unresolved() {
+                let text = disasm::disassemble(unresolved.opcode).unwrap_or_else(|| "???".to_string());
+
+                writeln!(out, "  {:03X}  {:04X}  {:<16}  {}", unresolved.addr, unresolved.opcode, text,
+                         unresolved.problem)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Writes the blocks and the edges between them as a Graphviz graph, with calls as dashed edges
+    // to the subroutines and the unresolved control flow in red
+    pub fn write_dot(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "digraph cfg {{")?;
+        writeln!(out, "    node [shape=box, fontname=\"monospace\"];")?;
+
+        for block in self.blocks.values() {
+            let mut label = String::new();
+
+            if self.functions.contains_key(&block.start) {
+                label.push_str(&format!("{}:\\l", disasm::subroutine_name(block.start)));
+            }
+
+            for (addr, opcode) in &block.instructions {
+                let text = disasm::disassemble(*opcode).unwrap_or_else(|| "???".to_string());
+
+                label.push_str(&format!("{:03X}  {}\\l", addr, text));
+            }
+
+            let style = if self.functions.contains_key(&block.start) { ", style=bold" } else { "" };
+
+            writeln!(out, "    b{:03X} [label=\"{}\"{}];", block.start, label, style)?;
+
+            for edge in &block.edges {
+                let attributes = match edge.kind {
+                    EdgeKind::Next => "",
+                    EdgeKind::Jump => " [label=\"jump\"]",
+                    EdgeKind::Skip => " [label=\"skip\"]",
+                };
+
+                writeln!(out, "    b{:03X} -> b{:03X}{};", block.start, edge.target, attributes)?;
+            }
+
+            for target in block.calls.iter().filter(|target| self.blocks.contains_key(target)) {
+                writeln!(out, "    b{:03X} -> b{:03X} [style=dashed, label=\"call\"];", block.start, target)?;
+            }
+
+            for (i, unresolved) in block.unresolved.iter().enumerate() {
+                writeln!(out, "    u{:03X}_{} [label=\"{:03X}: {}\", shape=octagon, color=red];", block.start, i,
+                         unresolved.addr, unresolved.problem)?;
+                writeln!(out, "    b{:03X} -> u{:03X}_{} [color=red];", block.start, block.start, i)?;
+            }
+        }
+
+        writeln!(out, "}}")
+    }
+
+    // Writes which subroutines call which as a Graphviz graph
+    pub fn write_call_graph_dot(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "digraph calls {{")?;
+        writeln!(out, "    node [shape=box, fontname=\"monospace\"];")?;
+
+        for function in self.functions.values() {
+            let name = disasm::subroutine_name(function.entry);
+
+            writeln!(out, "    {};", name)?;
+
+            for target in &function.calls {
+                writeln!(out, "    {} -> {};", name, disasm::subroutine_name(*target))?;
+            }
+        }
+
+        writeln!(out, "}}")
+    }
+}
@@ -0,0 +1,447 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::ops::Range;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::debug::{Debugger, Stop, WatchKind, Watchpoint};
+
+// Describes the registers to gdb: V0-VF, I, PC, SP, DT and ST, numbered in that order. I and PC
+// are 16 bits wide and sent little-endian, the rest are single bytes.
+pub const TARGET_XML: &str = include_str!("gdb-target.xml");
+
+const REGISTER_COUNT: usize = 21;
+const MEMORY_SIZE: usize = 4096;
+const FRAME: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+// Signals reported when the program stops
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+enum Packet {
+    Command(String),
+    // Ctrl-C from the client, sent outside of any packet
+    Interrupt,
+}
+
+// Serves a single gdb session over the remote serial protocol, with the program stopped between
+// commands. Continuing runs it at its normal speed until it stops or the client interrupts it.
+pub struct GdbServer {
+    debugger: Debugger,
+    stream: TcpStream,
+    buffer: Vec<u8>,
+    no_ack: bool,
+}
+
+impl GdbServer {
+    pub fn new(debugger: Debugger, stream: TcpStream) -> Self {
+        GdbServer {
+            debugger,
+            stream,
+            buffer: Vec::new(),
+            no_ack: false,
+        }
+    }
+
+    // Answers the client until it detaches, kills the program or disconnects
+    pub fn serve(&mut self) -> Result<(), String> {
+        self.run().map_err(|err| format!("Connection to gdb failed: {}", err))
+    }
+
+    fn run(&mut self) -> io::Result<()> {
+        loop {
+            let command = match self.read_packet()? {
+                Some(Packet::Command(command)) => command,
+                Some(Packet::Interrupt) => {
+                    self.send(&format!("S{:02x}", SIGINT))?;
+                    continue;
+                }
+                None => return Ok(()),
+            };
+
+            match command.as_str() {
+                "k" => return Ok(()),
+                "QStartNoAckMode" => {
+                    self.send("OK")?;
+                    self.no_ack = true;
+                }
+                _ if command.starts_with('D') => {
+                    self.send("OK")?;
+                    return Ok(());
+                }
+                _ => {
+                    let reply = self.handle(&command)?;
+                    self.send(&reply)?;
+                }
+            }
+        }
+    }
+
+    fn handle(&mut self, command: &str) -> io::Result<String> {
+        let mut chars = command.chars();
+        let kind = chars.next();
+        let args = chars.as_str();
+
+        let reply = match kind {
+            Some('?') => Some(format!("S{:02x}", SIGTRAP)),
+            Some('g') => Some(hex(&self.read_registers())),
+            Some('G') => self.write_registers(args),
+            Some('p') => self.read_register(args),
+            Some('P') => self.write_register(args),
+            Some('m') => self.read_memory(args),
+            Some('M') => self.write_memory(args),
+            Some('c') | Some('s') => {
+                if !args.is_empty() {
+                    match parse_number(args) {
+                        Some(addr) => self.debugger.get_cpu_mut().set_program_counter(addr as u16),
+                        None => return Ok("E01".to_string()),
+                    }
+                }
+
+                // Continuing without stopping means it was interrupted, stepping means it stepped
+                let reply = if kind == Some('c') {
+                    self.resume()?.map_or(format!("S{:02x}", SIGINT), stop_reply)
+                } else {
+                    self.debugger.step().map_or(format!("S{:02x}", SIGTRAP), stop_reply)
+                };
+
+                return Ok(reply);
+            }
+            Some('Z') | Some('z') => self.set_point(kind == Some('Z'), args),
+            Some('H') | Some('T') => Some("OK".to_string()),
+            Some('q') | Some('v') => Some(self.query(command)),
+            _ => Some(String::new()),
+        };
+
+        Ok(reply.unwrap_or_else(|| "E01".to_string()))
+    }
+
+    fn query(&self, command: &str) -> String {
+        if let Some(request) = command.strip_prefix("qXfer:features:read:target.xml:") {
+            return match parse_pair(request, ',') {
+                Some((offset, length)) => {
+                    let data = TARGET_XML.as_bytes();
+                    let start = (offset as usize).min(data.len());
+                    let end = (start + length as usize).min(data.len());
+                    let more = if end < data.len() { 'm' } else { 'l' };
+
+                    format!("{}{}", more, escape(&data[start..end]))
+                }
+                None => "E01".to_string(),
+            };
+        }
+
+        match command.split(':').next().unwrap_or_default() {
+            "qSupported" => "PacketSize=1000;qXfer:features:read+;QStartNoAckMode+".to_string(),
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            _ => String::new(),
+        }
+    }
+
+    fn read_registers(&self) -> Vec<u8> {
+        let cpu = self.debugger.get_cpu();
+        let mut bytes = cpu.get_registers().to_vec();
+
+        bytes.extend(cpu.get_program_index().to_le_bytes());
+        bytes.extend(cpu.get_program_counter().to_le_bytes());
+        bytes.extend([cpu.get_stack_pointer() as u8, cpu.get_delay_timer(), cpu.get_sound_timer()]);
+        bytes
+    }
+
+    fn read_register(&self, args: &str) -> Option<String> {
+        let range = register_range(parse_number(args)? as usize)?;
+
+        Some(hex(&self.read_registers()[range]))
+    }
+
+    fn write_registers(&mut self, args: &str) -> Option<String> {
+        let bytes = unhex(args)?;
+
+        if bytes.len() != register_range(REGISTER_COUNT - 1)?.end {
+            return None;
+        }
+
+        for n in 0..REGISTER_COUNT {
+            self.set_register(n, &bytes[register_range(n)?])?;
+        }
+
+        Some("OK".to_string())
+    }
+
+    fn write_register(&mut self, args: &str) -> Option<String> {
+        let (n, value) = args.split_once('=')?;
+
+        self.set_register(parse_number(n)? as usize, &unhex(value)?)?;
+
+        Some("OK".to_string())
+    }
+
+    fn set_register(&mut self, n: usize, bytes: &[u8]) -> Option<()> {
+        let cpu = self.debugger.get_cpu_mut();
+
+        match (n, bytes) {
+            (0..=15, [value]) => cpu.set_register(n, *value),
+            (16, [lo, hi]) => cpu.set_program_index(u16::from_le_bytes([*lo, *hi])),
+            (17, [lo, hi]) => cpu.set_program_counter(u16::from_le_bytes([*lo, *hi])),
+            (18, [value]) => cpu.set_stack_pointer(*value as usize),
+            (19, [value]) => cpu.set_delay_timer(*value),
+            (20, [value]) => cpu.set_sound_timer(*value),
+            _ => return None,
+        }
+
+        Some(())
+    }
+
+    fn read_memory(&self, args: &str) -> Option<String> {
+        let (addr, len) = parse_pair(args, ',')?;
+        let memory = self.debugger.get_cpu().get_memory();
+
+        if addr as usize >= MEMORY_SIZE {
+            return None;
+        }
+
+        let end = (addr as usize + len as usize).min(MEMORY_SIZE);
+
+        Some(hex(&memory[addr as usize..end]))
+    }
+
+    fn write_memory(&mut self, args: &str) -> Option<String> {
+        let (range, data) = args.split_once(':')?;
+        let (addr, len) = parse_pair(range, ',')?;
+        let bytes = unhex(data)?;
+
+        if bytes.len() != len as usize || addr as usize + bytes.len() > MEMORY_SIZE {
+            return None;
+        }
+
+        for (i, byte) in bytes.into_iter().enumerate() {
+            self.debugger.get_cpu_mut().set_byte(addr as u16 + i as u16, byte);
+        }
+
+        Some("OK".to_string())
+    }
+
+    // Z/z TYPE,ADDR,KIND inserts or removes a breakpoint (types 0 and 1) or watchpoint (2 to 4)
+    fn set_point(&mut self, insert: bool, args: &str) -> Option<String> {
+        let mut fields = args.split(',');
+        let kind = fields.next()?;
+        let addr = parse_number(fields.next()?)? as usize;
+        let len = parse_number(fields.next()?.split(';').next()?)? as usize;
+
+        // Anything past the 4 KiB of memory can never be hit
+        if addr >= MEMORY_SIZE {
+            return None;
+        }
+
+        let len = len.min(MEMORY_SIZE - addr) as u16;
+        let addr = addr as u16;
+
+        let watch = match kind {
+            "0" | "1" => {
+                if insert {
+                    self.debugger.add_breakpoint(addr);
+                } else {
+                    self.debugger.remove_breakpoint(addr);
+                }
+
+                return Some("OK".to_string());
+            }
+            "2" => WatchKind::Write,
+            "3" => WatchKind::Read,
+            "4" => WatchKind::Access,
+            _ => return Some(String::new()),
+        };
+
+        let watchpoint = Watchpoint { kind: watch, addr, len: len.max(1) };
+
+        if insert {
+            self.debugger.add_watchpoint(watchpoint);
+        } else {
+            self.debugger.remove_watchpoint(watchpoint);
+        }
+
+        Some("OK".to_string())
+    }
+
+    // Runs frame by frame until the program stops, or returns None if the client interrupted it
+    fn resume(&mut self) -> io::Result<Option<Stop>> {
+        let mut next_frame = Instant::now();
+
+        loop {
+            if let Some(stop) = self.debugger.run_frame() {
+                return Ok(Some(stop));
+            }
+
+            if self.poll_interrupt()? {
+                return Ok(None);
+            }
+
+            next_frame += FRAME;
+
+            match next_frame.checked_duration_since(Instant::now()) {
+                Some(wait) => thread::sleep(wait),
+                None => next_frame = Instant::now(),
+            }
+        }
+    }
+
+    fn poll_interrupt(&mut self) -> io::Result<bool> {
+        let mut chunk = [0; 256];
+
+        self.stream.set_nonblocking(true)?;
+        let result = self.stream.read(&mut chunk);
+        self.stream.set_nonblocking(false)?;
+
+        match result {
+            // The client went away, stop so that the next read notices
+            Ok(0) => Ok(true),
+            Ok(n) => {
+                self.buffer.extend_from_slice(&chunk[..n]);
+
+                match self.buffer.iter().position(|b| *b == 0x03) {
+                    Some(i) => {
+                        self.buffer.remove(i);
+                        Ok(true)
+                    }
+                    None => Ok(false),
+                }
+            }
+            Err(err) if err.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    // Reads the next packet, acknowledging it, or returns None once the client disconnects
+    fn read_packet(&mut self) -> io::Result<Option<Packet>> {
+        loop {
+            // Acknowledgements and anything else outside of a packet are skipped
+            while let Some(byte) = self.buffer.first() {
+                match byte {
+                    b'$' => break,
+                    0x03 => {
+                        self.buffer.remove(0);
+                        return Ok(Some(Packet::Interrupt));
+                    }
+                    _ => {
+                        self.buffer.remove(0);
+                    }
+                }
+            }
+
+            // The packet is complete once the two checksum digits after the # have arrived
+            let end = self.buffer.iter().position(|b| *b == b'#').filter(|end| self.buffer.len() > end + 2);
+
+            if let Some(end) = end {
+                let packet: Vec<u8> = self.buffer.drain(..end + 3).collect();
+                let data = &packet[1..end];
+                let checksum = std::str::from_utf8(&packet[end + 1..])
+                    .ok()
+                    .and_then(|digits| u8::from_str_radix(digits, 16).ok());
+
+                if checksum == Some(checksum_of(data)) || self.no_ack {
+                    if !self.no_ack {
+                        self.stream.write_all(b"+")?;
+                    }
+
+                    return Ok(Some(Packet::Command(String::from_utf8_lossy(data).into_owned())));
+                }
+
+                self.stream.write_all(b"-")?;
+                continue;
+            }
+
+            let mut chunk = [0; 1024];
+            let n = self.stream.read(&mut chunk)?;
+
+            if n == 0 {
+                return Ok(None);
+            }
+
+            self.buffer.extend_from_slice(&chunk[..n]);
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+
+        self.stream.write_all(packet.as_bytes())?;
+        self.stream.flush()
+    }
+}
+
+fn stop_reply(stop: Stop) -> String {
+    match stop {
+        Stop::Breakpoint(_) | Stop::Finished => format!("S{:02x}", SIGTRAP),
+        Stop::Watchpoint(watchpoint, addr, _) => {
+            let name = match watchpoint.kind {
+                WatchKind::Write => "watch",
+                WatchKind::Read => "rwatch",
+                WatchKind::Access => "awatch",
+            };
+
+            format!("T{:02x}{}:{:x};", SIGTRAP, name, addr)
+        }
+        Stop::Error(_) => format!("S{:02x}", SIGILL),
+    }
+}
+
+// Where register N is in the g packet
+fn register_range(n: usize) -> Option<Range<usize>> {
+    match n {
+        0..=15 => Some(n..n + 1),
+        16 => Some(16..18),
+        17 => Some(18..20),
+        18..=20 => Some(n + 2..n + 3),
+        _ => None,
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, b| sum.wrapping_add(*b))
+}
+
+fn parse_number(s: &str) -> Option<u32> {
+    u32::from_str_radix(s, 16).ok()
+}
+
+fn parse_pair(s: &str, separator: char) -> Option<(u32, u32)> {
+    let (a, b) = s.split_once(separator)?;
+
+    Some((parse_number(a)?, parse_number(b)?))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..s.len())
+        .step_by(2)
+        .map(|i| s.get(i..i + 2).and_then(|digits| u8::from_str_radix(digits, 16).ok()))
+        .collect()
+}
+
+// Escapes the characters which can't appear in binary replies as-is
+fn escape(data: &[u8]) -> String {
+    let mut escaped = String::new();
+
+    for &b in data {
+        match b {
+            b'#' | b'$' | b'}' | b'*' => {
+                escaped.push('}');
+                escaped.push((b ^ 0x20) as char);
+            }
+            _ => escaped.push(b as char),
+        }
+    }
+
+    escaped
+}
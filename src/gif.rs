@@ -0,0 +1,582 @@
+// A minimal GIF decoder which only extracts the colour indices of each image, which is all that
+// is needed to read the data hidden in Octo cartridges, and an encoder for recording the screen
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+// Pixels in all frames together. Octo cartridges are far smaller, this only stops images from
+// claiming sizes which would take gigabytes to decode.
+const MAX_PIXELS: usize = 1 << 24;
+
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    pub indices: Vec<u8>,
+}
+
+pub fn is_gif(data: &[u8]) -> bool {
+    data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a")
+}
+
+pub fn decode_frames(data: &[u8]) -> Result<Vec<Frame>, String> {
+    if !is_gif(data) {
+        return Err("Not a GIF image".to_string());
+    }
+
+    let mut reader = Reader { data, pos: 6 };
+
+    // Logical screen descriptor, only the global colour table needs to be skipped
+    reader.take(4)?;
+    let flags = reader.byte()?;
+    reader.take(2)?;
+    skip_color_table(&mut reader, flags)?;
+
+    let mut frames = Vec::new();
+    let mut pixels = 0;
+
+    loop {
+        match reader.byte()? {
+            // Extensions aren't needed
+            0x21 => {
+                reader.byte()?;
+                reader.sub_blocks()?;
+            }
+            0x2C => {
+                let frame = decode_image(&mut reader, MAX_PIXELS - pixels)?;
+                pixels += frame.indices.len();
+                frames.push(frame);
+            }
+            0x3B => break,
+            block => return Err(format!("Invalid GIF block type ({:02X})", block)),
+        }
+    }
+
+    Ok(frames)
+}
+
+fn skip_color_table(reader: &mut Reader, flags: u8) -> Result<(), String> {
+    if flags & 0x80 != 0 {
+        reader.take(3 << ((flags & 0x07) + 1))?;
+    }
+
+    Ok(())
+}
+
+fn decode_image(reader: &mut Reader, max_pixels: usize) -> Result<Frame, String> {
+    reader.take(4)?;
+    let width = reader.word()? as usize;
+    let height = reader.word()? as usize;
+
+    if width * height > max_pixels {
+        return Err(format!("GIF image is too large ({}x{})", width, height));
+    }
+    let flags = reader.byte()?;
+    skip_color_table(reader, flags)?;
+
+    let min_code_size = reader.byte()?;
+    let data = reader.sub_blocks()?;
+    let indices = decode_lzw(min_code_size, &data, width * height)?;
+
+    // Interlaced images store every 8th row, then every 8th row from row 4, then every 4th row
+    // from row 2 and finally the odd rows
+    let indices = if flags & 0x40 != 0 {
+        let rows = (0..height).step_by(8)
+            .chain((4..height).step_by(8))
+            .chain((2..height).step_by(4))
+            .chain((1..height).step_by(2));
+
+        let mut deinterlaced = vec![0; indices.len()];
+
+        for (src, dst) in rows.enumerate() {
+            deinterlaced[dst * width..(dst + 1) * width]
+                .copy_from_slice(&indices[src * width..(src + 1) * width]);
+        }
+
+        deinterlaced
+    } else {
+        indices
+    };
+
+    Ok(Frame { width, height, indices })
+}
+
+fn decode_lzw(min_code_size: u8, data: &[u8], pixel_count: usize) -> Result<Vec<u8>, String> {
+    const MAX_CODES: usize = 4096;
+
+    if !(1..=11).contains(&min_code_size) {
+        return Err(format!("Invalid GIF code size ({})", min_code_size));
+    }
+
+    let clear = 1usize << min_code_size;
+    let end = clear + 1;
+
+    // Each code is a previous code followed by one more index
+    let mut prefix = [0u16; MAX_CODES];
+    let mut suffix = [0u8; MAX_CODES];
+    let mut length = [0u16; MAX_CODES];
+
+    for code in 0..clear {
+        suffix[code] = code as u8;
+        length[code] = 1;
+    }
+
+    let first_index = |code: usize, prefix: &[u16], length: &[u16], suffix: &[u8]| {
+        let mut code = code;
+
+        while length[code] > 1 {
+            code = prefix[code] as usize;
+        }
+
+        suffix[code]
+    };
+
+    let mut output = Vec::new();
+    let mut code_size = min_code_size as u32 + 1;
+    let mut next = end + 1;
+    let mut previous: Option<usize> = None;
+
+    let mut bits = 0u32;
+    let mut bit_count = 0;
+    let mut bytes = data.iter();
+
+    while output.len() < pixel_count {
+        while bit_count < code_size {
+            match bytes.next() {
+                Some(byte) => {
+                    bits |= (*byte as u32) << bit_count;
+                    bit_count += 8;
+                }
+                None => break,
+            }
+        }
+
+        // Some encoders leave out the end code, so running out of data finishes the image
+        if bit_count < code_size {
+            break;
+        }
+
+        let code = (bits & ((1 << code_size) - 1)) as usize;
+        bits >>= code_size;
+        bit_count -= code_size;
+
+        if code == clear {
+            code_size = min_code_size as u32 + 1;
+            next = end + 1;
+            previous = None;
+            continue;
+        }
+
+        if code == end {
+            break;
+        }
+
+        match previous {
+            None if code < clear => (),
+            // The code may be the one which is about to be added
+            Some(prev) if code < next || (code == next && next < MAX_CODES) => {
+                if next < MAX_CODES {
+                    let first = if code < next { code } else { prev };
+
+                    prefix[next] = prev as u16;
+                    suffix[next] = first_index(first, &prefix, &length, &suffix);
+                    length[next] = length[prev] + 1;
+                    next += 1;
+
+                    if next == 1 << code_size && code_size < 12 {
+                        code_size += 1;
+                    }
+                }
+            }
+            _ => return Err(format!("Invalid GIF code ({})", code)),
+        }
+
+        let start = output.len();
+        let mut entry = code;
+
+        output.resize(start + length[code] as usize, 0);
+
+        for i in (start..output.len()).rev() {
+            output[i] = suffix[entry];
+            entry = prefix[entry] as usize;
+        }
+
+        previous = Some(code);
+    }
+
+    output.resize(pixel_count, 0);
+
+    Ok(output)
+}
+
+// Writes an animated GIF which loops forever. Every frame has its own colour table, so that
+// palette changes and upscalers which blend colours don't need the colours known up front.
+pub struct Encoder<W: Write> {
+    writer: W,
+    width: u16,
+    height: u16,
+}
+
+impl<W: Write> Encoder<W> {
+    pub fn new(mut writer: W, width: usize, height: usize) -> io::Result<Self> {
+        let size = |n: usize| u16::try_from(n)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Image too large for a GIF"));
+        let (width, height) = (size(width)?, size(height)?);
+
+        writer.write_all(b"GIF89a")?;
+        writer.write_all(&width.to_le_bytes())?;
+        writer.write_all(&height.to_le_bytes())?;
+        writer.write_all(&[0, 0, 0])?;
+
+        // The application extension which makes the animation repeat
+        writer.write_all(b"\x21\xFF\x0BNETSCAPE2.0\x03\x01\x00\x00\x00")?;
+
+        Ok(Encoder { writer, width, height })
+    }
+
+    // Adds a frame from pixels in the framebuffer's 0x00RRGGBB format, shown for the delay in
+    // hundredths of a second
+    pub fn add_frame(&mut self, pixels: &[u32], delay: u16) -> io::Result<()> {
+        if pixels.len() != self.width as usize * self.height as usize {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Frame size doesn't match the GIF"));
+        }
+
+        let (colors, indices) = index_colors(pixels);
+
+        // Colour tables have a power of two entries, at least two
+        let table_bits = (colors.len().max(2) - 1).ilog2() + 1;
+
+        self.writer.write_all(&[0x21, 0xF9, 0x04, 0x00])?;
+        self.writer.write_all(&delay.to_le_bytes())?;
+        self.writer.write_all(&[0x00, 0x00])?;
+
+        self.writer.write_all(&[0x2C, 0, 0, 0, 0])?;
+        self.writer.write_all(&self.width.to_le_bytes())?;
+        self.writer.write_all(&self.height.to_le_bytes())?;
+        self.writer.write_all(&[0x80 | (table_bits - 1) as u8])?;
+
+        for i in 0..1 << table_bits {
+            let [_, r, g, b] = colors.get(i).copied().unwrap_or(0).to_be_bytes();
+            self.writer.write_all(&[r, g, b])?;
+        }
+
+        let min_code_size = table_bits.max(2) as u8;
+        self.writer.write_all(&[min_code_size])?;
+
+        for block in encode_lzw(min_code_size, &indices).chunks(255) {
+            self.writer.write_all(&[block.len() as u8])?;
+            self.writer.write_all(block)?;
+        }
+
+        self.writer.write_all(&[0])
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.write_all(&[0x3B])?;
+        self.writer.flush()?;
+
+        Ok(self.writer)
+    }
+}
+
+// Makes a colour table of at most 256 colours for the pixels and looks them up in it. When there
+// are more colours the lowest bits of each channel are dropped until they fit.
+fn index_colors(pixels: &[u32]) -> (Vec<u32>, Vec<u8>) {
+    let mut mask = 0xFFFFFF;
+
+    loop {
+        let mut colors = Vec::new();
+        let mut lookup = HashMap::new();
+        let mut indices = Vec::with_capacity(pixels.len());
+
+        for pixel in pixels {
+            let color = pixel & mask;
+            let index = *lookup.entry(color).or_insert_with(|| {
+                colors.push(color);
+                colors.len() - 1
+            });
+
+            if index > 255 {
+                break;
+            }
+
+            indices.push(index as u8);
+        }
+
+        if indices.len() == pixels.len() {
+            return (colors, indices);
+        }
+
+        // Each pass drops another bit from every channel, and with two bits left there are at
+        // most 64 colours
+        mask = (mask << 1) & 0xFEFEFE;
+    }
+}
+
+fn encode_lzw(min_code_size: u8, indices: &[u8]) -> Vec<u8> {
+    const MAX_CODES: u16 = 4096;
+
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+
+    let mut output = Vec::new();
+    let mut bits = 0u32;
+    let mut bit_count = 0;
+    let mut code_size = min_code_size as u32 + 1;
+
+    let mut emit = |code: u16, code_size: u32| {
+        bits |= (code as u32) << bit_count;
+        bit_count += code_size;
+
+        while bit_count >= 8 {
+            output.push(bits as u8);
+            bits >>= 8;
+            bit_count -= 8;
+        }
+    };
+
+    // Codes for a previous code followed by one more index
+    let mut codes: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next = end + 1;
+
+    emit(clear, code_size);
+
+    let mut indices = indices.iter();
+    let mut current = match indices.next() {
+        Some(index) => *index as u16,
+        None => clear,
+    };
+
+    for index in indices {
+        if let Some(code) = codes.get(&(current, *index)) {
+            current = *code;
+            continue;
+        }
+
+        emit(current, code_size);
+
+        // The decoder adds each code one step later than here, so the code size grows once the
+        // code after the largest one which fits has been added
+        codes.insert((current, *index), next);
+
+        if next == 1 << code_size && code_size < 12 {
+            code_size += 1;
+        }
+
+        next += 1;
+
+        if next == MAX_CODES {
+            emit(clear, code_size);
+            codes.clear();
+            next = end + 1;
+            code_size = min_code_size as u32 + 1;
+        }
+
+        current = *index as u16;
+    }
+
+    if current != clear {
+        emit(current, code_size);
+    }
+
+    emit(end, code_size);
+
+    if bit_count > 0 {
+        output.push(bits as u8);
+    }
+
+    output
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let bytes = self.data
+            .get(self.pos..self.pos + len)
+            .ok_or("Unexpected end of GIF data")?;
+
+        self.pos += len;
+
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn word(&mut self) -> Result<u16, String> {
+        let bytes = self.take(2)?;
+
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    // Data is split into blocks of up to 255 bytes, ending with an empty block
+    fn sub_blocks(&mut self) -> Result<Vec<u8>, String> {
+        let mut data = Vec::new();
+
+        loop {
+            let len = self.byte()? as usize;
+
+            if len == 0 {
+                return Ok(data);
+            }
+
+            data.extend_from_slice(self.take(len)?);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(width: usize, height: usize, frames: &[&[u32]]) -> Vec<u8> {
+        let mut encoder = Encoder::new(Vec::new(), width, height).unwrap();
+
+        for pixels in frames {
+            encoder.add_frame(pixels, 2).unwrap();
+        }
+
+        encoder.finish().unwrap()
+    }
+
+    // Pixels from a fixed sequence of pseudo-random choices between the colours
+    fn noise(len: usize, colors: &[u32]) -> Vec<u32> {
+        let mut seed = 1u32;
+
+        (0..len)
+            .map(|_| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                colors[(seed >> 16) as usize % colors.len()]
+            })
+            .collect()
+    }
+
+    // A GIF with a four colour table and image data which clears the code table before every
+    // index, which keeps every code at 3 bits so that the data can be put together by hand
+    fn literal_gif(width: usize, height: usize, flags: u8, indices: &[u8]) -> Vec<u8> {
+        let mut gif = b"GIF89a".to_vec();
+
+        gif.extend((width as u16).to_le_bytes());
+        gif.extend((height as u16).to_le_bytes());
+        gif.extend([0x81, 0, 0]);
+        gif.extend([0; 12]);
+
+        gif.extend([0x2C, 0, 0, 0, 0]);
+        gif.extend((width as u16).to_le_bytes());
+        gif.extend((height as u16).to_le_bytes());
+        gif.extend([flags, 2]);
+
+        let mut data = Vec::new();
+        let mut bits = 0u32;
+        let mut bit_count = 0;
+
+        for code in indices.iter().flat_map(|index| [4, *index as u32]).chain([5]) {
+            bits |= code << bit_count;
+            bit_count += 3;
+
+            while bit_count >= 8 {
+                data.push(bits as u8);
+                bits >>= 8;
+                bit_count -= 8;
+            }
+        }
+
+        data.push(bits as u8);
+
+        for block in data.chunks(255) {
+            gif.push(block.len() as u8);
+            gif.extend(block);
+        }
+
+        gif.extend([0, 0x3B]);
+        gif
+    }
+
+    #[test]
+    fn cartridge_payload_is_decoded() {
+        let json = br#"{"program": ": main clear", "options": {"tickrate": 20}}"#;
+        let mut payload = (json.len() as u32).to_be_bytes().to_vec();
+        payload.extend(json);
+
+        // Four indices per byte, starting with the highest bits
+        let mut indices: Vec<u8> = payload
+            .iter()
+            .flat_map(|byte| [6, 4, 2, 0].map(|shift| (byte >> shift) & 3))
+            .collect();
+        indices.resize(indices.len().div_ceil(16) * 16, 0);
+
+        let gif = literal_gif(16, indices.len() / 16, 0x00, &indices);
+        let frames = decode_frames(&gif).unwrap();
+
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].indices, indices);
+
+        let cartridge = crate::cartridge::decode(&gif).unwrap();
+
+        assert_eq!(cartridge.program, ": main clear");
+        assert_eq!(cartridge.options.tickrate, Some(20));
+    }
+
+    #[test]
+    fn interlaced_rows_are_put_back_in_order() {
+        // Rows 0, 4, 2, 6 and then the odd ones
+        let gif = literal_gif(1, 8, 0x40, &[0, 0, 1, 1, 2, 2, 3, 3]);
+
+        assert_eq!(decode_frames(&gif).unwrap()[0].indices, [0, 2, 1, 2, 0, 3, 1, 3]);
+    }
+
+    #[test]
+    fn huge_images_are_rejected() {
+        let mut gif = literal_gif(1, 1, 0x00, &[0]);
+
+        // The image descriptor's size, without the data for it
+        gif[30..34].copy_from_slice(&[0xFF; 4]);
+
+        assert!(matches!(decode_frames(&gif), Err(err) if err.contains("too large")));
+    }
+
+    #[test]
+    fn encoded_frames_decode_to_their_indices() {
+        // Noise fills the code table several times over, so the codes are cleared in between
+        let few = noise(256 * 128, &[0x000000, 0xFFFFFF, 0xFF0000]);
+        let many = noise(256 * 128, &(0..200).map(|i| i * 0x010101).collect::<Vec<_>>());
+        let flat = vec![0x123456; 256 * 128];
+
+        let frames = decode_frames(&encode(256, 128, &[&few, &many, &flat])).unwrap();
+
+        assert_eq!(frames.len(), 3);
+
+        for (frame, pixels) in frames.iter().zip([&few, &many, &flat]) {
+            assert_eq!((frame.width, frame.height), (256, 128));
+            assert_eq!(frame.indices, index_colors(pixels).1);
+        }
+    }
+
+    #[test]
+    fn colours_are_reduced_to_fit_the_table() {
+        let pixels: Vec<u32> = (0..1000).map(|i| (i * 0x0F1B2D) & 0xFFFFFF).collect();
+        let (colors, indices) = index_colors(&pixels);
+
+        assert!(colors.len() <= 256);
+
+        // Colours only lose their lowest bits
+        for (pixel, index) in pixels.iter().zip(indices) {
+            let color = colors[index as usize];
+
+            assert_eq!(color & pixel, color);
+            assert_eq!(color & 0xC0C0C0, pixel & 0xC0C0C0);
+        }
+    }
+
+    #[test]
+    fn frames_must_fill_the_image() {
+        let mut encoder = Encoder::new(Vec::new(), 4, 4).unwrap();
+
+        assert!(encoder.add_frame(&[0; 15], 2).is_err());
+        assert!(Encoder::new(Vec::new(), 70000, 1).is_err());
+    }
+}
@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+// The keyboard character for each key on the hex keypad, in order from 0 to F. The default layout
+// maps the left side of a QWERTY keyboard onto the keypad:
+//
+// 1 2 3 4      1 2 3 C
+// Q W E R  ->  4 5 6 D
+// A S D F      7 8 9 E
+// Z X C V      A 0 B F
+pub const DEFAULT_LAYOUT: &str = "x123qweasdzc4rfv";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Keymap {
+    keys: [char; 16],
+}
+
+impl Keymap {
+    // Maps a character typed on the keyboard to its key on the keypad
+    pub fn map_char(&self, c: char) -> Option<usize> {
+        let c = c.to_ascii_lowercase();
+
+        self.keys.iter().position(|k| *k == c)
+    }
+
+    pub fn get_char(&self, key: usize) -> char {
+        self.keys[key]
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        DEFAULT_LAYOUT.parse().unwrap()
+    }
+}
+
+impl FromStr for Keymap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let chars: Vec<char> = s.chars().map(|c| c.to_ascii_lowercase()).collect();
+
+        let keys: [char; 16] = chars
+            .try_into()
+            .map_err(|_| format!("Keymap must contain exactly 16 characters ({})", s))?;
+
+        for (i, c) in keys.iter().enumerate() {
+            if keys[..i].contains(c) {
+                return Err(format!("Keymap contains duplicate key ({})", c));
+            }
+        }
+
+        Ok(Keymap { keys })
+    }
+}
+
+impl fmt::Display for Keymap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.keys.iter().try_for_each(|c| write!(f, "{}", c))
+    }
+}
+
+// Keypad keys which act as directions and buttons in a game, as suggested by the ROM database.
+// Frontends map these onto the arrow keys, space (A) and enter (B).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct KeyHints {
+    pub up: Option<usize>,
+    pub down: Option<usize>,
+    pub left: Option<usize>,
+    pub right: Option<usize>,
+    pub a: Option<usize>,
+    pub b: Option<usize>,
+}
+
+impl KeyHints {
+    pub fn from_map(keys: &HashMap<String, u8>) -> Self {
+        let get = |name: &str| keys.get(name).map(|k| *k as usize).filter(|k| *k < 16);
+
+        KeyHints {
+            up: get("up"),
+            down: get("down"),
+            left: get("left"),
+            right: get("right"),
+            a: get("a"),
+            b: get("b"),
+        }
+    }
+}
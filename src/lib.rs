@@ -0,0 +1,25 @@
+pub mod bmp;
+pub mod cartridge;
+pub mod chip8;
+pub mod compare;
+pub mod coverage;
+pub mod database;
+pub mod debug;
+pub mod disasm;
+pub mod filter;
+pub mod flow;
+pub mod font;
+// The gdb server talks over TCP and paces the program with thread::sleep, neither of which the web has
+#[cfg(not(target_arch = "wasm32"))]
+pub mod gdb;
+pub mod gif;
+pub mod keypad;
+pub mod octo;
+pub mod profile;
+pub mod record;
+pub mod rom;
+pub mod scale;
+pub mod symbols;
+pub mod trace;
+
+pub use crate::chip8::Chip8;
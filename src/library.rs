@@ -0,0 +1,203 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use chip8_rs::rom::{self, Rom};
+
+use crate::settings::Settings;
+
+// Frames run to take a thumbnail, long enough for most games to draw their title screen
+const THUMBNAIL_FRAMES: u32 = 120;
+
+// How many levels of subdirectories are searched for ROMs
+const MAX_DEPTH: usize = 4;
+
+// Files larger than this can't be ROMs, even as text or in an archive
+const MAX_FILE_SIZE: u64 = 1 << 20;
+
+pub struct LibraryEntry {
+    pub path: PathBuf,
+    pub title: String,
+    pub hash: String,
+    // The screen after a short run, in the palette the ROM will be played with
+    pub thumbnail: Vec<u32>,
+    pub last_played: Option<u64>,
+    pub favourite: bool,
+}
+
+// Favourites and play times are kept between sessions, keyed by the SHA-1 of the ROM so that
+// they follow it when the file is moved
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+struct LibraryState {
+    favourites: BTreeSet<String>,
+    last_played: BTreeMap<String, u64>,
+}
+
+pub struct Library {
+    pub entries: Vec<LibraryEntry>,
+    state: LibraryState,
+    state_path: Option<PathBuf>,
+}
+
+impl Library {
+    // Finds the ROMs in the given directories and their subdirectories. The settings for each
+    // ROM are needed to take its thumbnail.
+    pub fn scan(dirs: &[PathBuf], state_path: Option<PathBuf>,
+                settings_for: &dyn Fn(&Rom) -> Result<Settings, String>) -> Self {
+        let state = state_path.as_deref().map(load_state).unwrap_or_default();
+
+        let mut paths = Vec::new();
+
+        for dir in dirs {
+            collect_files(dir, 0, &mut paths);
+        }
+
+        paths.sort();
+        paths.dedup();
+
+        let entries = paths
+            .into_iter()
+            .filter_map(|path| {
+                let rom = load_candidate(&path)?;
+                let settings = settings_for(&rom)
+                    .map_err(|err| eprintln!("Skipping {}: {}", path.display(), err))
+                    .ok()?;
+
+                let hash = rom::hash(&rom.data);
+                let thumbnail = take_thumbnail(&rom, &settings)?;
+
+                let title = settings.title.clone().unwrap_or_else(|| {
+                    path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default()
+                });
+
+                Some(LibraryEntry {
+                    title,
+                    thumbnail,
+                    last_played: state.last_played.get(&hash).copied(),
+                    favourite: state.favourites.contains(&hash),
+                    hash,
+                    path,
+                })
+            })
+            .collect();
+
+        let mut library = Library { entries, state, state_path };
+        library.sort();
+
+        library
+    }
+
+    // Favourites come first, followed by the most recently played and then everything else by
+    // title
+    pub fn sort(&mut self) {
+        self.entries.sort_by_cached_key(|entry| {
+            (!entry.favourite, Reverse(entry.last_played), entry.title.to_lowercase())
+        });
+    }
+
+    pub fn toggle_favourite(&mut self, index: usize) {
+        let entry = &mut self.entries[index];
+        entry.favourite = !entry.favourite;
+
+        if entry.favourite {
+            self.state.favourites.insert(entry.hash.clone());
+        } else {
+            self.state.favourites.remove(&entry.hash);
+        }
+
+        self.save();
+    }
+
+    pub fn mark_played(&mut self, index: usize) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        let entry = &mut self.entries[index];
+        entry.last_played = Some(now);
+
+        self.state.last_played.insert(entry.hash.clone(), now);
+        self.save();
+    }
+
+    // Failing to save only loses the history, so it isn't fatal
+    fn save(&self) {
+        let Some(path) = &self.state_path else {
+            return;
+        };
+
+        let result = path.parent()
+            .map(fs::create_dir_all)
+            .unwrap_or(Ok(()))
+            .and_then(|()| fs::write(path, serde_json::to_string_pretty(&self.state).unwrap()));
+
+        if let Err(err) = result {
+            eprintln!("Failed to save the ROM library to {}: {}", path.display(), err);
+        }
+    }
+}
+
+fn load_state(path: &Path) -> LibraryState {
+    let Ok(text) = fs::read_to_string(path) else {
+        return LibraryState::default();
+    };
+
+    serde_json::from_str(&text).unwrap_or_else(|err| {
+        eprintln!("Ignoring invalid ROM library file {}: {}", path.display(), err);
+        LibraryState::default()
+    })
+}
+
+fn collect_files(dir: &Path, depth: usize, paths: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        eprintln!("Failed to read ROM directory {}", dir.display());
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+
+        match entry.metadata() {
+            Ok(meta) if meta.is_dir() && depth < MAX_DEPTH => collect_files(&path, depth + 1, paths),
+            Ok(meta) if meta.is_file() && meta.len() <= MAX_FILE_SIZE => paths.push(path),
+            _ => (),
+        }
+    }
+}
+
+// Loads a file if it looks like a ROM, archives with several ROMs are represented by the first
+fn load_candidate(path: &Path) -> Option<Rom> {
+    let data = fs::read(path).ok()?;
+
+    if !rom::looks_like_rom(&path.to_string_lossy(), &data) {
+        return None;
+    }
+
+    rom::load(path, &mut |_| Ok(0))
+        .map_err(|err| eprintln!("Skipping {}", err))
+        .ok()
+}
+
+// ROMs which can't run for as long as it takes to get the thumbnail still get one, but the ones
+// which can't be loaded at all are left out
+fn take_thumbnail(rom: &Rom, settings: &Settings) -> Option<Vec<u32>> {
+    let mut cpu = settings.create_cpu(&rom.data).ok()?;
+
+    for _ in 0..THUMBNAIL_FRAMES {
+        if cpu.run_frame(settings.speed).is_err() {
+            break;
+        }
+    }
+
+    Some(cpu.get_pixels().to_vec())
+}
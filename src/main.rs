@@ -1,600 +1,738 @@
-use std::env;
-use std::fs::File;
-use std::io::Read;
-use std::thread;
-use std::time::Duration;
-
-use xorshift::{Rng, SeedableRng, Xoroshiro128};
+// Without any frontend the binary can only report that it has nothing to show
+#![cfg_attr(not(any(feature = "sdl", feature = "terminal")), allow(dead_code, unused))]
+
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufWriter, IsTerminal, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use clap::Parser;
+
+use chip8_rs::chip8::{FRAMEBUFFER_HEIGHT, FRAMEBUFFER_WIDTH};
+use chip8_rs::compare::{self, Outcome};
+use chip8_rs::coverage::Coverage;
+use chip8_rs::database::{self, Database};
+use chip8_rs::debug::Debugger;
+use chip8_rs::gdb::GdbServer;
+use chip8_rs::profile::Profiler;
+use chip8_rs::record::Recorder;
+use chip8_rs::rom::{self, Rom};
+use chip8_rs::trace::{TraceFilter, Tracer};
+use chip8_rs::{bmp, disasm, flow, Chip8};
+
+use crate::app::{Application, Control, Frontend};
+use crate::cli::{BrowseArgs, CfgArgs, Cli, Command, CompareArgs, CoverageArgs, DapArgs, EmulatorArgs,
+                 GdbArgs, HeadlessArgs, ProfileArgs, RpcArgs, RunArgs, SourceArgs, TraceArgs};
+use crate::config::Config;
+use crate::rpc::{Loader, RpcServer};
+use crate::script::Script;
+use crate::settings::Settings;
+
+mod app;
+#[cfg(feature = "sdl")]
+mod browser;
+mod cli;
+mod config;
+mod dap;
+#[cfg(feature = "sdl")]
+mod library;
+mod memory;
+mod menu;
+mod osd;
+mod rpc;
+mod script;
+mod settings;
+#[cfg(feature = "sdl")]
+mod sdl;
+#[cfg(feature = "terminal")]
+mod terminal;
+
+const ROM_START: usize = 0x200;
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    let result = match cli.command {
+        Some(Command::Run(args)) => run(args),
+        Some(Command::Headless(args)) => headless(args),
+        Some(Command::Disasm(args)) => disassemble(&args.rom, &args.source),
+        Some(Command::Info(args)) => info(&args.rom, &args.source),
+        Some(Command::Browse(args)) => browse(args),
+        Some(Command::Compare(args)) => compare(args),
+        Some(Command::Coverage(args)) => coverage(args),
+        Some(Command::Cfg(args)) => control_flow(args),
+        Some(Command::Gdb(args)) => gdb(args),
+        Some(Command::Dap(args)) => debug_adapter(args),
+        Some(Command::Rpc(args)) => rpc(args),
+        None => run(cli.run),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
 
-use sdl2::Sdl;
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-use sdl2::pixels::{PixelFormatEnum};
-use sdl2::render::{TextureAccess, WindowCanvas};
+// Picks the ROM to run from an archive holding several, either by the name given on the
+// command line or by asking when there is a terminal to ask on
+fn load_rom(path: &Path, source: &SourceArgs) -> Result<Rom, String> {
+    let interactive = path.as_os_str() != "-" && io::stdin().is_terminal();
+
+    rom::load(path, &mut |names| {
+        if let Some(entry) = &source.entry {
+            return names
+                .iter()
+                .position(|name| name == entry)
+                .ok_or_else(|| format!("No ROM named {} in the archive", entry));
+        }
 
-const FRAMEBUFFER_WIDTH: usize = 64;
-const FRAMEBUFFER_HEIGHT: usize = 32;
-const FRAMEBUFFER_SIZE: usize = FRAMEBUFFER_WIDTH * FRAMEBUFFER_HEIGHT;
-const FRAMEBUFFER_PITCH: usize = FRAMEBUFFER_WIDTH * 4;
+        if !interactive {
+            return Err(format!("Archive contains several ROMs, choose one with --entry ({})", names.join(", ")));
+        }
 
-const DISPLAY_SCALE: usize = 16;
+        choose_entry(names)
+    })
+}
 
-fn main() {
-    let rom_path = env::args().skip(1).next().expect("Missing path argument");
+fn choose_entry(names: &[String]) -> Result<usize, String> {
+    eprintln!("The archive contains several ROMs:");
 
-    let mut rom: Vec<u8> = Vec::new();
+    for (i, name) in names.iter().enumerate() {
+        eprintln!("{:>4}) {}", i + 1, name);
+    }
 
-    let mut rom_file = File::open(rom_path)
-        .expect("Failed to open ROM file");
-    rom_file
-        .read_to_end(&mut rom)
-        .expect("Failed to read ROM file");
+    loop {
+        eprint!("Choose a ROM [1-{}]: ", names.len());
+        io::stderr().flush().ok();
 
-    let mut app = Application::new(rom);
-    app.run();
-}
+        let mut line = String::new();
 
-struct Application {
-    sdl: Sdl,
-    cpu: Chip8,
-    canvas: WindowCanvas
-}
+        if io::stdin().lock().read_line(&mut line).map_err(|err| err.to_string())? == 0 {
+            return Err("No ROM chosen".to_string());
+        }
 
-impl Application {
-    pub fn new(rom: Vec<u8>) -> Self {
-        let sdl = sdl2::init().expect("Failed to initialize SDL2");
-        let video_sys = sdl
-            .video()
-            .expect("Failed to initialize SDL2 Video");
-
-        let cpu = Chip8::new(&rom)
-            .expect("Failed to initialize CHIP-8 CPU");
-
-        let window = video_sys
-            .window("chip8-rs",
-                    (FRAMEBUFFER_WIDTH * DISPLAY_SCALE) as u32,
-                    (FRAMEBUFFER_HEIGHT * DISPLAY_SCALE) as u32)
-            .opengl()
-            .position_centered()
-            .build()
-            .expect("Failed to create SDL2 window");
-
-        let canvas = window
-            .into_canvas()
-            .build()
-            .expect("Failed to create SDL2 window surface");
-
-        Application {
-            sdl,
-            cpu,
-            canvas
+        match line.trim().parse::<usize>() {
+            Ok(n) if (1..=names.len()).contains(&n) => return Ok(n - 1),
+            _ => eprintln!("Enter a number between 1 and {}", names.len()),
         }
     }
+}
 
-    pub fn run(&mut self) {
-        let mut events = self.sdl
-            .event_pump()
-            .expect("Failed to create event pump");
-
-        let mut close = false;
-
-        let texture_creator = self.canvas.texture_creator();
-
-        let mut texture = texture_creator
-            .create_texture(PixelFormatEnum::RGB888, TextureAccess::Streaming,
-                            FRAMEBUFFER_WIDTH as u32, FRAMEBUFFER_HEIGHT as u32)
-            .expect("Failed to create streaming texture");
-
-        while !close {
-            for event in events.poll_iter() {
-                match event {
-                    Event::Quit { .. } => close = true,
-                    Event::KeyDown { keycode, .. } => {
-                        if let Some(key) = keycode {
-                            self.cpu.set_key_state(key, true);
-                        }
-                    }
-                    Event::KeyUp { keycode, .. } => {
-                        if let Some(key) = keycode {
-                            self.cpu.set_key_state(key, false);
-                        }
-                    }
-                    _ => (),
-                }
-            }
+fn load_database() -> Result<Database, String> {
+    let mut db = Database::bundled();
+
+    if let Some(dir) = config::database_dir() {
+        db.load_dir(&dir)?;
+    }
 
-            self.cpu.step();
+    Ok(db)
+}
 
-            texture.update(None, self.cpu.get_framebuffer(), FRAMEBUFFER_PITCH)
-                .expect("Failed to update texture");
+// Settings are layered as the defaults, the global configuration, the ROM database entry, the
+// options which came with the ROM, the configuration for the ROM and finally the command-line
+// arguments
+fn load_settings(args: &EmulatorArgs, rom: &Rom) -> Result<Settings, String> {
+    let config = Config::load(args.config.as_deref())?;
+    let db = if args.no_database { None } else { Some(load_database()?) };
 
-            self.canvas.copy(&texture, None, None)
-                .expect("Failed to copy texture");
+    resolve_settings(&config, db.as_ref(), args, rom, true)
+}
 
-            self.canvas.present();
+// The layering of load_settings with the configuration and database already loaded, so that it
+// can be repeated for every ROM in a library. Verbose reports where the settings came from.
+fn resolve_settings(config: &Config, db: Option<&Database>, args: &EmulatorArgs, rom: &Rom,
+                    verbose: bool) -> Result<Settings, String> {
+    let hash = rom::hash(&rom.data);
 
-            if cfg!(debug_assertions) {
-                print!(
-                    "OP:\t{:04X}\t| PC: \t{:04X}\t| I:\t{:04X}\t| SP:\t{:02X}\t",
-                    self.cpu.get_opcode(),
-                    self.cpu.get_program_counter(),
-                    self.cpu.get_program_index(),
-                    self.cpu.get_stack_pointer()
-                );
+    let mut settings = Settings::default();
+    settings.apply_config(config)?;
 
-                print!("\nS: \t");
-                self.cpu
-                    .get_stack()
-                    .iter()
-                    .for_each(|b| print!("{:04X} ", b));
+    if let Some(profile) = db.and_then(|db| db.get_profile(&hash)) {
+        let platform = profile.platform.as_deref().unwrap_or("unknown platform");
 
-                print!("\nV: \t");
-                self.cpu
-                    .get_registers()
-                    .iter()
-                    .for_each(|b| print!("{:02X} ", b));
+        if verbose {
+            eprintln!("Found \"{}\" ({}) in the ROM database", profile.title, platform);
 
-                print!("\n\n");
+            if !database::SUPPORTED_PLATFORMS.contains(&platform) {
+                eprintln!("warning: {} programs may use instructions which aren't supported", platform);
             }
+        }
+
+        settings.apply_profile(&profile);
+    }
 
-            thread::sleep(Duration::from_millis(16));
+    if let Some(profile) = &rom.profile {
+        if verbose {
+            eprintln!("Using the options embedded in \"{}\"", profile.title);
         }
+
+        settings.apply_profile(profile);
+    }
+
+    if let Some(overrides) = config.get_rom(&hash) {
+        settings.apply_config(overrides)?;
     }
+
+    settings.apply_emulator_args(args);
+
+    Ok(settings)
 }
 
-struct Chip8 {
-    memory: [u8; 4096],
-    registers: [u8; 16],
-    framebuffer: [u32; FRAMEBUFFER_SIZE],
-    stack: [u16; 16],
-    keys: [bool; 16],
-    opcode: u16,
+fn run(args: RunArgs) -> Result<(), String> {
+    let path = args.rom.ok_or("Missing path to the ROM file (see --help)")?;
+    let rom = load_rom(&path, &args.source)?;
 
-    index: u16,
-    program_counter: u16,
-    stack_pointer: usize,
+    let mut settings = load_settings(&args.emulator, &rom)?;
+    settings.apply_display_args(&args.display)?;
 
-    random: Xoroshiro128,
+    let mut cpu = settings.create_cpu(&rom.data)?;
+    let mut attached = Attached {
+        tracer: create_tracer(&args.trace)?,
+        ..Attached::default()
+    };
+
+    instrument(&mut cpu, &args.profile, &rom.data)?;
+
+    if let Some(path) = &args.script {
+        attached.script = Some(Script::load(path, &mut cpu)?);
+    }
 
-    delay_timer: u8,
-    sound_timer: u8,
+    if let Some(addr) = &args.rpc {
+        attached.rpc = Some(listen_rpc(addr, &args.emulator, &cpu, settings.speed)?);
+    }
 
-    beep_flag: bool,
+    match settings.terminal {
+        #[cfg(feature = "terminal")]
+        Some(mode) => {
+            let frontend = terminal::TerminalFrontend::new(mode, &settings)
+                .map_err(|err| format!("Failed to initialize terminal: {}", err))?;
 
-    last_key: Option<usize>,
+            run_with(frontend, cpu, &settings, &rom.data, attached, &args.profile)
+        }
+        #[cfg(feature = "sdl")]
+        _ => run_with(sdl::SdlFrontend::new(&settings)?, cpu, &settings, &rom.data, attached, &args.profile),
+        #[allow(unreachable_patterns)]
+        _ => Err("No frontend available (built without the \"sdl\" and \"terminal\" features)".to_string()),
+    }
 }
 
-impl Chip8 {
-    fn new(rom: &[u8]) -> Result<Self, String> {
-        let mut chip8 = Chip8 {
-            memory: [0; 4096],
-            registers: [0; 16],
-            framebuffer: [0; FRAMEBUFFER_SIZE],
-            stack: [0; 16],
-            keys: [false; 16],
-            opcode: 0,
+// Everything running alongside the machine in a window, which is set up before the window opens
+// so that it fails first
+#[derive(Default)]
+struct Attached {
+    tracer: Option<Tracer>,
+    rpc: Option<RpcServer>,
+    script: Option<Script>,
+}
 
-            index: 0,
-            program_counter: 0,
-            stack_pointer: 0,
+fn run_with<F: Frontend>(frontend: F, cpu: Chip8, settings: &Settings, rom: &[u8], attached: Attached,
+                         profile: &ProfileArgs) -> Result<(), String> {
+    let mut app = Application::new(cpu, frontend, settings);
 
-            random: Xoroshiro128::from_seed(&[0x7020de7ee5e88ab7, 0xe587fbb5ba4fccee]),
+    if let Some(tracer) = attached.tracer {
+        app.set_tracer(tracer);
+    }
 
-            delay_timer: 0,
-            sound_timer: 0,
+    if let Some(rpc) = attached.rpc {
+        app.set_rpc(rpc);
+    }
 
-            beep_flag: false,
+    if let Some(script) = attached.script {
+        app.set_script(script);
+    }
 
-            last_key: None,
-        };
+    // The profile is written even when the ROM crashed, as that is when it is most interesting
+    let result = play(&mut app, settings, rom, false).map(|_| ());
+    let written = write_reports(app.get_cpu(), profile);
+
+    result.and(written)
+}
+
+fn create_tracer(args: &TraceArgs) -> Result<Option<Tracer>, String> {
+    let Some(path) = &args.trace else {
+        return Ok(None);
+    };
 
-        chip8.load_fontset(include_bytes!("fontset.bin"))?;
-        chip8.load_rom(rom)?;
+    let mut filter = TraceFilter::default();
 
-        Ok(chip8)
+    if let Some(spec) = &args.trace_addr {
+        filter.set_addresses(spec)?;
     }
 
-    fn load_fontset(&mut self, bytes: &[u8]) -> Result<(), String> {
-        let start = 0x050;
-        let end = 0x0A0;
-        let len = end - start;
+    if let Some(spec) = &args.trace_ops {
+        filter.set_classes(spec)?;
+    }
 
-        if bytes.len() > len {
-            Err(format!("Fontset ROM exceeds maximum size (cap: {}, len: {})", len, bytes.len()))
-        } else {
-            self.memory[start..end]
-                .copy_from_slice(bytes);
+    if let Some(spec) = &args.trace_frames {
+        filter.set_frames(spec)?;
+    }
 
-            Ok(())
-        }
+    Tracer::create(path, args.trace_format, filter).map(Some)
+}
+
+// Starts profiling and recording coverage as asked, carrying on from the coverage of earlier runs
+fn instrument(cpu: &mut Chip8, args: &ProfileArgs, rom: &[u8]) -> Result<(), String> {
+    if args.profile.is_some() || args.profile_stacks.is_some() {
+        cpu.set_profiler(Profiler::new());
     }
 
-    fn load_rom(&mut self, bytes: &[u8]) -> Result<usize, String> {
-        let start = 0x200;
-        let end = self.memory.len();
+    if let Some(path) = &args.coverage {
+        let coverage = if path.exists() {
+            let coverage = load_coverage(path)?;
+
+            if coverage.get_rom_hash() != rom::hash(rom) {
+                return Err(format!("Coverage file {} is of a different ROM", path.display()));
+            }
 
-        if bytes.len() > end - start {
-            Err(format!("Game ROM exceeds maximum size (cap: {}, len: {})", end - start, bytes.len()))
+            coverage
         } else {
-            self.program_counter = start as u16;
-            self.index = 0x0;
+            Coverage::new(&rom::hash(rom))
+        };
 
-            self.stack = [0u16; 16];
-            self.stack_pointer = 0;
+        cpu.set_coverage(coverage);
+    }
 
-            for i in 0..bytes.len() {
-                self.memory[i + start] = bytes[i];
-            }
+    Ok(())
+}
+
+fn load_coverage(path: &Path) -> Result<Coverage, String> {
+    fs::read_to_string(path)
+        .map_err(|err| err.to_string())
+        .and_then(|text| Coverage::parse(&text))
+        .map_err(|err| format!("Failed to read coverage from {}: {}", path.display(), err))
+}
 
-            Ok(self.memory.len())
+// Writes the profile and coverage of a run, whichever were recorded
+fn write_reports(cpu: &Chip8, args: &ProfileArgs) -> Result<(), String> {
+    if let Some(profiler) = cpu.get_profiler() {
+        if let Some(path) = &args.profile {
+            write_file(path, "profile", &|out| profiler.write_report(out, args.profile_top))?;
+        }
+
+        if let Some(path) = &args.profile_stacks {
+            write_file(path, "profile", &|out| profiler.write_collapsed(out))?;
         }
     }
 
-    fn step(&mut self) {
-        self.opcode = (self.memory[self.program_counter as usize] as u16) << 8
-            | self.memory[self.program_counter as usize + 1] as u16;
-
-        match self.opcode & 0xF000 {
-            // 0NNN - Calls RCA 1802 program at address NNN
-            0x0000 => {
-                match self.opcode & 0x0FFF {
-                    0x0000 => {
-                        self.program_counter += 2;
-                    }
-                    // 00E0 - Clear framebuffer
-                    0x00E0 => {
-                        self.framebuffer.fill(0);
-                        self.program_counter += 2;
-                    }
-                    // 00EE - Returns from subroutine
-                    0x00EE => {
-                        if self.stack_pointer <= 0 {
-                            panic!("Couldn't pop from stack (stack is empty)");
-                        }
-
-                        self.stack_pointer -= 1;
-
-                        self.program_counter = self.stack[self.stack_pointer];
-                        self.program_counter += 2;
-                    }
-                    _ => panic!("Unknown instruction ({:04X})", self.opcode),
-                }
-            }
-            // 1NNN - Jumps to address NNN
-            0x1000 => {
-                self.program_counter = self.opcode & 0x0FFF;
-            }
-            // 2NNN - Calls subroutine at NNN
-            0x2000 => {
-                if self.stack_pointer >= 15 {
-                    panic!("Couldn't push into stack (stack has exceeded maximum size)");
-                }
+    if let (Some(coverage), Some(path)) = (cpu.get_coverage(), &args.coverage) {
+        write_file(path, "coverage", &|out| coverage.write(out))?;
+    }
 
-                self.stack[self.stack_pointer] = self.program_counter;
-                self.stack_pointer += 1;
+    Ok(())
+}
 
-                self.program_counter = self.opcode & 0x0FFF;
-            }
-            // 3XNN - Skips the next instruction if VX equals NN
-            0x3000 => {
-                if self.registers[(self.opcode as usize & 0x0F00) >> 8]
-                    == (self.opcode & 0x00FF) as u8
-                {
-                    self.program_counter += 4;
-                } else {
-                    self.program_counter += 2;
-                }
-            }
-            // 4XNN - Skips the next instruction if VX does not equal NN
-            0x4000 => {
-                if self.registers[(self.opcode as usize & 0x0F00) >> 8]
-                    != (self.opcode & 0x00FF) as u8
-                {
-                    self.program_counter += 4;
-                } else {
-                    self.program_counter += 2;
-                }
-            }
-            // 5XY0 - Skips the next instruction if VX equals VY
-            0x5000 => {
-                if self.registers[(self.opcode as usize & 0x0F00) >> 8]
-                    == self.registers[(self.opcode as usize & 0x00F0) >> 4]
-                {
-                    self.program_counter += 4;
-                } else {
-                    self.program_counter += 2;
-                }
-            }
-            // 6XNN - Sets VX to NN
-            0x6000 => {
-                self.registers[(self.opcode as usize & 0x0F00) >> 8] = (self.opcode & 0x00FF) as u8;
-                self.program_counter += 2;
-            }
-            // 7XNN - Adds NN to VX (carry flag is not changed)
-            0x7000 => {
-                let (result, _) = self.registers[(self.opcode as usize & 0x0F00) >> 8]
-                    .overflowing_add((self.opcode & 0x00FF) as u8);
+fn write_file(path: &Path, what: &str, f: &dyn Fn(&mut dyn Write) -> io::Result<()>) -> Result<(), String> {
+    File::create(path)
+        .and_then(|file| {
+            let mut out = BufWriter::new(file);
+            f(&mut out)?;
+            out.flush()
+        })
+        .map_err(|err| format!("Failed to write {} to {}: {}", what, path.display(), err))
+}
 
-                self.registers[(self.opcode as usize & 0x0F00) >> 8] = result;
-                self.program_counter += 2;
-            }
-            // 8XNO - Sets VX to a value calculated from VX and VY
-            0x8000 => {
-                let x = (self.opcode as usize & 0x0F00) >> 8;
-                let y = (self.opcode as usize & 0x00F0) >> 4;
-
-                match self.opcode & 0x000F {
-                    // 8XY0 - Sets VX to VY
-                    0x0000 => self.registers[x] = self.registers[y],
-                    // 8XY1 - Sets VX to VX OR VY
-                    0x0001 => self.registers[x] |= self.registers[y],
-                    // 8XY2 - Sets VX to VX AND VY
-                    0x0002 => self.registers[x] &= self.registers[y],
-                    // 8XY3 - Sets VX to VX XOR VY
-                    0x0003 => self.registers[x] ^= self.registers[y],
-                    // 8XY4 - Sets VX to VX + VY (sets VF to 1 if a carry occurs, otherwise 0)
-                    0x0004 => {
-                        let (result, carry) = self.registers[x].overflowing_add(self.registers[y]);
-
-                        self.registers[0xF] = if carry { 1 } else { 0 };
-                        self.registers[x] = result;
-                    }
-                    // 8XY5 - Sets VX to VX - VY (sets VF to 0 if a borrow occurs, otherwise 1)
-                    0x0005 => {
-                        let (result, borrow) = self.registers[x].overflowing_sub(self.registers[y]);
-
-                        self.registers[0xF] = if borrow { 0 } else { 1 };
-                        self.registers[x] = result;
-                    }
-                    // 8XY6 - Sets VX to VY >> 1 (sets VF to the least significant bit of VY before the shift)
-                    0x0006 => {
-                        self.registers[0xF] = self.registers[y] & 0b00000001;
-                        self.registers[x] = self.registers[y] >> 1;
-                    }
-                    // 8XY7 - Sets VX to VY - VX. (sets VF to 0 if a borrow occurs, otherwise 1)
-                    0x0007 => {
-                        let (result, borrow) = self.registers[y].overflowing_sub(self.registers[x]);
-
-                        self.registers[0xF] = if borrow { 0 } else { 1 };
-                        self.registers[x] = result;
-                    }
-                    // 8XYE - Sets VX to VY << 1 (sets VF to the most significant bit of VY before the shift)
-                    0x000E => {
-                        self.registers[0xF] = self.registers[y] & 0b10000000;
-                        self.registers[x] = self.registers[y] << 1;
-                    }
-                    _ => panic!("Unknown instruction ({:04X})", self.opcode),
-                }
+// Runs a ROM until the player quits, or goes back to the library when there is one to go back to
+fn play<F: Frontend>(app: &mut Application<F>, settings: &Settings, rom: &[u8],
+                     library: bool) -> Result<Control, String> {
+    let result = loop {
+        match app.run() {
+            Ok(Control::Reset) => match settings.create_cpu(rom) {
+                Ok(cpu) => app.set_cpu(cpu),
+                Err(err) => break Err(err),
+            },
+            Ok(Control::Library) if !library => (),
+            result => break result,
+        }
+    };
 
-                self.program_counter += 2;
-            }
-            // 9XY0 - Skips the next instruction if VX doesn't equal VY
-            0x9000 => {
-                if self.registers[(self.opcode as usize & 0x0F00) >> 8]
-                    != self.registers[(self.opcode as usize & 0x00F0) >> 4]
-                {
-                    self.program_counter += 4;
-                } else {
-                    self.program_counter += 2;
-                }
-            }
-            // ANNN - Sets I to the address NNN
-            0xA000 => {
-                self.index = self.opcode & 0x0FFF;
-                self.program_counter += 2;
-            }
-            // BNNN - Jumps to the address NNN plus V0
-            0xB000 => {
-                self.program_counter = (self.opcode & 0x0FFF) + self.registers[0x0] as u16;
-            }
-            // CXNN - Sets VX to the result of a bitwise and operation on a random number (between 0 and 255) and NN
-            0xC000 => {
-                self.registers[(self.opcode as usize & 0x0F00) >> 8] =
-                    self.rand() & (self.opcode & 0x00FF) as u8;
+    // A recording is only a valid GIF once it's finished, so it must be whatever the way out
+    app.stop_recording();
 
-                self.program_counter += 2;
-            }
-            // DXYN - Draws a sprite at coordinates (VX, VY) that has the dimensions of 8xN
-            0xD000 => {
-                let dst_x = self.registers[(self.opcode as usize & 0x0F00) >> 8] as usize;
-                let dst_y = self.registers[(self.opcode as usize & 0x00F0) >> 4] as usize;
+    result
+}
 
-                let width = 8;
-                let height = (self.opcode & 0x000F) as usize;
+#[cfg(feature = "sdl")]
+fn browse(args: BrowseArgs) -> Result<(), String> {
+    use std::thread;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-                self.registers[0xF] = 0;
+    use crate::browser::{Browser, BrowserAction, VIEW_HEIGHT, VIEW_WIDTH};
+    use crate::library::Library;
 
-                for y in 0..height {
-                    let src_pixel = self.memory[self.index as usize + y];
+    let config = Config::load(args.emulator.config.as_deref())?;
+    let db = if args.emulator.no_database { None } else { Some(load_database()?) };
 
-                    for x in 0..width {
-                        if dst_x + x >= FRAMEBUFFER_WIDTH || dst_y + y >= FRAMEBUFFER_HEIGHT {
-                            continue;
-                        }
+    let mut dirs = config.library.get_dirs();
+    dirs.extend(args.dirs.iter().cloned());
 
-                        if (src_pixel & (0x80 >> x)) != 0 {
-                            let dst = (dst_x + x) + ((dst_y + y) * FRAMEBUFFER_WIDTH);
+    if dirs.is_empty() {
+        return Err("No ROM directories, pass them as arguments or add them to [library] in the config file"
+            .to_string());
+    }
 
-                            if self.framebuffer[dst] != 0 {
-                                self.registers[0xF] = 1;
-                            }
+    let library = Library::scan(&dirs, config::library_path(), &|rom| {
+        resolve_settings(&config, db.as_ref(), &args.emulator, rom, false)
+    });
 
-                            self.framebuffer[dst] ^= 0xFFFFFFFF;
-                        }
-                    }
-                }
+    let mut browser = Browser::new(library);
 
-                self.program_counter += 2;
-            }
-            0xE000 => {
-                let x = (self.opcode as usize & 0x0F00) >> 8;
-
-                match self.opcode & 0x00FF {
-                    // EX9E - Skips the next instruction if the key stored in VX is pressed
-                    0x009E => {
-                        if self.keys[x] {
-                            self.program_counter += 4;
-                        } else {
-                            self.program_counter += 2;
-                        }
-                    }
-                    // EXA1 - Skips the next instruction if the key stored in VX is not pressed
-                    0x00A1 => {
-                        if !self.keys[x] {
-                            self.program_counter += 4;
-                        } else {
-                            self.program_counter += 2;
-                        }
-                    }
-                    _ => panic!("Unknown instruction ({:04X})", self.opcode),
-                }
-            }
-            0xF000 => {
-                let x = (self.opcode as usize & 0x0F00) >> 8;
-
-                match self.opcode & 0x00FF {
-                    // FX07 - Sets VX to the value of the delay timer
-                    0x0007 => {
-                        self.registers[x] = self.delay_timer;
-                        self.program_counter += 2;
-                    }
-                    // FX0A - Sets VX to the next key press, blocking all other instructions until it is received
-                    0x000A => {
-                        if let Some(key) = self.last_key {
-                            self.registers[x] = key as u8;
-                            self.program_counter += 2;
-                        }
-                    }
-                    // FX15 - Sets the delay timer to VX
-                    0x0015 => {
-                        self.delay_timer = self.registers[x];
-                        self.program_counter += 2;
-                    }
-                    // FX18 - Sets the sound timer to VX
-                    0x0018 => {
-                        self.sound_timer = self.registers[x];
-                        self.program_counter += 2;
-                    }
-                    // FX1E - Sets I to VX + I
-                    0x001E => {
-                        self.index += self.registers[x] as u16;
-                        self.program_counter += 2;
-                    }
-                    // FX29 - Sets I to the location of the sprite for the character in VX
-                    0x0029 => {
-                        let c = self.registers[x] as u16;
-
-                        self.index = 0x050 + (c * 5);
-                        self.program_counter += 2;
-                    }
-                    // FX33 - Sets VX to the binary-coded deciaml representation of I
-                    0x0033 => {
-                        let x = self.registers[x];
-
-                        self.memory[self.index as usize] = x / 100;
-                        self.memory[self.index as usize + 1] = (x / 10) % 10;
-                        self.memory[self.index as usize + 2] = (x % 100) % 10;
-
-                        self.program_counter += 2;
-                    }
-                    // FX55 - Stores V0 to VX (including VX) in memory starting at address I
-                    0x0055 => {
-                        for x in 0..=x {
-                            self.memory[self.index as usize] = self.registers[x];
-                            self.index += 1;
-                        }
-
-                        self.program_counter += 2;
-                    }
-                    // FX65 - Fills V0 to VX (including VX) with values from memory starting at address I
-                    0x0065 => {
-                        for x in 0..=x {
-                            self.registers[x] = self.memory[self.index as usize];
-                            self.index += 1;
-                        }
-
-                        self.program_counter += 2;
-                    }
-                    _ => panic!("Unknown instruction ({:04X})", self.opcode),
+    let mut menu_settings = Settings::default();
+    menu_settings.apply_config(&config)?;
+    menu_settings.apply_emulator_args(&args.emulator);
+    menu_settings.apply_display_args(&args.display)?;
+
+    let mut frontend = sdl::SdlFrontend::new(&menu_settings)?;
+
+    loop {
+        let Some(inputs) = frontend.poll_menu() else {
+            return Ok(());
+        };
+
+        for input in inputs {
+            let index = match browser.handle_input(input) {
+                Some(BrowserAction::Launch(index)) => index,
+                Some(BrowserAction::Quit) => return Ok(()),
+                None => continue,
+            };
+
+            // Archives are represented by their first ROM, as they were when scanning
+            let path = browser.library.entries[index].path.clone();
+            let loaded = rom::load(&path, &mut |_| Ok(0)).and_then(|rom| {
+                let mut settings = resolve_settings(&config, db.as_ref(), &args.emulator, &rom, true)?;
+                settings.apply_display_args(&args.display)?;
+
+                let cpu = settings.create_cpu(&rom.data)?;
+
+                Ok((rom, settings, cpu))
+            });
+
+            let (rom, settings, cpu) = match loaded {
+                Ok(loaded) => loaded,
+                Err(err) => {
+                    eprintln!("error: {}", err);
+                    continue;
                 }
+            };
+
+            browser.launched(index);
+            frontend.configure(&settings);
+
+            // The frontend is taken back whatever happens, so that errors in a ROM only end that ROM
+            let mut app = Application::new(cpu, frontend, &settings);
+            let control = play(&mut app, &settings, &rom.data, true);
+
+            frontend = app.into_frontend();
+            frontend.configure(&menu_settings);
+
+            match control {
+                Ok(Control::Quit) => return Ok(()),
+                Ok(_) => (),
+                Err(err) => eprintln!("error: {} ({})", err, path.display()),
             }
-            _ => panic!("Unknown instruction ({:04X})", self.opcode),
         }
 
-        if self.delay_timer > 0 {
-            self.delay_timer -= 1;
-        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
 
-        if self.sound_timer > 0 {
-            if self.sound_timer == 1 {
-                self.beep_flag = true;
-            }
+        frontend.present_image(browser.render(now), VIEW_WIDTH, VIEW_HEIGHT);
 
-            self.sound_timer -= 1;
+        thread::sleep(Duration::from_millis(16));
+    }
+}
+
+#[cfg(not(feature = "sdl"))]
+fn browse(_args: BrowseArgs) -> Result<(), String> {
+    Err("The ROM browser needs a window (built without the \"sdl\" feature)".to_string())
+}
+
+fn headless(args: HeadlessArgs) -> Result<(), String> {
+    let rom = load_rom(&args.rom, &args.source)?;
+    let settings = load_settings(&args.emulator, &rom)?;
+    let upscaler = args.upscaler.unwrap_or(settings.upscaler);
+
+    let mut cpu = settings.create_cpu(&rom.data)?;
+    let mut tracer = create_tracer(&args.trace)?;
+
+    let mut recording = match &args.record {
+        Some(path) => Some((Recorder::create(path, upscaler).map_err(|err| record_error(path, err))?, path)),
+        None => None,
+    };
+
+    instrument(&mut cpu, &args.profile, &rom.data)?;
+
+    let mut script = match &args.script {
+        Some(path) => Some(Script::load(path, &mut cpu)?),
+        None => None,
+    };
+
+    for frame in 0..args.frames {
+        let result = match (&mut script, &mut tracer) {
+            (Some(script), tracer) => script
+                .run_frame(&mut cpu, settings.speed, tracer.as_mut())
+                .and_then(|_| report_script(script)),
+            (None, Some(tracer)) => tracer.run_frame(&mut cpu, settings.speed),
+            (None, None) => cpu.run_frame(settings.speed),
+        };
+
+        if let Err(err) = result {
+            write_reports(&cpu, &args.profile)?;
+            finish_recording(recording)?;
+            return Err(format!("{} (frame {}, PC {:04X})", err, frame, cpu.get_program_counter()));
         }
 
-        self.last_key = None;
+        if let Some((recorder, path)) = &mut recording {
+            recorder.add_frame(cpu.get_pixels()).map_err(|err| record_error(path, err))?;
+        }
     }
 
-    pub fn get_registers(&self) -> &[u8; 16] {
-        &self.registers
+    finish_recording(recording)?;
+
+    write_reports(&cpu, &args.profile)?;
+
+    if let Some(tracer) = &mut tracer {
+        tracer.flush()?;
     }
 
-    pub fn get_framebuffer(&self) -> &[u8] {
-        let len = self.framebuffer.len();
-        let ptr = self.framebuffer.as_ptr() as *const u8;
+    for row in cpu.get_lit_pixels().chunks_exact(FRAMEBUFFER_WIDTH) {
+        let line: String = row
+            .iter()
+            .map(|lit| if *lit { '█' } else { ' ' })
+            .collect();
 
-        unsafe {
-            std::slice::from_raw_parts(ptr, len * 4)
-        }
+        println!("{}", line.trim_end());
     }
 
-    pub fn get_stack(&self) -> &[u16; 16] {
-        &self.stack
+    if let Some(path) = args.screenshot {
+        let mut scaled = Vec::new();
+        let (width, height) = upscaler.apply(cpu.get_pixels(), FRAMEBUFFER_WIDTH, FRAMEBUFFER_HEIGHT, &mut scaled);
+
+        bmp::write_bmp(&path, &scaled, width, height)
+            .map_err(|err| format!("Failed to save screenshot to {}: {}", path.display(), err))?;
     }
 
-    pub fn get_opcode(&self) -> u16 {
-        self.opcode
+    Ok(())
+}
+
+fn finish_recording(recording: Option<(Recorder, &PathBuf)>) -> Result<(), String> {
+    match recording {
+        Some((recorder, path)) => recorder.finish().map_err(|err| record_error(path, err)),
+        None => Ok(()),
     }
+}
+
+fn record_error(path: &Path, err: io::Error) -> String {
+    format!("Failed to record to {}: {}", path.display(), err)
+}
 
-    pub fn get_program_counter(&self) -> u16 {
-        self.program_counter
+// Without a display scripts print to stderr, and their errors end the run
+fn report_script(script: &mut Script) -> Result<(), String> {
+    for message in script.take_messages() {
+        eprintln!("{}", message);
     }
 
-    pub fn get_program_index(&self) -> u16 {
-        self.index
+    match script.get_error() {
+        Some(_) => Err("The script stopped".to_string()),
+        None => Ok(()),
     }
+}
+
+fn coverage(args: CoverageArgs) -> Result<(), String> {
+    let rom = load_rom(&args.rom, &args.source)?.data;
+    let mut coverage = Coverage::new(&rom::hash(&rom));
 
-    pub fn get_stack_pointer(&self) -> usize {
-        self.stack_pointer
+    for path in &args.coverage {
+        coverage
+            .merge(&load_coverage(path)?)
+            .map_err(|err| format!("{}: {}", path.display(), err))?;
     }
 
-    pub fn set_key_state(&mut self, key: Keycode, pressed: bool) {
-        let i = match key {
-            Keycode::Num1 => 0x1,
-            Keycode::Num2 => 0x2,
-            Keycode::Num3 => 0x3,
-            Keycode::Num4 => 0xC,
-            Keycode::Q => 0x4,
-            Keycode::W => 0x5,
-            Keycode::E => 0x6,
-            Keycode::R => 0xD,
-            Keycode::A => 0x7,
-            Keycode::S => 0x8,
-            Keycode::D => 0x9,
-            Keycode::F => 0xE,
-            Keycode::Z => 0xA,
-            Keycode::X => 0x0,
-            Keycode::C => 0xB,
-            Keycode::V => 0xF,
-            _ => return,
-        };
+    match &args.html {
+        Some(path) => {
+            let title = args.rom.file_name().unwrap_or_default().to_string_lossy();
+
+            write_file(path, "coverage report", &|out| coverage.write_html(out, &rom, &title))
+        }
+        None => coverage
+            .write_listing(&mut io::stdout().lock(), &rom)
+            .map_err(|err| format!("Failed to write coverage report: {}", err)),
+    }
+}
+
+fn control_flow(args: CfgArgs) -> Result<(), String> {
+    let rom = load_rom(&args.rom, &args.source)?.data;
+    let graph = flow::analyze(&rom);
+    let mut out = io::stdout().lock();
+
+    let result = if args.dot {
+        graph.write_dot(&mut out)
+    } else if args.calls {
+        graph.write_call_graph_dot(&mut out)
+    } else {
+        graph.write_summary(&mut out)
+    };
+
+    result.map_err(|err| format!("Failed to write control flow: {}", err))
+}
+
+fn gdb(args: GdbArgs) -> Result<(), String> {
+    let rom = load_rom(&args.rom, &args.source)?;
+    let settings = load_settings(&args.emulator, &rom)?;
+    let cpu = settings.create_cpu(&rom.data)?;
+
+    let listener = TcpListener::bind(("127.0.0.1", args.port))
+        .map_err(|err| format!("Failed to listen on port {}: {}", args.port, err))?;
+
+    eprintln!("Waiting for gdb on 127.0.0.1:{} (target remote :{})", args.port, args.port);
+
+    let (stream, addr) = listener.accept().map_err(|err| format!("Failed to accept connection: {}", err))?;
+
+    eprintln!("Connected to {}", addr);
+
+    GdbServer::new(Debugger::new(cpu, settings.speed), stream).serve()
+}
+
+fn debug_adapter(args: DapArgs) -> Result<(), String> {
+    let Some(port) = args.port else {
+        return dap::serve(Box::new(io::stdin()), Box::new(io::stdout()), &args.emulator);
+    };
+
+    let listener = TcpListener::bind(("127.0.0.1", port))
+        .map_err(|err| format!("Failed to listen on port {}: {}", port, err))?;
+
+    eprintln!("Waiting for a debugger on 127.0.0.1:{}", port);
+
+    let (stream, _) = listener.accept().map_err(|err| format!("Failed to accept connection: {}", err))?;
+    let input = stream.try_clone().map_err(|err| format!("Failed to accept connection: {}", err))?;
 
-        self.keys[i] = pressed;
-        self.last_key = Some(i);
+    dap::serve(Box::new(input), Box::new(stream), &args.emulator)
+}
+
+fn rpc(args: RpcArgs) -> Result<(), String> {
+    let mut machine = None;
+    let mut speed = 1;
+
+    if let Some(path) = &args.rom {
+        let rom = load_rom(path, &args.source)?;
+        let settings = load_settings(&args.emulator, &rom)?;
+
+        machine = Some(settings.create_cpu(&rom.data)?);
+        speed = settings.speed;
     }
 
-    fn rand(&mut self) -> u8 {
-        (self.random.next_u32() & 0x000000FF) as u8
+    let mut server = RpcServer::listen(&args.listen, create_loader(&args.emulator))?;
+
+    if let Some(cpu) = &machine {
+        server.set_initial(cpu, speed);
     }
+
+    eprintln!("Listening for JSON-RPC requests on {}", args.listen);
+
+    server.serve(&mut machine);
+
+    Ok(())
+}
+
+// Starts a server for a window, which resets go back to the ROM it was started with
+fn listen_rpc(addr: &str, args: &EmulatorArgs, cpu: &Chip8, speed: u32) -> Result<RpcServer, String> {
+    let mut server = RpcServer::listen(addr, create_loader(args))?;
+
+    server.set_initial(cpu, speed);
+    eprintln!("Listening for JSON-RPC requests on {}", addr);
+
+    Ok(server)
+}
+
+// ROMs loaded by clients are set up like the ones given on the command line
+fn create_loader(args: &EmulatorArgs) -> Loader {
+    let args = args.clone();
+
+    Box::new(move |path| {
+        let rom = load_rom(path, &SourceArgs::default())?;
+        let settings = load_settings(&args, &rom)?;
+
+        Ok((settings.create_cpu(&rom.data)?, settings.speed))
+    })
+}
+
+fn compare(args: CompareArgs) -> Result<(), String> {
+    let rom = load_rom(&args.rom, &args.source)?;
+    let settings = load_settings(&args.emulator, &rom)?;
+
+    let text = std::fs::read_to_string(&args.reference)
+        .map_err(|err| format!("Failed to read reference trace {}: {}", args.reference.display(), err))?;
+    let reference = compare::parse_reference(&text)
+        .map_err(|err| format!("Failed to parse reference trace {}: {}", args.reference.display(), err))?;
+
+    let mut cpu = settings.create_cpu(&rom.data)?;
+
+    match compare::compare(&mut cpu, &reference, settings.speed, args.context) {
+        Outcome::Match(steps) => {
+            println!("No divergence in {} steps", steps);
+            Ok(())
+        }
+        Outcome::Diverged(divergence) => {
+            print!("{}", divergence);
+            Err(format!("Traces diverge at step {}", divergence.step))
+        }
+    }
+}
+
+fn disassemble(path: &Path, source: &SourceArgs) -> Result<(), String> {
+    let rom = load_rom(path, source)?.data;
+
+    for (i, word) in rom.chunks(2).enumerate() {
+        let addr = ROM_START + (i * 2);
+
+        match *word {
+            [hi, lo] => {
+                let opcode = (hi as u16) << 8 | lo as u16;
+                let text = disasm::disassemble(opcode).unwrap_or_else(|| "???".to_string());
+
+                println!("{:03X}:  {:04X}  {}", addr, opcode, text);
+            }
+            [b] => println!("{:03X}:  {:02X}", addr, b),
+            _ => unreachable!(),
+        }
+    }
+
+    Ok(())
+}
+
+fn info(path: &Path, source: &SourceArgs) -> Result<(), String> {
+    let Rom { data: rom, format, profile: embedded } = load_rom(path, source)?;
+
+    let capacity = 4096 - ROM_START;
+    let known = rom
+        .chunks_exact(2)
+        .filter(|word| disasm::disassemble((word[0] as u16) << 8 | word[1] as u16).is_some())
+        .count();
+
+    println!("File:          {}", path.display());
+    println!("Format:        {}", format);
+    println!("SHA-1:         {}", rom::hash(&rom));
+    println!("Size:          {} bytes ({:.1}% of {} available)", rom.len(),
+             (rom.len() as f32 / capacity as f32) * 100.0, capacity);
+    println!("Instructions:  {} of {} words decode to known instructions", known, rom.len() / 2);
+
+    if rom.len() > capacity {
+        println!("Warning:       ROM is too large to be loaded");
+    }
+
+    if let Some(profile) = load_database()?.get_profile(&rom::hash(&rom)).or(embedded) {
+        println!("Title:         {}", profile.title);
+        println!("Platform:      {}", profile.platform.as_deref().unwrap_or("unknown"));
+        println!("Quirks:        {}", profile.quirks);
+
+        if let Some(tickrate) = profile.tickrate {
+            println!("Tickrate:      {}", tickrate);
+        }
+    }
+
+    Ok(())
 }
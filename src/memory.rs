@@ -0,0 +1,336 @@
+use chip8_rs::Chip8;
+
+use crate::app::MenuInput;
+use crate::osd::{Highlight, PanelView};
+
+const MEMORY_SIZE: usize = 4096;
+const ROW_BYTES: usize = 16;
+const VISIBLE_ROWS: usize = 16;
+
+// How many frames a byte stays highlighted after it was written
+const FLASH_FRAMES: u8 = 30;
+
+// Registers which can be edited, in the order they are shown
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Register {
+    V(usize),
+    Index,
+    ProgramCounter,
+    DelayTimer,
+    SoundTimer,
+}
+
+const REGISTERS: [Register; 20] = [
+    Register::V(0x0),
+    Register::V(0x1),
+    Register::V(0x2),
+    Register::V(0x3),
+    Register::V(0x4),
+    Register::V(0x5),
+    Register::V(0x6),
+    Register::V(0x7),
+    Register::V(0x8),
+    Register::V(0x9),
+    Register::V(0xA),
+    Register::V(0xB),
+    Register::V(0xC),
+    Register::V(0xD),
+    Register::V(0xE),
+    Register::V(0xF),
+    Register::Index,
+    Register::ProgramCounter,
+    Register::DelayTimer,
+    Register::SoundTimer,
+];
+
+// Registers per line of the view
+const REGISTER_COLUMNS: usize = 8;
+
+impl Register {
+    fn get_name(&self) -> String {
+        match self {
+            Register::V(x) => format!("V{:X}", x),
+            Register::Index => "I".to_string(),
+            Register::ProgramCounter => "PC".to_string(),
+            Register::DelayTimer => "DT".to_string(),
+            Register::SoundTimer => "ST".to_string(),
+        }
+    }
+
+    // Number of hex digits the value is shown and entered with
+    fn get_digits(&self) -> usize {
+        match self {
+            Register::Index | Register::ProgramCounter => 3,
+            _ => 2,
+        }
+    }
+
+    fn get(&self, cpu: &Chip8) -> u16 {
+        match self {
+            Register::V(x) => cpu.get_registers()[*x] as u16,
+            Register::Index => cpu.get_program_index(),
+            Register::ProgramCounter => cpu.get_program_counter(),
+            Register::DelayTimer => cpu.get_delay_timer() as u16,
+            Register::SoundTimer => cpu.get_sound_timer() as u16,
+        }
+    }
+
+    fn set(&self, cpu: &mut Chip8, value: u16) {
+        match self {
+            Register::V(x) => cpu.set_register(*x, value as u8),
+            Register::Index => cpu.set_program_index(value),
+            Register::ProgramCounter => cpu.set_program_counter(value),
+            Register::DelayTimer => cpu.set_delay_timer(value as u8),
+            Register::SoundTimer => cpu.set_sound_timer(value as u8),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq)]
+enum Mode {
+    // Moving around memory, hex digits overwrite the byte under the cursor
+    Memory,
+    // Moving between the registers, hex digits overwrite the selected one
+    Registers,
+    // Entering an address to jump to
+    GoTo,
+}
+
+// What the memory viewer asks of the application
+pub enum MemoryAction {
+    Close,
+    Pause,
+    Step,
+    Message(String),
+}
+
+// A hex editor over the whole memory, which follows the program while it runs and lets the
+// memory and registers be changed while it is paused
+pub struct MemoryEditor {
+    cursor: usize,
+    top: usize,
+    mode: Mode,
+    register: usize,
+    // Hex digits typed so far
+    entry: String,
+    // The memory as of the last update, and for how many more frames each byte is highlighted
+    previous: Vec<u8>,
+    flash: Vec<u8>,
+}
+
+impl Default for MemoryEditor {
+    fn default() -> Self {
+        MemoryEditor {
+            cursor: 0x200,
+            top: 0x200 / ROW_BYTES,
+            mode: Mode::Memory,
+            register: 0,
+            entry: String::new(),
+            previous: Vec::new(),
+            flash: vec![0; MEMORY_SIZE],
+        }
+    }
+}
+
+impl MemoryEditor {
+    // Opens where it was left, without highlighting what changed in the meantime
+    pub fn open(&mut self, cpu: &Chip8) {
+        self.previous = cpu.get_memory().to_vec();
+        self.flash.fill(0);
+        self.mode = Mode::Memory;
+        self.entry.clear();
+    }
+
+    // Highlights the bytes written since the last update, once per frame
+    pub fn update(&mut self, cpu: &Chip8) {
+        for ((flash, old), new) in self.flash.iter_mut().zip(self.previous.iter_mut()).zip(cpu.get_memory()) {
+            if old != new {
+                *flash = FLASH_FRAMES;
+                *old = *new;
+            } else {
+                *flash = flash.saturating_sub(1);
+            }
+        }
+    }
+
+    pub fn handle_input(&mut self, input: MenuInput, cpu: &mut Chip8, paused: bool) -> Option<MemoryAction> {
+        let digit = match input {
+            MenuInput::Char(c) => c.to_digit(16),
+            _ => None,
+        };
+
+        match (&self.mode, input) {
+            // Typing clears before anything closes
+            (_, MenuInput::Back) if !self.entry.is_empty() => self.entry.clear(),
+            (Mode::Memory, MenuInput::Back) => return Some(MemoryAction::Close),
+            (_, MenuInput::Back) => self.mode = Mode::Memory,
+            (Mode::GoTo, MenuInput::Select) => {
+                if let Ok(addr) = usize::from_str_radix(&self.entry, 16) {
+                    self.move_cursor(addr as isize - self.cursor as isize);
+                }
+
+                self.entry.clear();
+                self.mode = Mode::Memory;
+            }
+            (Mode::GoTo, _) if self.entry.len() < 3 => self.enter_digit(digit),
+            (Mode::GoTo, _) => (),
+            (_, MenuInput::Char('g')) => {
+                self.entry.clear();
+                self.mode = Mode::GoTo;
+            }
+            (Mode::Memory, MenuInput::Char('r')) => {
+                self.entry.clear();
+                self.mode = Mode::Registers;
+            }
+            (Mode::Registers, MenuInput::Char('r')) => {
+                self.entry.clear();
+                self.mode = Mode::Memory;
+            }
+            (_, MenuInput::Char('p')) => return Some(MemoryAction::Pause),
+            (_, MenuInput::Char('s')) => return Some(MemoryAction::Step),
+            (_, MenuInput::Char(_)) if digit.is_some() && !paused => {
+                return Some(MemoryAction::Message("Pause to edit (P)".to_string()));
+            }
+            (Mode::Memory, _) if digit.is_some() => {
+                self.enter_digit(digit);
+
+                if self.entry.len() == 2 {
+                    let value = u8::from_str_radix(&self.entry, 16).expect("Entries are hex digits");
+
+                    cpu.set_byte(self.cursor as u16, value);
+                    self.entry.clear();
+                    self.move_cursor(1);
+                }
+            }
+            (Mode::Registers, _) if digit.is_some() => {
+                let register = REGISTERS[self.register];
+
+                self.enter_digit(digit);
+
+                if self.entry.len() == register.get_digits() {
+                    let value = u16::from_str_radix(&self.entry, 16).expect("Entries are hex digits");
+
+                    register.set(cpu, value);
+                    self.entry.clear();
+                    self.register = (self.register + 1) % REGISTERS.len();
+                }
+            }
+            (Mode::Memory, MenuInput::Up) => self.move_cursor(-(ROW_BYTES as isize)),
+            (Mode::Memory, MenuInput::Down) => self.move_cursor(ROW_BYTES as isize),
+            (Mode::Memory, MenuInput::Left) => self.move_cursor(-1),
+            (Mode::Memory, MenuInput::Right) => self.move_cursor(1),
+            (Mode::Registers, MenuInput::Up) => self.move_register(-(REGISTER_COLUMNS as isize)),
+            (Mode::Registers, MenuInput::Down) => self.move_register(REGISTER_COLUMNS as isize),
+            (Mode::Registers, MenuInput::Left) => self.move_register(-1),
+            (Mode::Registers, MenuInput::Right) => self.move_register(1),
+            _ => (),
+        }
+
+        None
+    }
+
+    fn enter_digit(&mut self, digit: Option<u32>) {
+        self.entry.extend(digit.and_then(|d| char::from_digit(d, 16)).map(|c| c.to_ascii_uppercase()));
+    }
+
+    fn move_cursor(&mut self, offset: isize) {
+        self.cursor = (self.cursor as isize + offset).clamp(0, MEMORY_SIZE as isize - 1) as usize;
+        self.entry.clear();
+
+        let row = self.cursor / ROW_BYTES;
+
+        if row < self.top {
+            self.top = row;
+        } else if row >= self.top + VISIBLE_ROWS {
+            self.top = row + 1 - VISIBLE_ROWS;
+        }
+    }
+
+    fn move_register(&mut self, offset: isize) {
+        self.register = (self.register as isize + offset).rem_euclid(REGISTERS.len() as isize) as usize;
+        self.entry.clear();
+    }
+
+    pub fn view(&self, cpu: &Chip8, paused: bool) -> PanelView {
+        let memory = cpu.get_memory();
+        let pc = cpu.get_program_counter() as usize;
+        let index = cpu.get_program_index() as usize;
+        let stack = &cpu.get_stack()[..cpu.get_stack_pointer()];
+
+        let highlight = |addr: usize| {
+            if addr == self.cursor && self.mode != Mode::Registers {
+                Highlight::Cursor
+            } else if addr == pc || addr == pc + 1 {
+                Highlight::ProgramCounter
+            } else if addr == index {
+                Highlight::Index
+            } else if stack.iter().any(|ret| addr == *ret as usize || addr == *ret as usize + 1) {
+                Highlight::Stack
+            } else if self.flash[addr] > 0 {
+                Highlight::Written
+            } else {
+                Highlight::None
+            }
+        };
+
+        let mut lines = Vec::new();
+
+        for row in self.top..self.top + VISIBLE_ROWS {
+            let start = row * ROW_BYTES;
+            let mut line = vec![(format!("{:03X} ", start), Highlight::None)];
+
+            for (addr, byte) in memory.iter().enumerate().skip(start).take(ROW_BYTES) {
+                let text = match self.mode {
+                    Mode::Memory if addr == self.cursor && !self.entry.is_empty() => format!("{}_", self.entry),
+                    _ => format!("{:02X}", byte),
+                };
+
+                let gap = if addr % 8 == 0 { "  " } else { " " };
+
+                line.push((gap.to_string(), Highlight::None));
+                line.push((text, highlight(addr)));
+            }
+
+            lines.push(line);
+        }
+
+        lines.push(Vec::new());
+
+        for registers in REGISTERS.iter().enumerate().collect::<Vec<_>>().chunks(REGISTER_COLUMNS) {
+            let mut line = Vec::new();
+
+            for (i, register) in registers {
+                let digits = register.get_digits();
+                let selected = self.mode == Mode::Registers && *i == self.register;
+
+                let value = if selected && !self.entry.is_empty() {
+                    format!("{:_<digits$}", self.entry)
+                } else {
+                    format!("{:0digits$X}", register.get(cpu))
+                };
+
+                let gap = if line.is_empty() { "" } else { " " };
+
+                line.push((format!("{}{}=", gap, register.get_name()), Highlight::None));
+                line.push((value, if selected { Highlight::Cursor } else { Highlight::None }));
+            }
+
+            lines.push(line);
+        }
+
+        let help = match self.mode {
+            Mode::GoTo => format!("Go to: {}_", self.entry),
+            Mode::Registers => "Arrows: select  0-F: set  R: memory  P: pause  S: step  Esc: back".to_string(),
+            Mode::Memory => "Arrows: move  0-F: poke  G: go to  R: registers  P: pause  S: step".to_string(),
+        };
+
+        lines.push(Vec::new());
+        lines.push(vec![(help, Highlight::None)]);
+
+        PanelView {
+            title: format!("Memory {:03X}  SP={:X}  {}", self.cursor, stack.len(),
+                           if paused { "(paused)" } else { "(running)" }),
+            lines,
+        }
+    }
+}
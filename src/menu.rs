@@ -0,0 +1,148 @@
+use chip8_rs::chip8::Quirks;
+
+use crate::app::MenuInput;
+use crate::osd::MenuView;
+
+pub const SLOT_COUNT: usize = 4;
+
+// Speeds offered by the menu, as percentages of the speed the ROM was started with
+pub const SPEEDS: [u32; 9] = [25, 50, 75, 100, 150, 200, 300, 400, 800];
+pub const DEFAULT_SPEED: usize = 3;
+
+// Palettes offered by the menu, the first one is whichever the ROM was started with
+pub const PALETTES: [(&str, Option<[u32; 2]>); 6] = [
+    ("Default", None),
+    ("White", Some([0x000000, 0xFFFFFF])),
+    ("Amber", Some([0x1A0F00, 0xFFB000])),
+    ("Green", Some([0x051A05, 0x33FF66])),
+    ("LCD", Some([0x9BBC0F, 0x0F380F])),
+    ("Paper", Some([0xF0EAD8, 0x202020])),
+];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Item {
+    Resume,
+    Reset,
+    Speed,
+    Palette,
+    Quirk(&'static str),
+    Slot,
+    Save,
+    Load,
+    Quit,
+}
+
+const ITEMS: [Item; 15] = [
+    Item::Resume,
+    Item::Reset,
+    Item::Speed,
+    Item::Palette,
+    Item::Quirk(Quirks::NAMES[0]),
+    Item::Quirk(Quirks::NAMES[1]),
+    Item::Quirk(Quirks::NAMES[2]),
+    Item::Quirk(Quirks::NAMES[3]),
+    Item::Quirk(Quirks::NAMES[4]),
+    Item::Quirk(Quirks::NAMES[5]),
+    Item::Quirk(Quirks::NAMES[6]),
+    Item::Slot,
+    Item::Save,
+    Item::Load,
+    Item::Quit,
+];
+
+// What choosing a menu item asks of the application, changes are by one step in either direction
+pub enum MenuAction {
+    Resume,
+    Reset,
+    Quit,
+    Speed(isize),
+    Palette(isize),
+    ToggleQuirk(&'static str),
+    Save(usize),
+    Load(usize),
+}
+
+// The state the menu shows next to its items
+pub struct MenuStatus<'a> {
+    pub speed: usize,
+    pub palette: usize,
+    pub quirks: Quirks,
+    pub slots: &'a [bool],
+}
+
+#[derive(Default)]
+pub struct PauseMenu {
+    selected: usize,
+    slot: usize,
+}
+
+impl PauseMenu {
+    // Opens on the first item, but remembers the save slot
+    pub fn open(&mut self) {
+        self.selected = 0;
+    }
+
+    pub fn handle_input(&mut self, input: MenuInput) -> Option<MenuAction> {
+        let item = ITEMS[self.selected];
+
+        let step = match input {
+            MenuInput::Up => {
+                self.selected = (self.selected + ITEMS.len() - 1) % ITEMS.len();
+                return None;
+            }
+            MenuInput::Down => {
+                self.selected = (self.selected + 1) % ITEMS.len();
+                return None;
+            }
+            MenuInput::Back => return Some(MenuAction::Resume),
+            MenuInput::Left => -1,
+            MenuInput::Right | MenuInput::Select => 1,
+            MenuInput::Favourite | MenuInput::Char(_) => return None,
+        };
+
+        match item {
+            Item::Speed => Some(MenuAction::Speed(step)),
+            Item::Palette => Some(MenuAction::Palette(step)),
+            Item::Slot => {
+                self.slot = (self.slot as isize + step).rem_euclid(SLOT_COUNT as isize) as usize;
+                None
+            }
+            // The rest only react to being chosen, not to left and right
+            _ if input != MenuInput::Select => None,
+            Item::Resume => Some(MenuAction::Resume),
+            Item::Reset => Some(MenuAction::Reset),
+            Item::Quirk(name) => Some(MenuAction::ToggleQuirk(name)),
+            Item::Save => Some(MenuAction::Save(self.slot)),
+            Item::Load => Some(MenuAction::Load(self.slot)),
+            Item::Quit => Some(MenuAction::Quit),
+        }
+    }
+
+    pub fn view(&self, status: &MenuStatus) -> MenuView {
+        let on_off = |enabled| if enabled { "on" } else { "off" };
+
+        let items = ITEMS
+            .iter()
+            .map(|item| match *item {
+                Item::Resume => "Resume".to_string(),
+                Item::Reset => "Reset".to_string(),
+                Item::Speed => format!("Speed         < {}% >", SPEEDS[status.speed]),
+                Item::Palette => format!("Palette       < {} >", PALETTES[status.palette].0),
+                Item::Quirk(name) => format!("Quirk {:<15}{}", name, on_off(status.quirks.get(name))),
+                Item::Slot => {
+                    let used = if status.slots[self.slot] { "" } else { " (empty)" };
+                    format!("Slot          < {} >{}", self.slot + 1, used)
+                }
+                Item::Save => "Save state".to_string(),
+                Item::Load => "Load state".to_string(),
+                Item::Quit => "Quit".to_string(),
+            })
+            .collect();
+
+        MenuView {
+            title: "Paused".to_string(),
+            items,
+            selected: self.selected,
+        }
+    }
+}
@@ -0,0 +1,1026 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt::Display;
+
+use crate::symbols::SymbolMap;
+
+// An assembler for the Octo language (https://github.com/JohnEarnest/Octo), limited to the
+// instructions of the original CHIP-8. Octo cartridges only contain the source of a program, so
+// it has to be compiled before it can run.
+
+const PROGRAM_START: usize = 0x200;
+const MEMORY_SIZE: usize = 4096;
+
+// Guards against macros which expand into themselves forever
+const MAX_MACRO_EXPANSIONS: usize = 65536;
+
+// Statements and directives which only exist in the SUPER-CHIP and XO-CHIP extensions
+const UNSUPPORTED: [&str; 16] = [
+    "hires", "lores", "scroll-down", "scroll-up", "scroll-left", "scroll-right", "exit", "saveflags",
+    "loadflags", "plane", "audio", "pitch", "bighex", "long", ":pointer", ":stringmode",
+];
+
+const BINARY_OPERATORS: [&str; 19] = [
+    "+", "-", "*", "/", "%", "&", "|", "^", "<<", ">>", "pow", "min", "max", "<", ">", "<=", ">=", "==", "!=",
+];
+
+const UNARY_OPERATORS: [&str; 14] = [
+    "-", "~", "!", "sin", "cos", "tan", "exp", "log", "abs", "sqrt", "sign", "ceil", "floor", "@",
+];
+
+pub fn assemble(source: &str) -> Result<Vec<u8>, String> {
+    Assembler::new(source).run().map(|(rom, _)| rom)
+}
+
+// Assembles a program along with the source line of every instruction and its labels, with the
+// lines attributed to the given file name
+pub fn assemble_with_symbols(source: &str, file: &str) -> Result<(Vec<u8>, SymbolMap), String> {
+    let mut assembler = Assembler::new(source);
+    assembler.file = file.to_string();
+    assembler.run()
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+// Tokens are separated by whitespace, except for strings which are kept whole including the
+// quotes. Comments start with a # and run to the end of the line.
+fn tokenize(source: &str) -> VecDeque<Token> {
+    let mut tokens = VecDeque::new();
+
+    for (i, line) in source.lines().enumerate() {
+        let mut chars = line.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            if c.is_whitespace() {
+                continue;
+            }
+
+            if c == '#' {
+                break;
+            }
+
+            let mut text = String::from(c);
+
+            if c == '"' {
+                for c in chars.by_ref() {
+                    text.push(c);
+
+                    if c == '"' {
+                        break;
+                    }
+                }
+            } else {
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                    text.push(c);
+                }
+            }
+
+            tokens.push_back(Token { text, line: i + 1 });
+        }
+    }
+
+    tokens
+}
+
+fn parse_number(text: &str) -> Option<f64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = digits.strip_prefix("0b").or_else(|| digits.strip_prefix("0B")) {
+        i64::from_str_radix(bin, 2).ok()?
+    } else if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+
+    Some(if negative { -value } else { value } as f64)
+}
+
+#[derive(Clone, Copy)]
+enum FixupKind {
+    // The lower 12 bits of an instruction
+    Address,
+    // The immediates of the two instructions generated by :unpack
+    Unpack(u8),
+}
+
+// A reference to a label which hadn't been defined yet when it was used
+struct Fixup {
+    addr: usize,
+    name: String,
+    line: usize,
+    kind: FixupKind,
+}
+
+enum Block {
+    // Address of the jump over the body of an if ... begin or else block
+    If(usize),
+    Else(usize),
+    // Start of the loop and the addresses of the jumps out of it for each while
+    Loop(usize, Vec<usize>),
+}
+
+#[derive(Clone, Copy)]
+enum Operand {
+    Register(u8),
+    Byte(u8),
+}
+
+#[derive(Clone)]
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+    calls: usize,
+}
+
+struct Assembler {
+    tokens: VecDeque<Token>,
+    line: usize,
+    memory: Vec<Option<u8>>,
+    here: usize,
+    labels: HashMap<String, usize>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    blocks: Vec<(Block, usize)>,
+    expansions: usize,
+    // Source line of the first instruction emitted at each address
+    file: String,
+    lines: BTreeMap<usize, usize>,
+}
+
+impl Assembler {
+    fn new(source: &str) -> Self {
+        // Registers used by the code generated for :unpack and comparisons, which programs may
+        // change with :alias
+        let aliases = [("unpack-hi", 0x0), ("unpack-lo", 0x1), ("compare-temp", 0xF)]
+            .into_iter()
+            .map(|(name, reg)| (name.to_string(), reg))
+            .collect();
+
+        Assembler {
+            tokens: tokenize(source),
+            line: 0,
+            memory: vec![None; MEMORY_SIZE],
+            here: PROGRAM_START,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases,
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            blocks: Vec::new(),
+            expansions: 0,
+            file: String::new(),
+            lines: BTreeMap::new(),
+        }
+    }
+
+    fn run(mut self) -> Result<(Vec<u8>, SymbolMap), String> {
+        // The first instruction jumps to main, unless the program starts with it
+        let starts_with_main = self.tokens.len() >= 2
+            && self.tokens[0].text == ":"
+            && self.tokens[1].text == "main";
+
+        if !starts_with_main {
+            self.fixups.push(Fixup { addr: self.here, name: "main".to_string(), line: 1, kind: FixupKind::Address });
+            self.emit_word(0x1000)?;
+        }
+
+        while !self.tokens.is_empty() {
+            self.statement()?;
+        }
+
+        if let Some((block, line)) = self.blocks.last() {
+            let expected = if matches!(block, Block::Loop(..)) { "again" } else { "end" };
+
+            return Err(format!("Line {}: Block is missing its '{}'", line, expected));
+        }
+
+        if !self.labels.contains_key("main") {
+            return Err("Program has no 'main' label".to_string());
+        }
+
+        for fixup in &self.fixups {
+            let target = *self.labels
+                .get(&fixup.name)
+                .ok_or_else(|| format!("Line {}: Undefined name '{}'", fixup.line, fixup.name))?;
+
+            let byte = |memory: &[Option<u8>], addr: usize| memory[addr].unwrap_or(0);
+
+            match fixup.kind {
+                FixupKind::Address => {
+                    let opcode = byte(&self.memory, fixup.addr) & 0xF0;
+
+                    self.memory[fixup.addr] = Some(opcode | (target >> 8) as u8);
+                    self.memory[fixup.addr + 1] = Some(target as u8);
+                }
+                FixupKind::Unpack(nibble) => {
+                    self.memory[fixup.addr + 1] = Some(nibble << 4 | (target >> 8) as u8);
+                    self.memory[fixup.addr + 3] = Some(target as u8);
+                }
+            }
+        }
+
+        let end = self.memory
+            .iter()
+            .rposition(|b| b.is_some())
+            .map(|pos| pos + 1)
+            .unwrap_or(PROGRAM_START);
+
+        let mut symbols = SymbolMap::default();
+
+        // The jump to main added before the program has no line
+        for (addr, line) in self.lines.iter().filter(|(_, line)| **line > 0) {
+            symbols.add_line(*addr as u16, &self.file, *line);
+        }
+
+        for (name, addr) in &self.labels {
+            symbols.add_label(*addr as u16, name);
+        }
+
+        Ok((self.memory[PROGRAM_START..end].iter().map(|b| b.unwrap_or(0)).collect(), symbols))
+    }
+
+    fn error<T>(&self, message: impl Display) -> Result<T, String> {
+        Err(format!("Line {}: {}", self.line, message))
+    }
+
+    fn next_token(&mut self) -> Result<Token, String> {
+        match self.tokens.pop_front() {
+            Some(token) => {
+                self.line = token.line;
+                Ok(token)
+            }
+            None => self.error("Unexpected end of program"),
+        }
+    }
+
+    fn next(&mut self) -> Result<String, String> {
+        Ok(self.next_token()?.text)
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.front().map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        let token = self.next()?;
+
+        if token != expected {
+            return self.error(format!("Expected '{}' but found '{}'", expected, token));
+        }
+
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<(), String> {
+        let token = self.next()?;
+
+        if self.macros.contains_key(&token) {
+            return self.expand_macro(&token);
+        }
+
+        match token.as_str() {
+            ":" => {
+                let name = self.name()?;
+                self.define_label(name, self.here)
+            }
+            ":next" => {
+                let name = self.name()?;
+                self.define_label(name, self.here + 1)
+            }
+            ":const" => {
+                let name = self.name()?;
+                let value = self.value()?;
+                self.define_constant(name, value)
+            }
+            ":calc" => {
+                let name = self.name()?;
+                let value = self.calc()?;
+                self.define_constant(name, value)
+            }
+            ":alias" => {
+                let name = self.name()?;
+
+                let reg = if self.peek() == Some("{") {
+                    let value = self.calc()?;
+
+                    if !(0.0..=15.0).contains(&value) {
+                        return self.error(format!("Register index out of range ({})", value));
+                    }
+
+                    value as u8
+                } else {
+                    self.register()?
+                };
+
+                self.aliases.insert(name, reg);
+                Ok(())
+            }
+            ":unpack" => {
+                if self.peek() == Some("long") {
+                    return self.unsupported("long");
+                }
+
+                let nibble = self.value()?;
+
+                if !(0.0..=15.0).contains(&nibble) {
+                    return self.error(format!("Value out of range for a nibble ({})", nibble));
+                }
+
+                let hi = self.aliases["unpack-hi"] as u16;
+                let lo = self.aliases["unpack-lo"] as u16;
+                let token = self.next()?;
+                let addr = self.address_of(&token, FixupKind::Unpack(nibble as u8))?;
+
+                self.emit_word(0x6000 | hi << 8 | (nibble as u16) << 4 | addr >> 8)?;
+                self.emit_word(0x6000 | lo << 8 | (addr & 0xFF))
+            }
+            ":org" => {
+                let value = self.value()?;
+                let addr = self.to_address(value)? as usize;
+
+                if addr < PROGRAM_START {
+                    return self.error(format!("Address {:03X} is reserved for the interpreter", addr));
+                }
+
+                self.here = addr;
+                Ok(())
+            }
+            ":byte" => {
+                let value = if self.peek() == Some("{") { self.calc()? } else { self.value()? };
+                let byte = self.to_byte(value)?;
+                self.emit_byte(byte)
+            }
+            ":call" => self.emit_address(0x0000),
+            ":macro" => {
+                let name = self.name()?;
+                let mut params = Vec::new();
+
+                loop {
+                    match self.next()?.as_str() {
+                        "{" => break,
+                        param => params.push(param.to_string()),
+                    }
+                }
+
+                let body = self.block_tokens()?;
+                self.macros.insert(name, Macro { params, body, calls: 0 });
+                Ok(())
+            }
+            ":assert" => {
+                let message = match self.peek() {
+                    Some(text) if text.starts_with('"') => {
+                        let text = text.trim_matches('"').to_string();
+                        self.next()?;
+                        text
+                    }
+                    _ => String::new(),
+                };
+
+                if self.calc()? == 0.0 {
+                    return self.error(format!("Assertion failed {}", message).trim_end());
+                }
+
+                Ok(())
+            }
+            // Debugger directives have no effect on the program
+            ":breakpoint" => self.next().map(drop),
+            ":monitor" => {
+                self.next()?;
+                self.next().map(drop)
+            }
+            "return" | ";" => self.emit_word(0x00EE),
+            "clear" => self.emit_word(0x00E0),
+            "jump" => self.emit_address(0x1000),
+            "jump0" => self.emit_address(0xB000),
+            "bcd" => self.emit_register_op(0xF033),
+            "save" | "load" => {
+                let x = self.register()? as u16;
+
+                if self.peek() == Some("-") {
+                    return self.unsupported("save/load ranges");
+                }
+
+                self.emit_word(if token == "save" { 0xF055 } else { 0xF065 } | x << 8)
+            }
+            "sprite" => {
+                let x = self.register()? as u16;
+                let y = self.register()? as u16;
+                let value = self.value()?;
+
+                if !(0.0..=15.0).contains(&value) {
+                    return self.error(format!("Sprite height out of range ({})", value));
+                }
+
+                self.emit_word(0xD000 | x << 8 | y << 4 | value as u16)
+            }
+            "delay" | "buzzer" => {
+                self.expect(":=")?;
+                self.emit_register_op(if token == "delay" { 0xF015 } else { 0xF018 })
+            }
+            "i" => match self.next()?.as_str() {
+                ":=" => match self.peek() {
+                    Some("hex") => {
+                        self.next()?;
+                        self.emit_register_op(0xF029)
+                    }
+                    Some(word @ ("bighex" | "long")) => {
+                        let word = word.to_string();
+                        self.unsupported(&word)
+                    }
+                    _ => self.emit_address(0xA000),
+                },
+                "+=" => self.emit_register_op(0xF01E),
+                op => self.error(format!("Unknown operator for i ({})", op)),
+            },
+            "if" => {
+                let (x, op, rhs) = self.condition()?;
+
+                match self.next()?.as_str() {
+                    "then" => self.emit_skip(x, &op, rhs, false),
+                    "begin" => {
+                        self.emit_skip(x, &op, rhs, true)?;
+                        self.blocks.push((Block::If(self.here), self.line));
+                        self.emit_word(0x1000)
+                    }
+                    word => self.error(format!("Expected 'then' or 'begin' but found '{}'", word)),
+                }
+            }
+            "else" => match self.blocks.pop() {
+                Some((Block::If(addr), line)) => {
+                    self.blocks.push((Block::Else(self.here), line));
+                    self.emit_word(0x1000)?;
+                    self.patch_jump(addr, self.here);
+                    Ok(())
+                }
+                _ => self.error("'else' without a matching 'begin'"),
+            },
+            "end" => match self.blocks.pop() {
+                Some((Block::If(addr) | Block::Else(addr), _)) => {
+                    self.patch_jump(addr, self.here);
+                    Ok(())
+                }
+                _ => self.error("'end' without a matching 'begin'"),
+            },
+            "loop" => {
+                self.blocks.push((Block::Loop(self.here, Vec::new()), self.line));
+                Ok(())
+            }
+            "while" => {
+                let (x, op, rhs) = self.condition()?;
+
+                if !self.blocks.iter().any(|(block, _)| matches!(block, Block::Loop(..))) {
+                    return self.error("'while' outside of a loop");
+                }
+
+                self.emit_skip(x, &op, rhs, true)?;
+                let exit = self.here;
+
+                if let Some((Block::Loop(_, exits), _)) = self.blocks
+                    .iter_mut()
+                    .rev()
+                    .find(|(block, _)| matches!(block, Block::Loop(..))) {
+                    exits.push(exit);
+                }
+
+                self.emit_word(0x1000)
+            }
+            "again" => match self.blocks.pop() {
+                Some((Block::Loop(start, exits), _)) => {
+                    self.emit_word(0x1000 | start as u16)?;
+
+                    for exit in exits {
+                        self.patch_jump(exit, self.here);
+                    }
+
+                    Ok(())
+                }
+                _ => self.error("'again' without a matching 'loop'"),
+            },
+            word if UNSUPPORTED.contains(&word) => self.unsupported(word),
+            word => {
+                if let Some(x) = self.register_named(word) {
+                    return self.assignment(x);
+                }
+
+                // Numbers are emitted as data and any other name is a subroutine call
+                if let Some(value) = parse_number(word).or_else(|| self.constants.get(word).copied()) {
+                    let byte = self.to_byte(value)?;
+                    return self.emit_byte(byte);
+                }
+
+                if !is_name(word) {
+                    return self.error(format!("Unexpected '{}'", word));
+                }
+
+                let addr = self.address_of(word, FixupKind::Address)?;
+                self.emit_word(0x2000 | addr)
+            }
+        }
+    }
+
+    fn unsupported<T>(&self, word: &str) -> Result<T, String> {
+        self.error(format!("'{}' requires SUPER-CHIP or XO-CHIP, which isn't supported", word))
+    }
+
+    fn expand_macro(&mut self, name: &str) -> Result<(), String> {
+        self.expansions += 1;
+
+        if self.expansions > MAX_MACRO_EXPANSIONS {
+            return self.error("Too many macro expansions");
+        }
+
+        let mac = self.macros.get_mut(name).unwrap();
+        mac.calls += 1;
+        let mac = mac.clone();
+
+        let args = mac.params
+            .iter()
+            .map(|_| self.next())
+            .collect::<Result<Vec<String>, String>>()?;
+
+        for token in mac.body.iter().rev() {
+            let text = match mac.params.iter().position(|param| *param == token.text) {
+                Some(i) => args[i].clone(),
+                None if token.text == "CALLS" => (mac.calls - 1).to_string(),
+                None => token.text.clone(),
+            };
+
+            self.tokens.push_front(Token { text, line: token.line });
+        }
+
+        Ok(())
+    }
+
+    // Collects the tokens up to the closing brace, the opening one has already been consumed
+    fn block_tokens(&mut self) -> Result<Vec<Token>, String> {
+        let mut tokens = Vec::new();
+        let mut depth = 1;
+
+        loop {
+            let token = self.next_token()?;
+
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+
+                    if depth == 0 {
+                        return Ok(tokens);
+                    }
+                }
+                _ => (),
+            }
+
+            tokens.push(token);
+        }
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        let token = self.next()?;
+
+        if !is_name(&token) || self.register_named(&token).is_some() {
+            return self.error(format!("Invalid name ({})", token));
+        }
+
+        Ok(token)
+    }
+
+    fn define_label(&mut self, name: String, addr: usize) -> Result<(), String> {
+        if self.labels.contains_key(&name) || self.constants.contains_key(&name) {
+            return self.error(format!("Name '{}' is already defined", name));
+        }
+
+        self.labels.insert(name, addr);
+        Ok(())
+    }
+
+    // Unlike labels, constants can be redefined, which is often used to keep counters in :calc
+    fn define_constant(&mut self, name: String, value: f64) -> Result<(), String> {
+        if self.labels.contains_key(&name) {
+            return self.error(format!("Name '{}' is already defined", name));
+        }
+
+        self.constants.insert(name, value);
+        Ok(())
+    }
+
+    fn register_named(&self, text: &str) -> Option<u8> {
+        if let Some(reg) = self.aliases.get(text) {
+            return Some(*reg);
+        }
+
+        match text.as_bytes() {
+            [b'v' | b'V', digit] => (*digit as char).to_digit(16).map(|d| d as u8),
+            _ => None,
+        }
+    }
+
+    fn register(&mut self) -> Result<u8, String> {
+        let token = self.next()?;
+
+        match self.register_named(&token) {
+            Some(reg) => Ok(reg),
+            None => self.error(format!("Expected a register but found '{}'", token)),
+        }
+    }
+
+    fn resolve(&self, text: &str) -> Option<f64> {
+        parse_number(text)
+            .or_else(|| self.constants.get(text).copied())
+            .or_else(|| self.labels.get(text).map(|addr| *addr as f64))
+    }
+
+    fn value(&mut self) -> Result<f64, String> {
+        let token = self.next()?;
+        self.value_of(&token)
+    }
+
+    fn value_of(&self, text: &str) -> Result<f64, String> {
+        match self.resolve(text) {
+            Some(value) => Ok(value),
+            None => self.error(format!("Undefined name '{}'", text)),
+        }
+    }
+
+    fn to_byte(&self, value: f64) -> Result<u8, String> {
+        let n = value.floor() as i64;
+
+        if !(-128..=255).contains(&n) {
+            return self.error(format!("Value out of range for a byte ({})", value));
+        }
+
+        Ok(n as u8)
+    }
+
+    fn to_address(&self, value: f64) -> Result<u16, String> {
+        let n = value.floor() as i64;
+
+        if !(0..MEMORY_SIZE as i64).contains(&n) {
+            return self.error(format!("Address out of range ({})", value));
+        }
+
+        Ok(n as u16)
+    }
+
+    // Resolves an address, leaving it to be fixed up at the end when the label isn't defined yet
+    fn address_of(&mut self, text: &str, kind: FixupKind) -> Result<u16, String> {
+        if let Some(value) = self.resolve(text) {
+            return self.to_address(value);
+        }
+
+        if !is_name(text) {
+            return self.error(format!("Expected an address but found '{}'", text));
+        }
+
+        self.fixups.push(Fixup { addr: self.here, name: text.to_string(), line: self.line, kind });
+
+        Ok(0)
+    }
+
+    fn emit_address(&mut self, opcode: u16) -> Result<(), String> {
+        let token = self.next()?;
+        let addr = self.address_of(&token, FixupKind::Address)?;
+
+        self.emit_word(opcode | addr)
+    }
+
+    fn emit_register_op(&mut self, opcode: u16) -> Result<(), String> {
+        let x = self.register()? as u16;
+
+        self.emit_word(opcode | x << 8)
+    }
+
+    fn emit_byte(&mut self, byte: u8) -> Result<(), String> {
+        if self.here >= MEMORY_SIZE {
+            return self.error("Program is too large to fit in memory");
+        }
+
+        if self.memory[self.here].is_some() {
+            return self.error(format!("Data overlaps at address {:03X}", self.here));
+        }
+
+        self.memory[self.here] = Some(byte);
+        self.here += 1;
+
+        Ok(())
+    }
+
+    fn emit_word(&mut self, word: u16) -> Result<(), String> {
+        self.lines.entry(self.here).or_insert(self.line);
+        self.emit_byte((word >> 8) as u8)?;
+        self.emit_byte(word as u8)
+    }
+
+    fn patch_jump(&mut self, addr: usize, target: usize) {
+        self.memory[addr] = Some(0x10 | (target >> 8) as u8);
+        self.memory[addr + 1] = Some(target as u8);
+    }
+
+    fn assignment(&mut self, x: u8) -> Result<(), String> {
+        let op = self.next()?;
+        let x = (x as u16) << 8;
+
+        if op == ":=" {
+            match self.peek() {
+                Some("random") => {
+                    self.next()?;
+                    let value = self.value()?;
+                    let mask = self.to_byte(value)? as u16;
+                    return self.emit_word(0xC000 | x | mask);
+                }
+                Some("key") => {
+                    self.next()?;
+                    return self.emit_word(0xF00A | x);
+                }
+                Some("delay") => {
+                    self.next()?;
+                    return self.emit_word(0xF007 | x);
+                }
+                _ => (),
+            }
+        }
+
+        let logic = match op.as_str() {
+            ":=" => 0x0,
+            "|=" => 0x1,
+            "&=" => 0x2,
+            "^=" => 0x3,
+            "+=" => 0x4,
+            "-=" => 0x5,
+            ">>=" => 0x6,
+            "=-" => 0x7,
+            "<<=" => 0xE,
+            _ => return self.error(format!("Unknown operator ({})", op)),
+        };
+
+        let token = self.next()?;
+
+        if let Some(y) = self.register_named(&token) {
+            return self.emit_word(0x8000 | x | (y as u16) << 4 | logic);
+        }
+
+        let value = self.value_of(&token)?;
+
+        match op.as_str() {
+            ":=" => {
+                let byte = self.to_byte(value)? as u16;
+                self.emit_word(0x6000 | x | byte)
+            }
+            "+=" => {
+                let byte = self.to_byte(value)? as u16;
+                self.emit_word(0x7000 | x | byte)
+            }
+            "-=" => {
+                let byte = self.to_byte(-value)? as u16;
+                self.emit_word(0x7000 | x | byte)
+            }
+            _ => self.error(format!("Operator '{}' needs a register", op)),
+        }
+    }
+
+    fn condition(&mut self) -> Result<(u8, String, Option<Operand>), String> {
+        let x = self.register()?;
+        let op = self.next()?;
+
+        match op.as_str() {
+            "key" | "-key" => Ok((x, op, None)),
+            "==" | "!=" | "<" | ">" | "<=" | ">=" => {
+                let token = self.next()?;
+
+                let rhs = match self.register_named(&token) {
+                    Some(y) => Operand::Register(y),
+                    None => {
+                        let value = self.value_of(&token)?;
+                        Operand::Byte(self.to_byte(value)?)
+                    }
+                };
+
+                Ok((x, op, Some(rhs)))
+            }
+            _ => self.error(format!("Unknown comparison ({})", op)),
+        }
+    }
+
+    // Emits the instructions which skip the next one when the condition doesn't hold, or when it
+    // does if negated
+    fn emit_skip(&mut self, x: u8, op: &str, rhs: Option<Operand>, negate: bool) -> Result<(), String> {
+        let op = match (op, negate) {
+            (op, false) => op,
+            ("==", true) => "!=",
+            ("!=", true) => "==",
+            ("key", true) => "-key",
+            ("-key", true) => "key",
+            ("<", true) => ">=",
+            (">=", true) => "<",
+            (">", true) => "<=",
+            ("<=", true) => ">",
+            _ => unreachable!(),
+        };
+
+        let x = x as u16;
+
+        match (op, rhs) {
+            ("key", _) => self.emit_word(0xE0A1 | x << 8),
+            ("-key", _) => self.emit_word(0xE09E | x << 8),
+            ("==", Some(Operand::Byte(n))) => self.emit_word(0x4000 | x << 8 | n as u16),
+            ("==", Some(Operand::Register(y))) => self.emit_word(0x9000 | x << 8 | (y as u16) << 4),
+            ("!=", Some(Operand::Byte(n))) => self.emit_word(0x3000 | x << 8 | n as u16),
+            ("!=", Some(Operand::Register(y))) => self.emit_word(0x5000 | x << 8 | (y as u16) << 4),
+            (op, Some(rhs)) => {
+                // The right-hand side is copied into a temporary register and subtracted, the
+                // carry in vf then tells which side is larger
+                let t = (self.aliases["compare-temp"] as u16) << 8;
+
+                match rhs {
+                    Operand::Register(y) => self.emit_word(0x8000 | t | (y as u16) << 4)?,
+                    Operand::Byte(n) => self.emit_word(0x6000 | t | n as u16)?,
+                }
+
+                // vf is set when x >= rhs for < and >=, or when rhs >= x for > and <=
+                let subtract = if op == "<" || op == ">=" { 0x8007 } else { 0x8005 };
+                self.emit_word(subtract | t | x << 4)?;
+
+                self.emit_word(if op == "<" || op == ">" { 0x4F00 } else { 0x3F00 })
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn calc(&mut self) -> Result<f64, String> {
+        self.expect("{")?;
+
+        let tokens = self.block_tokens()?;
+        let mut pos = 0;
+        let value = self.expression(&tokens, &mut pos)?;
+
+        match tokens.get(pos) {
+            Some(token) => self.error(format!("Unexpected '{}' in expression", token.text)),
+            None => Ok(value),
+        }
+    }
+
+    // Octo has no operator precedence, binary operators are evaluated from right to left
+    fn expression(&self, tokens: &[Token], pos: &mut usize) -> Result<f64, String> {
+        let left = self.term(tokens, pos)?;
+
+        let op = match tokens.get(*pos) {
+            Some(token) if BINARY_OPERATORS.contains(&token.text.as_str()) => token.text.as_str(),
+            _ => return Ok(left),
+        };
+
+        *pos += 1;
+        let right = self.expression(tokens, pos)?;
+
+        let bool_value = |b: bool| if b { 1.0 } else { 0.0 };
+        let (l, r) = (left as i64, right as i64);
+
+        Ok(match op {
+            "+" => left + right,
+            "-" => left - right,
+            "*" => left * right,
+            "/" => left / right,
+            "%" => left % right,
+            "&" => (l & r) as f64,
+            "|" => (l | r) as f64,
+            "^" => (l ^ r) as f64,
+            "<<" => l.checked_shl(r as u32).unwrap_or(0) as f64,
+            ">>" => l.checked_shr(r as u32).unwrap_or(0) as f64,
+            "pow" => left.powf(right),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "<" => bool_value(left < right),
+            ">" => bool_value(left > right),
+            "<=" => bool_value(left <= right),
+            ">=" => bool_value(left >= right),
+            "==" => bool_value(left == right),
+            "!=" => bool_value(left != right),
+            _ => unreachable!(),
+        })
+    }
+
+    fn term(&self, tokens: &[Token], pos: &mut usize) -> Result<f64, String> {
+        let text = match tokens.get(*pos) {
+            Some(token) => token.text.as_str(),
+            None => return self.error("Unexpected end of expression"),
+        };
+
+        *pos += 1;
+
+        if text == "(" {
+            let value = self.expression(tokens, pos)?;
+
+            return match tokens.get(*pos) {
+                Some(token) if token.text == ")" => {
+                    *pos += 1;
+                    Ok(value)
+                }
+                _ => self.error("Missing ')' in expression"),
+            };
+        }
+
+        // Negative numbers are a single token, so a lone - is always an operator
+        if UNARY_OPERATORS.contains(&text) {
+            let value = self.term(tokens, pos)?;
+
+            return Ok(match text {
+                "-" => -value,
+                "~" => !(value as i64) as f64,
+                "!" => if value == 0.0 { 1.0 } else { 0.0 },
+                "sin" => value.sin(),
+                "cos" => value.cos(),
+                "tan" => value.tan(),
+                "exp" => value.exp(),
+                "log" => value.ln(),
+                "abs" => value.abs(),
+                "sqrt" => value.sqrt(),
+                "sign" => if value == 0.0 { 0.0 } else { value.signum() },
+                "ceil" => value.ceil(),
+                "floor" => value.floor(),
+                "@" => {
+                    let addr = self.to_address(value)? as usize;
+                    self.memory[addr].unwrap_or(0) as f64
+                }
+                _ => unreachable!(),
+            });
+        }
+
+        match text {
+            "HERE" => Ok(self.here as f64),
+            "PI" => Ok(std::f64::consts::PI),
+            "E" => Ok(std::f64::consts::E),
+            _ => self.value_of(text),
+        }
+    }
+}
+
+fn is_name(text: &str) -> bool {
+    text.starts_with(|c: char| c.is_alphabetic() || c == '_') && parse_number(text).is_none()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_can_be_used_before_they_are_defined() {
+        let rom = assemble(": main jump next : next v1 := 42 sub : sub return").unwrap();
+
+        assert_eq!(rom, [0x12, 0x02, 0x61, 0x2A, 0x22, 0x06, 0x00, 0xEE]);
+    }
+
+    #[test]
+    fn conditions_skip_when_they_are_false() {
+        let rom = assemble(": main v0 := 1 if v0 == 1 then v1 := 2 if v0 != v1 then clear").unwrap();
+
+        assert_eq!(rom, [0x60, 0x01, 0x40, 0x01, 0x61, 0x02, 0x50, 0x10, 0x00, 0xE0]);
+    }
+
+    #[test]
+    fn blocks_jump_over_their_bodies() {
+        let rom = assemble(": main loop v0 += 1 while v0 != 3 again").unwrap();
+
+        // The while skips its exit jump until V0 is 3, the loop jumps back to the start
+        assert_eq!(rom, [0x70, 0x01, 0x40, 0x03, 0x12, 0x08, 0x12, 0x00]);
+    }
+
+    #[test]
+    fn calc_and_call_directives() {
+        let rom = assemble(": main :calc answer { 6 * 7 } v0 := answer :call 0x300").unwrap();
+
+        assert_eq!(rom, [0x60, 0x2A, 0x03, 0x00]);
+    }
+
+    #[test]
+    fn macros_expand_with_their_arguments() {
+        let rom = assemble(":macro set reg value { reg := value } : main set v3 7 set v4 8").unwrap();
+
+        // The program doesn't start with main, so it starts with a jump to it
+        assert_eq!(rom, [0x12, 0x02, 0x63, 0x07, 0x64, 0x08]);
+    }
+
+    #[test]
+    fn super_chip_statements_are_rejected() {
+        for source in [": main hires", ": main scroll-down 4", ": main i := bighex v0", ": main save v0 - v3"] {
+            let err = assemble(source).unwrap_err();
+
+            assert!(err.starts_with("Line 1: ") && err.contains("requires SUPER-CHIP"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn errors_name_their_line() {
+        assert_eq!(assemble(": main\nv0 := 1\njump nowhere").unwrap_err().split(':').next(), Some("Line 3"));
+        assert!(assemble(": main\nif v0 == 1 begin\nclear").unwrap_err().contains("missing its 'end'"));
+    }
+}
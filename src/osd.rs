@@ -0,0 +1,277 @@
+// Only the window draws the display as pixels, the terminal prints it as text
+#![cfg_attr(not(feature = "sdl"), allow(dead_code))]
+
+use std::iter;
+use std::time::{Duration, Instant};
+
+use chip8_rs::font::{self, ADVANCE, GLYPH_HEIGHT};
+
+// How long a message stays on screen, and how many are shown at once
+const MESSAGE_DURATION: Duration = Duration::from_secs(2);
+const MAX_MESSAGES: usize = 3;
+
+// Text is drawn with the font at its natural size on a picture this wide, anything smaller is
+// scaled up by whole pixels first so that the text fits
+const MIN_WIDTH: usize = 512;
+
+const BACKGROUND: u32 = 0x101418;
+const TEXT: u32 = 0xE8E8E8;
+const DIM_TEXT: u32 = 0x8890A0;
+const SELECTION: u32 = 0x284060;
+
+// Backgrounds of the highlighted parts of panels
+const CURSOR: u32 = 0x3C70B0;
+const PROGRAM_COUNTER: u32 = 0x2A7038;
+const INDEX: u32 = 0x806020;
+const STACK: u32 = 0x603A78;
+const WRITTEN: u32 = 0xA03020;
+
+const PADDING: usize = 4;
+const LINE_HEIGHT: usize = GLYPH_HEIGHT + 3;
+
+// A menu as it is shown, the frontends only draw it and the application handles its input
+pub struct MenuView {
+    pub title: String,
+    pub items: Vec<String>,
+    pub selected: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Highlight {
+    None,
+    Cursor,
+    ProgramCounter,
+    Index,
+    Stack,
+    Written,
+}
+
+// A block of text with parts of it highlighted, i.e. the memory viewer. Every line is a list of
+// spans of text and how to highlight them.
+pub struct PanelView {
+    pub title: String,
+    pub lines: Vec<Vec<(String, Highlight)>>,
+}
+
+// Everything drawn over the emulated screen: transient messages, the status of the emulation
+// (i.e. paused), the performance counter, text drawn by scripts and the pause menu. Frontends
+// decide how to show it, i.e. the terminal prints it as text.
+#[derive(Default)]
+pub struct Osd {
+    messages: Vec<(String, Instant)>,
+    pub status: Option<String>,
+    pub stats: Option<String>,
+    pub menu: Option<MenuView>,
+    pub panel: Option<PanelView>,
+    // Text placed by a script, at positions in CHIP-8 pixels
+    pub overlay: Vec<(usize, usize, String)>,
+}
+
+impl Osd {
+    pub fn show_message(&mut self, text: impl Into<String>) {
+        if self.messages.len() == MAX_MESSAGES {
+            self.messages.remove(0);
+        }
+
+        self.messages.push((text.into(), Instant::now() + MESSAGE_DURATION));
+    }
+
+    pub fn expire_messages(&mut self, now: Instant) {
+        self.messages.retain(|(_, expiry)| *expiry > now);
+    }
+
+    pub fn get_messages(&self) -> impl Iterator<Item = &str> {
+        self.messages.iter().map(|(text, _)| text.as_str())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty() && self.status.is_none() && self.stats.is_none() && self.menu.is_none()
+            && self.panel.is_none() && self.overlay.is_empty()
+    }
+
+    // Draws the display over a picture into out and returns the size of the result, which is
+    // larger than the picture when it had to be scaled up for the text to fit
+    pub fn compose(&self, pixels: &[u32], width: usize, height: usize, out: &mut Vec<u32>) -> (usize, usize) {
+        let factor = MIN_WIDTH.div_ceil(width).max(1);
+        let (out_width, out_height) = (width * factor, height * factor);
+
+        out.clear();
+        out.reserve(out_width * out_height);
+
+        for row in pixels.chunks_exact(width) {
+            let start = out.len();
+
+            for pixel in row {
+                out.extend(iter::repeat_n(*pixel, factor));
+            }
+
+            for _ in 1..factor {
+                out.extend_from_within(start..start + out_width);
+            }
+        }
+
+        let scale = (out_width / MIN_WIDTH).max(1);
+        let canvas = Canvas { pixels: out, width: out_width, height: out_height, factor, scale };
+
+        canvas.draw(self);
+
+        (out_width, out_height)
+    }
+}
+
+struct Canvas<'a> {
+    pixels: &'a mut Vec<u32>,
+    width: usize,
+    height: usize,
+    // Size of a CHIP-8 pixel on the canvas
+    factor: usize,
+    scale: usize,
+}
+
+impl Canvas<'_> {
+    fn draw(mut self, osd: &Osd) {
+        let s = self.scale;
+
+        for (x, y, text) in &osd.overlay {
+            self.text_box(x * self.factor, y * self.factor, text, TEXT);
+        }
+
+        if let Some(status) = &osd.status {
+            self.text_box(0, 0, status, TEXT);
+        }
+
+        if let Some(stats) = &osd.stats {
+            let width = font::text_width(stats, s);
+            let x = self.width.saturating_sub(width + PADDING * 2 * s);
+
+            self.text_box(x, 0, stats, TEXT);
+        }
+
+        let count = osd.messages.len();
+
+        for (i, text) in osd.get_messages().enumerate() {
+            let y = self.height.saturating_sub((count - i) * (LINE_HEIGHT + PADDING) * s + PADDING * s);
+
+            self.text_box(0, y, text, TEXT);
+        }
+
+        if let Some(panel) = &osd.panel {
+            self.panel(panel);
+        }
+
+        if let Some(menu) = &osd.menu {
+            self.menu(menu);
+        }
+    }
+
+    // A line of text on a darkened box, with (x, y) at the top left of the box
+    fn text_box(&mut self, x: usize, y: usize, text: &str, color: u32) {
+        let s = self.scale;
+
+        self.darken(x, y, font::text_width(text, s) + PADDING * 2 * s, LINE_HEIGHT * s + PADDING * s);
+        font::draw_text(self.pixels, self.width, x + PADDING * s, y + (PADDING + 1) * s, text, color, s);
+    }
+
+    fn menu(&mut self, menu: &MenuView) {
+        let s = self.scale;
+
+        let chars = menu.items.iter().chain([&menu.title]).map(|item| item.chars().count()).max().unwrap_or(0);
+        let box_width = (chars * ADVANCE + PADDING * 4) * s;
+        let box_height = ((menu.items.len() + 2) * LINE_HEIGHT + PADDING * 2) * s;
+
+        let left = self.width.saturating_sub(box_width) / 2;
+        let top = self.height.saturating_sub(box_height) / 2;
+
+        self.fill(left, top, box_width, box_height, BACKGROUND);
+
+        let x = left + PADDING * 2 * s;
+        let mut y = top + PADDING * s + 2 * s;
+
+        font::draw_text(self.pixels, self.width, x, y, &menu.title, DIM_TEXT, s);
+
+        for (i, item) in menu.items.iter().enumerate() {
+            y += LINE_HEIGHT * s;
+
+            if i == 0 {
+                y += LINE_HEIGHT * s / 2;
+            }
+
+            if i == menu.selected {
+                self.fill(left + PADDING * s, y - 2 * s, box_width - PADDING * 2 * s, LINE_HEIGHT * s, SELECTION);
+            }
+
+            font::draw_text(self.pixels, self.width, x, y, item, TEXT, s);
+        }
+    }
+
+    fn panel(&mut self, panel: &PanelView) {
+        let s = self.scale;
+
+        let chars = panel
+            .lines
+            .iter()
+            .map(|line| line.iter().map(|(text, _)| text.chars().count()).sum())
+            .chain([panel.title.chars().count()])
+            .max()
+            .unwrap_or(0);
+
+        let box_width = (chars * ADVANCE + PADDING * 4) * s;
+        let box_height = ((panel.lines.len() + 1) * LINE_HEIGHT + PADDING * 3) * s;
+
+        let left = self.width.saturating_sub(box_width) / 2;
+        let top = self.height.saturating_sub(box_height) / 2;
+
+        self.fill(left, top, box_width, box_height, BACKGROUND);
+
+        let x = left + PADDING * 2 * s;
+        let mut y = top + PADDING * s + 2 * s;
+
+        font::draw_text(self.pixels, self.width, x, y, &panel.title, DIM_TEXT, s);
+        y += PADDING * s;
+
+        for line in &panel.lines {
+            y += LINE_HEIGHT * s;
+
+            let mut x = x;
+
+            for (text, highlight) in line {
+                let width = text.chars().count() * ADVANCE * s;
+                let background = match highlight {
+                    Highlight::None => None,
+                    Highlight::Cursor => Some(CURSOR),
+                    Highlight::ProgramCounter => Some(PROGRAM_COUNTER),
+                    Highlight::Index => Some(INDEX),
+                    Highlight::Stack => Some(STACK),
+                    Highlight::Written => Some(WRITTEN),
+                };
+
+                if let Some(color) = background {
+                    self.fill(x - s, y - 2 * s, width + s, LINE_HEIGHT * s, color);
+                }
+
+                font::draw_text(self.pixels, self.width, x, y, text, TEXT, s);
+                x += width;
+            }
+        }
+    }
+
+    fn darken(&mut self, x: usize, y: usize, width: usize, height: usize) {
+        self.apply(x, y, width, height, |pixel| (pixel >> 1) & 0x7F7F7F);
+    }
+
+    fn fill(&mut self, x: usize, y: usize, width: usize, height: usize, color: u32) {
+        self.apply(x, y, width, height, |_| color);
+    }
+
+    fn apply(&mut self, x: usize, y: usize, width: usize, height: usize, f: impl Fn(u32) -> u32) {
+        let right = (x + width).min(self.width);
+
+        if x >= right {
+            return;
+        }
+
+        for row in self.pixels.chunks_exact_mut(self.width).skip(y).take(height) {
+            row[x..right].iter_mut().for_each(|pixel| *pixel = f(*pixel));
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+
+use crate::disasm;
+
+// Where programs start, which is the root of the call tree
+const ENTRY_POINT: u16 = 0x200;
+
+// Counts the instructions executed at every address and of every kind, and attributes them to
+// subroutines by following 2NNN calls and 00EE returns. Time is measured in instructions, as
+// every instruction takes the same time on the interpreter.
+#[derive(Clone, Debug)]
+pub struct Profiler {
+    total: u64,
+    // Executions and the last opcode executed at every address, which can change when the
+    // program modifies itself
+    counts: Vec<u64>,
+    opcodes: Vec<u16>,
+    patterns: HashMap<&'static str, u64>,
+    // Instructions executed in every call stack, given as the entry points of the subroutines
+    // from the outermost in
+    stacks: HashMap<Vec<u16>, u64>,
+    // Calls made into the innermost subroutine of every call stack
+    calls: HashMap<Vec<u16>, u64>,
+    stack: Vec<u16>,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Profiler::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler {
+            total: 0,
+            counts: vec![0; 4096],
+            opcodes: vec![0; 4096],
+            patterns: HashMap::new(),
+            stacks: HashMap::new(),
+            calls: HashMap::new(),
+            stack: vec![ENTRY_POINT],
+        }
+    }
+
+    // Counts an instruction after it has been executed
+    pub fn record(&mut self, pc: u16, opcode: u16) {
+        self.total += 1;
+        self.counts[pc as usize] += 1;
+        self.opcodes[pc as usize] = opcode;
+
+        if let Some(pattern) = disasm::pattern(opcode) {
+            *self.patterns.entry(pattern).or_default() += 1;
+        }
+
+        count(&mut self.stacks, &self.stack);
+
+        if opcode & 0xF000 == 0x2000 {
+            self.stack.push(opcode & 0x0FFF);
+            count(&mut self.calls, &self.stack);
+        } else if opcode == 0x00EE && self.stack.len() > 1 {
+            self.stack.pop();
+        }
+    }
+
+    // Rebuilds the call stack from the return addresses on the interpreter's stack, which point at
+    // the calls, i.e. after a reset or loading a saved state
+    pub fn set_call_stack(&mut self, return_addresses: &[u16], memory: &[u8]) {
+        self.stack.truncate(1);
+
+        for addr in return_addresses {
+            let byte = |addr: usize| memory.get(addr).copied().unwrap_or(0) as u16;
+            let opcode = byte(*addr as usize) << 8 | byte(*addr as usize + 1);
+
+            self.stack.push(opcode & 0x0FFF);
+        }
+    }
+
+    pub fn get_total(&self) -> u64 {
+        self.total
+    }
+
+    pub fn get_count(&self, addr: u16) -> u64 {
+        self.counts.get(addr as usize).copied().unwrap_or(0)
+    }
+
+    // Writes the flat profile of the busiest addresses, the instructions by kind and the call tree
+    pub fn write_report(&self, out: &mut dyn Write, top: usize) -> io::Result<()> {
+        writeln!(out, "Profile of {} instructions", self.total)?;
+
+        let mut addresses: Vec<usize> = (0..self.counts.len()).filter(|addr| self.counts[*addr] > 0).collect();
+        addresses.sort_by(|a, b| self.counts[*b].cmp(&self.counts[*a]).then(a.cmp(b)));
+
+        writeln!(out, "\nFlat profile ({} of {} addresses)\n", top.min(addresses.len()), addresses.len())?;
+        writeln!(out, "       count        %  addr  opcode  instruction")?;
+
+        for addr in addresses.iter().take(top) {
+            let opcode = self.opcodes[*addr];
+            let text = disasm::disassemble(opcode).unwrap_or_else(|| "???".to_string());
+
+            writeln!(out, "  {:>10}  {:>6.2}%  {:04X}  {:04X}    {}", self.counts[*addr],
+                     percent(self.counts[*addr], self.total), addr, opcode, text)?;
+        }
+
+        let mut patterns: Vec<_> = self.patterns.iter().collect();
+        patterns.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+
+        writeln!(out, "\nInstructions\n")?;
+        writeln!(out, "       count        %  pattern")?;
+
+        for (pattern, count) in patterns {
+            writeln!(out, "  {:>10}  {:>6.2}%  {}", count, percent(*count, self.total), pattern)?;
+        }
+
+        writeln!(out, "\nCall tree\n")?;
+        writeln!(out, "       total        %        self   calls  subroutine")?;
+
+        self.build_tree().write(out, ENTRY_POINT, 0, self.total)
+    }
+
+    // Writes one line per call stack with the instructions executed in it, i.e. "main;sub_300 42",
+    // which is what flamegraph.pl and similar tools take
+    pub fn write_collapsed(&self, out: &mut dyn Write) -> io::Result<()> {
+        let mut stacks: Vec<_> = self.stacks.iter().collect();
+        stacks.sort();
+
+        for (stack, count) in stacks {
+            let names: Vec<String> = stack.iter().map(|addr| disasm::subroutine_name(*addr)).collect();
+
+            writeln!(out, "{} {}", names.join(";"), count)?;
+        }
+
+        Ok(())
+    }
+
+    fn build_tree(&self) -> Node {
+        let mut root = Node::default();
+
+        for (stack, count) in &self.stacks {
+            let mut node = &mut root;
+            node.total += count;
+
+            for addr in &stack[1..] {
+                node = node.children.entry(*addr).or_default();
+                node.total += count;
+            }
+
+            node.own += count;
+        }
+
+        for (stack, count) in &self.calls {
+            let node = stack[1..].iter().fold(&mut root, |node, addr| node.children.entry(*addr).or_default());
+            node.calls += count;
+        }
+
+        root
+    }
+}
+
+fn count(counts: &mut HashMap<Vec<u16>, u64>, stack: &[u16]) {
+    match counts.get_mut(stack) {
+        Some(count) => *count += 1,
+        None => {
+            counts.insert(stack.to_vec(), 1);
+        }
+    }
+}
+
+#[derive(Default)]
+struct Node {
+    total: u64,
+    own: u64,
+    calls: u64,
+    children: BTreeMap<u16, Node>,
+}
+
+impl Node {
+    // Writes the node and its children, the busiest first, indented by their depth in the tree
+    fn write(&self, out: &mut dyn Write, addr: u16, depth: usize, total: u64) -> io::Result<()> {
+        let calls = if depth == 0 { "-".to_string() } else { self.calls.to_string() };
+
+        writeln!(out, "  {:>10}  {:>6.2}%  {:>10}  {:>6}  {:indent$}{}", self.total, percent(self.total, total),
+                 self.own, calls, "", disasm::subroutine_name(addr), indent = depth * 2)?;
+
+        let mut children: Vec<_> = self.children.iter().collect();
+        children.sort_by(|a, b| b.1.total.cmp(&a.1.total).then(a.0.cmp(b.0)));
+
+        for (addr, child) in children {
+            child.write(out, *addr, depth + 1, total)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn percent(count: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        count as f64 * 100.0 / total as f64
+    }
+}
@@ -0,0 +1,73 @@
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+
+use crate::chip8::{FRAMEBUFFER_HEIGHT, FRAMEBUFFER_WIDTH};
+use crate::gif::Encoder;
+use crate::scale::Upscaler;
+
+// Records the screen as an animated GIF, upscaled like screenshots. Only every other frame is
+// kept, as browsers slow down GIFs with delays below 2/100 s, and frames which don't change the
+// screen make the previous one last longer instead.
+pub struct Recorder {
+    encoder: Encoder<BufWriter<File>>,
+    upscaler: Upscaler,
+    scaled: Vec<u32>,
+    pending: Vec<u32>,
+    // Frames added so far, and the one the pending image was first shown in
+    frames: u64,
+    pending_since: u64,
+}
+
+impl Recorder {
+    pub fn create(path: &Path, upscaler: Upscaler) -> io::Result<Self> {
+        let file = BufWriter::new(File::create(path)?);
+        let factor = upscaler.factor();
+
+        Ok(Recorder {
+            encoder: Encoder::new(file, FRAMEBUFFER_WIDTH * factor, FRAMEBUFFER_HEIGHT * factor)?,
+            upscaler,
+            scaled: Vec::new(),
+            pending: Vec::new(),
+            frames: 0,
+            pending_since: 0,
+        })
+    }
+
+    // Adds the screen at the end of a frame
+    pub fn add_frame(&mut self, pixels: &[u32]) -> io::Result<()> {
+        if self.frames.is_multiple_of(2) {
+            self.upscaler.apply(pixels, FRAMEBUFFER_WIDTH, FRAMEBUFFER_HEIGHT, &mut self.scaled);
+
+            if self.scaled != self.pending {
+                self.write_pending()?;
+
+                std::mem::swap(&mut self.scaled, &mut self.pending);
+                self.pending_since = self.frames;
+            }
+        }
+
+        self.frames += 1;
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.write_pending()?;
+        self.encoder.finish()?;
+
+        Ok(())
+    }
+
+    fn write_pending(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+
+        // Rounding the times rather than the delays keeps the frames in step at 60 per second
+        let centiseconds = |frame: u64| (frame * 100 + 30) / 60;
+        let delay = centiseconds(self.frames) - centiseconds(self.pending_since);
+
+        self.encoder.add_frame(&self.pending, delay.min(u16::MAX as u64) as u16)
+    }
+}
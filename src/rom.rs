@@ -0,0 +1,365 @@
+use std::fmt;
+use std::fs;
+use std::io::{self, Cursor, Read};
+use std::path::Path;
+
+use crate::cartridge;
+use crate::database::RomProfile;
+
+// Entries larger than this can't be ROMs, even as text, so they aren't extracted from archives
+const MAX_ENTRY_SIZE: u64 = 1 << 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Binary,
+    IntelHex,
+    HexText,
+    Cartridge,
+    // Octo source, which only the debug adapter assembles
+    OctoSource,
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Format::Binary => "Binary",
+            Format::IntelHex => "Intel HEX",
+            Format::HexText => "Hex text",
+            Format::Cartridge => "Octo cartridge",
+            Format::OctoSource => "Octo source",
+        })
+    }
+}
+
+pub struct Rom {
+    pub data: Vec<u8>,
+    pub format: Format,
+    // Settings which came with the ROM itself, i.e. the options of an Octo cartridge
+    pub profile: Option<RomProfile>,
+}
+
+// Picks one of the ROMs in an archive by its index in the list of entry names
+pub type Chooser<'a> = &'a mut dyn FnMut(&[String]) -> Result<usize, String>;
+
+// Loads a ROM from a file, or from stdin when the path is "-", detecting its format from the
+// contents. Archives holding several ROMs are resolved with the chooser.
+pub fn load(path: &Path, choose: Chooser) -> Result<Rom, String> {
+    let (data, name) = if path.as_os_str() == "-" {
+        let mut data = Vec::new();
+
+        io::stdin()
+            .read_to_end(&mut data)
+            .map_err(|err| format!("Failed to read ROM from stdin: {}", err))?;
+
+        (data, "stdin".to_string())
+    } else {
+        let data = fs::read(path)
+            .map_err(|err| format!("Failed to read ROM file {}: {}", path.display(), err))?;
+
+        (data, path.display().to_string())
+    };
+
+    if is_zip(&data) {
+        let (entry, data) = extract_zip(&data, choose)
+            .map_err(|err| format!("Failed to read archive {}: {}", name, err))?;
+
+        return parse(data, &entry).map_err(|err| format!("Failed to load {} from {}: {}", entry, name, err));
+    }
+
+    parse(data, &name)
+}
+
+// Decodes a ROM in any of the formats except archives. The name is used as the title of Octo
+// cartridges.
+pub fn parse(data: Vec<u8>, name: &str) -> Result<Rom, String> {
+    if cartridge::is_cartridge(&data) {
+        let cart = cartridge::decode(&data)
+            .map_err(|err| format!("Failed to read Octo cartridge {}: {}", name, err))?;
+        let title = Path::new(name).file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+
+        return Ok(Rom {
+            data: cart.assemble()?,
+            format: Format::Cartridge,
+            profile: Some(cart.get_profile(&title)),
+        });
+    }
+
+    if let Ok(text) = std::str::from_utf8(&data) {
+        if is_intel_hex(text) {
+            return Ok(Rom {
+                data: parse_intel_hex(text).map_err(|err| format!("Failed to parse Intel HEX {}: {}", name, err))?,
+                format: Format::IntelHex,
+                profile: None,
+            });
+        }
+
+        if let Some(bytes) = parse_hex_text(text) {
+            return Ok(Rom { data: bytes, format: Format::HexText, profile: None });
+        }
+    }
+
+    Ok(Rom { data, format: Format::Binary, profile: None })
+}
+
+// Lowercase hex SHA-1 of the ROM's contents, which is how ROMs are identified in the per-ROM
+// configuration
+pub fn hash(rom: &[u8]) -> String {
+    sha1_smol::Sha1::from(rom).digest().to_string()
+}
+
+fn is_zip(data: &[u8]) -> bool {
+    data.starts_with(b"PK\x03\x04") || data.starts_with(b"PK\x05\x06")
+}
+
+// Decides whether a file found next to ROMs, i.e. in an archive or a ROM directory, could be one.
+// Text files which aren't a ROM in one of the text formats are documentation and are skipped,
+// along with common images and documents and the metadata macOS leaves in archives.
+pub fn looks_like_rom(name: &str, data: &[u8]) -> bool {
+    const OTHER_FORMATS: [&[u8]; 3] = [b"\x89PNG", b"\xFF\xD8\xFF", b"%PDF"];
+
+    let file_name = name.rsplit(['/', '\\']).next().unwrap_or(name);
+
+    if name.starts_with("__MACOSX/") || file_name.starts_with('.') || data.is_empty() {
+        return false;
+    }
+
+    if OTHER_FORMATS.iter().any(|magic| data.starts_with(magic)) {
+        return false;
+    }
+
+    match std::str::from_utf8(data) {
+        Ok(text) => is_intel_hex(text) || parse_hex_text(text).is_some(),
+        Err(_) => true,
+    }
+}
+
+fn extract_zip(data: &[u8], choose: Chooser) -> Result<(String, Vec<u8>), String> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(data)).map_err(|err| err.to_string())?;
+    let mut entries = Vec::new();
+
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i).map_err(|err| err.to_string())?;
+
+        if !file.is_file() || file.size() > MAX_ENTRY_SIZE {
+            continue;
+        }
+
+        let name = file.name().to_string();
+        let mut contents = Vec::new();
+
+        file.read_to_end(&mut contents)
+            .map_err(|err| format!("Failed to extract {}: {}", name, err))?;
+
+        if looks_like_rom(&name, &contents) {
+            entries.push((name, contents));
+        }
+    }
+
+    match entries.len() {
+        0 => Err("Archive doesn't contain any ROMs".to_string()),
+        1 => Ok(entries.remove(0)),
+        _ => {
+            let names: Vec<String> = entries.iter().map(|(name, _)| name.clone()).collect();
+            let index = choose(&names)?;
+
+            if index >= entries.len() {
+                return Err(format!("Invalid archive entry ({})", index));
+            }
+
+            Ok(entries.swap_remove(index))
+        }
+    }
+}
+
+fn is_intel_hex(text: &str) -> bool {
+    let mut lines = text.lines().map(str::trim).filter(|line| !line.is_empty()).peekable();
+
+    lines.peek().is_some() && lines.all(|line| {
+        line.strip_prefix(':').is_some_and(|record| record.bytes().all(|b| b.is_ascii_hexdigit()))
+    })
+}
+
+// Intel HEX files either hold a ROM on its own from address 0, or an image of memory where the
+// program starts at 0x200. Files whose data all lies at 0x200 and above are images.
+fn parse_intel_hex(text: &str) -> Result<Vec<u8>, String> {
+    const MAX_ADDRESS: usize = 0x10000;
+
+    let mut chunks = Vec::new();
+    let mut base = 0;
+
+    for (i, line) in text.lines().map(str::trim).enumerate().filter(|(_, line)| !line.is_empty()) {
+        let digits = &line[1..];
+
+        if digits.len() % 2 != 0 || digits.len() < 10 {
+            return Err(format!("Line {}: Malformed record", i + 1));
+        }
+
+        let bytes: Vec<u8> = (0..digits.len())
+            .step_by(2)
+            .map(|j| u8::from_str_radix(&digits[j..j + 2], 16).unwrap())
+            .collect();
+
+        let len = bytes[0] as usize;
+
+        if bytes.len() != len + 5 {
+            return Err(format!("Line {}: Record length doesn't match its data", i + 1));
+        }
+
+        if bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+            return Err(format!("Line {}: Checksum mismatch", i + 1));
+        }
+
+        let addr = (bytes[1] as usize) << 8 | bytes[2] as usize;
+        let payload = &bytes[4..4 + len];
+
+        match bytes[3] {
+            0x00 => chunks.push((base + addr, payload.to_vec())),
+            0x01 => break,
+            // Extended segment and linear addresses
+            0x02 if len == 2 => base = ((payload[0] as usize) << 8 | payload[1] as usize) << 4,
+            0x04 if len == 2 => base = ((payload[0] as usize) << 8 | payload[1] as usize) << 16,
+            // Start addresses don't matter
+            0x03 | 0x05 => (),
+            kind => return Err(format!("Line {}: Unsupported record type ({:02X})", i + 1, kind)),
+        }
+    }
+
+    let end = match chunks.iter().map(|(addr, data)| addr + data.len()).max() {
+        Some(end) => end,
+        None => return Err("File doesn't contain any data".to_string()),
+    };
+
+    if end > MAX_ADDRESS {
+        return Err(format!("Data extends past the end of memory ({:X})", end));
+    }
+
+    let lowest = chunks.iter().filter(|(_, data)| !data.is_empty()).map(|(addr, _)| *addr).min();
+    let start = if lowest.is_some_and(|addr| addr >= 0x200) { 0x200 } else { 0 };
+    let mut rom = vec![0; end.saturating_sub(start)];
+
+    for (addr, data) in chunks.iter().filter(|(_, data)| !data.is_empty()) {
+        rom[addr - start..addr - start + data.len()].copy_from_slice(data);
+    }
+
+    Ok(rom)
+}
+
+// Parses whitespace-separated hex bytes or words as they are usually pasted, i.e. "00E0 A22A" or
+// "0x00, 0xE0". Tokens ending with a colon are addresses and anything after a # or ; is a
+// comment.
+fn parse_hex_text(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+
+    for line in text.lines() {
+        let line = line.split(['#', ';']).next().unwrap_or("");
+
+        for token in line.split_whitespace() {
+            let token = token.trim_end_matches(',');
+
+            if token.ends_with(':') || token.is_empty() {
+                continue;
+            }
+
+            let digits = token
+                .strip_prefix("0x")
+                .or_else(|| token.strip_prefix("0X"))
+                .or_else(|| token.strip_prefix('$'))
+                .unwrap_or(token);
+
+            if digits.is_empty() || digits.len() % 2 != 0 || !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return None;
+            }
+
+            for i in (0..digits.len()).step_by(2) {
+                bytes.push(u8::from_str_radix(&digits[i..i + 2], 16).ok()?);
+            }
+        }
+    }
+
+    if bytes.is_empty() {
+        None
+    } else {
+        Some(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An Intel HEX record with its checksum
+    fn record(addr: u16, kind: u8, data: &[u8]) -> String {
+        let mut bytes = vec![data.len() as u8, (addr >> 8) as u8, addr as u8, kind];
+        bytes.extend(data);
+        bytes.push(bytes.iter().fold(0u8, |sum, b| sum.wrapping_sub(*b)));
+
+        let digits: String = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        format!(":{}\n", digits)
+    }
+
+    fn intel_hex(records: &[(u16, &[u8])]) -> String {
+        let mut text: String = records.iter().map(|(addr, data)| record(*addr, 0x00, data)).collect();
+        text.push_str(&record(0, 0x01, &[]));
+        text
+    }
+
+    #[test]
+    fn intel_hex_program_at_0x200() {
+        let text = intel_hex(&[(0x200, &[0x00, 0xE0]), (0x204, &[0x12, 0x04])]);
+
+        assert!(is_intel_hex(&text));
+        assert_eq!(parse_intel_hex(&text).unwrap(), [0x00, 0xE0, 0x00, 0x00, 0x12, 0x04]);
+    }
+
+    #[test]
+    fn intel_hex_rom_past_512_bytes_keeps_its_start() {
+        // As objcopy writes a binary, in records of 16 bytes from address 0
+        let rom: Vec<u8> = (0..600).map(|i| i as u8).collect();
+        let records: Vec<(u16, &[u8])> = rom.chunks(16).enumerate().map(|(i, data)| (i as u16 * 16, data)).collect();
+
+        assert_eq!(parse_intel_hex(&intel_hex(&records)).unwrap(), rom);
+    }
+
+    #[test]
+    fn intel_hex_rom_on_its_own() {
+        let text = intel_hex(&[(0x000, &[0x00, 0xE0, 0x12, 0x02])]);
+
+        assert_eq!(parse_intel_hex(&text).unwrap(), [0x00, 0xE0, 0x12, 0x02]);
+    }
+
+    #[test]
+    fn intel_hex_errors() {
+        let mut corrupt = intel_hex(&[(0x200, &[0x00, 0xE0])]);
+        corrupt.replace_range(9..11, "01");
+
+        assert_eq!(parse_intel_hex(&corrupt).unwrap_err(), "Line 1: Checksum mismatch");
+        assert_eq!(parse_intel_hex(&record(0, 0x01, &[])).unwrap_err(), "File doesn't contain any data");
+
+        let past_memory = record(0x0001, 0x04, &[0x00, 0x01]) + &intel_hex(&[(0xFFFF, &[0x00])]);
+        assert!(parse_intel_hex(&past_memory).unwrap_err().starts_with("Data extends past the end of memory"));
+    }
+
+    #[test]
+    fn hex_text_formats() {
+        assert_eq!(parse_hex_text("00E0 A22A\n6000"), Some(vec![0x00, 0xE0, 0xA2, 0x2A, 0x60, 0x00]));
+        assert_eq!(parse_hex_text("0x00, 0xE0, $12"), Some(vec![0x00, 0xE0, 0x12]));
+        assert_eq!(parse_hex_text("200: 00E0 # clear\n202: 1202 ; loop"), Some(vec![0x00, 0xE0, 0x12, 0x02]));
+
+        assert_eq!(parse_hex_text("# only a comment"), None);
+        assert_eq!(parse_hex_text("00E"), None);
+        assert_eq!(parse_hex_text("Read me first"), None);
+    }
+
+    #[test]
+    fn roms_are_told_apart_from_other_files() {
+        assert!(looks_like_rom("game.ch8", &[0x00, 0xE0, 0xFF, 0xFE]));
+        assert!(looks_like_rom("game.txt", b"00E0 1202"));
+        assert!(looks_like_rom("roms/game.hex", intel_hex(&[(0x200, &[0x00, 0xE0])]).as_bytes()));
+
+        assert!(!looks_like_rom("README.txt", b"Use the arrow keys to move"));
+        assert!(!looks_like_rom("cover.png", b"\x89PNG\r\n\x1A\n"));
+        assert!(!looks_like_rom("__MACOSX/game.ch8", &[0x00, 0xE0]));
+        assert!(!looks_like_rom("roms/.DS_Store", &[0x00, 0xE0]));
+        assert!(!looks_like_rom("empty.ch8", &[]));
+    }
+}
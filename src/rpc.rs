@@ -0,0 +1,433 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+use serde_json::{json, Map, Value};
+
+use chip8_rs::chip8::{FRAMEBUFFER_HEIGHT, FRAMEBUFFER_WIDTH};
+use chip8_rs::Chip8;
+
+// Error codes defined by JSON-RPC 2.0, and the one used when the emulator fails
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const EMULATOR_ERROR: i64 = -32000;
+
+const MEMORY_SIZE: u64 = 4096;
+
+type RpcError = (i64, String);
+
+// The machine which the methods act on, either the application's or the headless server's
+pub trait Host {
+    fn get_machine(&mut self) -> Option<&mut Chip8>;
+
+    // Replaces the machine with one which runs at a number of instructions per frame
+    fn set_machine(&mut self, cpu: Chip8, speed: u32);
+
+    // Stops the host from running frames by itself, leaving it to the client
+    fn set_paused(&mut self, paused: bool);
+}
+
+// Creates a machine running the ROM at a path, along with its instructions per frame
+pub type Loader = Box<dyn Fn(&Path) -> Result<(Chip8, u32), String>>;
+
+// A request from a client and where to send the response, which is empty for notifications
+struct Call {
+    request: String,
+    reply: Sender<String>,
+}
+
+// Accepts JSON-RPC 2.0 requests on a local socket, one JSON object per line, and answers them
+// between frames on the thread which owns the machine. Connections are served on their own
+// threads, which only pass the requests on.
+pub struct RpcServer {
+    calls: Receiver<Call>,
+    loader: Loader,
+    // The machine as it was loaded, which resets go back to
+    initial: Option<(Chip8, u32)>,
+    speed: u32,
+    // Instructions executed since the start of the current frame
+    executed: u32,
+}
+
+impl RpcServer {
+    // Listens on a TCP port on 127.0.0.1 (PORT), a TCP address (HOST:PORT) or a Unix socket
+    // (unix:PATH)
+    pub fn listen(addr: &str, loader: Loader) -> Result<Self, String> {
+        let (sender, calls) = mpsc::channel();
+        let error = |err: std::io::Error| format!("Failed to listen on {}: {}", addr, err);
+
+        if let Some(path) = addr.strip_prefix("unix:") {
+            listen_unix(path, sender).map_err(error)?;
+        } else {
+            let addr = match addr.parse::<u16>() {
+                Ok(port) => format!("127.0.0.1:{}", port),
+                Err(_) => addr.to_string(),
+            };
+
+            let listener = TcpListener::bind(&addr).map_err(error)?;
+
+            thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    if let Ok(input) = stream.try_clone() {
+                        spawn_connection(input, stream, sender.clone());
+                    }
+                }
+            });
+        }
+
+        Ok(RpcServer {
+            calls,
+            loader,
+            initial: None,
+            speed: 1,
+            executed: 0,
+        })
+    }
+
+    // Remembers the machine which resets go back to
+    pub fn set_initial(&mut self, cpu: &Chip8, speed: u32) {
+        self.initial = Some((cpu.clone(), speed));
+        self.speed = speed.max(1);
+        self.executed = 0;
+    }
+
+    // Answers the requests which have arrived so far
+    pub fn poll(&mut self, host: &mut dyn Host) {
+        while let Ok(call) = self.calls.try_recv() {
+            self.answer(call, host);
+        }
+    }
+
+    // Answers requests as they arrive, for as long as the server runs
+    pub fn serve(&mut self, host: &mut dyn Host) {
+        while let Ok(call) = self.calls.recv() {
+            self.answer(call, host);
+        }
+    }
+
+    fn answer(&mut self, call: Call, host: &mut dyn Host) {
+        let response = match serde_json::from_str::<Value>(&call.request) {
+            Ok(request) => self.handle(&request, host),
+            Err(err) => Some(error_response(Value::Null, (PARSE_ERROR, err.to_string()))),
+        };
+
+        // The connection may have closed in the meantime, which is its own business
+        let _ = call.reply.send(response.map(|response| response.to_string()).unwrap_or_default());
+    }
+
+    fn handle(&mut self, request: &Value, host: &mut dyn Host) -> Option<Value> {
+        let id = request.get("id").cloned();
+
+        let Some(method) = request["method"].as_str().filter(|_| request["jsonrpc"] == "2.0") else {
+            return Some(error_response(id.unwrap_or_default(), (INVALID_REQUEST, "Invalid request".to_string())));
+        };
+
+        let empty = Map::new();
+        let params = match &request["params"] {
+            Value::Object(params) => Ok(params),
+            Value::Null => Ok(&empty),
+            _ => Err((INVALID_PARAMS, "Parameters must be given by name".to_string())),
+        };
+
+        let result = params.and_then(|params| self.call(method, params, host));
+
+        // Notifications don't get a response, not even for errors
+        let id = id?;
+
+        Some(match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(err) => error_response(id, err),
+        })
+    }
+
+    fn call(&mut self, method: &str, params: &Map<String, Value>, host: &mut dyn Host) -> Result<Value, RpcError> {
+        if method == "load" {
+            let path = get_string(params, "path")?;
+            let (cpu, speed) = (self.loader)(Path::new(path)).map_err(emulator_error)?;
+
+            self.set_initial(&cpu, speed);
+            host.set_machine(cpu, speed);
+
+            return Ok(json!({ "speed": speed }));
+        }
+
+        if method == "reset" {
+            let (initial, speed) = self.initial.clone().ok_or((EMULATOR_ERROR, "No ROM has been loaded".to_string()))?;
+
+            self.set_initial(&initial, speed);
+            host.set_machine(initial, speed);
+
+            return Ok(Value::Null);
+        }
+
+        if method == "pause" {
+            host.set_paused(get_bool(params, "paused", true)?);
+            return Ok(Value::Null);
+        }
+
+        let cpu = host.get_machine().ok_or((EMULATOR_ERROR, "No ROM has been loaded".to_string()))?;
+
+        match method {
+            "step" => {
+                for _ in 0..get_number(params, "count", Some(1), u32::MAX as u64)? {
+                    self.step(cpu)?;
+                }
+
+                Ok(registers(cpu))
+            }
+            "run_frames" => {
+                for _ in 0..get_number(params, "count", Some(1), u32::MAX as u64)? {
+                    self.step(cpu)?;
+
+                    while self.executed != 0 {
+                        self.step(cpu)?;
+                    }
+                }
+
+                Ok(registers(cpu))
+            }
+            "press_key" | "release_key" => {
+                let key = get_number(params, "key", None, 0xF)?;
+
+                cpu.set_key_state(key as usize, method == "press_key");
+
+                Ok(Value::Null)
+            }
+            "read_memory" => {
+                let address = get_number(params, "address", Some(0), MEMORY_SIZE - 1)?;
+                let length = get_number(params, "length", Some(MEMORY_SIZE - address), MEMORY_SIZE - address)?;
+
+                Ok(json!(cpu.get_memory()[address as usize..(address + length) as usize]))
+            }
+            "write_memory" => {
+                let address = get_number(params, "address", None, MEMORY_SIZE - 1)?;
+                let data = get_bytes(params, "data")?;
+
+                if address + data.len() as u64 > MEMORY_SIZE {
+                    return Err((INVALID_PARAMS, "Data runs past the end of memory".to_string()));
+                }
+
+                for (i, byte) in data.into_iter().enumerate() {
+                    cpu.set_byte(address as u16 + i as u16, byte);
+                }
+
+                Ok(Value::Null)
+            }
+            "get_registers" => Ok(registers(cpu)),
+            "set_registers" => {
+                // Everything is checked up front so that nothing changes when any of it is invalid
+                let v = match params.contains_key("v") {
+                    true => get_bytes(params, "v")?,
+                    false => Vec::new(),
+                };
+
+                if v.len() > 16 {
+                    return Err((INVALID_PARAMS, "There are only 16 V registers".to_string()));
+                }
+
+                let optional = |name, max| match params.contains_key(name) {
+                    true => get_number(params, name, None, max).map(Some),
+                    false => Ok(None),
+                };
+
+                let (i, pc, sp) = (optional("i", 0xFFF)?, optional("pc", 0xFFF)?, optional("sp", 16)?);
+                let (dt, st) = (optional("dt", 0xFF)?, optional("st", 0xFF)?);
+
+                for (x, value) in v.into_iter().enumerate() {
+                    cpu.set_register(x, value);
+                }
+
+                if let Some(i) = i {
+                    cpu.set_program_index(i as u16);
+                }
+
+                if let Some(pc) = pc {
+                    cpu.set_program_counter(pc as u16);
+                }
+
+                if let Some(sp) = sp {
+                    cpu.set_stack_pointer(sp as usize);
+                }
+
+                if let Some(dt) = dt {
+                    cpu.set_delay_timer(dt as u8);
+                }
+
+                if let Some(st) = st {
+                    cpu.set_sound_timer(st as u8);
+                }
+
+                Ok(registers(cpu))
+            }
+            "get_framebuffer" => {
+                let pixels: Vec<u8> = cpu.get_lit_pixels().iter().map(|lit| *lit as u8).collect();
+
+                Ok(json!({ "width": FRAMEBUFFER_WIDTH, "height": FRAMEBUFFER_HEIGHT, "pixels": pixels }))
+            }
+            "save_state" => {
+                let state: String = cpu.save_state().iter().map(|b| format!("{:02x}", b)).collect();
+
+                Ok(json!({ "state": state }))
+            }
+            "load_state" => {
+                let state = get_string(params, "state")?;
+                let bytes = (0..state.len())
+                    .step_by(2)
+                    .map(|i| state.get(i..i + 2).and_then(|digits| u8::from_str_radix(digits, 16).ok()))
+                    .collect::<Option<Vec<u8>>>()
+                    .ok_or((INVALID_PARAMS, "State must be a hex string".to_string()))?;
+
+                cpu.load_state(&bytes).map_err(emulator_error)?;
+
+                Ok(Value::Null)
+            }
+            _ => Err((METHOD_NOT_FOUND, format!("Unknown method ({})", method))),
+        }
+    }
+
+    // Executes an instruction, counting down the timers after every frame's worth
+    fn step(&mut self, cpu: &mut Chip8) -> Result<(), RpcError> {
+        cpu.step().map_err(emulator_error)?;
+
+        self.executed += 1;
+
+        if self.executed == self.speed {
+            cpu.tick_timers();
+            self.executed = 0;
+        }
+
+        Ok(())
+    }
+}
+
+impl Host for Option<Chip8> {
+    fn get_machine(&mut self) -> Option<&mut Chip8> {
+        self.as_mut()
+    }
+
+    fn set_machine(&mut self, cpu: Chip8, _speed: u32) {
+        *self = Some(cpu);
+    }
+
+    // Headless machines only ever run when asked to
+    fn set_paused(&mut self, _paused: bool) {}
+}
+
+#[cfg(unix)]
+fn listen_unix(path: &str, sender: Sender<Call>) -> std::io::Result<()> {
+    use std::fs;
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::UnixListener;
+
+    // A socket left behind by an earlier server would stop this one from binding
+    if fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        fs::remove_file(path)?;
+    }
+
+    let listener = UnixListener::bind(path)?;
+
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if let Ok(input) = stream.try_clone() {
+                spawn_connection(input, stream, sender.clone());
+            }
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn listen_unix(_path: &str, _sender: Sender<Call>) -> std::io::Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "Unix sockets aren't supported on this platform"))
+}
+
+// Passes the requests of a connection on one at a time and writes back the responses
+fn spawn_connection(input: impl Read + Send + 'static, mut output: impl Write + Send + 'static, calls: Sender<Call>) {
+    thread::spawn(move || {
+        let (reply, replies) = mpsc::channel();
+
+        for request in BufReader::new(input).lines() {
+            let Ok(request) = request else {
+                break;
+            };
+
+            if request.trim().is_empty() {
+                continue;
+            }
+
+            if calls.send(Call { request, reply: reply.clone() }).is_err() {
+                break;
+            }
+
+            let Ok(response) = replies.recv() else {
+                break;
+            };
+
+            if !response.is_empty() && writeln!(output, "{}", response).and_then(|_| output.flush()).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+fn registers(cpu: &Chip8) -> Value {
+    json!({
+        "v": cpu.get_registers(),
+        "i": cpu.get_program_index(),
+        "pc": cpu.get_program_counter(),
+        "sp": cpu.get_stack_pointer(),
+        "stack": &cpu.get_stack()[..cpu.get_stack_pointer()],
+        "dt": cpu.get_delay_timer(),
+        "st": cpu.get_sound_timer(),
+    })
+}
+
+fn error_response(id: Value, (code, message): RpcError) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+fn emulator_error(message: String) -> RpcError {
+    (EMULATOR_ERROR, message)
+}
+
+fn get_number(params: &Map<String, Value>, name: &str, default: Option<u64>, max: u64) -> Result<u64, RpcError> {
+    match (params.get(name), default) {
+        (None, Some(default)) => Ok(default),
+        (value, _) => value
+            .and_then(Value::as_u64)
+            .filter(|n| *n <= max)
+            .ok_or_else(|| (INVALID_PARAMS, format!("\"{}\" must be a number from 0 to {}", name, max))),
+    }
+}
+
+fn get_bool(params: &Map<String, Value>, name: &str, default: bool) -> Result<bool, RpcError> {
+    match params.get(name) {
+        None => Ok(default),
+        Some(value) => value.as_bool().ok_or_else(|| (INVALID_PARAMS, format!("\"{}\" must be true or false", name))),
+    }
+}
+
+fn get_string<'a>(params: &'a Map<String, Value>, name: &str) -> Result<&'a str, RpcError> {
+    params
+        .get(name)
+        .and_then(Value::as_str)
+        .ok_or_else(|| (INVALID_PARAMS, format!("\"{}\" must be a string", name)))
+}
+
+fn get_bytes(params: &Map<String, Value>, name: &str) -> Result<Vec<u8>, RpcError> {
+    params
+        .get(name)
+        .and_then(Value::as_array)
+        .and_then(|values| {
+            values
+                .iter()
+                .map(|value| value.as_u64().filter(|n| *n <= 0xFF).map(|n| n as u8))
+                .collect()
+        })
+        .ok_or_else(|| (INVALID_PARAMS, format!("\"{}\" must be an array of bytes", name)))
+}
@@ -0,0 +1,281 @@
+use std::fmt;
+use std::str::FromStr;
+
+// Pixel-art upscaling algorithms which are applied to the framebuffer before it is presented. They
+// work on exact colour matches (except for xBR) so they only ever make sense on unfiltered input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Upscaler {
+    None,
+    Scale2x,
+    Scale3x,
+    Epx,
+    Xbr,
+}
+
+impl Upscaler {
+    pub const ALL: [Upscaler; 5] = [
+        Upscaler::None,
+        Upscaler::Scale2x,
+        Upscaler::Scale3x,
+        Upscaler::Epx,
+        Upscaler::Xbr,
+    ];
+
+    pub fn factor(self) -> usize {
+        match self {
+            Upscaler::None => 1,
+            Upscaler::Scale2x | Upscaler::Epx | Upscaler::Xbr => 2,
+            Upscaler::Scale3x => 3,
+        }
+    }
+
+    pub fn next(self) -> Upscaler {
+        let i = Upscaler::ALL.iter().position(|&u| u == self).unwrap();
+
+        Upscaler::ALL[(i + 1) % Upscaler::ALL.len()]
+    }
+
+    // Upscales the source image into the destination buffer, which is resized as needed, and
+    // returns the dimensions of the result
+    pub fn apply(self, src: &[u32], width: usize, height: usize, dst: &mut Vec<u32>) -> (usize, usize) {
+        let factor = self.factor();
+
+        dst.clear();
+        dst.resize(width * factor * height * factor, 0);
+
+        let image = Image { pixels: src, width, height };
+
+        match self {
+            Upscaler::None => dst.copy_from_slice(src),
+            Upscaler::Scale2x => scale2x(&image, dst),
+            Upscaler::Scale3x => scale3x(&image, dst),
+            Upscaler::Epx => epx(&image, dst),
+            Upscaler::Xbr => xbr(&image, dst),
+        }
+
+        (width * factor, height * factor)
+    }
+}
+
+impl fmt::Display for Upscaler {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Upscaler::None => "none",
+            Upscaler::Scale2x => "scale2x",
+            Upscaler::Scale3x => "scale3x",
+            Upscaler::Epx => "epx",
+            Upscaler::Xbr => "xbr",
+        };
+
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Upscaler {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Upscaler::ALL
+            .iter()
+            .find(|u| u.to_string() == s)
+            .copied()
+            .ok_or_else(|| format!("Unknown upscaler ({})", s))
+    }
+}
+
+type Rotation = fn(isize, isize) -> (isize, isize);
+
+struct Image<'a> {
+    pixels: &'a [u32],
+    width: usize,
+    height: usize,
+}
+
+impl Image<'_> {
+    // Fetches the pixel at the given offset from (x, y), clamping to the edges of the image
+    fn get(&self, x: usize, y: usize, dx: isize, dy: isize) -> u32 {
+        let x = (x as isize + dx).clamp(0, self.width as isize - 1) as usize;
+        let y = (y as isize + dy).clamp(0, self.height as isize - 1) as usize;
+
+        self.pixels[x + (y * self.width)]
+    }
+}
+
+fn scale2x(src: &Image, dst: &mut [u32]) {
+    let pitch = src.width * 2;
+
+    for y in 0..src.height {
+        for x in 0..src.width {
+            let p = src.get(x, y, 0, 0);
+            let a = src.get(x, y, 0, -1);
+            let b = src.get(x, y, 1, 0);
+            let c = src.get(x, y, -1, 0);
+            let d = src.get(x, y, 0, 1);
+
+            let out = (x * 2) + (y * 2 * pitch);
+
+            dst[out] = if c == a && c != d && a != b { a } else { p };
+            dst[out + 1] = if a == b && a != c && b != d { b } else { p };
+            dst[out + pitch] = if d == c && d != b && c != a { c } else { p };
+            dst[out + pitch + 1] = if b == d && b != a && d != c { d } else { p };
+        }
+    }
+}
+
+fn epx(src: &Image, dst: &mut [u32]) {
+    let pitch = src.width * 2;
+
+    for y in 0..src.height {
+        for x in 0..src.width {
+            let p = src.get(x, y, 0, 0);
+            let a = src.get(x, y, 0, -1);
+            let b = src.get(x, y, 1, 0);
+            let c = src.get(x, y, -1, 0);
+            let d = src.get(x, y, 0, 1);
+
+            let mut e = [p; 4];
+
+            // Unlike Scale2x, EPX keeps the original pixel when three or more neighbours match
+            let matching = [(a, b), (a, c), (a, d), (b, c), (b, d), (c, d)]
+                .iter()
+                .filter(|(l, r)| l == r)
+                .count();
+
+            if matching < 3 {
+                if c == a {
+                    e[0] = a;
+                }
+
+                if a == b {
+                    e[1] = b;
+                }
+
+                if d == c {
+                    e[2] = c;
+                }
+
+                if b == d {
+                    e[3] = d;
+                }
+            }
+
+            let out = (x * 2) + (y * 2 * pitch);
+
+            dst[out] = e[0];
+            dst[out + 1] = e[1];
+            dst[out + pitch] = e[2];
+            dst[out + pitch + 1] = e[3];
+        }
+    }
+}
+
+fn scale3x(src: &Image, dst: &mut [u32]) {
+    let pitch = src.width * 3;
+
+    for y in 0..src.height {
+        for x in 0..src.width {
+            let a = src.get(x, y, -1, -1);
+            let b = src.get(x, y, 0, -1);
+            let c = src.get(x, y, 1, -1);
+            let d = src.get(x, y, -1, 0);
+            let e = src.get(x, y, 0, 0);
+            let f = src.get(x, y, 1, 0);
+            let g = src.get(x, y, -1, 1);
+            let h = src.get(x, y, 0, 1);
+            let i = src.get(x, y, 1, 1);
+
+            let out = if b != h && d != f {
+                [
+                    if d == b { d } else { e },
+                    if (d == b && e != c) || (b == f && e != a) { b } else { e },
+                    if b == f { f } else { e },
+                    if (d == b && e != g) || (d == h && e != a) { d } else { e },
+                    e,
+                    if (b == f && e != i) || (h == f && e != c) { f } else { e },
+                    if d == h { d } else { e },
+                    if (d == h && e != i) || (h == f && e != g) { h } else { e },
+                    if h == f { f } else { e },
+                ]
+            } else {
+                [e; 9]
+            };
+
+            for (row, pixels) in out.chunks_exact(3).enumerate() {
+                let start = (x * 3) + (((y * 3) + row) * pitch);
+
+                dst[start..start + 3].copy_from_slice(pixels);
+            }
+        }
+    }
+}
+
+// 2xBR (level 1) by Hyllian. Each corner of the output pixel is handled by rotating the
+// neighbourhood so that the corner being evaluated is always the bottom-right one.
+fn xbr(src: &Image, dst: &mut [u32]) {
+    let pitch = src.width * 2;
+
+    // Rotations of (dx, dy) and the matching output sub-pixel for each corner
+    let corners: [(Rotation, usize, usize); 4] = [
+        (|dx, dy| (dx, dy), 1, 1),
+        (|dx, dy| (-dy, dx), 0, 1),
+        (|dx, dy| (-dx, -dy), 0, 0),
+        (|dx, dy| (dy, -dx), 1, 0),
+    ];
+
+    for y in 0..src.height {
+        for x in 0..src.width {
+            for (rotate, sub_x, sub_y) in corners {
+                let px = |dx, dy| {
+                    let (dx, dy) = rotate(dx, dy);
+                    src.get(x, y, dx, dy)
+                };
+
+                let (b, c, d, e, f) = (px(0, -1), px(1, -1), px(-1, 0), px(0, 0), px(1, 0));
+                let (g, h, i) = (px(-1, 1), px(0, 1), px(1, 1));
+                let (f4, i4, h5, i5) = (px(2, 0), px(2, 1), px(0, 2), px(1, 2));
+
+                let wd1 = yuv_distance(e, c) + yuv_distance(e, g) + yuv_distance(i, f4)
+                    + yuv_distance(i, h5) + (4 * yuv_distance(h, f));
+                let wd2 = yuv_distance(h, d) + yuv_distance(h, i5) + yuv_distance(f, i4)
+                    + yuv_distance(f, b) + (4 * yuv_distance(e, i));
+
+                let color = if wd1 < wd2 && e != f && e != h {
+                    let edge = if yuv_distance(e, f) <= yuv_distance(e, h) { f } else { h };
+
+                    blend(e, edge)
+                } else {
+                    e
+                };
+
+                dst[(x * 2) + sub_x + (((y * 2) + sub_y) * pitch)] = color;
+            }
+        }
+    }
+}
+
+fn yuv_distance(a: u32, b: u32) -> u32 {
+    let (ay, au, av) = yuv(a);
+    let (by, bu, bv) = yuv(b);
+
+    ((48.0 * (ay - by).abs()) + (7.0 * (au - bu).abs()) + (6.0 * (av - bv).abs())) as u32
+}
+
+fn yuv(pixel: u32) -> (f32, f32, f32) {
+    let r = ((pixel >> 16) & 0xFF) as f32;
+    let g = ((pixel >> 8) & 0xFF) as f32;
+    let b = (pixel & 0xFF) as f32;
+
+    let y = (0.299 * r) + (0.587 * g) + (0.114 * b);
+    let u = (0.492 * (b - y)) + 128.0;
+    let v = (0.877 * (r - y)) + 128.0;
+
+    (y, u, v)
+}
+
+fn blend(a: u32, b: u32) -> u32 {
+    let r = (((a >> 16) & 0xFF) + ((b >> 16) & 0xFF)) / 2;
+    let g = (((a >> 8) & 0xFF) + ((b >> 8) & 0xFF)) / 2;
+    let bl = ((a & 0xFF) + (b & 0xFF)) / 2;
+
+    (r << 16) | (g << 8) | bl
+}
@@ -0,0 +1,294 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use rhai::{Dynamic, Engine, EvalAltResult, FnPtr, AST};
+
+use chip8_rs::chip8::{FRAMEBUFFER_HEIGHT, FRAMEBUFFER_WIDTH};
+use chip8_rs::trace::Tracer;
+use chip8_rs::Chip8;
+
+// Operations a single callback may take, so that a script stuck in a loop can't hang the emulator
+const MAX_OPERATIONS: u64 = 10_000_000;
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+// What the functions registered with the engine share with the script runner
+struct State {
+    // The machine while the script is running and a stand-in for it otherwise, as the engine
+    // can't borrow it
+    cpu: Chip8,
+    frame_callbacks: Vec<FnPtr>,
+    hooks: BTreeMap<u16, Vec<FnPtr>>,
+    // Text drawn during the current frame, at positions in CHIP-8 pixels
+    overlay: Vec<(usize, usize, String)>,
+    messages: Vec<String>,
+    frame: u64,
+}
+
+// A Rhai script controlling the emulator. Running the script registers callbacks for every
+// frame and for instructions at certain addresses, and the callbacks read and change the
+// machine, press keys and draw text over the screen:
+//
+//     on_exec(0x2A4, || print(`lives: ${peek(0x3F0)}`));
+//     on_frame(|| text(0, 0, `score ${get_v(3)}`));
+//
+// Errors stop the script, leaving the machine running without it.
+pub struct Script {
+    path: PathBuf,
+    engine: Engine,
+    ast: AST,
+    state: Rc<RefCell<State>>,
+    error: Option<String>,
+}
+
+impl Script {
+    pub fn load(path: &Path, cpu: &mut Chip8) -> Result<Self, String> {
+        let source = fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read script {}: {}", path.display(), err))?;
+
+        let state = Rc::new(RefCell::new(State {
+            cpu: cpu.clone(),
+            frame_callbacks: Vec::new(),
+            hooks: BTreeMap::new(),
+            overlay: Vec::new(),
+            messages: Vec::new(),
+            frame: 0,
+        }));
+
+        let engine = create_engine(&state);
+        let ast = engine
+            .compile(&source)
+            .map_err(|err| format!("Failed to compile script {}: {}", path.display(), err))?;
+
+        let mut script = Script {
+            path: path.to_path_buf(),
+            engine,
+            ast,
+            state,
+            error: None,
+        };
+
+        script
+            .with_machine(cpu, |engine, ast| engine.run_ast(ast))
+            .map_err(|err| format!("Failed to run script {}: {}", path.display(), err))?;
+
+        Ok(script)
+    }
+
+    // Loads the script again from its file, i.e. after it has been edited
+    pub fn reload(&self, cpu: &mut Chip8) -> Result<Self, String> {
+        Script::load(&self.path, cpu)
+    }
+
+    pub fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    // Why the script stopped, if it did
+    pub fn get_error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    pub fn get_overlay(&self) -> Vec<(usize, usize, String)> {
+        match self.error {
+            Some(_) => Vec::new(),
+            None => self.state.borrow().overlay.clone(),
+        }
+    }
+
+    // Everything the script printed since the last call, along with the error which stopped it
+    pub fn take_messages(&mut self) -> Vec<String> {
+        mem::take(&mut self.state.borrow_mut().messages)
+    }
+
+    // Runs a frame like Chip8::run_frame, calling the hooks for an address before the instruction
+    // there and the frame callbacks once the timers have been counted down
+    pub fn run_frame(&mut self, cpu: &mut Chip8, instructions: u32, mut tracer: Option<&mut Tracer>)
+                     -> Result<(), String> {
+        if self.error.is_some() {
+            return match tracer {
+                Some(tracer) => tracer.run_frame(cpu, instructions),
+                None => cpu.run_frame(instructions),
+            };
+        }
+
+        self.state.borrow_mut().overlay.clear();
+
+        for _ in 0..instructions {
+            let hooks = self.state.borrow().hooks.get(&cpu.get_program_counter()).cloned();
+
+            if let Some(hooks) = hooks {
+                self.call(cpu, &hooks);
+            }
+
+            match &mut tracer {
+                Some(tracer) => tracer.step(cpu)?,
+                None => cpu.step()?,
+            }
+        }
+
+        match tracer {
+            Some(tracer) => tracer.end_frame(cpu),
+            None => cpu.tick_timers(),
+        }
+
+        self.state.borrow_mut().frame += 1;
+
+        let callbacks = self.state.borrow().frame_callbacks.clone();
+        self.call(cpu, &callbacks);
+
+        Ok(())
+    }
+
+    fn call(&mut self, cpu: &mut Chip8, callbacks: &[FnPtr]) {
+        for callback in callbacks {
+            if self.error.is_some() {
+                return;
+            }
+
+            let result = self.with_machine(cpu, |engine, ast| callback.call::<Dynamic>(engine, ast, ()).map(|_| ()));
+
+            if let Err(err) = result {
+                let error = format!("Script error: {}", err);
+
+                self.state.borrow_mut().messages.push(error.clone());
+                self.error = Some(error);
+            }
+        }
+    }
+
+    // Lends the machine to the functions registered with the engine for as long as f runs
+    fn with_machine<T>(&mut self, cpu: &mut Chip8, f: impl FnOnce(&Engine, &AST) -> ScriptResult<T>)
+                       -> Result<T, String> {
+        mem::swap(cpu, &mut self.state.borrow_mut().cpu);
+        let result = f(&self.engine, &self.ast);
+        mem::swap(cpu, &mut self.state.borrow_mut().cpu);
+
+        // Errors in callbacks come wrapped in where the callback was called from
+        result.map_err(|err| err.unwrap_inner().to_string())
+    }
+}
+
+fn create_engine(state: &Rc<RefCell<State>>) -> Engine {
+    let mut engine = Engine::new();
+
+    engine.set_max_operations(MAX_OPERATIONS);
+
+    let shared = state.clone();
+    engine.on_print(move |text| shared.borrow_mut().messages.push(text.to_string()));
+
+    let shared = state.clone();
+    engine.on_debug(move |text, _, _| shared.borrow_mut().messages.push(text.to_string()));
+
+    let shared = state.clone();
+    engine.register_fn("peek", move |addr: i64| -> ScriptResult<i64> {
+        Ok(shared.borrow().cpu.get_memory()[check("address", addr, 0xFFF)?] as i64)
+    });
+
+    let shared = state.clone();
+    engine.register_fn("poke", move |addr: i64, value: i64| -> ScriptResult<()> {
+        let addr = check("address", addr, 0xFFF)?;
+        let value = check("value", value, 0xFF)?;
+
+        shared.borrow_mut().cpu.set_byte(addr as u16, value as u8);
+        Ok(())
+    });
+
+    let shared = state.clone();
+    engine.register_fn("get_v", move |x: i64| -> ScriptResult<i64> {
+        Ok(shared.borrow().cpu.get_registers()[check("register", x, 0xF)?] as i64)
+    });
+
+    let shared = state.clone();
+    engine.register_fn("set_v", move |x: i64, value: i64| -> ScriptResult<()> {
+        let x = check("register", x, 0xF)?;
+        let value = check("value", value, 0xFF)?;
+
+        shared.borrow_mut().cpu.set_register(x, value as u8);
+        Ok(())
+    });
+
+    let shared = state.clone();
+    engine.register_fn("get_i", move || shared.borrow().cpu.get_program_index() as i64);
+
+    let shared = state.clone();
+    engine.register_fn("set_i", move |value: i64| -> ScriptResult<()> {
+        shared.borrow_mut().cpu.set_program_index(check("I", value, 0xFFF)? as u16);
+        Ok(())
+    });
+
+    let shared = state.clone();
+    engine.register_fn("get_pc", move || shared.borrow().cpu.get_program_counter() as i64);
+
+    let shared = state.clone();
+    engine.register_fn("set_pc", move |value: i64| -> ScriptResult<()> {
+        shared.borrow_mut().cpu.set_program_counter(check("PC", value, 0xFFF)? as u16);
+        Ok(())
+    });
+
+    let shared = state.clone();
+    engine.register_fn("get_dt", move || shared.borrow().cpu.get_delay_timer() as i64);
+
+    let shared = state.clone();
+    engine.register_fn("set_dt", move |value: i64| -> ScriptResult<()> {
+        shared.borrow_mut().cpu.set_delay_timer(check("DT", value, 0xFF)? as u8);
+        Ok(())
+    });
+
+    let shared = state.clone();
+    engine.register_fn("get_st", move || shared.borrow().cpu.get_sound_timer() as i64);
+
+    let shared = state.clone();
+    engine.register_fn("set_st", move |value: i64| -> ScriptResult<()> {
+        shared.borrow_mut().cpu.set_sound_timer(check("ST", value, 0xFF)? as u8);
+        Ok(())
+    });
+
+    let shared = state.clone();
+    engine.register_fn("press", move |key: i64| -> ScriptResult<()> {
+        shared.borrow_mut().cpu.set_key_state(check("key", key, 0xF)?, true);
+        Ok(())
+    });
+
+    let shared = state.clone();
+    engine.register_fn("release", move |key: i64| -> ScriptResult<()> {
+        shared.borrow_mut().cpu.set_key_state(check("key", key, 0xF)?, false);
+        Ok(())
+    });
+
+    let shared = state.clone();
+    engine.register_fn("text", move |x: i64, y: i64, text: &str| -> ScriptResult<()> {
+        let x = check("x", x, FRAMEBUFFER_WIDTH as i64 - 1)?;
+        let y = check("y", y, FRAMEBUFFER_HEIGHT as i64 - 1)?;
+
+        shared.borrow_mut().overlay.push((x, y, text.to_string()));
+        Ok(())
+    });
+
+    let shared = state.clone();
+    engine.register_fn("frame", move || shared.borrow().frame as i64);
+
+    let shared = state.clone();
+    engine.register_fn("on_frame", move |callback: FnPtr| shared.borrow_mut().frame_callbacks.push(callback));
+
+    let shared = state.clone();
+    engine.register_fn("on_exec", move |addr: i64, callback: FnPtr| -> ScriptResult<()> {
+        let addr = check("address", addr, 0xFFF)?;
+
+        shared.borrow_mut().hooks.entry(addr as u16).or_default().push(callback);
+        Ok(())
+    });
+
+    engine
+}
+
+fn check(name: &str, value: i64, max: i64) -> ScriptResult<usize> {
+    match value {
+        0.. if value <= max => Ok(value as usize),
+        _ => Err(format!("{} must be from 0 to {} ({})", name, max, value).into()),
+    }
+}
@@ -0,0 +1,405 @@
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use sdl2::{AudioSubsystem, EventPump, GameControllerSubsystem};
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::controller::{Button, GameController};
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::render::{Texture, TextureAccess, TextureCreator, WindowCanvas};
+use sdl2::video::WindowContext;
+
+use chip8_rs::bmp;
+use chip8_rs::chip8::{FRAMEBUFFER_HEIGHT, FRAMEBUFFER_WIDTH};
+use chip8_rs::filter::{self, FilterChain};
+use chip8_rs::keypad::{KeyHints, Keymap};
+use chip8_rs::scale::Upscaler;
+use chip8_rs::Chip8;
+
+use crate::app::{Control, Frontend, MenuInput};
+use crate::osd::Osd;
+use crate::settings::{AudioSettings, Settings};
+
+struct SquareWave {
+    phase: f32,
+    phase_inc: f32,
+    volume: f32,
+}
+
+impl AudioCallback for SquareWave {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        for sample in out.iter_mut() {
+            *sample = if self.phase < 0.5 { self.volume } else { -self.volume };
+            self.phase = (self.phase + self.phase_inc) % 1.0;
+        }
+    }
+}
+
+pub struct SdlFrontend {
+    events: EventPump,
+    controller_sys: Option<GameControllerSubsystem>,
+    // Controllers are closed when dropped, so the open ones have to be kept
+    controllers: Vec<GameController>,
+    canvas: WindowCanvas,
+    texture_creator: TextureCreator<WindowContext>,
+    texture: Option<(Texture, (usize, usize))>,
+    beeper: Option<AudioDevice<SquareWave>>,
+    sound: bool,
+    filters: FilterChain,
+    upscaler: Upscaler,
+    keymap: Keymap,
+    key_hints: KeyHints,
+    scaled: Vec<u32>,
+    composed: Vec<u32>,
+    screenshot: bool,
+}
+
+impl SdlFrontend {
+    pub fn new(settings: &Settings) -> Result<Self, String> {
+        let sdl = sdl2::init()
+            .map_err(|err| format!("Failed to initialize SDL2: {}", err))?;
+        let video_sys = sdl
+            .video()
+            .map_err(|err| format!("Failed to initialize SDL2 Video: {}", err))?;
+
+        let window = video_sys
+            .window(&window_title(settings),
+                    (FRAMEBUFFER_WIDTH * settings.scale) as u32,
+                    (FRAMEBUFFER_HEIGHT * settings.scale) as u32)
+            .opengl()
+            .position_centered()
+            .build()
+            .map_err(|err| format!("Failed to create SDL2 window: {}", err))?;
+
+        let canvas = window
+            .into_canvas()
+            .build()
+            .map_err(|err| format!("Failed to create SDL2 window surface: {}", err))?;
+
+        let events = sdl
+            .event_pump()
+            .map_err(|err| format!("Failed to create event pump: {}", err))?;
+
+        let texture_creator = canvas.texture_creator();
+
+        // Controllers are optional too, they are opened as they are connected
+        let controller_sys = sdl.game_controller()
+            .map_err(|err| eprintln!("Failed to initialize game controllers: {}", err))
+            .ok();
+
+        // Missing audio shouldn't prevent playing, so the beeper is optional. It is opened even
+        // when muted as another ROM may enable it later.
+        let beeper = sdl.audio()
+            .and_then(|audio_sys| open_beeper(&audio_sys, &settings.audio))
+            .map_err(|err| {
+                if settings.audio.enabled {
+                    eprintln!("Failed to initialize audio, continuing without sound: {}", err)
+                }
+            })
+            .ok();
+
+        let filters = FilterChain::new(settings.filters.clone(), FRAMEBUFFER_WIDTH, FRAMEBUFFER_HEIGHT,
+                                       settings.scale);
+
+        Ok(SdlFrontend {
+            events,
+            controller_sys,
+            controllers: Vec::new(),
+            canvas,
+            texture_creator,
+            texture: None,
+            beeper,
+            sound: settings.audio.enabled,
+            filters,
+            upscaler: settings.upscaler,
+            keymap: settings.keymap.clone(),
+            key_hints: settings.key_hints,
+            scaled: Vec::new(),
+            composed: Vec::new(),
+            screenshot: false,
+        })
+    }
+
+    // Applies the settings of another ROM, except for the window size and the beeper's sound
+    pub fn configure(&mut self, settings: &Settings) {
+        if let Err(err) = self.canvas.window_mut().set_title(&window_title(settings)) {
+            eprintln!("Failed to set window title: {}", err);
+        }
+
+        self.sound = settings.audio.enabled;
+        self.filters = FilterChain::new(settings.filters.clone(), FRAMEBUFFER_WIDTH, FRAMEBUFFER_HEIGHT,
+                                        settings.scale);
+        self.upscaler = settings.upscaler;
+        self.keymap = settings.keymap.clone();
+        self.key_hints = settings.key_hints;
+    }
+
+    // Shows an image other than the emulated screen, stretched to the window without filters
+    pub fn present_image(&mut self, pixels: &[u32], width: usize, height: usize) {
+        if let Some(beeper) = &self.beeper {
+            beeper.pause();
+        }
+
+        draw_texture(&mut self.canvas, &self.texture_creator, &mut self.texture, pixels, (width, height));
+    }
+
+    fn open_controller(&mut self, index: u32) {
+        let Some(controller_sys) = &self.controller_sys else {
+            return;
+        };
+
+        match controller_sys.open(index) {
+            Ok(controller) => self.controllers.push(controller),
+            Err(err) => eprintln!("Failed to open game controller: {}", err),
+        }
+    }
+
+    fn save_screenshot(&self, width: usize, height: usize) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        let path = PathBuf::from(format!("chip8-rs-{}.bmp", timestamp));
+
+        match bmp::write_bmp(&path, &self.scaled, width, height) {
+            Ok(()) => println!("Saved screenshot to {}", path.display()),
+            Err(err) => eprintln!("Failed to save screenshot: {}", err),
+        }
+    }
+}
+
+impl Frontend for SdlFrontend {
+    fn poll_events(&mut self, cpu: &mut Chip8) -> Control {
+        while let Some(event) = self.events.poll_event() {
+            match event {
+                Event::Quit { .. } => return Control::Quit,
+                Event::KeyDown { keycode: Some(Keycode::Escape), repeat: false, .. } => return Control::Menu,
+                Event::KeyDown { keycode: Some(Keycode::F1), repeat: false, .. } => return Control::Library,
+                Event::KeyDown { keycode: Some(Keycode::F3), repeat: false, .. } => return Control::ToggleStats,
+                Event::KeyDown { keycode: Some(Keycode::F4), repeat: false, .. } => return Control::Memory,
+                Event::KeyDown { keycode: Some(Keycode::F5), repeat: false, .. } => return Control::Reset,
+                Event::KeyDown { keycode: Some(Keycode::F6 | Keycode::Pause), repeat: false, .. } => {
+                    return Control::Pause;
+                }
+                Event::KeyDown { keycode: Some(Keycode::F7), .. } => return Control::Step,
+                Event::KeyDown { keycode: Some(Keycode::F8), repeat: false, .. } => return Control::SlowMotion,
+                Event::KeyDown { keycode: Some(Keycode::F9), repeat: false, .. } => return Control::ReloadScript,
+                Event::KeyDown { keycode: Some(Keycode::Tab), repeat: false, .. } => return Control::FastForward(true),
+                Event::KeyUp { keycode: Some(Keycode::Tab), .. } => return Control::FastForward(false),
+                Event::KeyDown { keycode: Some(Keycode::F2), repeat: false, .. } => {
+                    self.upscaler = self.upscaler.next();
+                    println!("Upscaler: {}", self.upscaler);
+                }
+                Event::KeyDown { keycode: Some(Keycode::F10), repeat: false, .. } => {
+                    return Control::Record(self.upscaler);
+                }
+                Event::KeyDown { keycode: Some(Keycode::F12), repeat: false, .. } => {
+                    self.screenshot = true;
+                }
+                Event::KeyDown { keycode: Some(key), .. } => {
+                    if let Some(key) = map_keycode(&self.keymap, &self.key_hints, key) {
+                        cpu.set_key_state(key, true);
+                    }
+                }
+                Event::KeyUp { keycode: Some(key), .. } => {
+                    if let Some(key) = map_keycode(&self.keymap, &self.key_hints, key) {
+                        cpu.set_key_state(key, false);
+                    }
+                }
+                Event::ControllerDeviceAdded { which, .. } => self.open_controller(which),
+                Event::ControllerButtonDown { button: Button::Start, .. } => return Control::Menu,
+                Event::ControllerButtonDown { button: Button::Back, .. } => return Control::Pause,
+                Event::ControllerButtonDown { button: Button::RightShoulder, .. } => return Control::FastForward(true),
+                Event::ControllerButtonUp { button: Button::RightShoulder, .. } => return Control::FastForward(false),
+                Event::ControllerButtonDown { button, .. } => {
+                    if let Some(key) = map_button(&self.key_hints, button) {
+                        cpu.set_key_state(key, true);
+                    }
+                }
+                Event::ControllerButtonUp { button, .. } => {
+                    if let Some(key) = map_button(&self.key_hints, button) {
+                        cpu.set_key_state(key, false);
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        Control::Continue
+    }
+
+    fn poll_menu(&mut self) -> Option<Vec<MenuInput>> {
+        let mut inputs = Vec::new();
+
+        while let Some(event) = self.events.poll_event() {
+            let input = match event {
+                Event::Quit { .. } => return None,
+                Event::KeyDown { keycode: Some(key), .. } => {
+                    let name = key.name();
+                    let mut chars = name.chars();
+
+                    if let (Some(c), None) = (chars.next(), chars.next()) {
+                        if c.is_ascii_alphanumeric() {
+                            inputs.push(MenuInput::Char(c.to_ascii_lowercase()));
+                        }
+                    }
+
+                    match key {
+                        Keycode::Up => MenuInput::Up,
+                        Keycode::Down => MenuInput::Down,
+                        Keycode::Left | Keycode::PageUp => MenuInput::Left,
+                        Keycode::Right | Keycode::PageDown => MenuInput::Right,
+                        Keycode::Return | Keycode::Space => MenuInput::Select,
+                        Keycode::Escape | Keycode::Backspace => MenuInput::Back,
+                        Keycode::F => MenuInput::Favourite,
+                        _ => continue,
+                    }
+                }
+                Event::ControllerDeviceAdded { which, .. } => {
+                    self.open_controller(which);
+                    continue;
+                }
+                Event::ControllerButtonDown { button, .. } => match button {
+                    Button::DPadUp => MenuInput::Up,
+                    Button::DPadDown => MenuInput::Down,
+                    Button::DPadLeft | Button::LeftShoulder => MenuInput::Left,
+                    Button::DPadRight | Button::RightShoulder => MenuInput::Right,
+                    Button::A => MenuInput::Select,
+                    Button::B | Button::Start => MenuInput::Back,
+                    Button::Y => MenuInput::Favourite,
+                    _ => continue,
+                },
+                _ => continue,
+            };
+
+            inputs.push(input);
+        }
+
+        Some(inputs)
+    }
+
+    fn present(&mut self, cpu: &Chip8, osd: &Osd, beep: bool) {
+        if let Some(beeper) = &self.beeper {
+            if self.sound && beep {
+                beeper.resume();
+            } else {
+                beeper.pause();
+            }
+        }
+
+        let (width, height) = self.upscaler.apply(cpu.get_pixels(), FRAMEBUFFER_WIDTH,
+                                                  FRAMEBUFFER_HEIGHT, &mut self.scaled);
+
+        if self.screenshot {
+            self.screenshot = false;
+            self.save_screenshot(width, height);
+        }
+
+        // Without any filters there is no need to render at the output resolution, SDL can
+        // stretch the texture by itself
+        let (pixels, size) = if self.filters.is_empty() {
+            (&self.scaled[..], (width, height))
+        } else {
+            let size = self.filters.get_output_size();
+
+            (self.filters.process(&self.scaled, width, height), size)
+        };
+
+        // The display is drawn after the filters so that the text stays sharp
+        let (pixels, size) = if osd.is_empty() {
+            (pixels, size)
+        } else {
+            let size = osd.compose(pixels, size.0, size.1, &mut self.composed);
+
+            (&self.composed[..], size)
+        };
+
+        draw_texture(&mut self.canvas, &self.texture_creator, &mut self.texture, pixels, size);
+    }
+}
+
+// Copies the pixels to the window through a streaming texture, which is recreated whenever the
+// size changes
+fn draw_texture(canvas: &mut WindowCanvas, texture_creator: &TextureCreator<WindowContext>,
+                texture: &mut Option<(Texture, (usize, usize))>, pixels: &[u32], size: (usize, usize)) {
+    if texture.as_ref().map(|(_, s)| *s != size).unwrap_or(true) {
+        if let Some((old, _)) = texture.take() {
+            // SAFETY: The texture is no longer referenced and its renderer is still alive
+            unsafe { old.destroy() };
+        }
+
+        let new = texture_creator
+            .create_texture(PixelFormatEnum::RGB888, TextureAccess::Streaming, size.0 as u32, size.1 as u32)
+            .expect("Failed to create streaming texture");
+
+        *texture = Some((new, size));
+    }
+
+    let (texture, _) = texture.as_mut().unwrap();
+
+    texture.update(None, filter::as_bytes(pixels), size.0 * 4)
+        .expect("Failed to update texture");
+
+    canvas.copy(texture, None, None)
+        .expect("Failed to copy texture");
+
+    canvas.present();
+}
+
+fn window_title(settings: &Settings) -> String {
+    match &settings.title {
+        Some(title) => format!("chip8-rs - {}", title),
+        None => String::from("chip8-rs"),
+    }
+}
+
+fn open_beeper(audio_sys: &AudioSubsystem, settings: &AudioSettings) -> Result<AudioDevice<SquareWave>, String> {
+    let spec = AudioSpecDesired {
+        freq: Some(44100),
+        channels: Some(1),
+        samples: None,
+    };
+
+    audio_sys.open_playback(None, &spec, |spec| SquareWave {
+        phase: 0.0,
+        phase_inc: settings.frequency / spec.freq as f32,
+        volume: settings.volume,
+    })
+}
+
+// The face buttons and the D-pad only do something for ROMs with suggested keys
+fn map_button(hints: &KeyHints, button: Button) -> Option<usize> {
+    match button {
+        Button::DPadUp => hints.up,
+        Button::DPadDown => hints.down,
+        Button::DPadLeft => hints.left,
+        Button::DPadRight => hints.right,
+        Button::A => hints.a,
+        Button::B => hints.b,
+        _ => None,
+    }
+}
+
+fn map_keycode(keymap: &Keymap, hints: &KeyHints, key: Keycode) -> Option<usize> {
+    match key {
+        Keycode::Up => return hints.up,
+        Keycode::Down => return hints.down,
+        Keycode::Left => return hints.left,
+        Keycode::Right => return hints.right,
+        Keycode::Space if hints.a.is_some() => return hints.a,
+        Keycode::Return => return hints.b,
+        _ => (),
+    }
+
+    let name = key.name();
+    let mut chars = name.chars();
+
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => keymap.map_char(c),
+        _ => None,
+    }
+}
@@ -0,0 +1,263 @@
+use std::str::FromStr;
+
+use chip8_rs::chip8::{self, Quirks, DEFAULT_PALETTE};
+use chip8_rs::database::RomProfile;
+use chip8_rs::filter::{self, Filter};
+use chip8_rs::keypad::{KeyHints, Keymap};
+use chip8_rs::scale::Upscaler;
+use chip8_rs::Chip8;
+
+use crate::cli::{DisplayArgs, EmulatorArgs};
+use crate::config::Config;
+
+// Instructions per frame, far beyond what any ROM needs but low enough that the menu's speeds
+// can't overflow
+pub const MAX_SPEED: u32 = 1_000_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RenderMode {
+    // Two pixels per character cell using the upper half block with different colours
+    HalfBlock,
+    // Eight pixels per character cell using the braille patterns
+    Braille,
+}
+
+impl FromStr for RenderMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "halfblock" => Ok(RenderMode::HalfBlock),
+            "braille" => Ok(RenderMode::Braille),
+            _ => Err(format!("Unknown terminal render mode ({})", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Settings {
+    pub speed: u32,
+    pub seed: Option<u64>,
+    // Frames run per frame while fast-forwarding, 0 for as many as possible
+    pub fast_forward: u32,
+    pub quirks: Quirks,
+    pub palette: [u32; 2],
+    pub keymap: Keymap,
+    pub key_hints: KeyHints,
+    pub title: Option<String>,
+
+    pub scale: usize,
+    pub filters: Vec<Filter>,
+    pub upscaler: Upscaler,
+    pub terminal: Option<RenderMode>,
+
+    pub audio: AudioSettings,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct AudioSettings {
+    pub enabled: bool,
+    pub volume: f32,
+    pub frequency: f32,
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        AudioSettings {
+            enabled: true,
+            volume: 0.25,
+            frequency: 440.0,
+        }
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            speed: 10,
+            seed: None,
+            fast_forward: 4,
+            quirks: Quirks::default(),
+            palette: DEFAULT_PALETTE,
+            keymap: Keymap::default(),
+            key_hints: KeyHints::default(),
+            title: None,
+
+            scale: 16,
+            filters: Vec::new(),
+            upscaler: Upscaler::None,
+            terminal: None,
+
+            audio: AudioSettings::default(),
+        }
+    }
+}
+
+impl Settings {
+    // Applies the settings recommended by the ROM database
+    pub fn apply_profile(&mut self, profile: &RomProfile) {
+        self.title = Some(profile.title.clone());
+        self.quirks = profile.quirks;
+        self.key_hints = KeyHints::from_map(&profile.keys);
+
+        if let Some(tickrate) = profile.tickrate {
+            self.speed = tickrate.clamp(1, MAX_SPEED);
+        }
+
+        if let Some(palette) = profile.palette {
+            self.palette = palette;
+        }
+    }
+
+    pub fn apply_config(&mut self, config: &Config) -> Result<(), String> {
+        let display = &config.display;
+
+        if let Some(scale) = display.scale {
+            self.set_scale(scale)?;
+        }
+
+        if let Some(spec) = &display.filters {
+            self.filters = filter::parse_chain(spec)?;
+        }
+
+        if let Some(upscaler) = &display.upscaler {
+            self.upscaler = upscaler.parse()?;
+        }
+
+        if let Some(palette) = &display.palette {
+            self.palette = chip8::parse_palette(palette)?;
+        }
+
+        if let Some(mode) = &display.terminal {
+            self.terminal = match mode.as_str() {
+                "none" => None,
+                mode => Some(mode.parse()?),
+            };
+        }
+
+        let audio = &config.audio;
+
+        if let Some(enabled) = audio.enabled {
+            self.audio.enabled = enabled;
+        }
+
+        if let Some(volume) = audio.volume {
+            self.audio.volume = volume.clamp(0.0, 1.0);
+        }
+
+        if let Some(frequency) = audio.frequency {
+            self.audio.frequency = frequency;
+        }
+
+        if let Some(speed) = config.timing.speed {
+            self.set_speed(speed)?;
+        }
+
+        if config.timing.seed.is_some() {
+            self.seed = config.timing.seed;
+        }
+
+        if let Some(rate) = config.timing.fast_forward {
+            self.fast_forward = rate;
+        }
+
+        let quirks = &config.quirks;
+
+        for (name, value) in [("shift", quirks.shift), ("load-store", quirks.load_store), ("jump", quirks.jump),
+                              ("vf-reset", quirks.vf_reset), ("wrap", quirks.wrap),
+                              ("display-wait", quirks.display_wait), ("increment-by-x", quirks.increment_by_x)] {
+            if let Some(enabled) = value {
+                self.quirks.set(name, enabled)?;
+            }
+        }
+
+        if let Some(keymap) = &config.input.keymap {
+            self.keymap = keymap.parse()?;
+        }
+
+        Ok(())
+    }
+
+    fn set_speed(&mut self, speed: u32) -> Result<(), String> {
+        if !(1..=MAX_SPEED).contains(&speed) {
+            return Err(format!("Speed must be between 1 and {} ({})", MAX_SPEED, speed));
+        }
+
+        self.speed = speed;
+
+        Ok(())
+    }
+
+    fn set_scale(&mut self, scale: usize) -> Result<(), String> {
+        if scale == 0 {
+            return Err("Display scale must be at least 1".to_string());
+        }
+
+        self.scale = scale;
+
+        Ok(())
+    }
+
+    pub fn apply_emulator_args(&mut self, args: &EmulatorArgs) {
+        if let Some(speed) = args.speed {
+            self.speed = speed;
+        }
+
+        if args.seed.is_some() {
+            self.seed = args.seed;
+        }
+
+        if let Some(rate) = args.fast_forward {
+            self.fast_forward = rate;
+        }
+
+        if let Some(quirks) = args.quirks {
+            self.quirks = quirks;
+        }
+
+        if let Some(palette) = args.palette {
+            self.palette = palette;
+        }
+
+        if let Some(keymap) = &args.keymap {
+            self.keymap = keymap.clone();
+        }
+
+        if args.mute {
+            self.audio.enabled = false;
+        }
+    }
+
+    pub fn apply_display_args(&mut self, args: &DisplayArgs) -> Result<(), String> {
+        if let Some(scale) = args.scale {
+            self.set_scale(scale)?;
+        }
+
+        if let Some(spec) = &args.filters {
+            self.filters = filter::parse_chain(spec)?;
+        }
+
+        if let Some(upscaler) = args.upscaler {
+            self.upscaler = upscaler;
+        }
+
+        if args.terminal.is_some() {
+            self.terminal = args.terminal;
+        }
+
+        Ok(())
+    }
+
+    pub fn create_cpu(&self, rom: &[u8]) -> Result<Chip8, String> {
+        let mut cpu = Chip8::new(rom)?;
+
+        cpu.set_quirks(self.quirks);
+        cpu.set_palette(self.palette);
+
+        if let Some(seed) = self.seed {
+            cpu.set_seed(seed);
+        }
+
+        Ok(cpu)
+    }
+}
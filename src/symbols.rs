@@ -0,0 +1,72 @@
+use std::collections::BTreeMap;
+
+// Where the instructions of a program came from in its source, and the names of its labels. The
+// text format has one address per line, followed by either a FILE:LINE location or a label:
+//
+//     200 game.8o:12
+//     200 main
+//     2A4 draw-player
+//
+// Addresses are hex and lines count from 1. Blank lines and lines starting with # are ignored.
+#[derive(Clone, Debug, Default)]
+pub struct SymbolMap {
+    lines: BTreeMap<u16, (String, usize)>,
+    labels: BTreeMap<u16, String>,
+}
+
+impl SymbolMap {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut symbols = SymbolMap::default();
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let error = || format!("Line {}: Expected an address and a FILE:LINE or label ({})", i + 1, line);
+
+            let (addr, symbol) = line.split_once(char::is_whitespace).ok_or_else(error)?;
+            let addr = u16::from_str_radix(addr, 16).ok().filter(|addr| *addr < 0x1000).ok_or_else(error)?;
+            let symbol = symbol.trim();
+
+            match symbol.rsplit_once(':').map(|(file, n)| (file, n.parse::<usize>())) {
+                Some((file, Ok(n))) if !file.is_empty() && n > 0 => symbols.add_line(addr, file, n),
+                _ => symbols.add_label(addr, symbol),
+            }
+        }
+
+        Ok(symbols)
+    }
+
+    pub fn add_line(&mut self, addr: u16, file: &str, line: usize) {
+        self.lines.insert(addr, (file.to_string(), line));
+    }
+
+    pub fn add_label(&mut self, addr: u16, name: &str) {
+        self.labels.insert(addr, name.to_string());
+    }
+
+    pub fn get_label(&self, addr: u16) -> Option<&str> {
+        self.labels.get(&addr).map(|name| name.as_str())
+    }
+
+    // Source location of the instruction at an address. Instructions without one of their own
+    // belong to the closest one before them, i.e. the rest of the instructions a statement
+    // assembled into.
+    pub fn get_location(&self, addr: u16) -> Option<(&str, usize)> {
+        self.lines
+            .range(..=addr)
+            .next_back()
+            .map(|(_, (file, line))| (file.as_str(), *line))
+    }
+
+    // Address of the first instruction of a line, for files where the predicate matches
+    pub fn get_address(&self, matches_file: impl Fn(&str) -> bool, line: usize) -> Option<u16> {
+        self.lines
+            .iter()
+            .find(|(_, (file, n))| *n == line && matches_file(file))
+            .map(|(addr, _)| *addr)
+    }
+}
@@ -0,0 +1,469 @@
+use std::io::{self, Stdout, Write};
+use std::time::{Duration, Instant};
+
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+                       PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags};
+use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+
+use chip8_rs::chip8::{FRAMEBUFFER_HEIGHT, FRAMEBUFFER_WIDTH};
+use chip8_rs::keypad::{KeyHints, Keymap};
+use chip8_rs::Chip8;
+
+use crate::app::{Control, Frontend, MenuInput};
+use crate::osd::{Highlight, Osd};
+use crate::settings::{RenderMode, Settings};
+
+// Most terminals only report key presses (and auto-repeats while a key is held), so without
+// support for release events a key is released once it hasn't been seen for this long
+const KEY_RELEASE_TIMEOUT: Duration = Duration::from_millis(300);
+
+fn cell_size(mode: RenderMode) -> (usize, usize) {
+    match mode {
+        RenderMode::HalfBlock => (1, 2),
+        RenderMode::Braille => (2, 4),
+    }
+}
+
+pub struct TerminalFrontend {
+    stdout: Stdout,
+    mode: RenderMode,
+    keymap: Keymap,
+    key_hints: KeyHints,
+    size: (usize, usize),
+    last_frame: Vec<u32>,
+    last_osd: Vec<String>,
+    redraw: bool,
+    key_releases: bool,
+    pressed: [Option<Instant>; 16],
+    // Without release events fast-forward is toggled instead of held
+    fast_forward: bool,
+    output: Vec<u8>,
+    bell: bool,
+    beeping: bool,
+}
+
+impl TerminalFrontend {
+    pub fn new(mode: RenderMode, settings: &Settings) -> io::Result<Self> {
+        let mut stdout = io::stdout();
+
+        terminal::enable_raw_mode()?;
+        execute!(stdout, EnterAlternateScreen, Hide, Clear(ClearType::All))?;
+
+        let key_releases = terminal::supports_keyboard_enhancement().unwrap_or(false);
+
+        if key_releases {
+            execute!(stdout, PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES))?;
+        }
+
+        let (cols, rows) = terminal::size()?;
+
+        Ok(TerminalFrontend {
+            stdout,
+            mode,
+            keymap: settings.keymap.clone(),
+            key_hints: settings.key_hints,
+            size: (cols as usize, rows as usize),
+            last_frame: Vec::new(),
+            last_osd: Vec::new(),
+            redraw: true,
+            key_releases,
+            pressed: [None; 16],
+            fast_forward: false,
+            output: Vec::new(),
+            bell: settings.audio.enabled,
+            beeping: false,
+        })
+    }
+
+    fn handle_event(&mut self, event: Event, cpu: &mut Chip8) -> Control {
+        match event {
+            Event::Key(key) => {
+                let ctrl_c = key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+
+                if ctrl_c {
+                    return Control::Quit;
+                }
+
+                if key.code == KeyCode::Tab && key.kind != KeyEventKind::Repeat {
+                    self.fast_forward = if self.key_releases {
+                        key.kind == KeyEventKind::Press
+                    } else {
+                        !self.fast_forward
+                    };
+
+                    return Control::FastForward(self.fast_forward);
+                }
+
+                if key.code == KeyCode::F(7) && key.kind != KeyEventKind::Release {
+                    return Control::Step;
+                }
+
+                if key.kind == KeyEventKind::Press {
+                    match key.code {
+                        KeyCode::Esc => return Control::Menu,
+                        KeyCode::F(3) => return Control::ToggleStats,
+                        KeyCode::F(4) => return Control::Memory,
+                        KeyCode::F(5) => return Control::Reset,
+                        KeyCode::F(6) | KeyCode::Pause => return Control::Pause,
+                        KeyCode::F(8) => return Control::SlowMotion,
+                        KeyCode::F(9) => return Control::ReloadScript,
+                        _ => (),
+                    }
+                }
+
+                if let Some(k) = map_key(&self.keymap, &self.key_hints, key.code) {
+                    if key.kind == KeyEventKind::Release {
+                        self.pressed[k] = None;
+                        cpu.set_key_state(k, false);
+                    } else {
+                        self.pressed[k] = Some(Instant::now());
+                        cpu.set_key_state(k, true);
+                    }
+                }
+            }
+            Event::Resize(cols, rows) => {
+                self.size = (cols as usize, rows as usize);
+                self.redraw = true;
+            }
+            _ => (),
+        }
+
+        Control::Continue
+    }
+
+    fn render(&mut self, lit: &[bool], palette: [u32; 2], osd: &Osd) -> io::Result<()> {
+        let (cell_w, cell_h) = cell_size(self.mode);
+        let (cols, rows) = self.size;
+
+        let min_cols = FRAMEBUFFER_WIDTH / cell_w;
+        let min_rows = FRAMEBUFFER_HEIGHT / cell_h;
+
+        self.output.clear();
+
+        if self.redraw {
+            queue!(self.output, ResetColor, Clear(ClearType::All))?;
+        }
+
+        if cols < min_cols || rows < min_rows {
+            queue!(self.output, MoveTo(0, 0), Print(format!(
+                "Terminal too small ({}x{}, need {}x{})", cols, rows, min_cols, min_rows)))?;
+
+            return self.flush();
+        }
+
+        // Scale up by whole pixels to fill as much of the terminal as possible
+        let scale = (cols / min_cols).min(rows / min_rows);
+        let width = min_cols * scale;
+        let height = min_rows * scale;
+
+        let left = (cols - width) / 2;
+        let top = (rows - height) / 2;
+
+        let pixel = |x: usize, y: usize| lit[(x / scale) + ((y / scale) * FRAMEBUFFER_WIDTH)];
+
+        let mut colors = None;
+
+        for row in 0..height {
+            queue!(self.output, MoveTo(left as u16, (top + row) as u16))?;
+
+            for col in 0..width {
+                let x = col * cell_w;
+                let y = row * cell_h;
+
+                let (c, fg, bg) = match self.mode {
+                    RenderMode::HalfBlock => ('▀', palette[pixel(x, y) as usize], palette[pixel(x, y + 1) as usize]),
+                    RenderMode::Braille => {
+                        // Dot numbering of the braille patterns, indexed by [y][x]
+                        const DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+                        let mut bits = 0;
+
+                        for (dy, row) in DOTS.iter().enumerate() {
+                            for (dx, dot) in row.iter().enumerate() {
+                                if pixel(x + dx, y + dy) {
+                                    bits |= dot;
+                                }
+                            }
+                        }
+
+                        (char::from_u32(0x2800 + bits).unwrap(), palette[1], palette[0])
+                    }
+                };
+
+                if colors != Some((fg, bg)) {
+                    colors = Some((fg, bg));
+                    queue!(self.output, SetForegroundColor(to_color(fg)), SetBackgroundColor(to_color(bg)))?;
+                }
+
+                queue!(self.output, Print(c))?;
+            }
+        }
+
+        // Text from scripts is printed over the pixels it was placed at
+        queue!(self.output, SetForegroundColor(Color::White), SetBackgroundColor(Color::Black))?;
+
+        for (x, y, text) in &osd.overlay {
+            let col = left + x * scale / cell_w;
+            let row = top + y * scale / cell_h;
+            let text: String = text.chars().take(cols - col).collect();
+
+            queue!(self.output, MoveTo(col as u16, row as u16), Print(text))?;
+        }
+
+        self.render_osd(osd)?;
+
+        queue!(self.output, ResetColor)?;
+
+        self.flush()
+    }
+
+    // The on-screen display is printed as text over the picture: the status in the top left
+    // corner, the counter in the top right, messages in the bottom left and the menu in the
+    // middle
+    fn render_osd(&mut self, osd: &Osd) -> io::Result<()> {
+        let (cols, rows) = self.size;
+
+        queue!(self.output, SetForegroundColor(Color::White), SetBackgroundColor(Color::Black))?;
+
+        if let Some(status) = &osd.status {
+            queue!(self.output, MoveTo(0, 0), Print(status))?;
+        }
+
+        if let Some(stats) = &osd.stats {
+            queue!(self.output, MoveTo(cols.saturating_sub(stats.len()) as u16, 0), Print(stats))?;
+        }
+
+        let messages: Vec<&str> = osd.get_messages().collect();
+
+        for (i, text) in messages.iter().enumerate() {
+            queue!(self.output, MoveTo(0, rows.saturating_sub(messages.len() - i) as u16), Print(text))?;
+        }
+
+        if let Some(panel) = &osd.panel {
+            let width = panel
+                .lines
+                .iter()
+                .map(|line| line.iter().map(|(text, _)| text.chars().count()).sum())
+                .chain([panel.title.chars().count()])
+                .max()
+                .unwrap_or(0);
+
+            let left = cols.saturating_sub(width + 4) / 2;
+            let top = rows.saturating_sub(panel.lines.len() + 2) / 2;
+
+            queue!(self.output, SetForegroundColor(Color::White), SetBackgroundColor(Color::Black),
+                   MoveTo(left as u16, top as u16), Print(format!("  {:<width$}  ", panel.title)),
+                   MoveTo(left as u16, top as u16 + 1), Print(" ".repeat(width + 4)))?;
+
+            for (i, line) in panel.lines.iter().enumerate() {
+                queue!(self.output, MoveTo(left as u16, (top + 2 + i) as u16), Print("  "))?;
+
+                let mut used = 0;
+
+                for (text, highlight) in line {
+                    let background = match highlight {
+                        Highlight::None => Color::Black,
+                        Highlight::Cursor => Color::Blue,
+                        Highlight::ProgramCounter => Color::DarkGreen,
+                        Highlight::Index => Color::DarkYellow,
+                        Highlight::Stack => Color::DarkMagenta,
+                        Highlight::Written => Color::DarkRed,
+                    };
+
+                    queue!(self.output, SetBackgroundColor(background), Print(text))?;
+                    used += text.chars().count();
+                }
+
+                queue!(self.output, SetBackgroundColor(Color::Black),
+                       Print(" ".repeat(width.saturating_sub(used) + 2)))?;
+            }
+        }
+
+        if let Some(menu) = &osd.menu {
+            let width = menu.items.iter().chain([&menu.title]).map(|item| item.chars().count()).max().unwrap_or(0);
+            let left = cols.saturating_sub(width + 4) / 2;
+            let top = rows.saturating_sub(menu.items.len() + 2) / 2;
+
+            let title = format!("  {:<width$}  ", menu.title);
+            queue!(self.output, MoveTo(left as u16, top as u16), Print(title),
+                   MoveTo(left as u16, top as u16 + 1), Print(" ".repeat(width + 4)))?;
+
+            for (i, item) in menu.items.iter().enumerate() {
+                let (marker, fg, bg) = if i == menu.selected {
+                    ('>', Color::Black, Color::White)
+                } else {
+                    (' ', Color::White, Color::Black)
+                };
+
+                queue!(self.output, MoveTo(left as u16, (top + 2 + i) as u16),
+                       SetForegroundColor(fg), SetBackgroundColor(bg),
+                       Print(format!("{} {:<width$}  ", marker, item)))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stdout.write_all(&self.output)?;
+        self.stdout.flush()
+    }
+}
+
+impl Frontend for TerminalFrontend {
+    fn poll_events(&mut self, cpu: &mut Chip8) -> Control {
+        loop {
+            match event::poll(Duration::ZERO) {
+                Ok(true) => (),
+                Ok(false) => break,
+                Err(_) => return Control::Quit,
+            }
+
+            match event::read() {
+                Ok(event) => {
+                    let control = self.handle_event(event, cpu);
+
+                    if control != Control::Continue {
+                        return control;
+                    }
+                }
+                Err(_) => return Control::Quit,
+            }
+        }
+
+        if !self.key_releases {
+            for (key, pressed) in self.pressed.iter_mut().enumerate() {
+                if pressed.map(|t| t.elapsed() >= KEY_RELEASE_TIMEOUT).unwrap_or(false) {
+                    *pressed = None;
+                    cpu.set_key_state(key, false);
+                }
+            }
+        }
+
+        Control::Continue
+    }
+
+    fn poll_menu(&mut self) -> Option<Vec<MenuInput>> {
+        let mut inputs = Vec::new();
+
+        while event::poll(Duration::ZERO).ok()? {
+            let key = match event::read().ok()? {
+                Event::Key(key) if key.kind != KeyEventKind::Release => key,
+                Event::Resize(cols, rows) => {
+                    self.size = (cols as usize, rows as usize);
+                    self.redraw = true;
+                    continue;
+                }
+                _ => continue,
+            };
+
+            if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+                return None;
+            }
+
+            if let KeyCode::Char(c) = key.code {
+                if c.is_ascii_alphanumeric() {
+                    inputs.push(MenuInput::Char(c.to_ascii_lowercase()));
+                }
+            }
+
+            let input = match key.code {
+                KeyCode::Up => MenuInput::Up,
+                KeyCode::Down => MenuInput::Down,
+                KeyCode::Left | KeyCode::PageUp => MenuInput::Left,
+                KeyCode::Right | KeyCode::PageDown => MenuInput::Right,
+                KeyCode::Enter | KeyCode::Char(' ') => MenuInput::Select,
+                KeyCode::Esc | KeyCode::Backspace => MenuInput::Back,
+                KeyCode::Char('f') => MenuInput::Favourite,
+                _ => continue,
+            };
+
+            inputs.push(input);
+        }
+
+        Some(inputs)
+    }
+
+    fn present(&mut self, cpu: &Chip8, osd: &Osd, beep: bool) {
+        // The terminal bell is the closest thing to a beeper, so ring it whenever a beep starts
+        if self.bell && beep && !self.beeping {
+            let _ = self.stdout.write_all(b"\x07");
+        }
+
+        self.beeping = beep;
+
+        let pixels = cpu.get_pixels();
+        let osd_text = osd_text(osd);
+
+        // Text which is no longer shown can only be removed by drawing everything again
+        if osd_text != self.last_osd {
+            self.redraw = true;
+            self.last_osd = osd_text;
+        }
+
+        if !self.redraw && self.last_frame == pixels {
+            return;
+        }
+
+        if self.render(cpu.get_lit_pixels(), cpu.get_palette(), osd).is_ok() {
+            self.redraw = false;
+            self.last_frame.clear();
+            self.last_frame.extend_from_slice(pixels);
+        }
+    }
+}
+
+impl Drop for TerminalFrontend {
+    fn drop(&mut self) {
+        if self.key_releases {
+            let _ = execute!(self.stdout, PopKeyboardEnhancementFlags);
+        }
+
+        let _ = execute!(self.stdout, ResetColor, Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+// Everything the on-screen display shows, to tell when it has changed
+fn osd_text(osd: &Osd) -> Vec<String> {
+    let mut text: Vec<String> = osd.status.iter().chain(&osd.stats).cloned().collect();
+
+    text.extend(osd.get_messages().map(str::to_string));
+    text.extend(osd.overlay.iter().map(|(x, y, text)| format!("{},{} {}", x, y, text)));
+
+    if let Some(menu) = &osd.menu {
+        text.push(format!("{} {}", menu.title, menu.selected));
+        text.extend(menu.items.iter().cloned());
+    }
+
+    if let Some(panel) = &osd.panel {
+        text.push(panel.title.clone());
+        text.extend(panel.lines.iter().map(|line| line.iter().map(|(s, h)| format!("{}{:?}", s, h)).collect()));
+    }
+
+    text
+}
+
+fn to_color(pixel: u32) -> Color {
+    Color::Rgb {
+        r: (pixel >> 16) as u8,
+        g: (pixel >> 8) as u8,
+        b: pixel as u8,
+    }
+}
+
+fn map_key(keymap: &Keymap, hints: &KeyHints, code: KeyCode) -> Option<usize> {
+    match code {
+        KeyCode::Char(' ') if hints.a.is_some() => hints.a,
+        KeyCode::Char(c) => keymap.map_char(c),
+        KeyCode::Up => hints.up,
+        KeyCode::Down => hints.down,
+        KeyCode::Left => hints.left,
+        KeyCode::Right => hints.right,
+        KeyCode::Enter => hints.b,
+        _ => None,
+    }
+}
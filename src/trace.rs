@@ -0,0 +1,246 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::chip8::Chip8;
+use crate::disasm;
+
+// Written at the start of binary traces, the last character is the version of the format
+pub const BINARY_MAGIC: &[u8; 8] = b"C8TRACE1";
+
+// Size of a record in a binary trace
+pub const RECORD_SIZE: usize = 40;
+
+// Traces have one entry per executed instruction, with the state after executing it. The text
+// format has one line per instruction:
+//
+//   <cycle> <frame> <PC> <opcode>  <disassembly>  I=<I> SP=<SP> DT=<DT> ST=<ST>  <VX>=<value>...
+//
+// with the cycle and frame in decimal and everything else in hex. Only the registers which the
+// instruction changed are listed, i.e. "V0=1F VF=01".
+//
+// The binary format starts with BINARY_MAGIC followed by little-endian records of RECORD_SIZE
+// bytes: cycle (u64), frame (u32), PC (u16), opcode (u16), I (u16), SP, DT, ST (u8 each), a
+// reserved byte, a bit mask of the registers the instruction changed (u16, bit X for VX) and the
+// values of V0 to VF.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TraceFormat {
+    #[default]
+    Text,
+    Binary,
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(TraceFormat::Text),
+            "binary" => Ok(TraceFormat::Binary),
+            _ => Err(format!("Unknown trace format ({})", s)),
+        }
+    }
+}
+
+// Decides which instructions are written to the trace, all of them by default
+#[derive(Clone, Debug, Default)]
+pub struct TraceFilter {
+    // Addresses of the instructions, any of the ranges matches
+    pub addresses: Vec<RangeInclusive<u16>>,
+    // Bit mask of the opcode classes, i.e. the highest nibbles of the opcodes
+    pub classes: Option<u16>,
+    // Frames counted from 0 when the tracer was created
+    pub frames: Option<RangeInclusive<u64>>,
+}
+
+impl TraceFilter {
+    pub fn matches(&self, pc: u16, opcode: u16, frame: u64) -> bool {
+        let address = self.addresses.is_empty() || self.addresses.iter().any(|range| range.contains(&pc));
+        let class = self.classes.map(|mask| mask & (1 << (opcode >> 12)) != 0).unwrap_or(true);
+        let frame = self.frames.as_ref().map(|range| range.contains(&frame)).unwrap_or(true);
+
+        address && class && frame
+    }
+
+    // Parses comma-separated address ranges in hex, i.e. "200-2FF,3A0"
+    pub fn set_addresses(&mut self, spec: &str) -> Result<(), String> {
+        self.addresses = spec
+            .split(',')
+            .map(|part| parse_range(part, |s| u16::from_str_radix(s, 16).ok()))
+            .collect::<Option<_>>()
+            .ok_or_else(|| format!("Address ranges must be hex, i.e. 200-2FF,3A0 ({})", spec))?;
+
+        Ok(())
+    }
+
+    // Parses comma-separated opcode classes as the first hex digit of their opcodes, i.e. "8,D"
+    // or "8XY4,DXYN"
+    pub fn set_classes(&mut self, spec: &str) -> Result<(), String> {
+        let mut mask = 0;
+
+        for part in spec.split(',') {
+            let digit = part.trim().chars().next().and_then(|c| c.to_digit(16));
+
+            match digit {
+                Some(digit) => mask |= 1 << digit,
+                None => return Err(format!("Opcode classes must start with a hex digit, i.e. 8,D ({})", spec)),
+            }
+        }
+
+        self.classes = Some(mask);
+
+        Ok(())
+    }
+
+    // Parses a range of frames in decimal, i.e. "100-200", or a single frame
+    pub fn set_frames(&mut self, spec: &str) -> Result<(), String> {
+        let range = parse_range(spec, |s| s.parse().ok())
+            .ok_or_else(|| format!("Frame window must be a range of frames, i.e. 100-200 ({})", spec))?;
+
+        self.frames = Some(range);
+
+        Ok(())
+    }
+}
+
+fn parse_range<T: Copy + PartialOrd>(s: &str, parse: impl Fn(&str) -> Option<T>) -> Option<RangeInclusive<T>> {
+    let (start, end) = match s.trim().split_once('-') {
+        Some((start, end)) => (parse(start.trim())?, parse(end.trim())?),
+        None => {
+            let value = parse(s.trim())?;
+            (value, value)
+        }
+    };
+
+    (start <= end).then_some(start..=end)
+}
+
+pub struct Tracer {
+    out: Box<dyn Write>,
+    format: TraceFormat,
+    filter: TraceFilter,
+    cycle: u64,
+    frame: u64,
+}
+
+impl Tracer {
+    pub fn new(mut out: Box<dyn Write>, format: TraceFormat, filter: TraceFilter) -> io::Result<Self> {
+        if format == TraceFormat::Binary {
+            out.write_all(BINARY_MAGIC)?;
+        }
+
+        Ok(Tracer {
+            out,
+            format,
+            filter,
+            cycle: 0,
+            frame: 0,
+        })
+    }
+
+    pub fn create(path: &Path, format: TraceFormat, filter: TraceFilter) -> Result<Self, String> {
+        File::create(path)
+            .and_then(|file| Tracer::new(Box::new(BufWriter::new(file)), format, filter))
+            .map_err(|err| format!("Failed to create trace file {}: {}", path.display(), err))
+    }
+
+    // Executes one instruction, writing it to the trace if it passes the filter
+    pub fn step(&mut self, cpu: &mut Chip8) -> Result<(), String> {
+        let pc = cpu.get_program_counter();
+        let opcode = fetch(cpu, pc);
+        let before = *cpu.get_registers();
+
+        cpu.step()?;
+
+        if self.filter.matches(pc, opcode, self.frame) {
+            let changed = before
+                .iter()
+                .zip(cpu.get_registers())
+                .enumerate()
+                .filter(|(_, (old, new))| old != new)
+                .fold(0u16, |mask, (i, _)| mask | 1 << i);
+
+            self.write(cpu, pc, opcode, changed)
+                .map_err(|err| format!("Failed to write trace: {}", err))?;
+        }
+
+        self.cycle += 1;
+
+        Ok(())
+    }
+
+    // The traced equivalent of Chip8::run_frame
+    pub fn run_frame(&mut self, cpu: &mut Chip8, instructions: u32) -> Result<(), String> {
+        for _ in 0..instructions {
+            self.step(cpu)?;
+        }
+
+        self.end_frame(cpu);
+
+        Ok(())
+    }
+
+    // Finishes a frame whose instructions were run with step
+    pub fn end_frame(&mut self, cpu: &mut Chip8) {
+        cpu.tick_timers();
+        self.frame += 1;
+    }
+
+    pub fn flush(&mut self) -> Result<(), String> {
+        self.out.flush().map_err(|err| format!("Failed to write trace: {}", err))
+    }
+
+    fn write(&mut self, cpu: &Chip8, pc: u16, opcode: u16, changed: u16) -> io::Result<()> {
+        let registers = cpu.get_registers();
+
+        match self.format {
+            TraceFormat::Text => {
+                let text = disasm::disassemble(opcode).unwrap_or_else(|| "???".to_string());
+
+                write!(self.out, "{:>10} {:>7} {:04X} {:04X}  {:<16} I={:04X} SP={:X} DT={:02X} ST={:02X}",
+                       self.cycle, self.frame, pc, opcode, text, cpu.get_program_index(), cpu.get_stack_pointer(),
+                       cpu.get_delay_timer(), cpu.get_sound_timer())?;
+
+                let changes = registers.iter().enumerate().filter(|(x, _)| changed & (1 << x) != 0);
+
+                for (i, (x, value)) in changes.enumerate() {
+                    write!(self.out, "{}V{:X}={:02X}", if i == 0 { "  " } else { " " }, x, value)?;
+                }
+
+                writeln!(self.out)
+            }
+            TraceFormat::Binary => {
+                let mut record = [0u8; RECORD_SIZE];
+
+                record[0..8].copy_from_slice(&self.cycle.to_le_bytes());
+                record[8..12].copy_from_slice(&(self.frame as u32).to_le_bytes());
+                record[12..14].copy_from_slice(&pc.to_le_bytes());
+                record[14..16].copy_from_slice(&opcode.to_le_bytes());
+                record[16..18].copy_from_slice(&cpu.get_program_index().to_le_bytes());
+                record[18] = cpu.get_stack_pointer() as u8;
+                record[19] = cpu.get_delay_timer();
+                record[20] = cpu.get_sound_timer();
+                record[22..24].copy_from_slice(&changed.to_le_bytes());
+                record[24..40].copy_from_slice(registers);
+
+                self.out.write_all(&record)
+            }
+        }
+    }
+}
+
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        let _ = self.out.flush();
+    }
+}
+
+// The opcode at an address, which may be a partial one at the end of memory
+pub fn fetch(cpu: &Chip8, addr: u16) -> u16 {
+    let memory = cpu.get_memory();
+    let byte = |addr: usize| memory.get(addr).copied().unwrap_or(0) as u16;
+
+    byte(addr as usize) << 8 | byte(addr as usize + 1)
+}
@@ -0,0 +1,202 @@
+// The emulator compiled to WebAssembly for web pages, with the canvas frontend in www/. The page
+// runs the frames from requestAnimationFrame instead of sleeping between them, and plays the
+// beeper with WebAudio while is_beeping says so.
+
+use wasm_bindgen::prelude::*;
+
+use chip8_rs::chip8::{Quirks, FRAMEBUFFER_HEIGHT, FRAMEBUFFER_WIDTH};
+use chip8_rs::database::Database;
+use chip8_rs::keypad::{KeyHints, Keymap};
+use chip8_rs::{rom, Chip8};
+
+const DEFAULT_SPEED: u32 = 10;
+
+#[wasm_bindgen(js_name = Chip8)]
+pub struct Machine {
+    cpu: Chip8,
+    // The machine as the ROM was loaded, which resets go back to
+    initial: Chip8,
+    speed: u32,
+    keymap: Keymap,
+    hints: KeyHints,
+    title: Option<String>,
+}
+
+#[wasm_bindgen(js_class = Chip8)]
+impl Machine {
+    // Runs a ROM in any of the formats the emulator reads, with the settings the ROM database or
+    // the ROM itself has for it
+    #[wasm_bindgen(constructor)]
+    pub fn new(rom: &[u8], name: &str) -> Result<Machine, JsError> {
+        let rom = rom::parse(rom.to_vec(), name).map_err(|err| JsError::new(&err))?;
+        let mut cpu = Chip8::new(&rom.data).map_err(|err| JsError::new(&err))?;
+
+        let mut speed = DEFAULT_SPEED;
+        let mut hints = KeyHints::default();
+        let mut title = None;
+
+        // Options embedded in the ROM win over the database, like in the emulator
+        let profiles = [Database::bundled().get_profile(&rom::hash(&rom.data)), rom.profile];
+
+        for profile in profiles.iter().flatten() {
+            cpu.set_quirks(profile.quirks);
+
+            if let Some(palette) = profile.palette {
+                cpu.set_palette(palette);
+            }
+
+            speed = profile.tickrate.unwrap_or(speed);
+            hints = KeyHints::from_map(&profile.keys);
+            title = Some(profile.title.clone());
+        }
+
+        Ok(Machine {
+            initial: cpu.clone(),
+            cpu,
+            speed,
+            keymap: Keymap::default(),
+            hints,
+            title,
+        })
+    }
+
+    pub fn reset(&mut self) {
+        self.cpu = self.initial.clone();
+    }
+
+    #[wasm_bindgen(js_name = runFrame)]
+    pub fn run_frame(&mut self) -> Result<(), JsError> {
+        self.cpu.run_frame(self.speed).map_err(|err| JsError::new(&err))
+    }
+
+    // Presses the keypad key for a KeyboardEvent.key, returning whether there is one so that the
+    // page can leave other keys to the browser
+    #[wasm_bindgen(js_name = keyDown)]
+    pub fn key_down(&mut self, key: &str) -> bool {
+        self.set_key(key, true)
+    }
+
+    #[wasm_bindgen(js_name = keyUp)]
+    pub fn key_up(&mut self, key: &str) -> bool {
+        self.set_key(key, false)
+    }
+
+    #[wasm_bindgen(js_name = releaseKeys)]
+    pub fn release_keys(&mut self) {
+        self.cpu.release_keys();
+    }
+
+    // The screen as RGBA bytes for an ImageData
+    pub fn pixels(&self) -> Vec<u8> {
+        self.cpu
+            .get_pixels()
+            .iter()
+            .flat_map(|pixel| {
+                let [_, r, g, b] = pixel.to_be_bytes();
+                [r, g, b, 0xFF]
+            })
+            .collect()
+    }
+
+    #[wasm_bindgen(js_name = isBeeping)]
+    pub fn is_beeping(&self) -> bool {
+        self.cpu.get_sound_timer() > 0
+    }
+
+    // Seeds the random number generator, which otherwise starts the same way every time
+    #[wasm_bindgen(js_name = setSeed)]
+    pub fn set_seed(&mut self, seed: u64) {
+        self.initial.set_seed(seed);
+        self.cpu.set_seed(seed);
+    }
+
+    // Sets the quirks from a comma-separated list (shift, load-store, jump, vf-reset, wrap,
+    // display-wait, increment-by-x) or "none"
+    #[wasm_bindgen(js_name = setQuirks)]
+    pub fn set_quirks(&mut self, quirks: &str) -> Result<(), JsError> {
+        let quirks = quirks.parse::<Quirks>().map_err(|err| JsError::new(&err))?;
+
+        self.initial.set_quirks(quirks);
+        self.cpu.set_quirks(quirks);
+
+        Ok(())
+    }
+
+    #[wasm_bindgen(js_name = setPalette)]
+    pub fn set_palette(&mut self, background: u32, foreground: u32) {
+        self.initial.set_palette([background, foreground]);
+        self.cpu.set_palette([background, foreground]);
+    }
+
+    #[wasm_bindgen(js_name = saveState)]
+    pub fn save_state(&self) -> Vec<u8> {
+        self.cpu.save_state()
+    }
+
+    #[wasm_bindgen(js_name = loadState)]
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), JsError> {
+        self.cpu.load_state(state).map_err(|err| JsError::new(&err))
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn speed(&self) -> u32 {
+        self.speed
+    }
+
+    #[wasm_bindgen(setter)]
+    pub fn set_speed(&mut self, speed: u32) -> Result<(), JsError> {
+        if speed == 0 {
+            return Err(JsError::new("Speed must be at least 1"));
+        }
+
+        self.speed = speed;
+
+        Ok(())
+    }
+
+    // The title of the ROM in the database, if it's there
+    #[wasm_bindgen(getter)]
+    pub fn title(&self) -> Option<String> {
+        self.title.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn width(&self) -> usize {
+        FRAMEBUFFER_WIDTH
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn height(&self) -> usize {
+        FRAMEBUFFER_HEIGHT
+    }
+}
+
+impl Machine {
+    // Maps keys like the SDL frontend, with the arrow keys, space and enter standing in for the keys
+    // the database suggests for the ROM
+    fn set_key(&mut self, key: &str, pressed: bool) -> bool {
+        let hint = match key {
+            "ArrowUp" => self.hints.up,
+            "ArrowDown" => self.hints.down,
+            "ArrowLeft" => self.hints.left,
+            "ArrowRight" => self.hints.right,
+            " " => self.hints.a,
+            "Enter" => self.hints.b,
+            _ => None,
+        };
+
+        let mut chars = key.chars();
+        let mapped = hint.or_else(|| match (chars.next(), chars.next()) {
+            (Some(c), None) => self.keymap.map_char(c),
+            _ => None,
+        });
+
+        match mapped {
+            Some(key) => {
+                self.cpu.set_key_state(key, pressed);
+                true
+            }
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,170 @@
+// The canvas frontend of the WebAssembly build. ROMs come from the ?rom= parameter, the file picker
+// or being dropped onto the page, and run at 60 frames per second whatever the display's refresh
+// rate is.
+
+import init, { Chip8 } from "./pkg/chip8_web.js";
+
+const FRAME_TIME = 1000 / 60;
+
+// The beeper of the SDL frontend
+const BEEP_FREQUENCY = 440;
+const BEEP_VOLUME = 0.25;
+
+const canvas = document.getElementById("screen");
+const context = canvas.getContext("2d");
+const status = document.getElementById("status");
+
+let machine = null;
+let beeper = null;
+let lag = 0;
+let lastTime = null;
+
+// The keys the machine took, so that key repeats don't press them again
+const held = new Set();
+
+function start(data, name) {
+    try {
+        machine = new Chip8(data, name);
+    } catch (err) {
+        stop(err.message);
+        return;
+    }
+
+    machine.setSeed(BigInt(Math.floor(Math.random() * 2 ** 32)));
+    held.clear();
+
+    canvas.width = machine.width;
+    canvas.height = machine.height;
+    status.textContent = machine.title ?? name;
+}
+
+function stop(message) {
+    machine = null;
+    status.textContent = message;
+
+    if (beeper) {
+        beeper.gain.value = 0;
+    }
+}
+
+// Browsers only let pages play sound once the user has interacted with them
+function startBeeper() {
+    if (beeper) {
+        return;
+    }
+
+    const audio = new AudioContext();
+    const oscillator = new OscillatorNode(audio, { type: "square", frequency: BEEP_FREQUENCY });
+
+    beeper = new GainNode(audio, { gain: 0 });
+    oscillator.connect(beeper).connect(audio.destination);
+    oscillator.start();
+}
+
+function frame(time) {
+    requestAnimationFrame(frame);
+
+    if (!machine) {
+        lastTime = null;
+        return;
+    }
+
+    // After the page has been in the background the game carries on instead of catching up
+    lag = Math.min(lag + (lastTime === null ? FRAME_TIME : time - lastTime), FRAME_TIME * 4);
+    lastTime = time;
+
+    while (lag >= FRAME_TIME) {
+        lag -= FRAME_TIME;
+
+        try {
+            machine.runFrame();
+        } catch (err) {
+            stop(`The program stopped: ${err.message}`);
+            return;
+        }
+    }
+
+    const pixels = new ImageData(new Uint8ClampedArray(machine.pixels()), machine.width, machine.height);
+    context.putImageData(pixels, 0, 0);
+
+    if (beeper) {
+        beeper.gain.value = machine.isBeeping() ? BEEP_VOLUME : 0;
+    }
+}
+
+async function loadUrl(url) {
+    const response = await fetch(url);
+
+    if (!response.ok) {
+        stop(`Failed to fetch ${url}: ${response.status} ${response.statusText}`);
+        return;
+    }
+
+    start(new Uint8Array(await response.arrayBuffer()), url.split("/").pop());
+}
+
+async function loadFile(file) {
+    start(new Uint8Array(await file.arrayBuffer()), file.name);
+}
+
+document.addEventListener("keydown", (event) => {
+    startBeeper();
+
+    if (held.has(event.key)) {
+        event.preventDefault();
+    } else if (machine && machine.keyDown(event.key)) {
+        held.add(event.key);
+        event.preventDefault();
+    }
+});
+
+document.addEventListener("keyup", (event) => {
+    if (held.delete(event.key) && machine) {
+        machine.keyUp(event.key);
+    }
+});
+
+window.addEventListener("blur", () => {
+    held.clear();
+
+    if (machine) {
+        machine.releaseKeys();
+    }
+});
+
+document.addEventListener("dragover", (event) => {
+    event.preventDefault();
+    document.body.classList.add("dragging");
+});
+
+document.addEventListener("dragleave", () => document.body.classList.remove("dragging"));
+
+document.addEventListener("drop", (event) => {
+    event.preventDefault();
+    document.body.classList.remove("dragging");
+    startBeeper();
+
+    const file = event.dataTransfer.files[0];
+
+    if (file) {
+        loadFile(file);
+    }
+});
+
+document.getElementById("file").addEventListener("change", (event) => {
+    startBeeper();
+
+    if (event.target.files[0]) {
+        loadFile(event.target.files[0]);
+    }
+});
+
+await init();
+
+const rom = new URLSearchParams(location.search).get("rom");
+
+if (rom) {
+    loadUrl(rom);
+}
+
+requestAnimationFrame(frame);